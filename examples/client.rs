@@ -24,7 +24,7 @@ async fn main() -> Result<()> {
     let t2 = tokio::spawn(async move {
         let (res_tx, res_rx) = oneshot::channel();
         let cmd = ClientCommand {
-            cmd: RedisCommand::Set("hello".to_string(), "world".to_string(), vec![]),
+            cmd: RedisCommand::Set("hello".to_string(), Bytes::from("world"), vec![]),
             res_channel: res_tx,
         };
         tx2.send(cmd).await.unwrap();
@@ -32,7 +32,7 @@ async fn main() -> Result<()> {
         println!("[main] SET = {:?}", res);
     });
 
-    let clientManager = tokio::spawn(async move {
+    let client_manager = tokio::spawn(async move {
         let mut client = client::connect("127.0.0.1:6379").await.unwrap();
         while let Some(cmd) = rx.recv().await {
             match cmd.cmd {
@@ -41,16 +41,14 @@ async fn main() -> Result<()> {
                         println!("[manager] GET = {:?}", val);
                         let _ = cmd.res_channel.send(ServerResponse::Value(val));
                     }
-                    Err(e) => {
+                    Err(_) => {
                         let _ = cmd
                             .res_channel
                             .send(ServerResponse::Error("Key not found".to_string()));
                     }
                 },
                 RedisCommand::Set(key, val, _) => {
-                    let _ = client
-                        .set(&key, Bytes::from(val.clone().into_bytes()))
-                        .await;
+                    let _ = client.set(&key, val).await;
                     let _ = cmd.res_channel.send(ServerResponse::None);
                 }
                 _ => {}
@@ -60,7 +58,7 @@ async fn main() -> Result<()> {
 
     t1.await.unwrap();
     t2.await.unwrap();
-    clientManager.await.unwrap();
+    client_manager.await.unwrap();
 
     Ok(())
 }
@@ -70,6 +68,8 @@ struct ClientCommand {
     res_channel: oneshot::Sender<ServerResponse>,
 }
 
+// fields are only read through the Debug output
+#[allow(dead_code)]
 #[derive(Debug)]
 enum ServerResponse {
     Value(Option<Bytes>),
@@ -0,0 +1,43 @@
+use std::{path::Path, process::ExitCode};
+
+use redis_server::resp::check;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let (fix, target) = match args.as_slice() {
+        [_, target] => (false, target),
+        [_, flag, target] if flag == "--fix" => (true, target),
+        _ => {
+            eprintln!(
+                "Usage: {} [--fix] <file.manifest|file.aof|appenddir>",
+                args[0]
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = match check::check_aof(Path::new(target)) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Cannot check {}: {}", target, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", report);
+    if report.is_ok() {
+        return ExitCode::SUCCESS;
+    }
+    if !fix {
+        println!("Run with --fix to truncate the AOF to its last valid command");
+        return ExitCode::FAILURE;
+    }
+    match check::fix_aof(&report) {
+        Ok(discarded) => {
+            println!("Successfully truncated AOF, {} bytes discarded", discarded);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to fix the AOF: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
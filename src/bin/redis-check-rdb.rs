@@ -0,0 +1,26 @@
+use std::process::ExitCode;
+
+use redis_server::resp::check;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1) else {
+        eprintln!("Usage: {} <rdb-file-name>", args[0]);
+        return ExitCode::FAILURE;
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("[offset 0] Checking RDB file {}", path);
+    let report = check::check_rdb(&data);
+    print!("{}", report);
+    if report.is_ok() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
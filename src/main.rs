@@ -1,7 +1,36 @@
-mod resp;
-use resp::server::run_server;
+use std::process::ExitCode;
+
+use redis_server::resp::{
+    cli::{self, Invocation},
+    memtest,
+    server::run_server,
+};
+
+/// Passes over the memory for --test-memory.
+const MEMTEST_PASSES: usize = 4;
 
 #[tokio::main]
-async fn main() {
-    run_server().await.unwrap();
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let program = args.first().map_or("redis_server", String::as_str);
+    match cli::parse_args(&args[1.min(args.len())..]) {
+        Ok(Invocation::Version) => println!("{}", cli::version()),
+        Ok(Invocation::Help) => println!("{}", cli::usage(program)),
+        Ok(Invocation::TestMemory(megabytes)) => {
+            if !memtest::test_memory(megabytes, MEMTEST_PASSES) {
+                return ExitCode::FAILURE;
+            }
+        }
+        // run_server reports what went wrong before returning
+        Ok(Invocation::Run(server_args)) => {
+            if run_server(server_args).await.is_err() {
+                return ExitCode::FAILURE;
+            }
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::usage(program));
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,1205 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use ring::{
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use super::{
+    client::Client,
+    command_table::{self, CommandSpec, KeyAccess, CATEGORIES},
+    glob::glob_match,
+    persistence::write_atomically,
+    pubsub,
+    redisconfig::{self, quote, tokenize, Config},
+    resp_value::{bulk, RespType},
+};
+
+lazy_static! {
+    /// Every user by name. There is always a "default" user, the one clients
+    /// are logged in as when they connect.
+    static ref USERS: RwLock<BTreeMap<String, User>> = RwLock::new(default_users());
+    /// ACL LOG, newest first
+    static ref LOG: Mutex<Vec<LogEntry>> = Mutex::new(vec![]);
+}
+
+static NEXT_LOG_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Failures within this many milliseconds of the last one like them are
+/// counted in its ACL LOG entry instead of adding another.
+const LOG_GROUPING_MS: i64 = 60_000;
+
+/// What a user is allowed to do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: String,
+    enabled: bool,
+    /// any password is accepted
+    nopass: bool,
+    /// SHA-256 of each password, in lowercase hex
+    passwords: Vec<String>,
+    /// the +/- rules in the order given; the last one matching a command
+    /// decides whether the user may run it
+    commands: Vec<(bool, CommandRule)>,
+    keys: Vec<KeyPattern>,
+    /// glob patterns of the Pub/Sub channels the user may use
+    channels: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CommandRule {
+    All,
+    Category(&'static str),
+    Command(&'static str),
+    Subcommand(&'static str, &'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+/// Why a user may not run a command.
+#[derive(Debug, PartialEq)]
+pub enum Denial {
+    /// the command, as `config|get` for a subcommand
+    Command(String),
+    Key(String),
+    Channel(String),
+}
+
+impl Denial {
+    fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command(_) => "command",
+            Denial::Key(_) => "key",
+            Denial::Channel(_) => "channel",
+        }
+    }
+
+    fn object(&self) -> &str {
+        match self {
+            Denial::Command(object) | Denial::Key(object) | Denial::Channel(object) => object,
+        }
+    }
+
+    /// The error text, without the NOPERM code. Like redis, clients aren't
+    /// told which key or channel it was; ACL DRYRUN is, with `verbose`.
+    pub fn message(&self, user: &str, verbose: bool) -> String {
+        match self {
+            Denial::Command(command) => format!(
+                "User {} has no permissions to run the '{}' command",
+                user, command
+            ),
+            Denial::Key(key) if verbose => format!("No permissions to access the '{}' key", key),
+            Denial::Key(_) => "No permissions to access a key".to_string(),
+            Denial::Channel(channel) if verbose => {
+                format!("No permissions to access the '{}' channel", channel)
+            }
+            Denial::Channel(_) => "No permissions to access a channel".to_string(),
+        }
+    }
+}
+
+impl User {
+    /// A user as ACL SETUSER creates it: off, without passwords, commands or
+    /// keys, and with the channels of `acl-pubsub-default`.
+    pub fn new(name: &str) -> User {
+        let all_channels = redisconfig::get_config("acl-pubsub-default")
+            .is_some_and(|value| value == "allchannels");
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: vec![],
+            keys: vec![],
+            channels: if all_channels {
+                vec!["*".to_string()]
+            } else {
+                vec![]
+            },
+        }
+    }
+
+    /// The default user before any configuration: "on nopass ~* &* +@all".
+    fn default_user() -> User {
+        User {
+            name: "default".to_string(),
+            enabled: true,
+            nopass: true,
+            passwords: vec![],
+            commands: vec![(true, CommandRule::All)],
+            keys: vec![KeyPattern {
+                pattern: "*".to_string(),
+                read: true,
+                write: true,
+            }],
+            channels: vec!["*".to_string()],
+        }
+    }
+
+    /// Applies ACL SETUSER rules in order. On error the user may be left
+    /// half changed, so callers apply them to a copy.
+    pub fn apply(&mut self, rules: &[String]) -> Result<(), String> {
+        for rule in rules {
+            self.apply_rule(rule).map_err(|reason| {
+                format!("Error in ACL SETUSER modifier '{}': {}", rule, reason)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn apply_rule(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.add_keys("*", true, true),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.add_channel("*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.add_command_rule(true, CommandRule::All),
+            "nocommands" => self.add_command_rule(false, CommandRule::All),
+            "reset" => {
+                let name = std::mem::take(&mut self.name);
+                *self = User::new(&name);
+                self.add_command_rule(false, CommandRule::All);
+            }
+            // payloads are always checked by RESTORE
+            "sanitize-payload" | "skip-sanitize-payload" => {}
+            _ => return self.apply_pattern_rule(rule),
+        }
+        Ok(())
+    }
+
+    // rules whose first character says what follows, as in >password
+    fn apply_pattern_rule(&mut self, rule: &str) -> Result<(), &'static str> {
+        let mut chars = rule.chars();
+        let Some(first) = chars.next() else {
+            return Err("Syntax error");
+        };
+        let rest = chars.as_str();
+        match first {
+            '>' => self.add_password(hash_password(rest.as_bytes())),
+            '#' => {
+                let valid = rest.len() == 64
+                    && rest.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+                if !valid {
+                    return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters");
+                }
+                self.add_password(rest.to_string());
+            }
+            '<' | '!' => {
+                let hash = match first {
+                    '<' => hash_password(rest.as_bytes()),
+                    _ => rest.to_string(),
+                };
+                let Some(i) = self.passwords.iter().position(|p| *p == hash) else {
+                    return Err(
+                        "The password you are trying to remove from the user does not exist",
+                    );
+                };
+                self.passwords.remove(i);
+            }
+            '~' => self.add_keys(rest, true, true),
+            '%' => {
+                let (flags, pattern) = rest.split_once('~').ok_or("Syntax error")?;
+                let (mut read, mut write) = (false, false);
+                for flag in flags.chars() {
+                    match flag.to_ascii_uppercase() {
+                        'R' => read = true,
+                        'W' => write = true,
+                        _ => return Err("Syntax error"),
+                    }
+                }
+                if !read && !write {
+                    return Err("Syntax error");
+                }
+                self.add_keys(pattern, read, write);
+            }
+            '&' => self.add_channel(rest),
+            '+' | '-' => {
+                let rule =
+                    parse_command_rule(rest).ok_or("Unknown command or category name in ACL")?;
+                self.add_command_rule(first == '+', rule);
+            }
+            _ => return Err("Syntax error"),
+        }
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn add_keys(&mut self, pattern: &str, read: bool, write: bool) {
+        match self.keys.iter_mut().find(|k| k.pattern == pattern) {
+            Some(existing) => {
+                existing.read |= read;
+                existing.write |= write;
+            }
+            None => self.keys.push(KeyPattern {
+                pattern: pattern.to_string(),
+                read,
+                write,
+            }),
+        }
+    }
+
+    fn add_channel(&mut self, pattern: &str) {
+        if !self.channels.iter().any(|c| c == pattern) {
+            self.channels.push(pattern.to_string());
+        }
+    }
+
+    // +@all and -@all make every earlier rule moot, and a rule replaces an
+    // earlier one for the same command or category
+    fn add_command_rule(&mut self, allow: bool, rule: CommandRule) {
+        if rule == CommandRule::All {
+            self.commands.clear();
+        } else {
+            self.commands.retain(|(_, r)| *r != rule);
+        }
+        self.commands.push((allow, rule));
+    }
+
+    /// The rules that recreate this user, as ACL LIST and the aclfile show
+    /// them after the user name.
+    pub fn describe(&self) -> Vec<String> {
+        let mut rules = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{}", hash)));
+        rules.extend(self.describe_keys());
+        match self.channels.is_empty() {
+            true => rules.push("resetchannels".to_string()),
+            false => rules.extend(self.channels.iter().map(|c| format!("&{}", c))),
+        }
+        rules.extend(self.describe_commands());
+        rules
+    }
+
+    fn describe_keys(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|k| match (k.read, k.write) {
+                (true, true) => format!("~{}", k.pattern),
+                (true, false) => format!("%R~{}", k.pattern),
+                _ => format!("%W~{}", k.pattern),
+            })
+            .collect()
+    }
+
+    fn describe_commands(&self) -> Vec<String> {
+        let mut rules = vec![];
+        if self
+            .commands
+            .first()
+            .is_none_or(|(_, r)| *r != CommandRule::All)
+        {
+            rules.push("-@all".to_string());
+        }
+        for (allow, rule) in &self.commands {
+            let sign = if *allow { '+' } else { '-' };
+            rules.push(match rule {
+                CommandRule::All => format!("{}@all", sign),
+                CommandRule::Category(category) => format!("{}@{}", sign, category),
+                CommandRule::Command(command) => format!("{}{}", sign, command),
+                CommandRule::Subcommand(command, sub) => format!("{}{}|{}", sign, command, sub),
+            });
+        }
+        rules
+    }
+
+    fn password_matches(&self, password: &[u8]) -> bool {
+        if self.nopass {
+            return true;
+        }
+        let hash = hash_password(password);
+        // check them all, so the time taken doesn't tell which one matched
+        self.passwords.iter().fold(false, |found, p| {
+            constant_time_eq(hash.as_bytes(), p.as_bytes()) | found
+        })
+    }
+
+    /// Whether the user may run the command `name` (already resolved from
+    /// any rename) with `args`, which start with the name as sent.
+    pub fn check(&self, name: &str, args: &[Bytes]) -> Result<(), Denial> {
+        let Some(spec) = command_table::lookup(name) else {
+            // unknown commands fail on their own
+            return Ok(());
+        };
+        let sub = spec.subcommand(args);
+        if !self.command_allowed(spec, sub) {
+            let name = match sub {
+                Some(sub) => format!("{}|{}", spec.name, sub.name),
+                None => spec.name.to_string(),
+            };
+            return Err(Denial::Command(name));
+        }
+        for i in spec.key_positions(args) {
+            if !self.key_allowed(&args[i], spec.access) {
+                return Err(Denial::Key(String::from_utf8_lossy(&args[i]).to_string()));
+            }
+        }
+        let (channels, patterns) = pubsub::channel_args(spec.name, args);
+        for channel in channels {
+            if !self.channel_allowed(channel, patterns) {
+                return Err(Denial::Channel(
+                    String::from_utf8_lossy(channel).to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn command_allowed(&self, spec: &CommandSpec, sub: Option<&CommandSpec>) -> bool {
+        let categories = sub.unwrap_or(spec).categories;
+        let mut allowed = false;
+        for (allow, rule) in &self.commands {
+            let matches = match rule {
+                CommandRule::All => true,
+                CommandRule::Category(category) => categories.contains(category),
+                CommandRule::Command(command) => spec.name == *command,
+                CommandRule::Subcommand(command, name) => {
+                    spec.name == *command && sub.is_some_and(|sub| sub.name == *name)
+                }
+            };
+            if matches {
+                allowed = *allow;
+            }
+        }
+        allowed
+    }
+
+    fn key_allowed(&self, key: &[u8], access: KeyAccess) -> bool {
+        let (read, write) = match access {
+            KeyAccess::Read => (true, false),
+            KeyAccess::Write => (false, true),
+            KeyAccess::ReadWrite => (true, true),
+        };
+        self.keys.iter().any(|k| {
+            (k.read || !read) && (k.write || !write) && glob_match(k.pattern.as_bytes(), key, false)
+        })
+    }
+
+    /// Whether the user may publish or subscribe to `channel`. A pattern
+    /// subscribed to with PSUBSCRIBE has to be one of the user's channel
+    /// patterns as written, unless the user has all channels.
+    pub fn channel_allowed(&self, channel: &[u8], is_pattern: bool) -> bool {
+        self.channels.iter().any(|pattern| {
+            if is_pattern {
+                pattern == "*" || pattern.as_bytes() == channel
+            } else {
+                glob_match(pattern.as_bytes(), channel, false)
+            }
+        })
+    }
+}
+
+fn parse_command_rule(name: &str) -> Option<CommandRule> {
+    let name = name.to_lowercase();
+    if let Some(category) = name.strip_prefix('@') {
+        if category == "all" {
+            return Some(CommandRule::All);
+        }
+        let category = CATEGORIES.iter().find(|c| **c == category)?;
+        return Some(CommandRule::Category(category));
+    }
+    match name.split_once('|') {
+        Some((command, sub)) => {
+            let spec = command_table::lookup(command)?;
+            let sub = spec.subcommands.iter().find(|s| s.name == sub)?;
+            Some(CommandRule::Subcommand(spec.name, sub.name))
+        }
+        None => Some(CommandRule::Command(command_table::lookup(&name)?.name)),
+    }
+}
+
+fn hash_password(password: &[u8]) -> String {
+    digest::digest(&digest::SHA256, password)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Compares a password hash with a stored one. The time taken depends only
+/// on the length of what was given, so it tells nothing about how much of it
+/// was right.
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    let mut diff = (given.len() != expected.len()) as u8;
+    for (i, byte) in given.iter().enumerate() {
+        let other = if expected.is_empty() {
+            0
+        } else {
+            expected[i % expected.len()]
+        };
+        diff |= byte ^ other;
+    }
+    diff == 0
+}
+
+fn default_users() -> BTreeMap<String, User> {
+    BTreeMap::from([("default".to_string(), User::default_user())])
+}
+
+// like redis, requirepass is a password for the default user, and an empty
+// one lets everybody in
+fn set_default_password(user: &mut User, password: &str) {
+    user.passwords.clear();
+    user.nopass = password.is_empty();
+    if !password.is_empty() {
+        user.passwords.push(hash_password(password.as_bytes()));
+    }
+}
+
+/// The users a configuration sets up: the default user with `requirepass`,
+/// then those of the aclfile or of the `user` directives.
+fn users_from_config(config: &Config) -> Result<BTreeMap<String, User>, String> {
+    let aclfile = config.get("aclfile").unwrap_or_default();
+    if !aclfile.is_empty() && !config.users.is_empty() {
+        return Err("Configuring Redis with users defined in redis.conf and at the same setting an ACL file path is invalid. This setup is very likely to lead to configuration errors and security holes, please define either an ACL file or declare users directly in your redis.conf, but not both.".to_string());
+    }
+    let mut default = User::default_user();
+    set_default_password(&mut default, config.get("requirepass").unwrap_or_default());
+    if !aclfile.is_empty() {
+        return load_file(Path::new(aclfile), default);
+    }
+    let mut users = BTreeMap::from([("default".to_string(), default)]);
+    for line in &config.users {
+        let user = parse_user(line)?;
+        users.insert(user.name.clone(), user);
+    }
+    Ok(users)
+}
+
+/// A user from a `user` directive or aclfile line: the name, then the rules
+/// applied to a new user.
+pub fn parse_user(line: &[String]) -> Result<User, String> {
+    let Some(name) = line.first() else {
+        return Err("wrong number of arguments".to_string());
+    };
+    check_user_name(name)?;
+    let mut user = User::new(name);
+    user.apply(&line[1..])?;
+    Ok(user)
+}
+
+fn check_user_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '\0') {
+        return Err("Usernames can't contain spaces or null characters".to_string());
+    }
+    Ok(())
+}
+
+// the users of an aclfile; the default user stays as `default` unless the
+// file has it
+fn load_file(path: &Path, default: User) -> Result<BTreeMap<String, User>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut users = BTreeMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |reason: String| format!("{}:{}: {}", path.display(), i + 1, reason);
+        let args = tokenize(line).map_err(error)?;
+        if args[0] != "user" {
+            return Err(error("should start with user keyword".to_string()));
+        }
+        let user = parse_user(&args[1..]).map_err(error)?;
+        if users.contains_key(&user.name) {
+            return Err(error(format!("Duplicate user '{}' found", user.name)));
+        }
+        users.insert(user.name.clone(), user);
+    }
+    users.entry("default".to_string()).or_insert(default);
+    Ok(users)
+}
+
+/// Sets up the users from the running configuration, at startup.
+pub fn init() -> Result<(), String> {
+    *USERS.write() = users_from_config(&redisconfig::config())?;
+    Ok(())
+}
+
+/// Makes the running `requirepass` the default user's password.
+pub fn set_requirepass() {
+    let password = redisconfig::get_config("requirepass").unwrap_or_default();
+    if let Some(user) = USERS.write().get_mut("default") {
+        set_default_password(user, &password);
+    }
+}
+
+/// Every user as a `user` directive, without the directive name.
+pub fn describe_users() -> Vec<Vec<String>> {
+    describe(&USERS.read())
+}
+
+/// The users `config` sets up, in the form of `describe_users`.
+pub fn describe_config_users(config: &Config) -> Result<Vec<Vec<String>>, String> {
+    Ok(describe(&users_from_config(config)?))
+}
+
+fn describe(users: &BTreeMap<String, User>) -> Vec<Vec<String>> {
+    users
+        .values()
+        .map(|user| {
+            let mut line = vec![user.name.clone()];
+            line.extend(user.describe());
+            line
+        })
+        .collect()
+}
+
+pub fn user_exists(name: &str) -> bool {
+    USERS.read().contains_key(name)
+}
+
+/// Whether the default user takes any password, as when no `requirepass`
+/// is set.
+pub fn default_user_nopass() -> bool {
+    USERS.read().get("default").is_some_and(|user| user.nopass)
+}
+
+/// Whether new connections are logged in as the default user without AUTH.
+pub fn auto_login() -> bool {
+    USERS
+        .read()
+        .get("default")
+        .is_some_and(|user| user.nopass && user.enabled)
+}
+
+/// Whether `password` logs a client in as `name`.
+pub fn authenticate(name: &str, password: &[u8]) -> bool {
+    USERS
+        .read()
+        .get(name)
+        .is_some_and(|user| user.enabled && user.password_matches(password))
+}
+
+/// Checks a command against the user's permissions, as `User::check`.
+pub fn check(user: &str, name: &str, args: &[Bytes]) -> Result<(), Denial> {
+    match USERS.read().get(user) {
+        Some(user) => user.check(name, args),
+        None => Err(Denial::Command(name.to_string())),
+    }
+}
+
+#[derive(Debug)]
+struct LogEntry {
+    count: u64,
+    reason: &'static str,
+    object: String,
+    username: String,
+    client_info: String,
+    entry_id: u64,
+    /// milliseconds since the epoch
+    created: i64,
+    updated: i64,
+}
+
+impl LogEntry {
+    fn reply(&self, now: i64) -> RespType {
+        RespType::Array(Some(vec![
+            bulk("count"),
+            RespType::Integer(self.count as i64),
+            bulk("reason"),
+            bulk(self.reason),
+            bulk("context"),
+            bulk("toplevel"),
+            bulk("object"),
+            bulk(&self.object),
+            bulk("username"),
+            bulk(&self.username),
+            bulk("age-seconds"),
+            bulk(format!("{:.3}", (now - self.created) as f64 / 1000.0)),
+            bulk("client-info"),
+            bulk(&self.client_info),
+            bulk("entry-id"),
+            RespType::Integer(self.entry_id as i64),
+            bulk("timestamp-created"),
+            RespType::Integer(self.created),
+            bulk("timestamp-last-updated"),
+            RespType::Integer(self.updated),
+        ]))
+    }
+}
+
+/// Records a command refused to a client in the ACL LOG.
+pub fn log_denial(client: &Client, denial: &Denial) {
+    log(client, denial.reason(), denial.object(), &client.user);
+}
+
+/// Records a failed AUTH in the ACL LOG.
+pub fn log_auth_failure(client: &Client, username: &str) {
+    log(client, "auth", "AUTH", username);
+}
+
+// like redis, failures like a recent one only count in its entry
+fn log(client: &Client, reason: &'static str, object: &str, username: &str) {
+    let now = Utc::now().timestamp_millis();
+    let client_info = format!(
+        "id={} name={} user={}",
+        client.id,
+        client.name.as_deref().unwrap_or_default(),
+        client.user
+    );
+    let mut log = LOG.lock();
+    if let Some(entry) = log.iter_mut().find(|e| {
+        e.reason == reason
+            && e.object == object
+            && e.username == username
+            && now - e.updated < LOG_GROUPING_MS
+    }) {
+        entry.count += 1;
+        entry.updated = now;
+        entry.client_info = client_info;
+        return;
+    }
+    log.insert(
+        0,
+        LogEntry {
+            count: 1,
+            reason,
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info,
+            entry_id: NEXT_LOG_ID.fetch_add(1, Ordering::Relaxed),
+            created: now,
+            updated: now,
+        },
+    );
+    log.truncate(redisconfig::get_int("acllog-max-len").max(0) as usize);
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString("OK".to_string())
+}
+
+const NO_ACLFILE: &str = "ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.";
+
+/// ACL and its subcommands, run for `client`; `args` starts with ACL.
+pub fn acl_command(client: &Client, args: &[Bytes]) -> RespType {
+    let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+    let Some(spec) = command_table::lookup("acl").and_then(|acl| acl.subcommand(args)) else {
+        return match args.len() {
+            1 => RespType::Error("ERR wrong number of arguments for 'acl' command".to_string()),
+            _ => RespType::Error(format!(
+                "ERR unknown subcommand '{}'. Try ACL HELP.",
+                text(1)
+            )),
+        };
+    };
+    if !spec.arity_matches(args.len()) {
+        return RespType::Error(format!(
+            "ERR wrong number of arguments for 'acl|{}' command",
+            spec.name
+        ));
+    }
+    match spec.name {
+        "cat" => acl_cat(args.get(2).map(|_| text(2))),
+        "deluser" => acl_deluser(&args[2..]),
+        "dryrun" => acl_dryrun(&args[2..]),
+        "genpass" => acl_genpass(args.get(2).map(|_| text(2))),
+        "getuser" => acl_getuser(&text(2)),
+        "list" => RespType::Array(Some(
+            describe_users()
+                .iter()
+                .map(|line| bulk(format!("user {}", line.join(" "))))
+                .collect(),
+        )),
+        "load" => acl_load(),
+        "log" => acl_log(args.get(2).map(|_| text(2))),
+        "save" => acl_save(),
+        "setuser" => acl_setuser(&args[2..]),
+        "users" => RespType::Array(Some(USERS.read().keys().map(bulk).collect())),
+        "whoami" => bulk(&client.user),
+        _ => RespType::Array(Some(
+            [
+                "ACL <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "CAT [<category>]",
+                "    List all commands that belong to <category>, or all command categories",
+                "    when no category is specified.",
+                "DELUSER <username> [<username> ...]",
+                "    Delete a list of users.",
+                "DRYRUN <username> <command> [<arg> ...]",
+                "    Returns whether the user can execute the given command without executing the command.",
+                "GETUSER <username>",
+                "    Get the user's details.",
+                "GENPASS [<bits>]",
+                "    Generate a secure 256-bit user password. The optional `bits` argument can",
+                "    be used to specify a different size.",
+                "LIST",
+                "    Show users details in config file format.",
+                "LOAD",
+                "    Reload users from the ACL file.",
+                "LOG [<count> | RESET]",
+                "    Show the ACL log entries.",
+                "SAVE",
+                "    Save the current config to the ACL file.",
+                "SETUSER <username> <attribute> [<attribute> ...]",
+                "    Create or modify a user with the specified attributes.",
+                "USERS",
+                "    List all the registered usernames.",
+                "WHOAMI",
+                "    Return the current connection username.",
+            ]
+            .iter()
+            .map(|line| RespType::SimpleString(line.to_string()))
+            .collect(),
+        )),
+    }
+}
+
+fn acl_cat(category: Option<String>) -> RespType {
+    let Some(category) = category else {
+        return RespType::Array(Some(CATEGORIES.iter().map(bulk).collect()));
+    };
+    let category = category.to_lowercase();
+    if !CATEGORIES.contains(&category.as_str()) {
+        return RespType::Error(format!("ERR Unknown category '{}'", category));
+    }
+    let mut names = vec![];
+    for spec in command_table::COMMANDS {
+        if spec.categories.contains(&category.as_str()) {
+            names.push(bulk(spec.name));
+        }
+        for sub in spec.subcommands {
+            if sub.categories.contains(&category.as_str()) {
+                names.push(bulk(format!("{}|{}", spec.name, sub.name)));
+            }
+        }
+    }
+    RespType::Array(Some(names))
+}
+
+fn acl_setuser(args: &[Bytes]) -> RespType {
+    let args: Vec<String> = args
+        .iter()
+        .map(|a| String::from_utf8_lossy(a).to_string())
+        .collect();
+    if let Err(e) = check_user_name(&args[0]) {
+        return RespType::Error(format!("ERR {}", e));
+    }
+    let mut users = USERS.write();
+    let mut user = users
+        .get(&args[0])
+        .cloned()
+        .unwrap_or_else(|| User::new(&args[0]));
+    match user.apply(&args[1..]) {
+        Ok(()) => {
+            users.insert(args[0].clone(), user);
+            ok()
+        }
+        Err(e) => RespType::Error(format!("ERR {}", e)),
+    }
+}
+
+fn acl_deluser(names: &[Bytes]) -> RespType {
+    let mut users = USERS.write();
+    let mut deleted = 0;
+    for name in names {
+        let name = String::from_utf8_lossy(name);
+        if name == "default" {
+            return RespType::Error("ERR The 'default' user cannot be removed".to_string());
+        }
+        // clients logged in as the user are disconnected by `server::process`
+        if users.remove(name.as_ref()).is_some() {
+            deleted += 1;
+        }
+    }
+    RespType::Integer(deleted)
+}
+
+fn acl_getuser(name: &str) -> RespType {
+    let users = USERS.read();
+    let Some(user) = users.get(name) else {
+        return RespType::Array(None);
+    };
+    let mut flags = vec![bulk(if user.enabled { "on" } else { "off" })];
+    if user.nopass {
+        flags.push(bulk("nopass"));
+    }
+    let channels: Vec<String> = user.channels.iter().map(|c| format!("&{}", c)).collect();
+    RespType::Array(Some(vec![
+        bulk("flags"),
+        RespType::Array(Some(flags)),
+        bulk("passwords"),
+        RespType::Array(Some(user.passwords.iter().map(bulk).collect())),
+        bulk("commands"),
+        bulk(user.describe_commands().join(" ")),
+        bulk("keys"),
+        bulk(user.describe_keys().join(" ")),
+        bulk("channels"),
+        bulk(channels.join(" ")),
+        bulk("selectors"),
+        RespType::Array(Some(vec![])),
+    ]))
+}
+
+fn acl_dryrun(args: &[Bytes]) -> RespType {
+    let name = String::from_utf8_lossy(&args[0]);
+    let users = USERS.read();
+    let Some(user) = users.get(name.as_ref()) else {
+        return RespType::Error(format!("ERR User '{}' not found", name));
+    };
+    let command = &args[1..];
+    let command_name = String::from_utf8_lossy(&command[0]).to_lowercase();
+    let Some(spec) = command_table::lookup(&command_name) else {
+        return RespType::Error(format!("ERR Command '{}' not found", command_name));
+    };
+    if !spec.arity_matches(command.len()) {
+        return RespType::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            spec.name
+        ));
+    }
+    match user.check(spec.name, command) {
+        Ok(()) => ok(),
+        Err(denial) => bulk(denial.message(&user.name, true)),
+    }
+}
+
+fn acl_genpass(bits: Option<String>) -> RespType {
+    let bits = match bits.map(|b| b.parse::<usize>()) {
+        None => 256,
+        Some(Ok(bits)) if (1..=4096).contains(&bits) => bits,
+        Some(_) => {
+            return RespType::Error(
+                "ERR ACL GENPASS argument must be the number of bits for the output password, a positive number up to 4096"
+                    .to_string(),
+            )
+        }
+    };
+    let chars = bits.div_ceil(4);
+    let mut random = vec![0; chars.div_ceil(2)];
+    if SystemRandom::new().fill(&mut random).is_err() {
+        return RespType::Error("ERR Failed to generate a random password".to_string());
+    }
+    let mut password: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    password.truncate(chars);
+    bulk(&password)
+}
+
+fn acl_log(arg: Option<String>) -> RespType {
+    let count = match arg {
+        None => 10,
+        Some(arg) if arg.eq_ignore_ascii_case("reset") => {
+            LOG.lock().clear();
+            return ok();
+        }
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                return RespType::Error("ERR value is out of range, must be positive".to_string())
+            }
+        },
+    };
+    let now = Utc::now().timestamp_millis();
+    RespType::Array(Some(
+        LOG.lock()
+            .iter()
+            .take(count)
+            .map(|entry| entry.reply(now))
+            .collect(),
+    ))
+}
+
+fn aclfile() -> Option<String> {
+    redisconfig::get_config("aclfile").filter(|path| !path.is_empty())
+}
+
+fn acl_load() -> RespType {
+    let Some(path) = aclfile() else {
+        return RespType::Error(NO_ACLFILE.to_string());
+    };
+    let default = USERS.read()["default"].clone();
+    match load_file(Path::new(&path), default) {
+        Ok(users) => {
+            *USERS.write() = users;
+            ok()
+        }
+        Err(e) => RespType::Error(format!("ERR {}", e)),
+    }
+}
+
+fn acl_save() -> RespType {
+    let Some(path) = aclfile() else {
+        return RespType::Error(NO_ACLFILE.to_string());
+    };
+    let text: String = describe_users()
+        .iter()
+        .map(|line| {
+            let args: Vec<String> = line.iter().map(|a| quote(a)).collect();
+            format!("user {}\n", args.join(" "))
+        })
+        .collect();
+    match write_atomically(&path, text.as_bytes()) {
+        Ok(()) => ok(),
+        Err(e) => {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Failed to save the ACLs to {}: {}", path, e);
+            }
+            RespType::Error(
+                "ERR There was an error trying to save the ACLs. Please check the server logs for more information"
+                    .to_string(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::test_helpers::args;
+
+    fn user(rules: &str) -> User {
+        let rules: Vec<String> = rules.split_whitespace().map(str::to_string).collect();
+        let mut user = User::new("alice");
+        user.apply(&rules).unwrap();
+        user
+    }
+
+    fn check(user: &User, line: &str) -> Result<(), Denial> {
+        let args = args(line);
+        user.check(&String::from_utf8_lossy(&args[0]).to_lowercase(), &args)
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secreT", b"secret"));
+        assert!(!constant_time_eq(b"secretsecret", b"secret"));
+        assert!(!constant_time_eq(b"", b"secret"));
+        assert!(!constant_time_eq(b"x", b""));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_setuser_rules() {
+        let alice = user("on >secret ~cache:* %R~app:* &news +@read -hgetall +config|get");
+        assert_eq!(
+            alice.describe().join(" "),
+            format!(
+                "on #{} ~cache:* %R~app:* &news -@all +@read -hgetall +config|get",
+                hash_password(b"secret")
+            )
+        );
+        assert!(alice.password_matches(b"secret"));
+        assert!(!alice.password_matches(b"Secret"));
+        // +@all drops everything before it
+        assert_eq!(user("+get -@all +@all").describe_commands(), vec!["+@all"]);
+        assert_eq!(user("").describe(), vec!["off", "resetchannels", "-@all"]);
+        assert_eq!(
+            user("on nopass allkeys allchannels allcommands reset").describe(),
+            vec!["off", "resetchannels", "-@all"]
+        );
+
+        let mut bob = User::new("bob");
+        for (rule, error) in [
+            ("+nosuch", "Unknown command or category name in ACL"),
+            ("+@nosuch", "Unknown command or category name in ACL"),
+            ("+get|x", "Unknown command or category name in ACL"),
+            ("%X~k", "Syntax error"),
+            ("bogus", "Syntax error"),
+            ("<nope", "The password you are trying to remove from the user does not exist"),
+            ("#abc", "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"),
+        ] {
+            assert_eq!(
+                bob.apply(&[rule.to_string()]),
+                Err(format!("Error in ACL SETUSER modifier '{}': {}", rule, error))
+            );
+        }
+        bob.apply(&[">pw".to_string(), "<pw".to_string()]).unwrap();
+        assert!(bob.passwords.is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let alice = user("on ~cache:* %R~app:* +@read +@write -del +config|get");
+        assert_eq!(check(&alice, "GET cache:1"), Ok(()));
+        assert_eq!(check(&alice, "GET app:1"), Ok(()));
+        assert_eq!(
+            check(&alice, "SET app:1 x"),
+            Err(Denial::Key("app:1".to_string()))
+        );
+        assert_eq!(check(&alice, "HSET cache:h f v"), Ok(()));
+        assert_eq!(
+            check(&alice, "DEL cache:1"),
+            Err(Denial::Command("del".to_string()))
+        );
+        assert_eq!(check(&alice, "CONFIG GET maxmemory"), Ok(()));
+        assert_eq!(
+            check(&alice, "CONFIG SET maxmemory 1"),
+            Err(Denial::Command("config|set".to_string()))
+        );
+        assert_eq!(
+            check(&alice, "PING"),
+            Err(Denial::Command("ping".to_string()))
+        );
+        assert_eq!(
+            Denial::Key("app:1".to_string()).message("alice", false),
+            "No permissions to access a key"
+        );
+        assert_eq!(
+            Denial::Key("app:1".to_string()).message("alice", true),
+            "No permissions to access the 'app:1' key"
+        );
+        let default = User::default_user();
+        assert_eq!(check(&default, "MIGRATE h 1 k 0 10"), Ok(()));
+        assert!(default.channel_allowed(b"news", false));
+        assert!(!alice.channel_allowed(b"news", false));
+
+        let bob = user("on +@pubsub &news.*");
+        assert_eq!(check(&bob, "PUBLISH news.1 hi"), Ok(()));
+        assert_eq!(check(&bob, "SUBSCRIBE news.1 news.2"), Ok(()));
+        assert_eq!(
+            check(&bob, "SUBSCRIBE news.1 sport"),
+            Err(Denial::Channel("sport".to_string()))
+        );
+        // patterns have to match the user's as written
+        assert_eq!(check(&bob, "PSUBSCRIBE news.*"), Ok(()));
+        assert_eq!(
+            check(&bob, "PSUBSCRIBE news.1*"),
+            Err(Denial::Channel("news.1*".to_string()))
+        );
+        assert_eq!(check(&bob, "UNSUBSCRIBE sport"), Ok(()));
+    }
+
+    #[test]
+    fn test_load_file() {
+        let path = std::env::temp_dir().join(format!("acl-test-{}.acl", std::process::id()));
+        fs::write(
+            &path,
+            "# users\nuser alice on >pw ~* +@all\nuser \"bob\" off\n",
+        )
+        .unwrap();
+        let users = load_file(&path, User::default_user()).unwrap();
+        assert_eq!(
+            users.keys().collect::<Vec<_>>(),
+            ["alice", "bob", "default"]
+        );
+        assert!(users["alice"].password_matches(b"pw"));
+        assert_eq!(users["default"], User::default_user());
+
+        fs::write(&path, "user alice on\nuser alice off\n").unwrap();
+        assert_eq!(
+            load_file(&path, User::default_user()).err(),
+            Some(format!(
+                "{}:2: Duplicate user 'alice' found",
+                path.display()
+            ))
+        );
+        fs::write(&path, "alice on\n").unwrap();
+        assert_eq!(
+            load_file(&path, User::default_user()).err(),
+            Some(format!(
+                "{}:1: should start with user keyword",
+                path.display()
+            ))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_acl_command() {
+        let client = Client::new();
+        let run = |line: &str| acl_command(&client, &args(line));
+        assert_eq!(run("ACL WHOAMI"), bulk("default"));
+        assert_eq!(run("ACL SETUSER acl-test on >pw +get ~k*"), ok());
+        assert!(authenticate("acl-test", b"pw"));
+        assert!(!authenticate("acl-test", b"other"));
+        assert_eq!(run("ACL DRYRUN acl-test GET k1"), ok());
+        assert_eq!(
+            run("ACL DRYRUN acl-test GET x"),
+            bulk("No permissions to access the 'x' key")
+        );
+        assert_eq!(
+            run("ACL DRYRUN acl-test SET k1 v"),
+            bulk("User acl-test has no permissions to run the 'set' command")
+        );
+        assert_eq!(
+            run("ACL SETUSER acl-test +bogus"),
+            RespType::Error(
+                "ERR Error in ACL SETUSER modifier '+bogus': Unknown command or category name in ACL"
+                    .to_string()
+            )
+        );
+        let RespType::Array(Some(getuser)) = run("ACL GETUSER acl-test") else {
+            panic!("expected the user");
+        };
+        assert_eq!(getuser[5], bulk("-@all +get"));
+        assert_eq!(getuser[7], bulk("~k*"));
+        assert_eq!(run("ACL DELUSER acl-test nosuch"), RespType::Integer(1));
+        assert_eq!(run("ACL GETUSER acl-test"), RespType::Array(None));
+        assert_eq!(
+            run("ACL DELUSER default"),
+            RespType::Error("ERR The 'default' user cannot be removed".to_string())
+        );
+        assert!(matches!(
+            run("ACL GENPASS 10"),
+            RespType::BulkString(Some(p)) if p.len() == 3
+        ));
+        assert!(matches!(
+            run("ACL GENPASS"),
+            RespType::BulkString(Some(p)) if p.len() == 64
+        ));
+        assert!(matches!(run("ACL CAT"), RespType::Array(Some(c)) if c.len() == CATEGORIES.len()));
+        assert!(matches!(
+            run("ACL CAT hash"),
+            RespType::Array(Some(c)) if c.contains(&bulk("hget"))
+        ));
+        assert_eq!(
+            run("ACL WHOAMI extra"),
+            RespType::Error("ERR wrong number of arguments for 'acl|whoami' command".to_string())
+        );
+        assert_eq!(run("ACL LOAD"), RespType::Error(NO_ACLFILE.to_string()));
+    }
+
+    #[test]
+    fn test_acl_log() {
+        let mut client = Client::new();
+        client.user = "acl-log-test".to_string();
+        let denial = Denial::Command("flushall".to_string());
+        log_denial(&client, &denial);
+        log_denial(&client, &denial);
+        let RespType::Array(Some(entries)) = acl_log(None) else {
+            panic!("expected the log");
+        };
+        let entry = entries
+            .iter()
+            .find_map(|e| match e {
+                RespType::Array(Some(e)) if e[9] == bulk("acl-log-test") => Some(e.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(entry[1], RespType::Integer(2));
+        assert_eq!(entry[3], bulk("command"));
+        assert_eq!(entry[7], bulk("flushall"));
+    }
+}
@@ -0,0 +1,968 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+
+use super::{
+    commands::handle_input_cmd, datastore::Db, errors::AofError, persistence::write_atomically,
+    rdb, redisconfig, resp_value::RespType,
+};
+
+/// After a failed rewrite, automatic rewrites wait this long before retrying.
+const REWRITE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// When to fsync the log, as set by `appendfsync`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    /// after every write, before the client gets its reply
+    Always,
+    /// once a second, from a background thread
+    EverySec,
+    /// leave it to the OS
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None,
+        }
+    }
+}
+
+/// Where the AOF lives. Like redis 7, it is split into a base file and
+/// incremental files inside `dir`, all named after `basename`
+/// (`appendfilename`), with a manifest listing which of them are current.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AofPaths {
+    pub dir: PathBuf,
+    pub basename: String,
+    /// where the single-file log from before manifests was written; it is
+    /// read if there is no manifest and becomes the first base file
+    pub legacy: PathBuf,
+}
+
+impl AofPaths {
+    pub fn new(dir: impl Into<PathBuf>, basename: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            basename: basename.to_string(),
+            legacy: PathBuf::from(basename),
+        }
+    }
+
+    pub fn from_config() -> Self {
+        Self::new(
+            redisconfig::get_config("appenddirname").unwrap_or_default(),
+            &redisconfig::get_config("appendfilename").unwrap_or_default(),
+        )
+    }
+
+    pub fn manifest(&self) -> PathBuf {
+        self.dir.join(format!("{}.manifest", self.basename))
+    }
+
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Base,
+    Incr,
+    /// replaced by a rewrite and waiting to be deleted
+    History,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub seq: u64,
+    pub kind: FileKind,
+}
+
+/// The list of files that make up the AOF, in the order they are replayed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub base: Option<ManifestEntry>,
+    pub incrs: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Parses lines of the form `file <name> seq <n> type <b|i|h>`.
+    pub fn parse(text: &str) -> Result<Self, AofError> {
+        let mut manifest = Manifest::default();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let line_start = offset;
+            offset += line.len();
+            let corrupt = |reason: &str| AofError::Corrupt {
+                offset: line_start,
+                reason: reason.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+            if !fields.len().is_multiple_of(2) {
+                return Err(corrupt("invalid manifest line"));
+            }
+            let (mut name, mut seq, mut kind) = (None, None, None);
+            for pair in fields.chunks(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = pair[1].parse().ok(),
+                    "type" => {
+                        kind = match pair[1] {
+                            "b" => Some(FileKind::Base),
+                            "i" => Some(FileKind::Incr),
+                            "h" => Some(FileKind::History),
+                            _ => return Err(corrupt("unknown file type")),
+                        }
+                    }
+                    // unknown keys are skipped, for files written by newer versions
+                    _ => {}
+                }
+            }
+            let (Some(name), Some(seq), Some(kind)) = (name, seq, kind) else {
+                return Err(corrupt("manifest line is missing file, seq or type"));
+            };
+            if name.contains('/') {
+                return Err(corrupt("file names must not contain a path"));
+            }
+            let entry = ManifestEntry { name, seq, kind };
+            match kind {
+                FileKind::Base if manifest.base.is_some() => {
+                    return Err(corrupt("more than one base file"))
+                }
+                FileKind::Base => manifest.base = Some(entry),
+                FileKind::Incr => {
+                    if manifest.incrs.last().is_some_and(|last| last.seq >= seq) {
+                        return Err(corrupt("incr files out of order"));
+                    }
+                    manifest.incrs.push(entry);
+                }
+                FileKind::History => {}
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn encode(&self) -> String {
+        self.files()
+            .map(|entry| {
+                let kind = match entry.kind {
+                    FileKind::Base => 'b',
+                    FileKind::Incr => 'i',
+                    FileKind::History => 'h',
+                };
+                format!("file {} seq {} type {}\n", entry.name, entry.seq, kind)
+            })
+            .collect()
+    }
+
+    /// Base file first, then the incr files.
+    pub fn files(&self) -> impl Iterator<Item = &ManifestEntry> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    fn next_seq(&self, kind: FileKind) -> u64 {
+        let last = match kind {
+            FileKind::Base => self.base.as_ref().map(|b| b.seq),
+            _ => self.incrs.last().map(|i| i.seq),
+        };
+        last.unwrap_or(0) + 1
+    }
+}
+
+fn write_manifest(paths: &AofPaths, manifest: &Manifest) -> io::Result<()> {
+    write_atomically(paths.manifest(), manifest.encode().as_bytes())
+}
+
+fn file_size(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// An open append-only log, appending to the last incr file of the manifest.
+#[derive(Debug)]
+pub struct Aof {
+    paths: AofPaths,
+    manifest: Manifest,
+    file: File,
+    policy: FsyncPolicy,
+    // bytes written since the last fsync was started
+    unsynced: bool,
+    last_fsync: Instant,
+    fsync_in_flight: Arc<AtomicBool>,
+    pub last_write_ok: bool,
+    /// size of the base file
+    pub base_size: u64,
+    /// total size of the incr files
+    pub incr_size: u64,
+    pub rewrite_in_progress: bool,
+    pub last_rewrite_ok: bool,
+    last_rewrite_start: Option<Instant>,
+}
+
+impl Aof {
+    fn open(paths: AofPaths, manifest: Manifest, policy: FsyncPolicy) -> io::Result<Self> {
+        let current = manifest
+            .incrs
+            .last()
+            .ok_or_else(|| io::Error::other("the manifest lists no incr file"))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(paths.file(&current.name))?;
+        let base_size = manifest
+            .base
+            .as_ref()
+            .map_or(0, |b| file_size(&paths.file(&b.name)));
+        let incr_size = manifest
+            .incrs
+            .iter()
+            .map(|i| file_size(&paths.file(&i.name)))
+            .sum();
+        Ok(Self {
+            paths,
+            manifest,
+            file,
+            policy,
+            unsynced: false,
+            last_fsync: Instant::now(),
+            fsync_in_flight: Arc::new(AtomicBool::new(false)),
+            last_write_ok: true,
+            base_size,
+            incr_size,
+            rewrite_in_progress: false,
+            last_rewrite_ok: true,
+            last_rewrite_start: None,
+        })
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: FsyncPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Appends one command. With `appendfsync always` it is on disk when this returns.
+    pub fn append(&mut self, args: &[Bytes]) -> io::Result<()> {
+        let data = encode_command(args);
+        let res = self.file.write_all(&data).and_then(|_| {
+            self.unsynced = true;
+            self.incr_size += data.len() as u64;
+            match self.policy {
+                FsyncPolicy::Always => self.fsync(),
+                _ => Ok(()),
+            }
+        });
+        self.last_write_ok = res.is_ok();
+        res
+    }
+
+    pub fn fsync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = false;
+        self.last_fsync = Instant::now();
+        Ok(())
+    }
+
+    /// For `everysec`: starts an fsync on another thread when a second has
+    /// passed since the last one and the previous one has finished.
+    pub fn background_fsync(&mut self) {
+        if self.policy != FsyncPolicy::EverySec
+            || !self.unsynced
+            || self.last_fsync.elapsed() < Duration::from_secs(1)
+            || self.fsync_in_flight.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+        let file = match self.file.try_clone() {
+            Ok(file) => file,
+            Err(e) => {
+                if redisconfig::log_enabled("warning") {
+                    eprintln!("Failed to fsync the AOF: {}", e);
+                }
+                self.fsync_in_flight.store(false, Ordering::Release);
+                return;
+            }
+        };
+        self.unsynced = false;
+        self.last_fsync = Instant::now();
+        let in_flight = self.fsync_in_flight.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = file.sync_data() {
+                if redisconfig::log_enabled("warning") {
+                    eprintln!("Failed to fsync the AOF: {}", e);
+                }
+            }
+            in_flight.store(false, Ordering::Release);
+        });
+    }
+
+    // moves appends to a fresh incr file, recorded in the manifest before use
+    fn open_new_incr(&mut self) -> io::Result<()> {
+        let seq = self.manifest.next_seq(FileKind::Incr);
+        let name = format!("{}.{}.incr.aof", self.paths.basename, seq);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.paths.file(&name))?;
+        let mut manifest = self.manifest.clone();
+        manifest.incrs.push(ManifestEntry {
+            name,
+            seq,
+            kind: FileKind::Incr,
+        });
+        write_manifest(&self.paths, &manifest)?;
+        // whatever went to the old file must be durable before it stops being appended to
+        self.fsync()?;
+        self.manifest = manifest;
+        self.file = file;
+        Ok(())
+    }
+
+    // makes a finished rewrite current: the new base plus the incr file
+    // opened when the rewrite started, dropping everything the base replaced
+    fn install_base(&mut self, name: String, seq: u64) -> io::Result<()> {
+        let current = self
+            .manifest
+            .incrs
+            .last()
+            .cloned()
+            .ok_or_else(|| io::Error::other("the manifest lists no incr file"))?;
+        let manifest = Manifest {
+            base: Some(ManifestEntry {
+                name,
+                seq,
+                kind: FileKind::Base,
+            }),
+            incrs: vec![current],
+        };
+        write_manifest(&self.paths, &manifest)?;
+        let old = std::mem::replace(&mut self.manifest, manifest);
+        for entry in old.files() {
+            if !self.manifest.files().any(|e| e.name == entry.name) {
+                if let Err(e) = std::fs::remove_file(self.paths.file(&entry.name)) {
+                    if redisconfig::log_enabled("warning") {
+                        eprintln!("Failed to remove old AOF file {}: {}", entry.name, e);
+                    }
+                }
+            }
+        }
+        self.base_size = self
+            .manifest
+            .base
+            .as_ref()
+            .map_or(0, |b| file_size(&self.paths.file(&b.name)));
+        self.incr_size = self
+            .manifest
+            .incrs
+            .iter()
+            .map(|i| file_size(&self.paths.file(&i.name)))
+            .sum();
+        Ok(())
+    }
+
+    /// Fields for the INFO persistence section.
+    pub fn info_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "aof_rewrite_in_progress",
+                (self.rewrite_in_progress as u8).to_string(),
+            ),
+            (
+                "aof_last_bgrewrite_status",
+                if self.last_rewrite_ok { "ok" } else { "err" }.to_string(),
+            ),
+            (
+                "aof_last_write_status",
+                if self.last_write_ok { "ok" } else { "err" }.to_string(),
+            ),
+            (
+                "aof_current_size",
+                (self.base_size + self.incr_size).to_string(),
+            ),
+            ("aof_base_size", self.base_size.to_string()),
+        ]
+    }
+}
+
+/// A command in the RESP form it is logged in.
+pub fn encode_command(args: &[Bytes]) -> Vec<u8> {
+    RespType::Array(Some(
+        args.iter()
+            .map(|arg| RespType::BulkString(Some(arg.clone())))
+            .collect(),
+    ))
+    .serialize()
+}
+
+// reads "<prefix><number>\r\n" at `pos`, returning the number and the next position
+fn parse_header(data: &[u8], pos: usize, prefix: u8) -> Result<(usize, usize), AofError> {
+    let rest = data.get(pos..).unwrap_or_default();
+    let end = match rest.iter().position(|b| *b == b'\n') {
+        Some(end) => end,
+        None => return Err(AofError::Truncated { offset: pos }),
+    };
+    let line = &rest[..end];
+    let corrupt = |reason: &str| AofError::Corrupt {
+        offset: pos,
+        reason: reason.to_string(),
+    };
+    if line.first() != Some(&prefix) || line.last() != Some(&b'\r') {
+        return Err(corrupt(&format!("expected '{}'", prefix as char)));
+    }
+    let n = std::str::from_utf8(&line[1..line.len() - 1])
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or(corrupt("invalid length"))?;
+    Ok((n, pos + end + 1))
+}
+
+/// Parses the command starting at `pos`, returning its arguments and the
+/// position after it, or None at the end of the data.
+pub fn parse_command(data: &[u8], pos: usize) -> Result<Option<(Vec<Bytes>, usize)>, AofError> {
+    if pos >= data.len() {
+        return Ok(None);
+    }
+    let start = pos;
+    // a truncated tail is reported at the start of the command it cut off
+    let at_start = |e: AofError| match e {
+        AofError::Truncated { .. } => AofError::Truncated { offset: start },
+        e => e,
+    };
+    let (argc, mut pos) = parse_header(data, pos, b'*').map_err(at_start)?;
+    if argc == 0 {
+        return Err(AofError::Corrupt {
+            offset: start,
+            reason: "empty command".to_string(),
+        });
+    }
+    let mut args = Vec::with_capacity(argc.min(1024));
+    for _ in 0..argc {
+        let (len, next) = parse_header(data, pos, b'$').map_err(at_start)?;
+        let end = next.saturating_add(len);
+        if end.saturating_add(2) > data.len() {
+            return Err(AofError::Truncated { offset: start });
+        }
+        if &data[end..end + 2] != b"\r\n" {
+            return Err(AofError::Corrupt {
+                offset: end,
+                reason: "bulk string not terminated by CRLF".to_string(),
+            });
+        }
+        args.push(Bytes::copy_from_slice(&data[next..end]));
+        pos = end + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct LoadStats {
+    pub preamble_keys: usize,
+    pub commands: usize,
+    /// where a command cut off by the end of the file started
+    pub truncated_at: Option<usize>,
+}
+
+/// Replays an AOF through the command dispatcher. A leading rdb preamble is
+/// loaded first. Everything before a truncated tail is applied, and the
+/// truncation is reported in the stats for the caller to judge.
+pub fn replay(data: &[u8], db: &mut Db) -> Result<LoadStats, AofError> {
+    let mut stats = LoadStats::default();
+    let mut pos = 0;
+    if data.starts_with(b"REDIS") {
+        let (file, consumed) = rdb::decode_prefix(data).map_err(AofError::Rdb)?;
+        let now = Utc::now().timestamp_millis();
+        for entry in file.entries {
+            if entry.db != 0 || entry.expire_at.is_some_and(|at| at <= now) {
+                continue;
+            }
+            let key = String::from_utf8_lossy(&entry.key).to_string();
+            db.restore(&key, entry.value, entry.expire_at);
+            stats.preamble_keys += 1;
+        }
+        db.restore_indexes(&file.aux);
+        pos = consumed;
+    }
+    loop {
+        match parse_command(data, pos) {
+            Ok(Some((args, next))) => {
+                handle_input_cmd(args, db).map_err(|e| AofError::Command {
+                    offset: pos,
+                    reason: e.to_string(),
+                })?;
+                stats.commands += 1;
+                pos = next;
+            }
+            Ok(None) => break,
+            Err(AofError::Truncated { offset }) => {
+                stats.truncated_at = Some(offset);
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(stats)
+}
+
+// replays one file, cutting off an incomplete tail when that is allowed
+fn load_file(path: &Path, db: &mut Db, allow_truncated: bool) -> Result<LoadStats, AofError> {
+    let data =
+        std::fs::read(path).map_err(|e| AofError::Io(format!("{}: {}", path.display(), e)))?;
+    let stats = replay(&data, db)?;
+    if let Some(offset) = stats.truncated_at {
+        if !allow_truncated {
+            return Err(AofError::Truncated { offset });
+        }
+        if redisconfig::log_enabled("warning") {
+            eprintln!(
+                "AOF {} was truncated at offset {}, dropping the incomplete command",
+                path.display(),
+                offset
+            );
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| AofError::Io(e.to_string()))?;
+        file.set_len(offset as u64)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| AofError::Io(e.to_string()))?;
+    }
+    Ok(stats)
+}
+
+pub fn read_manifest(paths: &AofPaths) -> Result<Option<Manifest>, AofError> {
+    match std::fs::read_to_string(paths.manifest()) {
+        Ok(text) => Manifest::parse(&text).map(Some),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AofError::Io(e.to_string())),
+    }
+}
+
+/// Loads the AOF, returning None when there is none yet. Files are replayed
+/// in manifest order. A log from before the manifest existed is read as a
+/// single file. With `allow_truncated` (`aof-load-truncated yes`), an
+/// incomplete command at the very end of the last file is dropped.
+pub fn load(
+    paths: &AofPaths,
+    db: &mut Db,
+    allow_truncated: bool,
+) -> Result<Option<LoadStats>, AofError> {
+    let manifest = match read_manifest(paths)? {
+        Some(manifest) => manifest,
+        None if paths.legacy.exists() => {
+            return load_file(&paths.legacy, db, allow_truncated).map(Some)
+        }
+        None => return Ok(None),
+    };
+    let mut total = LoadStats::default();
+    let files: Vec<_> = manifest.files().collect();
+    for (i, entry) in files.iter().enumerate() {
+        let is_last = i + 1 == files.len();
+        let stats = load_file(&paths.file(&entry.name), db, allow_truncated && is_last)?;
+        total.preamble_keys += stats.preamble_keys;
+        total.commands += stats.commands;
+        total.truncated_at = stats.truncated_at;
+    }
+    Ok(Some(total))
+}
+
+/// Opens the AOF for appending, creating its directory and manifest on the
+/// first run. A new log starts from a base holding the current data, so keys
+/// loaded from an rdb dump survive the next restart, when only the AOF is
+/// read. A single-file log from before manifests becomes the first base.
+pub fn enable(db: &Db, paths: &AofPaths, policy: FsyncPolicy) -> io::Result<()> {
+    std::fs::create_dir_all(&paths.dir)?;
+    let mut manifest = match read_manifest(paths).map_err(|e| io::Error::other(e.to_string()))? {
+        Some(manifest) => manifest,
+        None => {
+            let name = if paths.legacy.exists() {
+                let name = format!("{}.1.base.aof", paths.basename);
+                std::fs::rename(&paths.legacy, paths.file(&name))?;
+                name
+            } else {
+                let name = format!("{}.1.base.rdb", paths.basename);
+                write_atomically(paths.file(&name), &db.snapshot().to_rdb())?;
+                name
+            };
+            Manifest {
+                base: Some(ManifestEntry {
+                    name,
+                    seq: 1,
+                    kind: FileKind::Base,
+                }),
+                incrs: vec![],
+            }
+        }
+    };
+    if manifest.incrs.is_empty() {
+        let seq = manifest.next_seq(FileKind::Incr);
+        manifest.incrs.push(ManifestEntry {
+            name: format!("{}.{}.incr.aof", paths.basename, seq),
+            seq,
+            kind: FileKind::Incr,
+        });
+    }
+    write_manifest(paths, &manifest)?;
+    *db.aof().lock() = Some(Aof::open(paths.clone(), manifest, policy)?);
+    Ok(())
+}
+
+/// Turns the AOF on while running, as CONFIG SET appendonly yes does. Files
+/// left from an earlier run hold stale data, so a new base is written from
+/// the current data before the old files are dropped.
+pub fn start(db: &Db, paths: &AofPaths, policy: FsyncPolicy) -> io::Result<()> {
+    let mut guard = db.aof().lock();
+    if guard.is_some() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&paths.dir)?;
+    let old = read_manifest(paths)
+        .map_err(|e| io::Error::other(e.to_string()))?
+        .unwrap_or_default();
+    let seq = old
+        .next_seq(FileKind::Base)
+        .max(old.next_seq(FileKind::Incr));
+    let base = format!("{}.{}.base.rdb", paths.basename, seq);
+    write_atomically(paths.file(&base), &db.snapshot().to_rdb())?;
+    let manifest = Manifest {
+        base: Some(ManifestEntry {
+            name: base,
+            seq,
+            kind: FileKind::Base,
+        }),
+        incrs: vec![ManifestEntry {
+            name: format!("{}.{}.incr.aof", paths.basename, seq),
+            seq,
+            kind: FileKind::Incr,
+        }],
+    };
+    write_manifest(paths, &manifest)?;
+    for entry in old.files() {
+        let _ = std::fs::remove_file(paths.file(&entry.name));
+    }
+    *guard = Some(Aof::open(paths.clone(), manifest, policy)?);
+    Ok(())
+}
+
+/// Turns the AOF off, flushing what was written to disk first.
+pub fn stop(db: &Db) -> io::Result<()> {
+    let mut guard = db.aof().lock();
+    if let Some(aof) = guard.as_mut() {
+        aof.fsync()?;
+    }
+    *guard = None;
+    Ok(())
+}
+
+/// What BGREWRITEAOF did with the request.
+#[derive(Debug, PartialEq)]
+pub enum RewriteStart {
+    Started,
+    AlreadyRunning,
+    NotEnabled,
+}
+
+/// Rewrites the AOF in the background. New writes go to a fresh incr file
+/// from the moment the rewrite starts, and a snapshot taken at that same
+/// moment becomes the new base, so the dispatcher is never blocked on it.
+pub fn rewrite(db: &Db) -> io::Result<RewriteStart> {
+    let mut guard = db.aof().lock();
+    let Some(aof) = guard.as_mut() else {
+        return Ok(RewriteStart::NotEnabled);
+    };
+    if aof.rewrite_in_progress {
+        return Ok(RewriteStart::AlreadyRunning);
+    }
+    aof.last_rewrite_start = Some(Instant::now());
+    if let Err(e) = aof.open_new_incr() {
+        aof.last_rewrite_ok = false;
+        return Err(e);
+    }
+    // writers hold the aof lock, so no write falls between the switch and the snapshot
+    let snapshot = db.snapshot();
+    aof.rewrite_in_progress = true;
+    let paths = aof.paths.clone();
+    let seq = aof.manifest.next_seq(FileKind::Base);
+    drop(guard);
+
+    let db = db.clone();
+    std::thread::spawn(move || {
+        let name = format!("{}.{}.base.rdb", paths.basename, seq);
+        let res = write_atomically(paths.file(&name), &snapshot.to_rdb());
+        let mut guard = db.aof().lock();
+        // the AOF may have been turned off, or off and on again, meanwhile
+        let Some(aof) = guard.as_mut().filter(|aof| aof.rewrite_in_progress) else {
+            let _ = std::fs::remove_file(paths.file(&name));
+            return;
+        };
+        let res = res.and_then(|_| aof.install_base(name.clone(), seq));
+        if let Err(e) = &res {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Background AOF rewrite failed: {}", e);
+            }
+            let _ = std::fs::remove_file(paths.file(&name));
+        }
+        aof.rewrite_in_progress = false;
+        aof.last_rewrite_ok = res.is_ok();
+    });
+    Ok(RewriteStart::Started)
+}
+
+// whether the log has grown enough past its last rewrite to need another
+fn rewrite_due(aof: &Aof) -> bool {
+    let percentage = redisconfig::get_int("auto-aof-rewrite-percentage") as u64;
+    let min_size = redisconfig::get_memory("auto-aof-rewrite-min-size");
+    let size = aof.base_size + aof.incr_size;
+    let retry_ok = aof.last_rewrite_ok
+        || aof
+            .last_rewrite_start
+            .is_none_or(|at| at.elapsed() > REWRITE_RETRY_DELAY);
+    percentage > 0
+        && !aof.rewrite_in_progress
+        && retry_ok
+        && size >= min_size
+        && (size - aof.base_size.min(size)) * 100 / aof.base_size.max(1) >= percentage
+}
+
+/// Called periodically by the server to drive `everysec` fsyncs and
+/// automatic rewrites.
+pub fn cron(db: &Db) {
+    let due = match db.aof().lock().as_mut() {
+        Some(aof) => {
+            aof.background_fsync();
+            rewrite_due(aof)
+        }
+        None => false,
+    };
+    if due {
+        if redisconfig::log_enabled("notice") {
+            println!("Starting automatic rewriting of AOF");
+        }
+        if let Err(e) = rewrite(db) {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Failed to start the AOF rewrite: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{commands::execute, test_helpers::args};
+
+    fn log(cmds: &[&str]) -> Vec<u8> {
+        cmds.iter().flat_map(|c| encode_command(&args(c))).collect()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let data = log(&["SET a 1", "DEL a"]);
+        let (first, next) = parse_command(&data, 0).unwrap().unwrap();
+        assert_eq!(first, args("SET a 1"));
+        let (second, end) = parse_command(&data, next).unwrap().unwrap();
+        assert_eq!(second, args("DEL a"));
+        assert_eq!(end, data.len());
+        assert_eq!(parse_command(&data, end), Ok(None));
+
+        // every cut inside the second command is a truncation at its start
+        for cut in next + 1..data.len() {
+            assert_eq!(
+                parse_command(&data[..cut], next),
+                Err(AofError::Truncated { offset: next })
+            );
+        }
+        assert!(matches!(
+            parse_command(b"+OK\r\n", 0),
+            Err(AofError::Corrupt { offset: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut db = Db::new(2);
+        let mut data = log(&["SET a 1", "HSET h f v", "SET b 2", "DEL b"]);
+        let full = data.len();
+        data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nc");
+        let stats = replay(&data, &mut db).unwrap();
+        assert_eq!(stats.commands, 4);
+        assert_eq!(stats.truncated_at, Some(full));
+        assert_eq!(db.get("a"), Ok(Bytes::from("1")));
+        assert_eq!(db.hget("h", "f"), Ok(Some(Bytes::from("v"))));
+        assert!(db.get("b").is_err());
+        assert!(db.get("c").is_err());
+    }
+
+    fn temp_paths(name: &str) -> AofPaths {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        AofPaths::new(dir, "appendonly.aof")
+    }
+
+    #[test]
+    fn test_load_truncated_file() {
+        let paths = temp_paths("aof-truncated");
+        std::fs::create_dir_all(&paths.dir).unwrap();
+        let path = paths.file("appendonly.aof.1.incr.aof");
+        let mut data = log(&["SET a 1"]);
+        let good = data.len();
+        data.extend_from_slice(b"*2\r\n$3\r\nDEL");
+        std::fs::write(&path, &data).unwrap();
+
+        let mut db = Db::new(1);
+        assert_eq!(
+            load_file(&path, &mut db, false),
+            Err(AofError::Truncated { offset: good })
+        );
+        let stats = load_file(&path, &mut Db::new(1), true).unwrap();
+        assert_eq!(stats.commands, 1);
+        assert_eq!(std::fs::read(&path).unwrap().len(), good);
+        std::fs::remove_dir_all(&paths.dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+                    file appendonly.aof.1.incr.aof seq 1 type h\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\n\
+                    file appendonly.aof.4.incr.aof seq 4 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+        assert_eq!(manifest.base.as_ref().unwrap().seq, 2);
+        assert_eq!(manifest.incrs.len(), 2);
+        assert_eq!(manifest.next_seq(FileKind::Incr), 5);
+        assert_eq!(Manifest::parse(&manifest.encode()), Ok(manifest));
+
+        assert!(Manifest::parse("file a seq 1 type x\n").is_err());
+        assert!(Manifest::parse("file a seq 1\n").is_err());
+        assert!(matches!(
+            Manifest::parse("file a seq 2 type i\nfile b seq 1 type i\n"),
+            Err(AofError::Corrupt { offset: 20, .. })
+        ));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let paths = temp_paths("aof-rewrite");
+        let mut db = Db::new(2);
+        db.set("from-rdb", Bytes::from("1"), vec![]).unwrap();
+        enable(&db, &paths, FsyncPolicy::No).unwrap();
+        for i in 0..50 {
+            execute(args(&format!("SET counter {}", i)), &mut db).unwrap();
+        }
+        execute(
+            args("FT.CREATE idx PREFIX 1 doc: SCHEMA title TEXT"),
+            &mut db,
+        )
+        .unwrap();
+        execute(args("HSET doc:1 title hello"), &mut db).unwrap();
+        assert_eq!(rewrite(&db).unwrap(), RewriteStart::Started);
+        execute(args("SET after rewrite"), &mut db).unwrap();
+        while db.aof().lock().as_ref().unwrap().rewrite_in_progress {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let manifest = read_manifest(&paths).unwrap().unwrap();
+        assert_eq!(
+            manifest
+                .files()
+                .map(|f| f.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["appendonly.aof.2.base.rdb", "appendonly.aof.2.incr.aof"]
+        );
+        // only the files in the manifest, plus the manifest itself, are left
+        assert_eq!(std::fs::read_dir(&paths.dir).unwrap().count(), 3);
+
+        let mut loaded = Db::new(2);
+        let stats = load(&paths, &mut loaded, false).unwrap().unwrap();
+        assert_eq!(stats.preamble_keys, 3);
+        assert_eq!(stats.commands, 1);
+        assert_eq!(loaded.get("from-rdb"), Ok(Bytes::from("1")));
+        // the index is kept in the base, as it was only logged before
+        assert_eq!(
+            execute(args("FT._LIST"), &mut loaded).unwrap(),
+            RespType::Array(Some(vec![RespType::BulkString(Some(Bytes::from("idx")))]))
+        );
+        assert_eq!(
+            execute(args("FT.SEARCH idx hello NOCONTENT"), &mut loaded).unwrap(),
+            RespType::Array(Some(vec![
+                RespType::Integer(1),
+                RespType::BulkString(Some(Bytes::from("doc:1")))
+            ]))
+        );
+        assert_eq!(loaded.get("counter"), Ok(Bytes::from("49")));
+        assert_eq!(loaded.get("after"), Ok(Bytes::from("rewrite")));
+        std::fs::remove_dir_all(&paths.dir).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_single_file_log() {
+        let mut paths = temp_paths("aof-legacy");
+        paths.legacy = std::env::temp_dir().join(format!("legacy-{}.aof", std::process::id()));
+        std::fs::write(&paths.legacy, log(&["SET k v"])).unwrap();
+        let mut db = Db::new(1);
+        load(&paths, &mut db, false).unwrap().unwrap();
+        assert_eq!(db.get("k"), Ok(Bytes::from("v")));
+
+        enable(&db, &paths, FsyncPolicy::No).unwrap();
+        assert!(!paths.legacy.exists());
+        assert!(paths.file("appendonly.aof.1.base.aof").exists());
+        let stats = load(&paths, &mut Db::new(1), false).unwrap().unwrap();
+        assert_eq!(stats.commands, 1);
+        std::fs::remove_dir_all(&paths.dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_rdb_preamble() {
+        let source = Db::new(2);
+        source.set("k", Bytes::from("old"), vec![]).unwrap();
+        let mut data = source.snapshot().to_rdb();
+        data.extend(log(&["SET k new", "SET other 1"]));
+        let mut db = Db::new(2);
+        let stats = replay(&data, &mut db).unwrap();
+        assert_eq!(stats.preamble_keys, 1);
+        assert_eq!(stats.commands, 2);
+        assert_eq!(db.get("k"), Ok(Bytes::from("new")));
+    }
+
+    #[test]
+    fn test_execute_logs_writes() {
+        let paths = temp_paths("aof-execute");
+        let mut db = Db::new(2);
+        enable(&db, &paths, FsyncPolicy::Always).unwrap();
+        for cmd in ["SET k v EX 100", "GET k", "DEL missing", "HSET h f v"] {
+            execute(args(cmd), &mut db).unwrap();
+        }
+        let data = std::fs::read(paths.file("appendonly.aof.1.incr.aof")).unwrap();
+        std::fs::remove_dir_all(&paths.dir).unwrap();
+
+        let (set, next) = parse_command(&data, 0).unwrap().unwrap();
+        let at = db.expire_at("k").unwrap().to_string();
+        assert_eq!(set, args(&format!("SET k v PXAT {}", at)));
+        let (hset, end) = parse_command(&data, next).unwrap().unwrap();
+        assert_eq!(hset, args("HSET h f v"));
+        assert_eq!(end, data.len());
+    }
+}
@@ -0,0 +1,924 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+use super::{
+    client::Client,
+    commands::wrong_type,
+    datastore::Db,
+    errors::DataStoreError,
+    redisconfig,
+    resp_value::{bulk, RespType},
+    stats::Stats,
+    stream::{self, ReadFrom, StreamId},
+};
+
+/// What a pop takes from the first of its keys that has something.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pop {
+    /// BLPOP and BRPOP: one value, replied with its key
+    Value { head: bool },
+    /// LMPOP and BLMPOP: up to `count` values, replied with their key
+    Values { head: bool, count: usize },
+    /// BLMOVE: one value, pushed onto another list
+    Move {
+        destination: String,
+        from_head: bool,
+        to_head: bool,
+    },
+    /// BZPOPMIN and BZPOPMAX: one member, replied with its key and score
+    Member { max: bool },
+    /// ZMPOP and BZMPOP: up to `count` members, replied with their key
+    Members { max: bool, count: usize },
+    /// XREAD: the entries of each key after an ID, without taking them
+    Read {
+        after: HashMap<String, StreamId>,
+        count: Option<usize>,
+    },
+    /// XREADGROUP with `>`: the entries never delivered to the group
+    ReadGroup {
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        noack: bool,
+    },
+}
+
+fn error(msg: &str) -> RespType {
+    RespType::Error(msg.to_string())
+}
+
+fn side(head: bool) -> Bytes {
+    Bytes::from(if head { "LEFT" } else { "RIGHT" })
+}
+
+impl Pop {
+    /// Pops from `key` and builds the reply, or returns None when the key
+    /// holds nothing to pop.
+    pub fn run(&self, db: &Db, key: &str) -> Result<Option<RespType>, DataStoreError> {
+        let key_reply = bulk(key.as_bytes());
+        let reply = match self {
+            Pop::Value { head } => db
+                .pop(key, 1, *head)?
+                .pop()
+                .map(|value| RespType::Array(Some(vec![key_reply, bulk(&value)]))),
+            Pop::Values { head, count } => Some(db.pop(key, *count, *head)?)
+                .filter(|values| !values.is_empty())
+                .map(|values| {
+                    let values = values.iter().map(bulk).collect();
+                    RespType::Array(Some(vec![key_reply, RespType::Array(Some(values))]))
+                }),
+            Pop::Move {
+                destination,
+                from_head,
+                to_head,
+            } => db
+                .lmove(key, destination, *from_head, *to_head)?
+                .map(|value| bulk(&value)),
+            Pop::Member { max } => db.zpop(key, 1, *max)?.pop().map(|(member, score)| {
+                RespType::Array(Some(vec![
+                    key_reply,
+                    bulk(&member),
+                    bulk(score.to_string().as_bytes()),
+                ]))
+            }),
+            Pop::Members { max, count } => Some(db.zpop(key, *count, *max)?)
+                .filter(|members| !members.is_empty())
+                .map(|members| {
+                    let members = members
+                        .iter()
+                        .map(|(member, score)| {
+                            RespType::Array(Some(vec![
+                                bulk(member),
+                                bulk(score.to_string().as_bytes()),
+                            ]))
+                        })
+                        .collect();
+                    RespType::Array(Some(vec![key_reply, RespType::Array(Some(members))]))
+                }),
+            Pop::Read { after, count } => stream::read_key(db, key, after[key], *count)?,
+            Pop::ReadGroup {
+                group,
+                consumer,
+                count,
+                noack,
+            } => {
+                // the stream or group going away ends the wait
+                match db.read_stream(key, |stream| stream.is_some()) {
+                    Ok(true) => {}
+                    Ok(false) | Err(DataStoreError::WrongType) => {
+                        return Ok(Some(error("UNBLOCKED the stream key no longer exists")))
+                    }
+                    Err(e) => return Err(e),
+                }
+                match stream::read_group_key(db, key, (group, consumer), None, *count, *noack) {
+                    Err(DataStoreError::InvalidInput(_)) => Some(error(
+                        "NOGROUP the consumer group this client was blocked on no longer exists",
+                    )),
+                    res => res?,
+                }
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Whether the pop reads every key that has something, as the stream
+    /// reads do, rather than popping from the first. Such reads leave the
+    /// key for the clients waiting after them.
+    fn reads_every_key(&self) -> bool {
+        matches!(self, Pop::Read { .. } | Pop::ReadGroup { .. })
+    }
+
+    // The reply to a client served from one key.
+    fn served(&self, reply: RespType) -> RespType {
+        match reply {
+            RespType::Error(_) => reply,
+            reply if self.reads_every_key() => RespType::Array(Some(vec![reply])),
+            reply => reply,
+        }
+    }
+
+    /// The reply when the pop timed out, or could not wait inside MULTI.
+    pub fn timed_out(&self) -> RespType {
+        match self {
+            Pop::Move { .. } => RespType::BulkString(None),
+            _ => RespType::Array(None),
+        }
+    }
+
+    // The non-blocking command doing what the pop did on `key`, which is
+    // what goes into the AOF; None when it wrote nothing.
+    fn aof_form(&self, key: &str) -> Option<Vec<Bytes>> {
+        let key = Bytes::from(key.to_string());
+        let pop = |head: bool| Bytes::from(if head { "LPOP" } else { "RPOP" });
+        let zpop = |max: bool| Bytes::from(if max { "ZPOPMAX" } else { "ZPOPMIN" });
+        let count = |count: &usize| Bytes::from(count.to_string());
+        let command = match self {
+            Pop::Value { head } => vec![pop(*head), key],
+            Pop::Values { head, count: n } => vec![pop(*head), key, count(n)],
+            Pop::Move {
+                destination,
+                from_head,
+                to_head,
+            } => vec![
+                Bytes::from("LMOVE"),
+                key,
+                Bytes::from(destination.clone()),
+                side(*from_head),
+                side(*to_head),
+            ],
+            Pop::Member { max } => vec![zpop(*max), key],
+            Pop::Members { max, count: n } => vec![zpop(*max), key, count(n)],
+            Pop::Read { .. } => return None,
+            Pop::ReadGroup {
+                group,
+                consumer,
+                count: n,
+                noack,
+            } => {
+                let mut command = vec![
+                    Bytes::from("XREADGROUP"),
+                    Bytes::from("GROUP"),
+                    Bytes::from(group.clone()),
+                    Bytes::from(consumer.clone()),
+                ];
+                if let Some(n) = n {
+                    command.extend([Bytes::from("COUNT"), count(n)]);
+                }
+                if *noack {
+                    command.push(Bytes::from("NOACK"));
+                }
+                command.extend([Bytes::from("STREAMS"), key, Bytes::from(">")]);
+                command
+            }
+        };
+        Some(command)
+    }
+
+    // Like `run`, and logs the pop to the AOF, holding its lock across both
+    // so the log has writes in the order they were applied. A failed append
+    // replies MISCONF, as it does for any other write.
+    fn run_logged(&self, db: &Db, key: &str) -> Result<Option<RespType>, DataStoreError> {
+        let mut aof = db.aof().lock();
+        let reply = self.run(db, key)?;
+        if matches!(reply, Some(RespType::Error(_)) | None) {
+            return Ok(reply);
+        }
+        if let (Some(command), Some(aof)) = (self.aof_form(key), aof.as_mut()) {
+            if let Err(e) = aof.append(&command) {
+                if redisconfig::log_enabled("warning") {
+                    eprintln!("Failed to write to the AOF: {}", e);
+                }
+                return Ok(Some(RespType::Error(format!(
+                    "MISCONF Errors writing to the AOF file: {}",
+                    e
+                ))));
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// Runs a pop on the first of the keys that has something, for the
+/// commands that don't block, and for the blocking ones before they do.
+/// The error is a WRONGTYPE key coming before any that has something.
+fn pop_first(db: &Db, keys: &[String], pop: &Pop, logged: bool) -> Option<RespType> {
+    keys.iter().find_map(|key| {
+        let res = if logged {
+            pop.run_logged(db, key)
+        } else {
+            pop.run(db, key)
+        };
+        match res {
+            Ok(reply) => reply,
+            Err(DataStoreError::WrongType) => Some(wrong_type()),
+            Err(e) => Some(RespType::Error(format!("ERR {}", e))),
+        }
+    })
+}
+
+/// Like `pop_first`, but the stream reads read every key, and reply with
+/// those that have something.
+fn pop_keys(db: &Db, keys: &[String], pop: &Pop, logged: bool) -> Option<RespType> {
+    if !pop.reads_every_key() {
+        return pop_first(db, keys, pop, logged);
+    }
+    let mut replies = vec![];
+    for key in keys {
+        let res = if logged {
+            pop.run_logged(db, key)
+        } else {
+            pop.run(db, key)
+        };
+        match res {
+            Ok(Some(RespType::Error(e))) => return Some(RespType::Error(e)),
+            Ok(Some(reply)) => replies.push(reply),
+            Ok(None) => {}
+            Err(DataStoreError::WrongType) => return Some(wrong_type()),
+            Err(e) => return Some(RespType::Error(format!("ERR {}", e))),
+        }
+    }
+    (!replies.is_empty()).then_some(RespType::Array(Some(replies)))
+}
+
+/// LMPOP, and ZMPOP with `zset`; `args` start after the command name.
+pub fn mpop(db: &Db, args: &[Bytes], zset: bool) -> RespType {
+    match parse_mpop(args, zset) {
+        Ok((keys, pop)) => pop_first(db, &keys, &pop, false).unwrap_or(pop.timed_out()),
+        Err(e) => e,
+    }
+}
+
+// numkeys key [key ...] LEFT|RIGHT|MIN|MAX [COUNT count]
+fn parse_mpop(args: &[Bytes], zset: bool) -> Result<(Vec<String>, Pop), RespType> {
+    let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+    let numkeys = text(0)
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| error("ERR numkeys should be greater than 0"))?;
+    if numkeys + 1 >= args.len() {
+        return Err(error("ERR syntax error"));
+    }
+    let keys = (1..=numkeys).map(text).collect();
+    let first = match (text(numkeys + 1).to_lowercase().as_str(), zset) {
+        ("left", false) | ("min", true) => true,
+        ("right", false) | ("max", true) => false,
+        _ => return Err(error("ERR syntax error")),
+    };
+    let count = match &args[numkeys + 2..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => String::from_utf8_lossy(count)
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| error("ERR count should be greater than 0"))?,
+        _ => return Err(error("ERR syntax error")),
+    };
+    let pop = match zset {
+        false => Pop::Values { head: first, count },
+        true => Pop::Members { max: !first, count },
+    };
+    Ok((keys, pop))
+}
+
+// seconds, possibly fractional; 0 waits forever
+fn parse_timeout(arg: &[u8]) -> Result<Option<Duration>, RespType> {
+    let seconds = String::from_utf8_lossy(arg)
+        .parse::<f64>()
+        .ok()
+        .filter(|s| s.is_finite())
+        .ok_or_else(|| error("ERR timeout is not a float or out of range"))?;
+    if seconds < 0.0 {
+        return Err(error("ERR timeout is negative"));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| error("ERR timeout is out of range"))
+}
+
+// XREAD and XREADGROUP with BLOCK. XREADGROUP only blocks reading new
+// entries; reading a consumer's history is left to the command.
+#[allow(clippy::type_complexity)]
+fn parse_stream_read(
+    db: &Db,
+    args: &[Bytes],
+    group: bool,
+) -> Option<Result<(Vec<String>, Pop, Option<Duration>), RespType>> {
+    let read = match stream::parse_read(args, group) {
+        Ok(read) => read,
+        Err(e) => return Some(Err(e)),
+    };
+    let block = read.block?;
+    if group && read.streams.iter().any(|(_, from)| *from != ReadFrom::New) {
+        return None;
+    }
+    if let Some(e) = stream::missing_group(db, &read) {
+        return Some(Err(e));
+    }
+    let timeout = (block > 0).then(|| Duration::from_millis(block));
+    let keys = read.streams.iter().map(|(key, _)| key.clone()).collect();
+    let pop = match read.group {
+        Some((group, consumer)) => Pop::ReadGroup {
+            group,
+            consumer,
+            count: read.count,
+            noack: read.noack,
+        },
+        None => {
+            let mut after = HashMap::new();
+            for (key, from) in read.streams {
+                let id = match from {
+                    ReadFrom::After(id) => id,
+                    _ => match stream::last_id(db, &key) {
+                        Ok(id) => id,
+                        Err(DataStoreError::WrongType) => return Some(Err(wrong_type())),
+                        Err(e) => return Some(Err(RespType::Error(format!("ERR {}", e)))),
+                    },
+                };
+                // a key named twice reads from the first of its IDs
+                after.entry(key).or_insert(id);
+            }
+            Pop::Read {
+                after,
+                count: read.count,
+            }
+        }
+    };
+    Some(Ok((keys, pop, timeout)))
+}
+
+// The keys, pop and timeout of a blocking command, or None for another
+// command, or for XREAD and XREADGROUP without BLOCK. `args` start with
+// the name, already resolved.
+#[allow(clippy::type_complexity)]
+fn parse_blocking(
+    name: &str,
+    args: &[Bytes],
+    db: &Db,
+) -> Option<Result<(Vec<String>, Pop, Option<Duration>), RespType>> {
+    let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+    let last = args.len() - 1;
+    let parsed = match name {
+        "blpop" | "brpop" => parse_timeout(&args[last]).map(|timeout| {
+            let pop = Pop::Value {
+                head: name == "blpop",
+            };
+            ((1..last).map(text).collect(), pop, timeout)
+        }),
+        "bzpopmin" | "bzpopmax" => parse_timeout(&args[last]).map(|timeout| {
+            let pop = Pop::Member {
+                max: name == "bzpopmax",
+            };
+            ((1..last).map(text).collect(), pop, timeout)
+        }),
+        "blmove" => {
+            let from = text(3).to_lowercase();
+            let to = text(4).to_lowercase();
+            let sides = ["left", "right"];
+            if !sides.contains(&from.as_str()) || !sides.contains(&to.as_str()) {
+                return Some(Err(error("ERR syntax error")));
+            }
+            parse_timeout(&args[5]).map(|timeout| {
+                let pop = Pop::Move {
+                    destination: text(2),
+                    from_head: from == "left",
+                    to_head: to == "left",
+                };
+                (vec![text(1)], pop, timeout)
+            })
+        }
+        "blmpop" | "bzmpop" => parse_timeout(&args[1]).and_then(|timeout| {
+            let (keys, pop) = parse_mpop(&args[2..], name == "bzmpop")?;
+            Ok((keys, pop, timeout))
+        }),
+        "xread" | "xreadgroup" => return parse_stream_read(db, &args[1..], name == "xreadgroup"),
+        _ => return None,
+    };
+    Some(parsed)
+}
+
+/// A client blocked on keys until it can be served, and how to reply to it.
+#[derive(Debug)]
+struct Waiter {
+    keys: Vec<String>,
+    pop: Pop,
+    reply: oneshot::Sender<RespType>,
+}
+
+#[derive(Debug, Default)]
+struct Waiters {
+    /// the clients blocked on each key, in the order they blocked
+    queues: HashMap<String, VecDeque<u64>>,
+    clients: HashMap<u64, Waiter>,
+}
+
+#[derive(Debug, Default)]
+struct Ready {
+    /// the keys clients are blocked on, or about to block on
+    blocked: HashSet<String>,
+    /// those of them written to since they were last served
+    keys: Vec<String>,
+}
+
+/// The clients blocked on keys, served first come, first served when a
+/// key they wait on gets something to pop.
+///
+/// Writes only mark the keys as ready; the clients are served after the
+/// command, or the transaction, that made them ready. The waiters lock is
+/// taken before the aof and shard locks, and the ready lock after them.
+#[derive(Debug, Default)]
+pub struct Blocking {
+    waiters: Mutex<Waiters>,
+    ready: Mutex<Ready>,
+}
+
+impl Blocking {
+    /// Marks a key as written, if clients are blocked on it. Called with the
+    /// key's shard locked.
+    pub fn signal(&self, key: &str) {
+        let mut ready = self.ready.lock();
+        if ready.blocked.contains(key) && !ready.keys.iter().any(|k| k == key) {
+            ready.keys.push(key.to_string());
+        }
+    }
+
+    /// Serves the clients blocked on the keys written since the last call,
+    /// each in the order they blocked, for as long as the keys have
+    /// something. A client waiting for another type than the key holds
+    /// stays blocked, except group readers, which are told the stream is
+    /// gone.
+    pub fn serve(&self, db: &Db) {
+        if self.ready.lock().keys.is_empty() {
+            return;
+        }
+        let mut waiters = self.waiters.lock();
+        loop {
+            // serving can make more keys ready, as BLMOVE pushes
+            let keys = std::mem::take(&mut self.ready.lock().keys);
+            if keys.is_empty() {
+                return;
+            }
+            for key in keys {
+                let queue: Vec<u64> = waiters
+                    .queues
+                    .get(&key)
+                    .into_iter()
+                    .flatten()
+                    .copied()
+                    .collect();
+                for id in queue {
+                    let pop = waiters.clients[&id].pop.clone();
+                    match pop.run_logged(db, &key) {
+                        Ok(Some(reply)) => {
+                            if let Some(waiter) = self.remove(&mut waiters, id) {
+                                let _ = waiter.reply.send(pop.served(reply));
+                            }
+                        }
+                        Ok(None) if !pop.reads_every_key() => break,
+                        Ok(None) | Err(_) => continue,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Unblocks a client, as CLIENT UNBLOCK does, replying as if it timed out
+    /// or with an error. Returns false when the client isn't blocked.
+    pub fn unblock(&self, id: u64, with_error: bool) -> bool {
+        let mut waiters = self.waiters.lock();
+        let Some(waiter) = self.remove(&mut waiters, id) else {
+            return false;
+        };
+        let reply = match with_error {
+            true => error("UNBLOCKED client unblocked via CLIENT UNBLOCK"),
+            false => waiter.pop.timed_out(),
+        };
+        let _ = waiter.reply.send(reply);
+        true
+    }
+
+    /// How many clients are blocked.
+    pub fn blocked_clients(&self) -> usize {
+        self.waiters.lock().clients.len()
+    }
+
+    fn remove(&self, waiters: &mut Waiters, id: u64) -> Option<Waiter> {
+        let waiter = waiters.clients.remove(&id)?;
+        for key in &waiter.keys {
+            if let Some(queue) = waiters.queues.get_mut(key) {
+                queue.retain(|other| *other != id);
+                if queue.is_empty() {
+                    waiters.queues.remove(key);
+                }
+            }
+        }
+        self.forget(waiters, &waiter.keys);
+        Some(waiter)
+    }
+
+    // stops marking the keys nobody is blocked on any more
+    fn forget(&self, waiters: &Waiters, keys: &[String]) {
+        let mut ready = self.ready.lock();
+        for key in keys {
+            if !waiters.queues.contains_key(key) {
+                ready.blocked.remove(key);
+            }
+        }
+    }
+}
+
+/// A client blocked by a command, until it is served or times out.
+/// Dropping it, as when the client disconnects, unblocks it.
+#[derive(Debug)]
+pub struct Blocked {
+    id: u64,
+    blocking: Arc<Blocking>,
+    reply: oneshot::Receiver<RespType>,
+    deadline: Option<Instant>,
+}
+
+impl Blocked {
+    /// Waits for the reply: what was popped for the client, or the reply to
+    /// timing out or being unblocked. Dropping the future leaves the client
+    /// blocked, so it can be waited on again.
+    pub async fn wait(&mut self) -> RespType {
+        let served = async { (&mut self.reply).await.ok() };
+        let reply = match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, served)
+                .await
+                .ok()
+                .flatten(),
+            None => served.await,
+        };
+        if let Some(reply) = reply {
+            return reply;
+        }
+        let mut waiters = self.blocking.waiters.lock();
+        match self.blocking.remove(&mut waiters, self.id) {
+            Some(waiter) => waiter.pop.timed_out(),
+            // served while the timeout fired
+            None => self.reply.try_recv().unwrap_or(RespType::Array(None)),
+        }
+    }
+}
+
+impl Drop for Blocked {
+    fn drop(&mut self) {
+        let mut waiters = self.blocking.waiters.lock();
+        self.blocking.remove(&mut waiters, self.id);
+    }
+}
+
+/// Answers BLPOP, BRPOP, BLMOVE, BLMPOP, BZPOPMIN, BZPOPMAX and BZMPOP,
+/// and XREAD and XREADGROUP with BLOCK. Returns None for other commands.
+///
+/// When none of the keys has anything to pop, the client blocks: it gets no
+/// reply yet and `client.blocked` is set. Without `can_block`, as inside
+/// MULTI, it gets the timeout reply right away instead.
+pub fn blocking_command(
+    client: &mut Client,
+    args: &[Bytes],
+    db: &Db,
+    can_block: bool,
+) -> Option<Vec<RespType>> {
+    let name = redisconfig::resolve_command_name(&String::from_utf8_lossy(&args[0]))?;
+    let (keys, pop, timeout) = match parse_blocking(&name, args, db)? {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(vec![e]),
+    };
+    // EXEC already holds it exclusively
+    let exec_lock = db.exec_lock();
+    let _running = can_block.then(|| exec_lock.read());
+    Stats::add(&db.stats().commands_processed, 1);
+    let blocking = db.blocking().clone();
+    let mut waiters = blocking.waiters.lock();
+    // marked before trying them, so a push landing after the try finds the
+    // keys blocked on and makes them ready
+    blocking.ready.lock().blocked.extend(keys.iter().cloned());
+    if let Some(reply) = pop_keys(db, &keys, &pop, true) {
+        blocking.forget(&waiters, &keys);
+        drop(waiters);
+        if can_block {
+            blocking.serve(db);
+        }
+        return Some(vec![reply]);
+    }
+    if !can_block {
+        blocking.forget(&waiters, &keys);
+        return Some(vec![pop.timed_out()]);
+    }
+    let (tx, rx) = oneshot::channel();
+    for key in &keys {
+        let queue = waiters.queues.entry(key.clone()).or_default();
+        // a key named twice is waited on once
+        if !queue.contains(&client.id) {
+            queue.push_back(client.id);
+        }
+    }
+    waiters.clients.insert(
+        client.id,
+        Waiter {
+            keys,
+            pop,
+            reply: tx,
+        },
+    );
+    client.blocked = Some(Blocked {
+        id: client.id,
+        blocking: blocking.clone(),
+        reply: rx,
+        deadline: timeout.map(|t| Instant::now() + t),
+    });
+    Some(vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::test_helpers::{args, send};
+
+    fn array(items: &[&str]) -> RespType {
+        RespType::Array(Some(items.iter().map(|i| bulk(i.as_bytes())).collect()))
+    }
+
+    #[tokio::test]
+    async fn test_served_in_order() {
+        let mut db = Db::new(4);
+        let mut first = Client::new();
+        let mut second = Client::new();
+        let mut producer = Client::new();
+        assert_eq!(send(&mut first, &mut db, "BLPOP jobs other 0"), vec![]);
+        assert_eq!(send(&mut second, &mut db, "BRPOP jobs 0"), vec![]);
+        assert_eq!(db.blocking().blocked_clients(), 2);
+
+        send(&mut producer, &mut db, "RPUSH jobs a b c");
+        let reply = first.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, array(&["jobs", "a"]));
+        let reply = second.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, array(&["jobs", "c"]));
+        assert_eq!(db.blocking().blocked_clients(), 0);
+        assert_eq!(db.pop("jobs", 10, true).unwrap(), vec![Bytes::from("b")]);
+
+        // one push serves one client; the other keeps waiting
+        first.blocked = None;
+        second.blocked = None;
+        send(&mut first, &mut db, "BLPOP jobs 0");
+        send(&mut second, &mut db, "BLPOP jobs 0");
+        send(&mut producer, &mut db, "LPUSH jobs x");
+        let reply = first.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, array(&["jobs", "x"]));
+        assert_eq!(db.blocking().blocked_clients(), 1);
+
+        // and disconnecting unblocks
+        drop(second);
+        assert_eq!(db.blocking().blocked_clients(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_immediate_and_timeout() {
+        let mut db = Db::new(4);
+        let mut client = Client::new();
+        send(&mut client, &mut db, "RPUSH b 1 2");
+        send(&mut client, &mut db, "ZADD z 1 one 2 two 3 three");
+        assert_eq!(
+            send(&mut client, &mut db, "BLPOP a b 1"),
+            vec![array(&["b", "1"])]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "BZPOPMAX z 1"),
+            vec![array(&["z", "three", "3"])]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "BZMPOP 1 2 a z MIN COUNT 5"),
+            vec![RespType::Array(Some(vec![
+                bulk(b"z"),
+                RespType::Array(Some(vec![array(&["one", "1"]), array(&["two", "2"])])),
+            ]))]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "BLMOVE b moved RIGHT LEFT 1"),
+            vec![bulk(b"2")]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "BLPOP a -1"),
+            vec![error("ERR timeout is negative")]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "BLPOP a x"),
+            vec![error("ERR timeout is not a float or out of range")]
+        );
+        send(&mut client, &mut db, "SET s v");
+        assert_eq!(send(&mut client, &mut db, "BLPOP s 1"), vec![wrong_type()]);
+
+        assert_eq!(
+            send(&mut client, &mut db, "BLMOVE a b LEFT LEFT 0.05"),
+            vec![]
+        );
+        let reply = client.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, RespType::BulkString(None));
+        assert_eq!(db.blocking().blocked_clients(), 0);
+
+        // inside MULTI nothing blocks
+        let args = args("BLPOP a 0");
+        assert_eq!(
+            blocking_command(&mut client, &args, &db, false),
+            Some(vec![RespType::Array(None)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unblock_and_types() {
+        let mut db = Db::new(4);
+        let mut list = Client::new();
+        let mut zset = Client::new();
+        let mut other = Client::new();
+        send(&mut zset, &mut db, "BZPOPMIN k 0");
+        send(&mut list, &mut db, "BLMPOP 0 1 k RIGHT COUNT 2");
+
+        // the sorted set client waits on, since the key holds a list
+        send(&mut other, &mut db, "RPUSH k a b c");
+        let reply = list.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            reply,
+            RespType::Array(Some(vec![bulk(b"k"), array(&["c", "b"])]))
+        );
+        assert_eq!(db.blocking().blocked_clients(), 1);
+
+        assert!(db.blocking().unblock(zset.id, true));
+        assert!(!db.blocking().unblock(zset.id, true));
+        let reply = zset.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            reply,
+            error("UNBLOCKED client unblocked via CLIENT UNBLOCK")
+        );
+
+        zset.blocked = None;
+        send(&mut zset, &mut db, "BZPOPMIN k2 0");
+        assert!(db.blocking().unblock(zset.id, false));
+        let reply = zset.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, RespType::Array(None));
+    }
+
+    // the key and IDs of a stream read reply
+    fn read_ids(reply: &RespType) -> Vec<(String, Vec<String>)> {
+        let text = |r: &RespType| match r {
+            RespType::BulkString(Some(b)) => String::from_utf8_lossy(b).to_string(),
+            _ => panic!("not a bulk string: {:?}", r),
+        };
+        let RespType::Array(Some(keys)) = reply else {
+            panic!("not a read reply: {:?}", reply);
+        };
+        keys.iter()
+            .map(|key| match key {
+                RespType::Array(Some(pair)) => match &pair[1] {
+                    RespType::Array(Some(entries)) => (
+                        text(&pair[0]),
+                        entries
+                            .iter()
+                            .map(|e| match e {
+                                RespType::Array(Some(e)) => text(&e[0]),
+                                _ => panic!("not an entry"),
+                            })
+                            .collect(),
+                    ),
+                    _ => panic!("no entries"),
+                },
+                _ => panic!("not a key"),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_stream_reads() {
+        let mut db = Db::new(4);
+        let mut reader = Client::new();
+        let mut consumer = Client::new();
+        let mut producer = Client::new();
+        send(&mut producer, &mut db, "XADD s 1 f v");
+        send(&mut producer, &mut db, "XGROUP CREATE s g $");
+
+        // $ is the last entry when blocking, so only later ones are read
+        assert_eq!(
+            send(&mut reader, &mut db, "XREAD BLOCK 0 STREAMS other s $ $"),
+            vec![]
+        );
+        assert_eq!(
+            send(
+                &mut consumer,
+                &mut db,
+                "XREADGROUP GROUP g c BLOCK 0 STREAMS s >"
+            ),
+            vec![]
+        );
+        send(&mut producer, &mut db, "XADD s 2 f v");
+        let reply = reader.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            read_ids(&reply),
+            vec![("s".to_string(), vec!["2-0".to_string()])]
+        );
+        let reply = consumer.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            read_ids(&reply),
+            vec![("s".to_string(), vec!["2-0".to_string()])]
+        );
+
+        // what is there already is read without blocking, from every key
+        reader.blocked = None;
+        send(&mut producer, &mut db, "XADD t 1 f v");
+        let reply = send(&mut reader, &mut db, "XREAD BLOCK 0 STREAMS s t 1 0");
+        assert_eq!(
+            read_ids(&reply[0]),
+            vec![
+                ("s".to_string(), vec!["2-0".to_string()]),
+                ("t".to_string(), vec!["1-0".to_string()]),
+            ]
+        );
+        // reading history never blocks
+        let reply = send(
+            &mut consumer,
+            &mut db,
+            "XREADGROUP GROUP g c BLOCK 0 STREAMS s 0",
+        );
+        assert_eq!(
+            read_ids(&reply[0]),
+            vec![("s".to_string(), vec!["2-0".to_string()])]
+        );
+        assert_eq!(
+            send(
+                &mut consumer,
+                &mut db,
+                "XREADGROUP GROUP nosuch c BLOCK 0 STREAMS s >"
+            ),
+            vec![error(&stream::no_group("s", "nosuch"))]
+        );
+        assert_eq!(
+            send(&mut reader, &mut db, "XREAD BLOCK 50 STREAMS s $"),
+            vec![]
+        );
+        let reply = reader.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, RespType::Array(None));
+
+        // group readers are woken when the group or the stream goes away,
+        // while plain readers keep waiting
+        reader.blocked = None;
+        consumer.blocked = None;
+        send(&mut reader, &mut db, "XREAD BLOCK 0 STREAMS s $");
+        send(
+            &mut consumer,
+            &mut db,
+            "XREADGROUP GROUP g c BLOCK 0 STREAMS s >",
+        );
+        send(&mut producer, &mut db, "XGROUP DESTROY s g");
+        let reply = consumer.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            reply,
+            error("NOGROUP the consumer group this client was blocked on no longer exists")
+        );
+        consumer.blocked = None;
+        send(&mut producer, &mut db, "XGROUP CREATE s g $");
+        send(
+            &mut consumer,
+            &mut db,
+            "XREADGROUP GROUP g c BLOCK 0 STREAMS s >",
+        );
+        send(&mut producer, &mut db, "DEL s");
+        let reply = consumer.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(reply, error("UNBLOCKED the stream key no longer exists"));
+        assert_eq!(db.blocking().blocked_clients(), 1);
+        send(&mut producer, &mut db, "XADD s 5 f v");
+        let reply = reader.blocked.as_mut().unwrap().wait().await;
+        assert_eq!(
+            read_ids(&reply),
+            vec![("s".to_string(), vec!["5-0".to_string()])]
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+
+use super::{
+    aof::{self, FileKind, Manifest},
+    errors::{AofError, RdbError},
+    rdb::{self, RdbEntry, RdbFile},
+};
+
+/// Key counts gathered while reading a snapshot.
+#[derive(Debug, Default)]
+pub struct KeyStats {
+    pub by_type: BTreeMap<&'static str, usize>,
+    pub with_expiry: usize,
+    /// keys whose ttl had already passed when the check ran
+    pub already_expired: usize,
+    pub outside_db0: usize,
+}
+
+impl KeyStats {
+    fn add(&mut self, entry: &RdbEntry, now: i64) {
+        *self.by_type.entry(entry.value.type_name()).or_default() += 1;
+        if let Some(at) = entry.expire_at {
+            self.with_expiry += 1;
+            if at <= now {
+                self.already_expired += 1;
+            }
+        }
+        if entry.db != 0 {
+            self.outside_db0 += 1;
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.by_type.values().sum()
+    }
+}
+
+impl fmt::Display for KeyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[info] {} keys read", self.total())?;
+        for (kind, count) in &self.by_type {
+            writeln!(f, "[info]   {}: {}", kind, count)?;
+        }
+        writeln!(f, "[info] {} expires", self.with_expiry)?;
+        writeln!(f, "[info] {} already expired", self.already_expired)?;
+        if self.outside_db0 > 0 {
+            writeln!(f, "[info] {} keys outside db 0", self.outside_db0)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct RdbReport {
+    pub header: RdbFile,
+    pub keys: KeyStats,
+    /// bytes taken by the snapshot, when it was read to the end
+    pub size: Option<usize>,
+    pub error: Option<RdbError>,
+}
+
+impl RdbReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for RdbReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.header.version > 0 {
+            writeln!(f, "[info] RDB version {}", self.header.version)?;
+        }
+        for (key, value) in &self.header.aux {
+            writeln!(f, "[info] AUX FIELD {} = '{}'", key, value)?;
+        }
+        match (&self.error, self.header.checksum) {
+            (Some(e), _) => {
+                match e.offset() {
+                    Some(offset) => writeln!(f, "[offset {}] {}", offset, e)?,
+                    None => writeln!(f, "[error] {}", e)?,
+                }
+                writeln!(f, "[info] keys read before the error:")?;
+            }
+            (None, Some(checksum)) => writeln!(f, "[info] Checksum OK ({:016x})", checksum)?,
+            (None, None) => writeln!(f, "[info] No checksum stored, skipped")?,
+        }
+        write!(f, "{}", self.keys)?;
+        if self.is_ok() {
+            writeln!(f, "\\o/ RDB looks OK! \\o/")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a whole snapshot, or the rdb preamble at the start of an AOF file.
+pub fn check_rdb(data: &[u8]) -> RdbReport {
+    let now = Utc::now().timestamp_millis();
+    let mut header = RdbFile::default();
+    let mut keys = KeyStats::default();
+    let res = rdb::decode_into(data, &mut header, |entry| keys.add(&entry, now));
+    let (size, error) = match res {
+        Ok(size) => (Some(size), None),
+        Err(e) => (None, Some(e)),
+    };
+    RdbReport {
+        header,
+        keys,
+        size,
+        error,
+    }
+}
+
+#[derive(Debug)]
+pub struct AofFileReport {
+    pub path: PathBuf,
+    pub kind: Option<FileKind>,
+    pub size: usize,
+    pub preamble: Option<RdbReport>,
+    /// commands read, by lowercased name
+    pub commands: BTreeMap<String, usize>,
+    /// the file is valid up to this offset: the start of the first bad command
+    pub valid_up_to: usize,
+    pub error: Option<AofError>,
+}
+
+impl AofFileReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl fmt::Display for AofFileReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            Some(FileKind::Base) => " (base)",
+            Some(FileKind::Incr) => " (incr)",
+            _ => "",
+        };
+        writeln!(
+            f,
+            "Checking {}{}, {} bytes",
+            self.path.display(),
+            kind,
+            self.size
+        )?;
+        if let Some(preamble) = &self.preamble {
+            writeln!(f, "[info] RDB preamble:")?;
+            write!(f, "{}", preamble)?;
+        }
+        let total: usize = self.commands.values().sum();
+        writeln!(f, "[info] {} commands read", total)?;
+        for (name, count) in &self.commands {
+            writeln!(f, "[info]   {}: {}", name, count)?;
+        }
+        match &self.error {
+            Some(e) => {
+                writeln!(f, "[error] {}", e)?;
+                writeln!(
+                    f,
+                    "[info] valid up to offset {}, {} bytes after it are bad",
+                    self.valid_up_to,
+                    self.size - self.valid_up_to
+                )
+            }
+            None => writeln!(f, "AOF {} is valid", self.path.display()),
+        }
+    }
+}
+
+/// Checks one AOF file, base or incr, with or without an rdb preamble.
+pub fn check_aof_file(path: &Path, kind: Option<FileKind>) -> AofFileReport {
+    let mut report = AofFileReport {
+        path: path.to_path_buf(),
+        kind,
+        size: 0,
+        preamble: None,
+        commands: BTreeMap::new(),
+        valid_up_to: 0,
+        error: None,
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            report.error = Some(AofError::Io(format!("{}: {}", path.display(), e)));
+            return report;
+        }
+    };
+    report.size = data.len();
+    let mut pos = 0;
+    if data.starts_with(b"REDIS") {
+        let preamble = check_rdb(&data);
+        let res = match (&preamble.error, preamble.size) {
+            (Some(e), _) => Err(AofError::Rdb(e.clone())),
+            (None, size) => Ok(size.unwrap_or(0)),
+        };
+        report.preamble = Some(preamble);
+        match res {
+            Ok(size) => pos = size,
+            Err(e) => {
+                report.error = Some(e);
+                return report;
+            }
+        }
+    }
+    loop {
+        match aof::parse_command(&data, pos) {
+            Ok(Some((args, next))) => {
+                let name = String::from_utf8_lossy(&args[0]).to_lowercase();
+                *report.commands.entry(name).or_default() += 1;
+                pos = next;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                report.error = Some(e);
+                break;
+            }
+        }
+    }
+    report.valid_up_to = pos;
+    report
+}
+
+#[derive(Debug, Default)]
+pub struct AofReport {
+    pub manifest: Option<PathBuf>,
+    /// files the manifest lists; checking stops at the first bad one
+    pub listed: usize,
+    pub files: Vec<AofFileReport>,
+}
+
+impl AofReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|f| f.is_ok())
+    }
+}
+
+impl fmt::Display for AofReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(manifest) = &self.manifest {
+            writeln!(
+                f,
+                "Manifest {} lists {} files",
+                manifest.display(),
+                self.listed
+            )?;
+        }
+        for file in &self.files {
+            write!(f, "{}", file)?;
+        }
+        Ok(())
+    }
+}
+
+fn find_manifest(dir: &Path) -> Result<PathBuf, AofError> {
+    let io = |e: std::io::Error| AofError::Io(format!("{}: {}", dir.display(), e));
+    let mut manifests = vec![];
+    for entry in std::fs::read_dir(dir).map_err(io)? {
+        let path = entry.map_err(io)?.path();
+        if path.extension().is_some_and(|ext| ext == "manifest") {
+            manifests.push(path);
+        }
+    }
+    match manifests.len() {
+        1 => Ok(manifests.remove(0)),
+        n => Err(AofError::Io(format!(
+            "expected one manifest in {}, found {}",
+            dir.display(),
+            n
+        ))),
+    }
+}
+
+/// Checks an AOF given as a directory, its manifest, or a single file.
+pub fn check_aof(target: &Path) -> Result<AofReport, AofError> {
+    let manifest_path = if target.is_dir() {
+        find_manifest(target)?
+    } else if target.extension().is_some_and(|ext| ext == "manifest") {
+        target.to_path_buf()
+    } else {
+        return Ok(AofReport {
+            manifest: None,
+            listed: 1,
+            files: vec![check_aof_file(target, None)],
+        });
+    };
+    let text = std::fs::read_to_string(&manifest_path)
+        .map_err(|e| AofError::Io(format!("{}: {}", manifest_path.display(), e)))?;
+    let manifest = Manifest::parse(&text)?;
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    let mut files = vec![];
+    for entry in manifest.files() {
+        let report = check_aof_file(&dir.join(&entry.name), Some(entry.kind));
+        let failed = !report.is_ok();
+        files.push(report);
+        // later files build on this one, so there is no point going on
+        if failed {
+            break;
+        }
+    }
+    Ok(AofReport {
+        manifest: Some(manifest_path),
+        listed: manifest.files().count(),
+        files,
+    })
+}
+
+/// Truncates the last file of a failed check to its last valid command.
+/// Only a bad tail can be cut off this way: an error in an earlier file, or
+/// in an rdb preamble, would lose the commands that follow it.
+pub fn fix_aof(report: &AofReport) -> Result<usize, String> {
+    let Some(bad) = report.files.iter().find(|f| !f.is_ok()) else {
+        return Ok(0);
+    };
+    if report.files.len() < report.listed || !std::ptr::eq(bad, report.files.last().unwrap()) {
+        return Err(format!(
+            "{} is not the last file of the AOF, refusing to truncate it",
+            bad.path.display()
+        ));
+    }
+    if matches!(bad.error, Some(AofError::Rdb(_)) | Some(AofError::Io(_))) {
+        return Err(format!(
+            "{} can not be fixed by truncating it",
+            bad.path.display()
+        ));
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&bad.path)
+        .map_err(|e| e.to_string())?;
+    file.set_len(bad.valid_up_to as u64)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| e.to_string())?;
+    Ok(bad.size - bad.valid_up_to)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::resp::datastore::Value;
+
+    #[test]
+    fn test_check_rdb() {
+        let mut writer = rdb::RdbWriter::new(false);
+        writer.select_db(0, 3, 2);
+        writer.entry(b"a", &Value::String(Bytes::from("1")), Some(1));
+        writer.entry(b"b", &Value::String(Bytes::from("2")), Some(i64::MAX));
+        writer.entry(b"h", &Value::Hash(Default::default()), None);
+        let data = writer.finish();
+
+        let report = check_rdb(&data);
+        assert!(report.is_ok());
+        assert_eq!(report.keys.by_type.get("string"), Some(&2));
+        assert_eq!(report.keys.by_type.get("hash"), Some(&1));
+        assert_eq!(report.keys.with_expiry, 2);
+        assert_eq!(report.keys.already_expired, 1);
+
+        // cut inside the hash entry: the strings before it are still counted
+        let report = check_rdb(&data[..data.len() - 11]);
+        assert!(matches!(report.error, Some(RdbError::Corrupt { .. })));
+        assert_eq!(report.keys.total(), 2);
+    }
+
+    #[test]
+    fn test_check_and_fix_aof() {
+        let dir = std::env::temp_dir().join(format!("check-aof-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("appendonly.aof.1.incr.aof");
+        let mut data =
+            aof::encode_command(&[Bytes::from("SET"), Bytes::from("k"), Bytes::from("v")]);
+        let good = data.len();
+        data.extend_from_slice(b"*2\r\n$3\r\nDEL\r\n$1");
+        std::fs::write(&path, &data).unwrap();
+        std::fs::write(
+            dir.join("appendonly.aof.manifest"),
+            "file appendonly.aof.1.incr.aof seq 1 type i\n",
+        )
+        .unwrap();
+
+        let report = check_aof(&dir).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.files[0].commands.get("set"), Some(&1));
+        assert_eq!(report.files[0].valid_up_to, good);
+        assert_eq!(fix_aof(&report), Ok(data.len() - good));
+        assert!(check_aof(&dir).unwrap().is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
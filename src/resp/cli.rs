@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+/// What the server binary was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Invocation {
+    Run(ServerArgs),
+    Version,
+    Help,
+    /// test this many megabytes of memory and exit
+    TestMemory(usize),
+}
+
+/// How to configure a server that is started.
+#[derive(Debug, Default, PartialEq)]
+pub struct ServerArgs {
+    pub config_file: Option<PathBuf>,
+    /// directives from the command line, each a name followed by its arguments
+    pub options: Vec<Vec<String>>,
+}
+
+/// Reads the arguments after the program name the way redis-server does:
+/// an optional config file first, then `--name value...` directives. Every
+/// argument up to the next `--` belongs to the directive before it, so
+/// `--save 900 1 --port 7000` sets two directives.
+pub fn parse_args(args: &[String]) -> Result<Invocation, String> {
+    match args.first().map(String::as_str) {
+        Some("-v" | "--version") => return Ok(Invocation::Version),
+        Some("-h" | "--help") => return Ok(Invocation::Help),
+        Some("--test-memory") => {
+            return match args.get(1).map(|mb| mb.parse::<usize>()) {
+                Some(Ok(megabytes)) if megabytes > 0 => Ok(Invocation::TestMemory(megabytes)),
+                _ => Err("Please specify the amount of memory to test in megabytes.".to_string()),
+            };
+        }
+        _ => {}
+    }
+    let mut server_args = ServerArgs::default();
+    let mut rest = args;
+    if let Some(first) = args.first().filter(|a| !a.starts_with("--")) {
+        server_args.config_file = Some(PathBuf::from(first));
+        rest = &args[1..];
+    }
+    for arg in rest {
+        match arg.strip_prefix("--") {
+            Some("") => return Err("Invalid option '--'".to_string()),
+            Some(name) => server_args.options.push(vec![name.to_string()]),
+            None => match server_args.options.last_mut() {
+                Some(option) => option.push(arg.clone()),
+                None => return Err(format!("Invalid option '{}'", arg)),
+            },
+        }
+    }
+    Ok(Invocation::Run(server_args))
+}
+
+pub fn version() -> String {
+    format!(
+        "Redis server v={} bits={}",
+        env!("CARGO_PKG_VERSION"),
+        usize::BITS
+    )
+}
+
+pub fn usage(program: &str) -> String {
+    [
+        format!("Usage: {} [/path/to/redis.conf] [options]", program),
+        format!("       {} -v or --version", program),
+        format!("       {} -h or --help", program),
+        format!("       {} --test-memory <megabytes>", program),
+        String::new(),
+        "Examples:".to_string(),
+        format!(
+            "       {} (run the server with ./redis.conf if present, otherwise the defaults)",
+            program
+        ),
+        format!("       {} /etc/redis/6379.conf", program),
+        format!("       {} --port 7777", program),
+        format!("       {} --port 7777 --dir /var/lib/redis-7777", program),
+        format!("       {} /etc/myredis.conf --loglevel verbose", program),
+    ]
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(&args("")),
+            Ok(Invocation::Run(ServerArgs::default()))
+        );
+        assert_eq!(parse_args(&args("--version")), Ok(Invocation::Version));
+        assert_eq!(parse_args(&args("-h")), Ok(Invocation::Help));
+        assert_eq!(
+            parse_args(&args("--test-memory 64")),
+            Ok(Invocation::TestMemory(64))
+        );
+        assert!(parse_args(&args("--test-memory lots")).is_err());
+        assert_eq!(
+            parse_args(&args(
+                "/etc/redis.conf --port 7000 --save 900 1 --bind 127.0.0.1 -::1"
+            )),
+            Ok(Invocation::Run(ServerArgs {
+                config_file: Some(PathBuf::from("/etc/redis.conf")),
+                options: vec![
+                    args("port 7000"),
+                    args("save 900 1"),
+                    args("bind 127.0.0.1 -::1"),
+                ],
+            }))
+        );
+        assert_eq!(
+            parse_args(&args("--dir /tmp")),
+            Ok(Invocation::Run(ServerArgs {
+                config_file: None,
+                options: vec![args("dir /tmp")],
+            }))
+        );
+        assert!(parse_args(&args("a.conf b.conf")).is_err());
+    }
+}
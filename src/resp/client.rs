@@ -0,0 +1,379 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use super::{
+    acl,
+    blocking::Blocked,
+    command_table,
+    datastore::{Db, WatchedKey},
+    errors::UserInputError,
+    pubsub, redisconfig,
+    resp_value::{bulk, RespType},
+    transaction::Transaction,
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What the server keeps for each connection.
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    /// whether commands other than AUTH, HELLO and QUIT may run
+    pub authenticated: bool,
+    /// the ACL user whose permissions apply
+    pub user: String,
+    /// set by HELLO SETNAME
+    pub name: Option<String>,
+    /// the commands queued since MULTI
+    pub transaction: Option<Transaction>,
+    /// the keys whose changes fail the next EXEC
+    pub watched: Vec<WatchedKey>,
+    /// set by the first SUBSCRIBE or PSUBSCRIBE
+    pub subscriber: Option<pubsub::Subscriber>,
+    /// set while a blocking command, like BLPOP, waits
+    pub blocked: Option<Blocked>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// A new connection, as the default user. Like redis, it needs to AUTH
+    /// if that user has a password when it connects; setting one later
+    /// doesn't log it out.
+    pub fn new() -> Self {
+        Client {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            authenticated: acl::auto_login(),
+            user: "default".to_string(),
+            name: None,
+            transaction: None,
+            watched: vec![],
+            subscriber: None,
+            blocked: None,
+        }
+    }
+
+    /// Answers the commands about the connection itself: AUTH, HELLO and
+    /// QUIT, the only ones allowed before authenticating, and ACL. QUIT is
+    /// answered with `RespType::Quit`. Returns None for every other command,
+    /// or the error when the client may not run it: a wrong number of
+    /// arguments, NOAUTH before authenticating, NOPERM when its user lacks
+    /// the permissions.
+    ///
+    /// After MULTI, commands other than EXEC, DISCARD, MULTI and QUIT are
+    /// queued instead and answered with QUEUED. An error while queueing
+    /// makes EXEC discard the transaction.
+    pub fn connection_command(&mut self, args: &[Bytes]) -> Option<RespType> {
+        let reply = self.check_and_queue(args);
+        if let (Some(RespType::Error(_)), Some(transaction)) = (&reply, &mut self.transaction) {
+            transaction.aborted = true;
+        }
+        reply
+    }
+
+    fn check_and_queue(&mut self, args: &[Bytes]) -> Option<RespType> {
+        let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+        let queueing = self.transaction.is_some();
+        let unknown = || {
+            let line: Vec<_> = (0..args.len()).map(text).collect();
+            RespType::Error(format!(
+                "ERR {}",
+                UserInputError::UnknownCommand(line.join(" "))
+            ))
+        };
+        let Some(name) = redisconfig::resolve_command_name(&text(0)) else {
+            return queueing.then(unknown);
+        };
+        if let Some(error) = command_table::arity_error(&name, args) {
+            return Some(error);
+        }
+        if self.is_subscribed()
+            && !pubsub::is_subscribe_command(&name)
+            && !matches!(name.as_str(), "ping" | "quit")
+        {
+            return Some(RespType::Error(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                name
+            )));
+        }
+        let queueing = queueing && !matches!(name.as_str(), "exec" | "discard" | "multi" | "quit");
+        if queueing {
+            match command_table::lookup(&name) {
+                None => return Some(unknown()),
+                // EXEC runs with everything else held off, which MIGRATE's
+                // network I/O mustn't do, and subscribing replies go out
+                // with the messages, not in EXEC's
+                Some(spec)
+                    if spec.flags.contains(&"no_multi")
+                        || name == "migrate"
+                        || pubsub::is_subscribe_command(&name) =>
+                {
+                    return Some(RespType::Error(
+                        "ERR Command not allowed inside a transaction".to_string(),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        let reply = match name.as_str() {
+            "auth" if !queueing => match args.len() {
+                2 if acl::default_user_nopass() => RespType::Error(
+                    "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                        .to_string(),
+                ),
+                2 => self.auth("default", &args[1]),
+                3 => self.auth(&text(1), &args[2]),
+                _ => RespType::Error("ERR syntax error".to_string()),
+            },
+            "hello" if !queueing => self.hello(args),
+            "quit" => RespType::Quit,
+            _ if !self.authenticated => {
+                RespType::Error("NOAUTH Authentication required.".to_string())
+            }
+            _ => match acl::check(&self.user, &name, args) {
+                Err(denial) => {
+                    acl::log_denial(self, &denial);
+                    RespType::Error(format!("NOPERM {}", denial.message(&self.user, false)))
+                }
+                Ok(()) if queueing => {
+                    if let Some(transaction) = &mut self.transaction {
+                        transaction.commands.push(args.to_vec());
+                    }
+                    RespType::SimpleString("QUEUED".to_string())
+                }
+                Ok(()) if name == "acl" => acl::acl_command(self, args),
+                Ok(()) => return None,
+            },
+        };
+        Some(reply)
+    }
+
+    /// CLIENT ID, UNBLOCK and HELP. Returns None for other commands.
+    pub fn client_command(&mut self, args: &[Bytes], db: &Db) -> Option<RespType> {
+        let name = redisconfig::resolve_command_name(&String::from_utf8_lossy(&args[0]))?;
+        if name != "client" {
+            return None;
+        }
+        let sub = String::from_utf8_lossy(&args[1]).to_lowercase();
+        let reply = match sub.as_str() {
+            "id" => RespType::Integer(self.id as i64),
+            // CLIENT UNBLOCK id [TIMEOUT|ERROR]
+            "unblock" => {
+                let Ok(id) = String::from_utf8_lossy(&args[2]).parse::<u64>() else {
+                    return Some(RespType::Error(
+                        "ERR value is not an integer or out of range".to_string(),
+                    ));
+                };
+                let with_error = match args
+                    .get(3)
+                    .map(|a| String::from_utf8_lossy(a).to_lowercase())
+                {
+                    None => false,
+                    Some(reason) if reason == "timeout" => false,
+                    Some(reason) if reason == "error" => true,
+                    Some(_) => {
+                        return Some(RespType::Error(
+                            "ERR CLIENT UNBLOCK reason should be TIMEOUT or ERROR".to_string(),
+                        ))
+                    }
+                };
+                RespType::Integer(db.blocking().unblock(id, with_error) as i64)
+            }
+            "help" => RespType::Array(Some(
+                [
+                    "CLIENT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                    "ID",
+                    "    Return the ID of the current connection.",
+                    "UNBLOCK <clientid> [TIMEOUT|ERROR]",
+                    "    Unblock the specified blocked client.",
+                    "HELP",
+                    "    Print this help.",
+                ]
+                .iter()
+                .map(|line| RespType::SimpleString(line.to_string()))
+                .collect(),
+            )),
+            _ => RespType::Error(format!(
+                "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+                String::from_utf8_lossy(&args[1])
+            )),
+        };
+        Some(reply)
+    }
+
+    /// Whether the client is in subscriber mode, where it may only change
+    /// its subscriptions, PING and QUIT.
+    pub fn is_subscribed(&self) -> bool {
+        self.subscriber.as_ref().is_some_and(|s| s.count() > 0)
+    }
+
+    fn auth(&mut self, user: &str, password: &[u8]) -> RespType {
+        if acl::authenticate(user, password) {
+            self.authenticated = true;
+            self.user = user.to_string();
+            return RespType::SimpleString("OK".to_string());
+        }
+        acl::log_auth_failure(self, user);
+        RespType::Error("WRONGPASS invalid username-password pair or user is disabled.".to_string())
+    }
+
+    // HELLO [protover [AUTH username password] [SETNAME clientname]]
+    fn hello(&mut self, args: &[Bytes]) -> RespType {
+        let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+        if let Some(version) = args.get(1) {
+            match String::from_utf8_lossy(version).parse::<i64>() {
+                // RESP3 replies are not implemented
+                Ok(2) => {}
+                Ok(_) => {
+                    return RespType::Error("NOPROTO unsupported protocol version".to_string())
+                }
+                Err(_) => {
+                    return RespType::Error(
+                        "ERR Protocol version is not an integer or out of range".to_string(),
+                    )
+                }
+            }
+        }
+        let mut setname = None;
+        let mut i = 2;
+        while i < args.len() {
+            let more = args.len() - i - 1;
+            match text(i).to_lowercase().as_str() {
+                "auth" if more >= 2 => {
+                    let reply = self.auth(&text(i + 1), &args[i + 2]);
+                    if matches!(reply, RespType::Error(_)) {
+                        return reply;
+                    }
+                    i += 3;
+                }
+                "setname" if more >= 1 => {
+                    setname = Some(text(i + 1));
+                    i += 2;
+                }
+                _ => {
+                    return RespType::Error(format!(
+                        "ERR Syntax error in HELLO option '{}'",
+                        text(i)
+                    ))
+                }
+            }
+        }
+        if !self.authenticated {
+            return RespType::Error(
+                "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+                    .to_string(),
+            );
+        }
+        if let Some(name) = setname {
+            self.name = Some(name);
+        }
+        RespType::Array(Some(vec![
+            bulk("server"),
+            bulk("redis"),
+            bulk("version"),
+            bulk(env!("CARGO_PKG_VERSION")),
+            bulk("proto"),
+            RespType::Integer(2),
+            bulk("id"),
+            RespType::Integer(self.id as i64),
+            bulk("mode"),
+            bulk("standalone"),
+            bulk("role"),
+            bulk("master"),
+            bulk("modules"),
+            RespType::Array(Some(vec![])),
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::test_helpers::args;
+
+    #[test]
+    fn test_connection_commands() {
+        // no requirepass in the tests' configuration
+        let mut client = Client::new();
+        assert!(client.authenticated);
+        assert_eq!(client.connection_command(&args("GET k")), None);
+        assert_eq!(
+            client.connection_command(&args("QUIT")),
+            Some(RespType::Quit)
+        );
+        assert_eq!(
+            client.connection_command(&args("AUTH pass")),
+            Some(RespType::Error(
+                "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                    .to_string()
+            ))
+        );
+        assert_eq!(
+            client.connection_command(&args("AUTH someone pass")),
+            Some(RespType::Error(
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string()
+            ))
+        );
+        let Some(RespType::Array(Some(hello))) =
+            client.connection_command(&args("HELLO 2 SETNAME app"))
+        else {
+            panic!("expected the HELLO map");
+        };
+        assert_eq!(hello[5], RespType::Integer(2));
+        assert_eq!(client.name.as_deref(), Some("app"));
+        assert_eq!(
+            client.connection_command(&args("HELLO 3")),
+            Some(RespType::Error(
+                "NOPROTO unsupported protocol version".to_string()
+            ))
+        );
+
+        client.authenticated = false;
+        assert_eq!(
+            client.connection_command(&args("GET k")),
+            Some(RespType::Error(
+                "NOAUTH Authentication required.".to_string()
+            ))
+        );
+        assert!(matches!(
+            client.connection_command(&args("HELLO 2")),
+            Some(RespType::Error(e)) if e.starts_with("NOAUTH HELLO must be called")
+        ));
+    }
+
+    #[test]
+    fn test_acl_permissions() {
+        let mut admin = Client::new();
+        assert_eq!(
+            admin.connection_command(&args("ACL SETUSER client-test on >pw +get ~k*")),
+            Some(RespType::SimpleString("OK".to_string()))
+        );
+        let mut client = Client::new();
+        assert_eq!(
+            client.connection_command(&args("AUTH client-test pw")),
+            Some(RespType::SimpleString("OK".to_string()))
+        );
+        assert_eq!(client.user, "client-test");
+        assert_eq!(client.connection_command(&args("GET k1")), None);
+        assert_eq!(
+            client.connection_command(&args("GET x")),
+            Some(RespType::Error(
+                "NOPERM No permissions to access a key".to_string()
+            ))
+        );
+        assert_eq!(
+            client.connection_command(&args("ACL WHOAMI")),
+            Some(RespType::Error(
+                "NOPERM User client-test has no permissions to run the 'acl|whoami' command"
+                    .to_string()
+            ))
+        );
+        admin.connection_command(&args("ACL DELUSER client-test"));
+        assert!(!acl::user_exists(&client.user));
+    }
+}
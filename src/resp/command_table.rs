@@ -0,0 +1,1286 @@
+use bytes::Bytes;
+
+use super::{
+    glob::glob_match,
+    resp_value::{bulk, RespType},
+};
+
+/// How a command uses the keys it names, which decides the `%R~`/`%W~` key
+/// permissions an ACL user needs for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// What the server knows about a command without running it.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// the number of arguments, the name included; negative for at least
+    /// that many
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// position of the first key, 0 when the command takes none
+    pub first_key: i64,
+    /// position of the last key, negative counting from the end
+    pub last_key: i64,
+    pub step: i64,
+    pub access: KeyAccess,
+    /// ACL categories, without the @
+    pub categories: &'static [&'static str],
+    pub subcommands: &'static [CommandSpec],
+    /// what COMMAND DOCS tells about it
+    pub summary: &'static str,
+    pub since: &'static str,
+    pub group: &'static str,
+    pub complexity: &'static str,
+}
+
+const fn command(
+    name: &'static str,
+    arity: i64,
+    flags: &'static [&'static str],
+    keys: (i64, i64, i64),
+    access: KeyAccess,
+    categories: &'static [&'static str],
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        arity,
+        flags,
+        first_key: keys.0,
+        last_key: keys.1,
+        step: keys.2,
+        access,
+        categories,
+        subcommands: &[],
+        summary: "",
+        since: "",
+        group: "",
+        complexity: "",
+    }
+}
+
+impl CommandSpec {
+    const fn docs(
+        self,
+        group: &'static str,
+        since: &'static str,
+        complexity: &'static str,
+        summary: &'static str,
+    ) -> CommandSpec {
+        CommandSpec {
+            group,
+            since,
+            complexity,
+            summary,
+            ..self
+        }
+    }
+
+    const fn with_subcommands(self, subcommands: &'static [CommandSpec]) -> CommandSpec {
+        CommandSpec {
+            subcommands,
+            ..self
+        }
+    }
+}
+
+const NO_KEYS: (i64, i64, i64) = (0, 0, 0);
+const ONE_KEY: (i64, i64, i64) = (1, 1, 1);
+const ALL_KEYS: (i64, i64, i64) = (1, -1, 1);
+
+use KeyAccess::{Read, ReadWrite, Write};
+
+/// Every ACL category, in the order ACL CAT lists them.
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+    "search",
+];
+
+const ADMIN: &[&str] = &["admin", "slow", "dangerous"];
+const ADMIN_FLAGS: &[&str] = &["admin", "noscript", "loading", "stale"];
+const INFO_FLAGS: &[&str] = &["loading", "stale"];
+const NOSCRIPT_FLAGS: &[&str] = &["noscript", "loading", "stale"];
+const SLOW_CONNECTION: &[&str] = &["slow", "connection"];
+const CONNECTION_FLAGS: &[&str] = &[
+    "noscript",
+    "loading",
+    "stale",
+    "fast",
+    "no_auth",
+    "allow_busy",
+];
+const TRANSACTION_FLAGS: &[&str] = &["noscript", "loading", "stale", "fast", "allow_busy"];
+const FAST_TRANSACTION: &[&str] = &["fast", "transaction"];
+const PUBSUB_FLAGS: &[&str] = &["pubsub", "loading", "stale"];
+const SUBSCRIBE_FLAGS: &[&str] = &["pubsub", "noscript", "loading", "stale"];
+const SLOW_PUBSUB: &[&str] = &["pubsub", "slow"];
+const HELP: &str = "Returns helpful text about the different subcommands.";
+
+static ACL_SUBCOMMANDS: &[CommandSpec] = &[
+    command("cat", -2, NOSCRIPT_FLAGS, NO_KEYS, Read, &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1) since the categories and commands are a fixed set.",
+        "Lists the ACL categories, or the commands inside a category.",
+    ),
+    command("deluser", -3, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(1) amortized time considering the typical user.",
+        "Deletes ACL users, and terminates their connections.",
+    ),
+    command("dryrun", -4, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "7.0.0",
+        "O(1).",
+        "Simulates the execution of a command by a user, without executing the command.",
+    ),
+    command("genpass", -2, NOSCRIPT_FLAGS, NO_KEYS, Read, &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1)",
+        "Generates a pseudorandom, secure password that can be used to identify ACL users.",
+    ),
+    command("getuser", 3, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of password, command and pattern rules that the user has.",
+        "Lists the ACL rules of a user.",
+    ),
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, &["slow"]).docs("server", "6.0.0", "O(1)", HELP),
+    command("list", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Dumps the effective rules in ACL file format.",
+    ),
+    command("load", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Reloads the rules from the configured ACL file.",
+    ),
+    command("log", -2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N) with N being the number of entries shown.",
+        "Lists recent security events generated due to ACL rules.",
+    ),
+    command("save", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Saves the effective ACL rules in the configured ACL file.",
+    ),
+    command("setuser", -3, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of rules provided.",
+        "Creates and modifies an ACL user and its rules.",
+    ),
+    command("users", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "6.0.0",
+        "O(N). Where N is the number of configured users.",
+        "Lists all ACL users.",
+    ),
+    command("whoami", 2, NOSCRIPT_FLAGS, NO_KEYS, Read, &["slow"]).docs(
+        "server",
+        "6.0.0",
+        "O(1)",
+        "Returns the authenticated username of the current connection.",
+    ),
+];
+
+static CLIENT_SUBCOMMANDS: &[CommandSpec] = &[
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "connection",
+        "5.0.0",
+        "O(1)",
+        HELP,
+    ),
+    command("id", 2, NOSCRIPT_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "connection",
+        "5.0.0",
+        "O(1)",
+        "Returns the unique client ID of the connection.",
+    ),
+    command(
+        "unblock",
+        -3,
+        ADMIN_FLAGS,
+        NO_KEYS,
+        Read,
+        &["admin", "slow", "dangerous", "connection"],
+    )
+    .docs(
+        "connection",
+        "5.0.0",
+        "O(log N) where N is the number of client connections",
+        "Unblocks a client blocked by a blocking command from a different connection.",
+    ),
+];
+
+static COMMAND_SUBCOMMANDS: &[CommandSpec] = &[
+    command("count", 2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "server",
+        "2.8.13",
+        "O(1)",
+        "Returns a count of commands.",
+    ),
+    command("docs", -2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "server",
+        "7.0.0",
+        "O(N) where N is the number of commands to look up",
+        "Returns documentary information about one, multiple or all commands.",
+    ),
+    command("getkeys", -3, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "server",
+        "2.8.13",
+        "O(N) where N is the number of arguments to the command",
+        "Extracts the key names from an arbitrary command.",
+    ),
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION)
+        .docs("server", "5.0.0", "O(1)", HELP),
+    command("info", -2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "server",
+        "2.8.13",
+        "O(N) where N is the number of commands to look up",
+        "Returns information about one, multiple or all commands.",
+    ),
+    command("list", -2, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION).docs(
+        "server",
+        "7.0.0",
+        "O(N) where N is the total number of Redis commands",
+        "Returns a list of command names.",
+    ),
+];
+
+static CONFIG_SUBCOMMANDS: &[CommandSpec] = &[
+    command("get", -3, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "2.0.0",
+        "O(N) when N is the number of configuration parameters provided",
+        "Returns the effective values of configuration parameters.",
+    ),
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, &["slow"]).docs("server", "5.0.0", "O(1)", HELP),
+    command("resetstat", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "2.0.0",
+        "O(1)",
+        "Resets the server's statistics.",
+    ),
+    command("rewrite", 2, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "2.8.0",
+        "O(1)",
+        "Persists the effective configuration to file.",
+    ),
+    command("set", -4, ADMIN_FLAGS, NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "2.0.0",
+        "O(N) when N is the number of configuration parameters provided",
+        "Sets configuration parameters in-flight.",
+    ),
+];
+
+static PUBSUB_SUBCOMMANDS: &[CommandSpec] = &[
+    command("channels", -2, PUBSUB_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.8.0",
+        "O(N) where N is the number of active channels, and assuming constant time pattern matching (relatively short channels and patterns)",
+        "Returns the active channels.",
+    ),
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, &["slow"]).docs("pubsub", "6.2.0", "O(1)", HELP),
+    command("numpat", 2, PUBSUB_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.8.0",
+        "O(1)",
+        "Returns a count of unique pattern subscriptions.",
+    ),
+    command("numsub", -2, PUBSUB_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.8.0",
+        "O(N) for the NUMSUB subcommand, where N is the number of requested channels",
+        "Returns a count of subscribers to channels.",
+    ),
+    command("shardchannels", -2, PUBSUB_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "7.0.0",
+        "O(N) where N is the number of active shard channels, and assuming constant time pattern matching (relatively short shard channels).",
+        "Returns the active shard channels.",
+    ),
+    command("shardnumsub", -2, PUBSUB_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "7.0.0",
+        "O(N) for the SHARDNUMSUB subcommand, where N is the number of requested shard channels",
+        "Returns the count of subscribers of shard channels.",
+    ),
+];
+
+static XGROUP_SUBCOMMANDS: &[CommandSpec] = &[
+    command(
+        "create",
+        -5,
+        &["write", "denyoom"],
+        (2, 2, 1),
+        Write,
+        &["write", "stream", "slow"],
+    )
+    .docs("stream", "5.0.0", "O(1)", "Creates a consumer group."),
+    command(
+        "createconsumer",
+        5,
+        &["write", "denyoom"],
+        (2, 2, 1),
+        Write,
+        &["write", "stream", "slow"],
+    )
+    .docs(
+        "stream",
+        "6.2.0",
+        "O(1)",
+        "Creates a consumer in a consumer group.",
+    ),
+    command(
+        "delconsumer",
+        5,
+        &["write"],
+        (2, 2, 1),
+        Write,
+        &["write", "stream", "slow"],
+    )
+    .docs(
+        "stream",
+        "5.0.0",
+        "O(1)",
+        "Deletes a consumer from a consumer group.",
+    ),
+    command(
+        "destroy",
+        4,
+        &["write"],
+        (2, 2, 1),
+        Write,
+        &["write", "stream", "slow"],
+    )
+    .docs(
+        "stream",
+        "5.0.0",
+        "O(N) where N is the number of entries in the group's pending entries list (PEL).",
+        "Destroys a consumer group.",
+    ),
+    command("help", 2, INFO_FLAGS, NO_KEYS, Read, &["stream", "slow"])
+        .docs("stream", "5.0.0", "O(1)", HELP),
+    command(
+        "setid",
+        -5,
+        &["write"],
+        (2, 2, 1),
+        Write,
+        &["write", "stream", "slow"],
+    )
+    .docs(
+        "stream",
+        "5.0.0",
+        "O(1)",
+        "Sets the last-delivered ID of a consumer group.",
+    ),
+];
+
+/// The commands this server runs.
+pub static COMMANDS: &[CommandSpec] = &[
+    command("acl", -2, &[], NO_KEYS, Read, &["slow"])
+        .with_subcommands(ACL_SUBCOMMANDS)
+        .docs(
+            "server",
+            "6.0.0",
+            "Depends on subcommand.",
+            "A container for Access List Control commands.",
+        ),
+    command("auth", -2, CONNECTION_FLAGS, NO_KEYS, Read, &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(N) where N is the number of passwords defined for the user",
+        "Authenticates the connection.",
+    ),
+    command("bgrewriteaof", 1, &["admin", "noscript", "no_async_loading"], NO_KEYS, Read, ADMIN)
+        .docs(
+            "server",
+            "1.0.0",
+            "O(1)",
+            "Asynchronously rewrites the append-only file to disk.",
+        ),
+    command("bgsave", -1, &["admin", "noscript", "no_async_loading"], NO_KEYS, Read, ADMIN).docs(
+        "server",
+        "1.0.0",
+        "O(1)",
+        "Asynchronously saves the database(s) to disk.",
+    ),
+    command("blmove", 6, &["write", "denyoom", "blocking"], (1, 2, 1), ReadWrite, &["write", "list", "slow", "blocking"])
+        .docs(
+            "list",
+            "6.2.0",
+            "O(1)",
+            "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved.",
+        ),
+    command("blmpop", -5, &["write", "blocking", "movablekeys"], NO_KEYS, ReadWrite, &["write", "list", "slow", "blocking"])
+        .docs(
+            "list",
+            "7.0.0",
+            "O(N+M) where N is the number of provided keys and M is the number of elements returned.",
+            "Pops the first element from one of multiple lists. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+        ),
+    command("blpop", -3, &["write", "blocking"], (1, -2, 1), ReadWrite, &["write", "list", "slow", "blocking"])
+        .docs(
+            "list",
+            "2.0.0",
+            "O(N) where N is the number of provided keys.",
+            "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+        ),
+    command("brpop", -3, &["write", "blocking"], (1, -2, 1), ReadWrite, &["write", "list", "slow", "blocking"])
+        .docs(
+            "list",
+            "2.0.0",
+            "O(N) where N is the number of provided keys.",
+            "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+        ),
+    command("bzmpop", -5, &["write", "blocking", "movablekeys"], NO_KEYS, ReadWrite, &["write", "sortedset", "slow", "blocking"])
+        .docs(
+            "sorted_set",
+            "7.0.0",
+            "O(K) + O(M*log(N)) where K is the number of provided keys, N being the number of elements in the sorted set, and M being the number of elements popped.",
+            "Removes and returns a member by score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped.",
+        ),
+    command("bzpopmax", -3, &["write", "blocking", "fast"], (1, -2, 1), ReadWrite, &["write", "sortedset", "fast", "blocking"])
+        .docs(
+            "sorted_set",
+            "5.0.0",
+            "O(log(N)) with N being the number of elements in the sorted set.",
+            "Removes and returns the member with the highest score from one or more sorted sets. Blocks until a member available otherwise.  Deletes the sorted set if the last element was popped.",
+        ),
+    command("bzpopmin", -3, &["write", "blocking", "fast"], (1, -2, 1), ReadWrite, &["write", "sortedset", "fast", "blocking"])
+        .docs(
+            "sorted_set",
+            "5.0.0",
+            "O(log(N)) with N being the number of elements in the sorted set.",
+            "Removes and returns the member with the lowest score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped.",
+        ),
+    command("client", -2, &[], NO_KEYS, Read, &["slow"])
+        .with_subcommands(CLIENT_SUBCOMMANDS)
+        .docs(
+            "connection",
+            "2.4.0",
+            "Depends on subcommand.",
+            "A container for client connection commands.",
+        ),
+    command("command", -1, INFO_FLAGS, NO_KEYS, Read, SLOW_CONNECTION)
+        .with_subcommands(COMMAND_SUBCOMMANDS)
+        .docs(
+            "server",
+            "2.8.13",
+            "O(N) where N is the total number of Redis commands",
+            "Returns detailed information about all commands.",
+        ),
+    command("config", -2, &[], NO_KEYS, Read, &["slow"])
+        .with_subcommands(CONFIG_SUBCOMMANDS)
+        .docs(
+            "server",
+            "2.0.0",
+            "Depends on subcommand.",
+            "A container for server configuration commands.",
+        ),
+    command("del", -2, &["write"], ALL_KEYS, Write, &["keyspace", "write", "slow"]).docs(
+        "generic",
+        "1.0.0",
+        "O(N) where N is the number of keys that will be removed. When a key to remove holds a value other than a string, the individual complexity for this key is O(M) where M is the number of elements in the list, set, sorted set or hash. Removing a single key that holds a string value is O(1).",
+        "Deletes one or more keys.",
+    ),
+    command("discard", 1, TRANSACTION_FLAGS, NO_KEYS, Read, FAST_TRANSACTION).docs(
+        "transactions",
+        "2.0.0",
+        "O(N), when N is the number of queued commands",
+        "Discards a transaction.",
+    ),
+    command("dump", 2, &["readonly"], ONE_KEY, Read, &["keyspace", "read", "slow"]).docs(
+        "generic",
+        "2.6.0",
+        "O(1) to access the key and additional O(N*M) to serialize it, where N is the number of Redis objects composing the value and M their average size.",
+        "Returns a serialized representation of the value stored at a key.",
+    ),
+    command("echo", 2, &["fast"], NO_KEYS, Read, &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Returns the given string.",
+    ),
+    command("exec", 1, &["noscript", "loading", "stale", "skip_slowlog"], NO_KEYS, Read, &["slow", "transaction"])
+        .docs(
+            "transactions",
+            "1.2.0",
+            "Depends on commands in the transaction",
+            "Executes all commands in a transaction.",
+        ),
+    command("ft._list", 1, &["readonly"], NO_KEYS, Read, &["search", "read", "slow"]).docs(
+        "module",
+        "2.0.0",
+        "O(1)",
+        "Returns a list of all existing indexes",
+    ),
+    command("ft.create", -2, &["write", "denyoom"], NO_KEYS, Read, &["search", "write", "slow"])
+        .docs(
+            "module",
+            "1.0.0",
+            "O(K) at creation where K is the number of fields, O(N) if scanning the keyspace is triggered, where N is the number of keys in the keyspace",
+            "Creates an index with the given spec",
+        ),
+    command("ft.dropindex", -2, &["write"], NO_KEYS, Read, &["search", "write", "slow"]).docs(
+        "module",
+        "2.0.0",
+        "O(1) or O(N) if documents are deleted, where N is the number of keys in the keyspace",
+        "Deletes the index",
+    ),
+    command("ft.info", 2, &["readonly"], NO_KEYS, Read, &["search", "read", "slow"]).docs(
+        "module",
+        "1.0.0",
+        "O(1)",
+        "Returns information and statistics on the index",
+    ),
+    command("ft.search", -3, &["readonly"], NO_KEYS, Read, &["search", "read", "slow"]).docs(
+        "module",
+        "1.0.0",
+        "O(N)",
+        "Searches the index with a textual query, returning either documents or just ids",
+    ),
+    command("get", 2, &["readonly", "fast"], ONE_KEY, Read, &["read", "string", "fast"]).docs(
+        "string",
+        "1.0.0",
+        "O(1)",
+        "Returns the string value of a key.",
+    ),
+    command("hdel", -3, &["write", "fast"], ONE_KEY, Write, &["write", "hash", "fast"]).docs(
+        "hash",
+        "2.0.0",
+        "O(N) where N is the number of fields to be removed.",
+        "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain.",
+    ),
+    command("hello", -1, CONNECTION_FLAGS, NO_KEYS, Read, &["fast", "connection"]).docs(
+        "connection",
+        "6.0.0",
+        "O(1)",
+        "Handshakes with the Redis server.",
+    ),
+    command("hget", 3, &["readonly", "fast"], ONE_KEY, Read, &["read", "hash", "fast"]).docs(
+        "hash",
+        "2.0.0",
+        "O(1)",
+        "Returns the value of a field in a hash.",
+    ),
+    command("hgetall", 2, &["readonly"], ONE_KEY, Read, &["read", "hash", "slow"]).docs(
+        "hash",
+        "2.0.0",
+        "O(N) where N is the size of the hash.",
+        "Returns all fields and values in a hash.",
+    ),
+    command("hset", -4, &["write", "denyoom", "fast"], ONE_KEY, Write, &["write", "hash", "fast"])
+        .docs(
+            "hash",
+            "2.0.0",
+            "O(1) for each field/value pair added, so O(N) to add N field/value pairs when the command is called with multiple field/value pairs.",
+            "Creates or modifies the value of a field in a hash.",
+        ),
+    command("info", -1, INFO_FLAGS, NO_KEYS, Read, &["slow", "dangerous"]).docs(
+        "server",
+        "1.0.0",
+        "O(1)",
+        "Returns information and statistics about the server.",
+    ),
+    command("lastsave", 1, &["loading", "stale", "fast"], NO_KEYS, Read, &["admin", "fast", "dangerous"])
+        .docs(
+            "server",
+            "1.0.0",
+            "O(1)",
+            "Returns the Unix timestamp of the last successful save to disk.",
+        ),
+    command("lmove", 5, &["write", "denyoom"], (1, 2, 1), ReadWrite, &["write", "list", "slow"]).docs(
+        "list",
+        "6.2.0",
+        "O(1)",
+        "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved.",
+    ),
+    command("lmpop", -4, &["write", "movablekeys"], NO_KEYS, ReadWrite, &["write", "list", "slow"]).docs(
+        "list",
+        "7.0.0",
+        "O(N+M) where N is the number of provided keys and M is the number of elements returned.",
+        "Returns multiple elements from a list after removing them. Deletes the list if the last element was popped.",
+    ),
+    command("lpop", -2, &["write", "fast"], ONE_KEY, ReadWrite, &["write", "list", "fast"]).docs(
+        "list",
+        "1.0.0",
+        "O(N) where N is the number of elements returned",
+        "Returns the first elements in a list after removing it. Deletes the list if the last element was popped.",
+    ),
+    command("lpush", -3, &["write", "denyoom", "fast"], ONE_KEY, Write, &["write", "list", "fast"]).docs(
+        "list",
+        "1.0.0",
+        "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.",
+        "Prepends one or more elements to a list. Creates the key if it doesn't exist.",
+    ),
+    command("migrate", -6, &["write"], (3, 3, 1), ReadWrite, &["keyspace", "write", "slow", "dangerous"])
+        .docs(
+            "generic",
+            "2.6.0",
+            "This command actually executes a DUMP+DEL in the source instance, and a RESTORE in the target instance. See the pages of these commands for time complexity. Also an O(N) data transfer between the two instances is performed.",
+            "Atomically transfers a key from one Redis instance to another.",
+        ),
+    command("multi", 1, TRANSACTION_FLAGS, NO_KEYS, Read, FAST_TRANSACTION).docs(
+        "transactions",
+        "1.2.0",
+        "O(1)",
+        "Starts a transaction.",
+    ),
+    command("ping", -1, &["fast"], NO_KEYS, Read, &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Returns the server's liveliness response.",
+    ),
+    command("psubscribe", -2, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.0.0",
+        "O(N) where N is the number of patterns to subscribe to.",
+        "Listens for messages published to channels that match one or more patterns.",
+    ),
+    command("publish", 3, &["pubsub", "loading", "stale", "fast", "may_replicate"], NO_KEYS, Read, &["pubsub", "fast"])
+        .docs(
+            "pubsub",
+            "2.0.0",
+            "O(N+M) where N is the number of clients subscribed to the receiving channel and M is the total number of subscribed patterns (by any client).",
+            "Posts a message to a channel.",
+        ),
+    command("pubsub", -2, &[], NO_KEYS, Read, &["slow"])
+        .with_subcommands(PUBSUB_SUBCOMMANDS)
+        .docs(
+            "pubsub",
+            "2.8.0",
+            "Depends on subcommand.",
+            "A container for Pub/Sub commands.",
+        ),
+    command("punsubscribe", -1, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.0.0",
+        "O(N) where N is the number of patterns to unsubscribe.",
+        "Stops listening to messages published to channels that match one or more patterns.",
+    ),
+    command("quit", -1, CONNECTION_FLAGS, NO_KEYS, Read, &["fast", "connection"]).docs(
+        "connection",
+        "1.0.0",
+        "O(1)",
+        "Closes the connection.",
+    ),
+    command("restore", -4, &["write", "denyoom"], ONE_KEY, Write, &["keyspace", "write", "slow", "dangerous"])
+        .docs(
+            "generic",
+            "2.6.0",
+            "O(1) to create the new key and additional O(N*M) to reconstruct the serialized value, where N is the number of Redis objects composing the value and M their average size.",
+            "Creates a key from the serialized representation of a value.",
+        ),
+    command("rpop", -2, &["write", "fast"], ONE_KEY, ReadWrite, &["write", "list", "fast"]).docs(
+        "list",
+        "1.0.0",
+        "O(N) where N is the number of elements returned",
+        "Returns and removes the last elements of the list. Deletes the list if the last element was popped.",
+    ),
+    command("rpush", -3, &["write", "denyoom", "fast"], ONE_KEY, Write, &["write", "list", "fast"]).docs(
+        "list",
+        "1.0.0",
+        "O(1) for each element added, so O(N) to add N elements when the command is called with multiple arguments.",
+        "Appends one or more elements to a list. Creates the key if it doesn't exist.",
+    ),
+    command("save", 1, &["admin", "noscript", "no_async_loading", "no_multi"], NO_KEYS, Read, ADMIN)
+        .docs(
+            "server",
+            "1.0.0",
+            "O(N) where N is the total number of keys in all databases",
+            "Synchronously saves the database(s) to disk.",
+        ),
+    command("set", -3, &["write", "denyoom"], ONE_KEY, Write, &["write", "string", "slow"]).docs(
+        "string",
+        "1.0.0",
+        "O(1)",
+        "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+    ),
+    command("spublish", 3, &["pubsub", "loading", "stale", "fast", "may_replicate"], NO_KEYS, Read, &["pubsub", "fast"])
+        .docs(
+            "pubsub",
+            "7.0.0",
+            "O(N) where N is the number of clients subscribed to the receiving shard channel.",
+            "Post a message to a shard channel",
+        ),
+    command("ssubscribe", -2, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "7.0.0",
+        "O(N) where N is the number of shard channels to subscribe to.",
+        "Listens for messages published to shard channels.",
+    ),
+    command("subscribe", -2, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.0.0",
+        "O(N) where N is the number of channels to subscribe to.",
+        "Listens for messages published to channels.",
+    ),
+    command("sunsubscribe", -1, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "7.0.0",
+        "O(N) where N is the number of shard channels to unsubscribe.",
+        "Stops listening to messages posted to shard channels.",
+    ),
+    command("unsubscribe", -1, SUBSCRIBE_FLAGS, NO_KEYS, Read, SLOW_PUBSUB).docs(
+        "pubsub",
+        "2.0.0",
+        "O(N) where N is the number of channels to unsubscribe.",
+        "Stops listening to messages posted to channels.",
+    ),
+    command("unwatch", 1, TRANSACTION_FLAGS, NO_KEYS, Read, FAST_TRANSACTION).docs(
+        "transactions",
+        "2.2.0",
+        "O(1)",
+        "Forgets about watched keys of a transaction.",
+    ),
+    command("watch", -2, &["noscript", "loading", "stale", "fast", "no_multi", "allow_busy"], ALL_KEYS, Read, FAST_TRANSACTION)
+        .docs(
+            "transactions",
+            "2.2.0",
+            "O(1) for every key.",
+            "Monitors changes to keys to determine the execution of a transaction.",
+        ),
+    command("xack", -4, &["write", "fast"], ONE_KEY, Write, &["write", "stream", "fast"]).docs(
+        "stream",
+        "5.0.0",
+        "O(1) for each message ID processed.",
+        "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+    ),
+    command("xadd", -5, &["write", "denyoom", "fast"], ONE_KEY, Write, &["write", "stream", "fast"]).docs(
+        "stream",
+        "5.0.0",
+        "O(1) when adding a new entry, O(N) when trimming where N being the number of entries evicted.",
+        "Appends a new message to a stream. Creates the key if it doesn't exist.",
+    ),
+    command("xgroup", -2, &[], NO_KEYS, Write, &["slow"])
+        .with_subcommands(XGROUP_SUBCOMMANDS)
+        .docs(
+            "stream",
+            "5.0.0",
+            "Depends on subcommand.",
+            "A container for consumer groups commands.",
+        ),
+    command("xlen", 2, &["readonly", "fast"], ONE_KEY, Read, &["read", "stream", "fast"]).docs(
+        "stream",
+        "5.0.0",
+        "O(1)",
+        "Return the number of messages in a stream.",
+    ),
+    command("xrange", -4, &["readonly"], ONE_KEY, Read, &["read", "stream", "slow"]).docs(
+        "stream",
+        "5.0.0",
+        "O(N) with N being the number of elements being returned. If N is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1).",
+        "Returns the messages from a stream within a range of IDs.",
+    ),
+    command("xread", -4, &["readonly", "blocking", "movablekeys"], NO_KEYS, Read, &["read", "stream", "slow", "blocking"])
+        .docs(
+            "stream",
+            "5.0.0",
+            "For each stream mentioned: O(N) with N being the number of elements being returned, it means that XREAD-ing with a fixed COUNT is O(1). Note that when the BLOCK option is used, XADD will pay O(M) time in order to serve the M clients blocked on the stream getting new data.",
+            "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.",
+        ),
+    command("xreadgroup", -7, &["write", "blocking", "movablekeys"], NO_KEYS, ReadWrite, &["write", "stream", "slow", "blocking"])
+        .docs(
+            "stream",
+            "5.0.0",
+            "For each stream mentioned: O(M) with M being the number of elements returned. If M is constant (e.g. always asking for the first 10 elements with COUNT), you can consider it O(1). On the other side when XREADGROUP blocks, XADD will pay the O(N) time in order to serve the N clients blocked on the stream getting new data.",
+            "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.",
+        ),
+    command("zadd", -4, &["write", "denyoom", "fast"], ONE_KEY, Write, &["write", "sortedset", "fast"]).docs(
+        "sorted_set",
+        "1.2.0",
+        "O(log(N)) for each item added, where N is the number of elements in the sorted set.",
+        "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist.",
+    ),
+    command("zmpop", -4, &["write", "movablekeys"], NO_KEYS, ReadWrite, &["write", "sortedset", "slow"]).docs(
+        "sorted_set",
+        "7.0.0",
+        "O(K) + O(M*log(N)) where K is the number of provided keys, N being the number of elements in the sorted set, and M being the number of elements popped.",
+        "Returns the highest- or lowest-scoring members from one or more sorted sets after removing them. Deletes the sorted set if the last member was popped.",
+    ),
+    command("zpopmax", -2, &["write", "fast"], ONE_KEY, ReadWrite, &["write", "sortedset", "fast"]).docs(
+        "sorted_set",
+        "5.0.0",
+        "O(log(N)*M) with N being the number of elements in the sorted set, and M being the number of elements popped.",
+        "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    ),
+    command("zpopmin", -2, &["write", "fast"], ONE_KEY, ReadWrite, &["write", "sortedset", "fast"]).docs(
+        "sorted_set",
+        "5.0.0",
+        "O(log(N)*M) with N being the number of elements in the sorted set, and M being the number of elements popped.",
+        "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    ),
+];
+
+/// The command a client-sent name stands for, ignoring case.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+/// The error for calling the command `name` (already resolved from any
+/// rename), or one of its subcommands, with the wrong number of arguments.
+/// `args` start with the name as sent. Unknown commands and subcommands are
+/// left to report themselves.
+pub fn arity_error(name: &str, args: &[Bytes]) -> Option<RespType> {
+    let spec = lookup(name)?;
+    if !spec.arity_matches(args.len()) {
+        return Some(RespType::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            spec.name
+        )));
+    }
+    let sub = spec.subcommand(args)?;
+    if !sub.arity_matches(args.len()) {
+        return Some(RespType::Error(format!(
+            "ERR wrong number of arguments for '{}|{}' command",
+            spec.name, sub.name
+        )));
+    }
+    None
+}
+
+impl CommandSpec {
+    /// Whether `argc` arguments, the name included, suit the arity.
+    pub fn arity_matches(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    /// The subcommand named by the second argument, for container commands
+    /// like CONFIG and ACL.
+    pub fn subcommand(&self, args: &[Bytes]) -> Option<&'static CommandSpec> {
+        let name = String::from_utf8_lossy(args.get(1)?);
+        self.subcommands
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(&name))
+    }
+
+    /// Positions of the keys in `args`, which start with the command name.
+    pub fn key_positions(&self, args: &[Bytes]) -> Vec<usize> {
+        match self.name {
+            "migrate" => return migrate_key_positions(args),
+            "lmpop" | "zmpop" => return numkeys_key_positions(args, 1),
+            "blmpop" | "bzmpop" => return numkeys_key_positions(args, 2),
+            "xread" | "xreadgroup" => return streams_key_positions(args),
+            // every subcommand but HELP names the key after its own name
+            "xgroup" => return (args.len() > 2).then_some(2).into_iter().collect(),
+            _ => {}
+        }
+        if self.first_key <= 0 || self.first_key as usize >= args.len() {
+            return vec![];
+        }
+        let last = if self.last_key < 0 {
+            args.len() as i64 + self.last_key
+        } else {
+            self.last_key.min(args.len() as i64 - 1)
+        };
+        (self.first_key..=last)
+            .step_by(self.step.max(1) as usize)
+            .map(|i| i as usize)
+            .collect()
+    }
+
+    // the COMMAND INFO entry; `name` is "parent|name" for a subcommand
+    fn info(&self, name: &str) -> RespType {
+        let simple = |s: &str| RespType::SimpleString(s.to_string());
+        RespType::Array(Some(vec![
+            bulk(name),
+            RespType::Integer(self.arity),
+            RespType::Array(Some(self.flags.iter().map(|f| simple(f)).collect())),
+            RespType::Integer(self.first_key),
+            RespType::Integer(self.last_key),
+            RespType::Integer(self.step),
+            RespType::Array(Some(
+                self.categories
+                    .iter()
+                    .map(|c| simple(&format!("@{}", c)))
+                    .collect(),
+            )),
+            // tips and key specifications
+            RespType::Array(Some(vec![])),
+            RespType::Array(Some(vec![])),
+            RespType::Array(Some(
+                self.subcommands
+                    .iter()
+                    .map(|sub| sub.info(&format!("{}|{}", name, sub.name)))
+                    .collect(),
+            )),
+        ]))
+    }
+
+    // the COMMAND DOCS map, flattened as in RESP2
+    fn docs_reply(&self, name: &str) -> RespType {
+        let mut docs = vec![
+            bulk("summary"),
+            bulk(self.summary),
+            bulk("since"),
+            bulk(self.since),
+            bulk("group"),
+            bulk(self.group),
+            bulk("complexity"),
+            bulk(self.complexity),
+        ];
+        if !self.subcommands.is_empty() {
+            docs.push(bulk("subcommands"));
+            docs.push(RespType::Array(Some(
+                self.subcommands
+                    .iter()
+                    .flat_map(|sub| {
+                        let name = format!("{}|{}", name, sub.name);
+                        [bulk(&name), sub.docs_reply(&name)]
+                    })
+                    .collect(),
+            )));
+        }
+        RespType::Array(Some(docs))
+    }
+}
+
+// MIGRATE host port key|"" db timeout [COPY] [REPLACE] [AUTH pw]
+// [AUTH2 user pw] [KEYS key...]: the keys follow KEYS when the key is ""
+fn migrate_key_positions(args: &[Bytes]) -> Vec<usize> {
+    if args.len() < 6 {
+        return vec![];
+    }
+    if !args[3].is_empty() {
+        return vec![3];
+    }
+    let mut i = 6;
+    while i < args.len() {
+        match String::from_utf8_lossy(&args[i]).to_lowercase().as_str() {
+            "keys" => return (i + 1..args.len()).collect(),
+            "auth" => i += 2,
+            "auth2" => i += 3,
+            _ => i += 1,
+        }
+    }
+    vec![]
+}
+
+// LMPOP numkeys key... and friends, with numkeys at `at`
+fn numkeys_key_positions(args: &[Bytes], at: usize) -> Vec<usize> {
+    let numkeys = args
+        .get(at)
+        .and_then(|n| String::from_utf8_lossy(n).parse::<usize>().ok())
+        .unwrap_or(0);
+    (at + 1..args.len()).take(numkeys).collect()
+}
+
+// XREAD and XREADGROUP: the first half of what follows STREAMS, the other
+// half being their IDs
+fn streams_key_positions(args: &[Bytes]) -> Vec<usize> {
+    let mut i = 1;
+    while i < args.len() {
+        match String::from_utf8_lossy(&args[i]).to_lowercase().as_str() {
+            "streams" => {
+                let keys = (args.len() - i - 1) / 2;
+                return (i + 1..=i + keys).collect();
+            }
+            "count" | "block" => i += 2,
+            "group" => i += 3,
+            _ => i += 1,
+        }
+    }
+    vec![]
+}
+
+// every command and subcommand, by its full name
+fn all_commands() -> Vec<(String, &'static CommandSpec)> {
+    let mut all = vec![];
+    for spec in COMMANDS {
+        all.push((spec.name.to_string(), spec));
+        for sub in spec.subcommands {
+            all.push((format!("{}|{}", spec.name, sub.name), sub));
+        }
+    }
+    all
+}
+
+/// COMMAND and its subcommands; `args` starts with COMMAND.
+pub fn command_command(args: &[Bytes]) -> RespType {
+    let text = |i: usize| String::from_utf8_lossy(&args[i]).to_string();
+    let names = || (2..args.len()).map(text);
+    let all_info = || RespType::Array(Some(COMMANDS.iter().map(|c| c.info(c.name)).collect()));
+    let Some(sub) = args.get(1).map(|_| text(1).to_lowercase()) else {
+        return all_info();
+    };
+    match sub.as_str() {
+        "count" => RespType::Integer(COMMANDS.len() as i64),
+        "info" if args.len() == 2 => all_info(),
+        "info" => RespType::Array(Some(
+            names()
+                .map(|name| match lookup(&name) {
+                    Some(spec) => spec.info(spec.name),
+                    None => RespType::Array(None),
+                })
+                .collect(),
+        )),
+        "docs" => {
+            let specs: Vec<&CommandSpec> = if args.len() == 2 {
+                COMMANDS.iter().collect()
+            } else {
+                names().filter_map(|name| lookup(&name)).collect()
+            };
+            RespType::Array(Some(
+                specs
+                    .iter()
+                    .flat_map(|spec| [bulk(spec.name), spec.docs_reply(spec.name)])
+                    .collect(),
+            ))
+        }
+        "list" => command_list(&names().collect::<Vec<_>>()),
+        "getkeys" => command_getkeys(&args[2..]),
+        "help" => RespType::Array(Some(
+            [
+                "COMMAND <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "(no subcommand)",
+                "    Return details about all Redis commands.",
+                "COUNT",
+                "    Return the total number of commands in this Redis server.",
+                "LIST [FILTERBY (MODULE <module-name>|ACLCAT <category>|PATTERN <pattern>)]",
+                "    Return a list of all commands in this Redis server.",
+                "INFO [<command-name> ...]",
+                "    Return details about multiple Redis commands.",
+                "    If no command names are given, documentation details for all",
+                "    commands are returned.",
+                "DOCS [<command-name> ...]",
+                "    Return documentation details about multiple Redis commands.",
+                "    If no command names are given, documentation details for all",
+                "    commands are returned.",
+                "GETKEYS <full-command>",
+                "    Return the keys from a full Redis command.",
+            ]
+            .iter()
+            .map(|line| RespType::SimpleString(line.to_string()))
+            .collect(),
+        )),
+        _ => RespType::Error(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            text(1)
+        )),
+    }
+}
+
+// COMMAND LIST [FILTERBY MODULE name | ACLCAT category | PATTERN pattern]
+fn command_list(args: &[String]) -> RespType {
+    let all = all_commands();
+    let names: Vec<&String> = match args {
+        [] => all.iter().map(|(name, _)| name).collect(),
+        [filterby, kind, value] if filterby.eq_ignore_ascii_case("filterby") => {
+            let category = value.to_lowercase();
+            match kind.to_lowercase().as_str() {
+                // there are no modules
+                "module" => vec![],
+                "aclcat" => all
+                    .iter()
+                    .filter(|(_, spec)| spec.categories.contains(&category.as_str()))
+                    .map(|(name, _)| name)
+                    .collect(),
+                "pattern" => all
+                    .iter()
+                    .filter(|(name, _)| glob_match(value.as_bytes(), name.as_bytes(), true))
+                    .map(|(name, _)| name)
+                    .collect(),
+                _ => return RespType::Error("ERR syntax error".to_string()),
+            }
+        }
+        _ => return RespType::Error("ERR syntax error".to_string()),
+    };
+    RespType::Array(Some(names.into_iter().map(bulk).collect()))
+}
+
+fn command_getkeys(command: &[Bytes]) -> RespType {
+    let Some(spec) = lookup(&String::from_utf8_lossy(&command[0])) else {
+        return RespType::Error("ERR Invalid command specified".to_string());
+    };
+    if !spec.arity_matches(command.len()) {
+        return RespType::Error(
+            "ERR Invalid number of arguments specified for command".to_string(),
+        );
+    }
+    let keys = spec.key_positions(command);
+    if keys.is_empty() {
+        return RespType::Error("ERR The command has no key arguments".to_string());
+    }
+    RespType::Array(Some(
+        keys.into_iter()
+            .map(|i| RespType::BulkString(Some(command[i].clone())))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::test_helpers::args;
+
+    #[test]
+    fn test_key_positions() {
+        let keys = |line: &str| {
+            let args = args(line);
+            lookup(&String::from_utf8_lossy(&args[0]))
+                .unwrap()
+                .key_positions(&args)
+        };
+        assert_eq!(keys("GET a"), vec![1]);
+        assert_eq!(keys("SET a 1 EX 10"), vec![1]);
+        assert_eq!(keys("DEL a b c"), vec![1, 2, 3]);
+        assert_eq!(keys("HSET h f v"), vec![1]);
+        assert_eq!(keys("PING"), Vec::<usize>::new());
+        assert_eq!(keys("MIGRATE h 6379 a 0 100"), vec![3]);
+        assert_eq!(
+            keys("MIGRATE h 6379  0 100 AUTH keys REPLACE KEYS a b"),
+            vec![10, 11]
+        );
+        assert_eq!(keys("BLPOP a b 0"), vec![1, 2]);
+        assert_eq!(keys("LMPOP 2 a b LEFT COUNT 2"), vec![2, 3]);
+        assert_eq!(keys("BZMPOP 0.5 1 a MIN"), vec![3]);
+        assert_eq!(keys("XREAD COUNT 2 STREAMS a b 0 $"), vec![4, 5]);
+        assert_eq!(
+            keys("XREADGROUP GROUP streams c NOACK STREAMS s >"),
+            vec![6]
+        );
+        assert_eq!(keys("XGROUP CREATE s g $"), vec![2]);
+        assert_eq!(keys("XGROUP HELP"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("GET").unwrap().name, "get");
+        assert!(lookup("nosuch").is_none());
+        let config = lookup("config").unwrap();
+        assert_eq!(
+            config.subcommand(&args("CONFIG GET x")).unwrap().name,
+            "get"
+        );
+        assert!(config.subcommand(&args("CONFIG NOSUCH")).is_none());
+        for (name, spec) in all_commands() {
+            assert!(spec.categories.iter().all(|c| CATEGORIES.contains(c)));
+            assert!(!spec.summary.is_empty(), "{} has no docs", name);
+        }
+    }
+
+    #[test]
+    fn test_arity_error() {
+        let error = |line: &str| {
+            let args = args(line);
+            arity_error(&String::from_utf8_lossy(&args[0]).to_lowercase(), &args)
+        };
+        let wrong = |name: &str| {
+            Some(RespType::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            )))
+        };
+        assert_eq!(error("GET a"), None);
+        assert_eq!(error("GET a b"), wrong("get"));
+        assert_eq!(error("SET a"), wrong("set"));
+        assert_eq!(error("CONFIG"), wrong("config"));
+        assert_eq!(error("CONFIG GET"), wrong("config|get"));
+        assert_eq!(error("CONFIG NOSUCH"), None);
+        assert_eq!(error("NOSUCH"), None);
+    }
+
+    #[test]
+    fn test_command_command() {
+        let run = |line: &str| command_command(&args(line));
+        assert_eq!(
+            run("COMMAND COUNT"),
+            RespType::Integer(COMMANDS.len() as i64)
+        );
+        let RespType::Array(Some(info)) = run("COMMAND INFO get nosuch") else {
+            panic!("expected the entries");
+        };
+        let RespType::Array(Some(get)) = &info[0] else {
+            panic!("expected the GET entry");
+        };
+        assert_eq!(get[0], bulk("get"));
+        assert_eq!(get[1], RespType::Integer(2));
+        assert_eq!(
+            get[3..6],
+            [
+                RespType::Integer(1),
+                RespType::Integer(1),
+                RespType::Integer(1)
+            ]
+        );
+        assert_eq!(info[1], RespType::Array(None));
+        let RespType::Array(Some(docs)) = run("COMMAND DOCS get") else {
+            panic!("expected the docs");
+        };
+        assert_eq!(docs[0], bulk("get"));
+        assert_eq!(
+            run("COMMAND LIST FILTERBY PATTERN config|*"),
+            RespType::Array(Some(
+                [
+                    "config|get",
+                    "config|help",
+                    "config|resetstat",
+                    "config|rewrite",
+                    "config|set"
+                ]
+                .iter()
+                .map(bulk)
+                .collect()
+            ))
+        );
+        assert!(matches!(
+            run("COMMAND LIST FILTERBY ACLCAT hash"),
+            RespType::Array(Some(names)) if names.len() == 4
+        ));
+        assert_eq!(
+            run("COMMAND GETKEYS DEL a b"),
+            RespType::Array(Some(vec![bulk("a"), bulk("b")]))
+        );
+        assert_eq!(
+            run("COMMAND GETKEYS PING"),
+            RespType::Error("ERR The command has no key arguments".to_string())
+        );
+        assert_eq!(
+            run("COMMAND GETKEYS GET"),
+            RespType::Error("ERR Invalid number of arguments specified for command".to_string())
+        );
+    }
+}
@@ -1,74 +1,544 @@
+use std::fmt;
+
 use super::{
+    aof, blocking, command_table,
     datastore::{self},
-    errors::UserInputError,
+    errors::{DataStoreError, RdbError, UserInputError},
+    migrate, persistence, rdb, redisconfig,
     resp_value::RespType,
+    search,
+    stats::Stats,
+    stream,
 };
 use bytes::Bytes;
-use once_cell::sync::Lazy;
-use redis::{Client, Connection};
+use chrono::Utc;
 
 pub enum RedisCommand {
     Ping,
     Echo(String),
     Get(String),
-    Set(String, String, Vec<String>), // key, value, options
+    Set(String, Bytes, Vec<String>), // key, value, options
+    Del(Vec<String>),
+    HSet(String, Vec<Bytes>), // key, field value pairs
+    HGet(String, String),
+    HGetAll(String),
+    HDel(String, Vec<String>),
+    LPush(String, Vec<Bytes>), // key, values
+    RPush(String, Vec<Bytes>),
+    LPop(String, Option<String>), // key, count
+    RPop(String, Option<String>),
+    LMove(Vec<String>),
+    LMPop(Vec<Bytes>),
+    ZAdd(String, Vec<Bytes>),        // key, score member pairs
+    ZPopMin(String, Option<String>), // key, count
+    ZPopMax(String, Option<String>),
+    ZMPop(Vec<Bytes>),
+    XAdd(Vec<Bytes>),
+    XLen(String),
+    XRange(Vec<Bytes>),
+    XRead(Vec<Bytes>),
+    XReadGroup(Vec<Bytes>),
+    XAck(Vec<Bytes>),
+    XGroup(Vec<Bytes>),
+    FtCreate(Vec<String>),
+    FtSearch(Vec<Bytes>),
+    FtInfo(Vec<String>),
+    FtDropIndex(Vec<String>),
+    FtList,
+    Save,
+    BgSave(Vec<String>),
+    LastSave,
+    Info(Vec<String>),
+    BgRewriteAof,
+    Dump(String),
+    Restore(String, String, Bytes, Vec<String>), // key, ttl, payload, options
+    Migrate(Vec<String>),
     Unknown(String),
     Config(Vec<String>),
+    Command(Vec<Bytes>),
+}
+
+fn lossy(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).to_string()
+}
+
+fn args_from(cmd: &[Bytes], start: usize) -> Vec<String> {
+    cmd.get(start..)
+        .unwrap_or_default()
+        .iter()
+        .map(|x| lossy(x))
+        .collect()
+}
+
+fn raw_args_from(cmd: &[Bytes], start: usize) -> Vec<Bytes> {
+    cmd.get(start..).unwrap_or_default().to_vec()
 }
 
 impl RedisCommand {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(cmd: Vec<&str>) -> Self {
-        match cmd.get(0).unwrap().to_lowercase().as_str() {
+        let cmd: Vec<Bytes> = cmd
+            .iter()
+            .map(|x| Bytes::copy_from_slice(x.as_bytes()))
+            .collect();
+        Self::from_args(&cmd)
+    }
+
+    /// Builds a command from raw arguments. Keys and options are read as text,
+    /// while values stay binary-safe.
+    pub fn from_args(cmd: &[Bytes]) -> Self {
+        let arg = |i: usize| cmd.get(i).map(|x| lossy(x)).unwrap_or_default();
+        let raw_arg = |i: usize| cmd.get(i).cloned().unwrap_or_default();
+        match arg(0).to_lowercase().as_str() {
             "ping" => RedisCommand::Ping,
-            "echo" => RedisCommand::Echo(cmd.get(1).unwrap_or(&"").to_string()),
-            "get" => RedisCommand::Get(cmd.get(1).unwrap_or(&"").to_string()),
-            "set" => RedisCommand::Set(
-                cmd.get(1).unwrap_or(&"").to_string(),
-                cmd.get(2).unwrap_or(&"").to_string(),
-                cmd[3..].to_vec().iter().map(|x| x.to_string()).collect(),
+            "echo" => RedisCommand::Echo(arg(1)),
+            "get" => RedisCommand::Get(arg(1)),
+            "set" => RedisCommand::Set(arg(1), raw_arg(2), args_from(cmd, 3)),
+            "del" => RedisCommand::Del(args_from(cmd, 1)),
+            "hset" => RedisCommand::HSet(arg(1), raw_args_from(cmd, 2)),
+            "hget" => RedisCommand::HGet(arg(1), arg(2)),
+            "hgetall" => RedisCommand::HGetAll(arg(1)),
+            "hdel" => RedisCommand::HDel(arg(1), args_from(cmd, 2)),
+            "lpush" => RedisCommand::LPush(arg(1), raw_args_from(cmd, 2)),
+            "rpush" => RedisCommand::RPush(arg(1), raw_args_from(cmd, 2)),
+            "lpop" => RedisCommand::LPop(arg(1), cmd.get(2).map(|c| lossy(c))),
+            "rpop" => RedisCommand::RPop(arg(1), cmd.get(2).map(|c| lossy(c))),
+            "lmove" => RedisCommand::LMove(args_from(cmd, 1)),
+            "lmpop" => RedisCommand::LMPop(raw_args_from(cmd, 1)),
+            "zadd" => RedisCommand::ZAdd(arg(1), raw_args_from(cmd, 2)),
+            "zpopmin" => RedisCommand::ZPopMin(arg(1), cmd.get(2).map(|c| lossy(c))),
+            "zpopmax" => RedisCommand::ZPopMax(arg(1), cmd.get(2).map(|c| lossy(c))),
+            "zmpop" => RedisCommand::ZMPop(raw_args_from(cmd, 1)),
+            "xadd" => RedisCommand::XAdd(raw_args_from(cmd, 1)),
+            "xlen" => RedisCommand::XLen(arg(1)),
+            "xrange" => RedisCommand::XRange(raw_args_from(cmd, 1)),
+            "xread" => RedisCommand::XRead(raw_args_from(cmd, 1)),
+            "xreadgroup" => RedisCommand::XReadGroup(raw_args_from(cmd, 1)),
+            "xack" => RedisCommand::XAck(raw_args_from(cmd, 1)),
+            "xgroup" => RedisCommand::XGroup(raw_args_from(cmd, 1)),
+            "ft.create" => RedisCommand::FtCreate(args_from(cmd, 1)),
+            "ft.search" => RedisCommand::FtSearch(raw_args_from(cmd, 1)),
+            "ft.info" => RedisCommand::FtInfo(args_from(cmd, 1)),
+            "ft.dropindex" => RedisCommand::FtDropIndex(args_from(cmd, 1)),
+            "ft._list" => RedisCommand::FtList,
+            "save" => RedisCommand::Save,
+            "bgsave" => RedisCommand::BgSave(args_from(cmd, 1)),
+            "lastsave" => RedisCommand::LastSave,
+            "info" => RedisCommand::Info(args_from(cmd, 1)),
+            "bgrewriteaof" => RedisCommand::BgRewriteAof,
+            "dump" => RedisCommand::Dump(arg(1)),
+            "restore" => RedisCommand::Restore(arg(1), arg(2), raw_arg(3), args_from(cmd, 4)),
+            "migrate" => RedisCommand::Migrate(args_from(cmd, 1)),
+            "config" => RedisCommand::Config(args_from(cmd, 1)),
+            "command" => RedisCommand::Command(raw_args_from(cmd, 1)),
+            _ => RedisCommand::Unknown(args_from(cmd, 0).join(" ")),
+        }
+    }
+}
+
+/// Whether a command can change the dataset and so gets logged to the AOF,
+/// going by the `write` flag in the command table. MIGRATE logs its local
+/// deletes itself, so the AOF is not locked over its network I/O.
+pub fn is_write(name: &str) -> bool {
+    !name.eq_ignore_ascii_case("migrate")
+        && command_table::lookup(name).is_some_and(|spec| spec.flags.contains(&"write"))
+}
+
+impl fmt::Display for RedisCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let texts = |args: &[String]| args.to_vec();
+        let raws = |args: &[Bytes]| args.iter().map(|a| lossy(a)).collect::<Vec<_>>();
+        let keyed =
+            |key: &String, rest: Vec<String>| [key.clone()].into_iter().chain(rest).collect();
+        let (name, args): (&str, Vec<String>) = match self {
+            RedisCommand::Ping => ("PING", vec![]),
+            RedisCommand::Echo(s) => ("ECHO", vec![s.clone()]),
+            RedisCommand::Get(key) => ("GET", vec![key.clone()]),
+            RedisCommand::Set(key, value, options) => (
+                "SET",
+                [key.clone(), lossy(value)]
+                    .into_iter()
+                    .chain(texts(options))
+                    .collect(),
             ),
-            "config" => {
-                RedisCommand::Config(cmd[1..].to_vec().iter().map(|x| x.to_string()).collect())
+            RedisCommand::Del(keys) => ("DEL", texts(keys)),
+            RedisCommand::HSet(key, pairs) => (
+                "HSET",
+                [key.clone()].into_iter().chain(raws(pairs)).collect(),
+            ),
+            RedisCommand::HGet(key, field) => ("HGET", vec![key.clone(), field.clone()]),
+            RedisCommand::HGetAll(key) => ("HGETALL", vec![key.clone()]),
+            RedisCommand::HDel(key, fields) => (
+                "HDEL",
+                [key.clone()].into_iter().chain(texts(fields)).collect(),
+            ),
+            RedisCommand::LPush(key, values) => ("LPUSH", keyed(key, raws(values))),
+            RedisCommand::RPush(key, values) => ("RPUSH", keyed(key, raws(values))),
+            RedisCommand::LPop(key, count) => ("LPOP", keyed(key, count.iter().cloned().collect())),
+            RedisCommand::RPop(key, count) => ("RPOP", keyed(key, count.iter().cloned().collect())),
+            RedisCommand::LMove(args) => ("LMOVE", texts(args)),
+            RedisCommand::LMPop(args) => ("LMPOP", raws(args)),
+            RedisCommand::ZAdd(key, pairs) => ("ZADD", keyed(key, raws(pairs))),
+            RedisCommand::ZPopMin(key, count) => {
+                ("ZPOPMIN", keyed(key, count.iter().cloned().collect()))
             }
-            _ => RedisCommand::Unknown(cmd.join(" ")),
+            RedisCommand::ZPopMax(key, count) => {
+                ("ZPOPMAX", keyed(key, count.iter().cloned().collect()))
+            }
+            RedisCommand::ZMPop(args) => ("ZMPOP", raws(args)),
+            RedisCommand::XAdd(args) => ("XADD", raws(args)),
+            RedisCommand::XLen(key) => ("XLEN", vec![key.clone()]),
+            RedisCommand::XRange(args) => ("XRANGE", raws(args)),
+            RedisCommand::XRead(args) => ("XREAD", raws(args)),
+            RedisCommand::XReadGroup(args) => ("XREADGROUP", raws(args)),
+            RedisCommand::XAck(args) => ("XACK", raws(args)),
+            RedisCommand::XGroup(args) => ("XGROUP", raws(args)),
+            RedisCommand::FtCreate(args) => ("FT.CREATE", texts(args)),
+            RedisCommand::FtSearch(args) => ("FT.SEARCH", raws(args)),
+            RedisCommand::FtInfo(args) => ("FT.INFO", texts(args)),
+            RedisCommand::FtDropIndex(args) => ("FT.DROPINDEX", texts(args)),
+            RedisCommand::FtList => ("FT._LIST", vec![]),
+            RedisCommand::Save => ("SAVE", vec![]),
+            RedisCommand::BgSave(args) => ("BGSAVE", texts(args)),
+            RedisCommand::LastSave => ("LASTSAVE", vec![]),
+            RedisCommand::Info(sections) => ("INFO", texts(sections)),
+            RedisCommand::BgRewriteAof => ("BGREWRITEAOF", vec![]),
+            RedisCommand::Dump(key) => ("DUMP", vec![key.clone()]),
+            RedisCommand::Restore(key, ttl, payload, options) => (
+                "RESTORE",
+                [key.clone(), ttl.clone(), lossy(payload)]
+                    .into_iter()
+                    .chain(texts(options))
+                    .collect(),
+            ),
+            RedisCommand::Migrate(args) => ("MIGRATE", texts(args)),
+            RedisCommand::Unknown(cmd) => return write!(f, "{}", cmd),
+            RedisCommand::Config(ops) => ("CONFIG", texts(ops)),
+            RedisCommand::Command(args) => ("COMMAND", raws(args)),
+        };
+        write!(f, "{}", name)?;
+        for arg in args {
+            write!(f, " {}", arg)?;
         }
+        Ok(())
     }
+}
+
+pub fn wrong_type() -> RespType {
+    RespType::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+}
+
+fn bulk_or_null(val: Option<Bytes>) -> RespType {
+    RespType::BulkString(val)
+}
+
+/// The form a write is logged in. Relative expiries are turned into absolute
+/// ones (PXAT, ABSTTL) so replaying the log later does not extend them, and
+/// XADD gets the ID it added, in place of `*`.
+fn aof_form(
+    command: &RedisCommand,
+    args: Vec<Bytes>,
+    db: &datastore::Db,
+    reply: &RespType,
+) -> Vec<Bytes> {
+    match command {
+        RedisCommand::Set(key, value, options)
+            if options
+                .iter()
+                .any(|o| ["ex", "px", "exat"].contains(&o.to_lowercase().as_str())) =>
+        {
+            match db.expire_at(key) {
+                Some(at) => vec![
+                    Bytes::from("SET"),
+                    Bytes::from(key.clone()),
+                    value.clone(),
+                    Bytes::from("PXAT"),
+                    Bytes::from(at.to_string()),
+                ],
+                None => args,
+            }
+        }
+        RedisCommand::Restore(key, _, payload, _) => match db.key_type(key) {
+            // the value arrived already expired and only removed the old key
+            None => vec![Bytes::from("DEL"), Bytes::from(key.clone())],
+            Some(_) => vec![
+                Bytes::from("RESTORE"),
+                Bytes::from(key.clone()),
+                Bytes::from(db.expire_at(key).unwrap_or(0).to_string()),
+                payload.clone(),
+                Bytes::from("REPLACE"),
+                Bytes::from("ABSTTL"),
+            ],
+        },
+        RedisCommand::XAdd(_) => stream::xadd_aof_form(args, reply),
+        _ => args,
+    }
+}
 
-    pub fn to_string(&self) -> String {
-        match self {
-            RedisCommand::Ping => "PING".to_string(),
-            RedisCommand::Echo(s) => format!("ECHO {}", s),
-            RedisCommand::Get(key) => format!("GET {}", key),
-            RedisCommand::Set(key, value, options) => {
-                let mut res = format!("SET {} {}", key, value);
-                for opt in options {
-                    res.push_str(&format!(" {}", opt));
+fn restore(
+    db: &datastore::Db,
+    key: &str,
+    ttl: &str,
+    payload: &[u8],
+    options: &[String],
+) -> Result<RespType, UserInputError> {
+    let error = |msg: &str| Ok(RespType::Error(format!("ERR {}", msg)));
+    let Ok(ttl) = ttl.parse::<i64>() else {
+        return error("value is not an integer or out of range");
+    };
+    if ttl < 0 {
+        return error("Invalid TTL value, must be >= 0");
+    }
+    let (mut replace, mut absttl) = (false, false);
+    // the keyspace keeps no LRU/LFU data, so IDLETIME and FREQ are only
+    // validated; like redis, at most one of them may be given
+    let (mut idle, mut freq) = (None, None);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "replace" => replace = true,
+            "absttl" => absttl = true,
+            "idletime" if freq.is_none() => {
+                match options.next().and_then(|v| v.parse::<i64>().ok()) {
+                    None => return error("value is not an integer or out of range"),
+                    Some(v) if v < 0 => return error("Invalid IDLETIME value, must be >= 0"),
+                    Some(v) => idle = Some(v),
                 }
-                res
             }
-            RedisCommand::Unknown(cmd) => cmd.to_string(),
-            RedisCommand::Config(ops) => {
-                let mut res = "CONFIG".to_string();
-                for op in ops {
-                    res.push_str(&format!(" {}", op));
+            "freq" if idle.is_none() => match options.next().and_then(|v| v.parse::<i64>().ok()) {
+                None => return error("value is not an integer or out of range"),
+                Some(v) if !(0..=255).contains(&v) => {
+                    return error("Invalid FREQ value, must be >= 0 and <= 255")
                 }
-                res
+                Some(v) => freq = Some(v),
+            },
+            _ => return error("syntax error"),
+        }
+    }
+    let value = match rdb::parse_payload(payload) {
+        Ok(value) => value,
+        Err(RdbError::ChecksumMismatch { .. } | RdbError::Unsupported { .. }) => {
+            return error("DUMP payload version or checksum are wrong")
+        }
+        Err(RdbError::Corrupt { .. }) => return error("Bad data format"),
+    };
+    let expire_at = match ttl {
+        0 => None,
+        ttl if absttl => Some(ttl),
+        ttl => Some(Utc::now().timestamp_millis().saturating_add(ttl)),
+    };
+    match db.restore_key(key, value, expire_at, replace) {
+        Ok(()) => Ok(RespType::SimpleString("OK".to_string())),
+        Err(DataStoreError::BusyKey) => Ok(RespType::Error(
+            "BUSYKEY Target key name already exists.".to_string(),
+        )),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// LPUSH and RPUSH: the length of the list after the push
+fn list_push(
+    db: &datastore::Db,
+    key: &str,
+    values: &[Bytes],
+    head: bool,
+) -> Result<RespType, UserInputError> {
+    match db.push(key, values, head) {
+        Ok(len) => Ok(RespType::Integer(len as i64)),
+        Err(DataStoreError::WrongType) => Ok(wrong_type()),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// LPOP and RPOP: one value, or with a count, an array of up to that many
+fn list_pop(
+    db: &datastore::Db,
+    key: &str,
+    count: Option<&str>,
+    head: bool,
+) -> Result<RespType, UserInputError> {
+    let Some(count) = count else {
+        return match db.pop(key, 1, head) {
+            Ok(mut values) => Ok(bulk_or_null(values.pop())),
+            Err(DataStoreError::WrongType) => Ok(wrong_type()),
+            Err(e) => Err(UserInputError::DataStoreError(e)),
+        };
+    };
+    let Ok(count) = count.parse::<usize>() else {
+        return Ok(RespType::Error(
+            "ERR value is out of range, must be positive".to_string(),
+        ));
+    };
+    match db.pop(key, count, head) {
+        Ok(values) if values.is_empty() && db.key_type(key).is_none() => Ok(RespType::Array(None)),
+        Ok(values) => Ok(RespType::Array(Some(
+            values.into_iter().map(|v| bulk_or_null(Some(v))).collect(),
+        ))),
+        Err(DataStoreError::WrongType) => Ok(wrong_type()),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// LMOVE source destination LEFT|RIGHT LEFT|RIGHT
+fn lmove(db: &datastore::Db, args: &[String]) -> Result<RespType, UserInputError> {
+    let side = |arg: &str| match arg.to_lowercase().as_str() {
+        "left" => Some(true),
+        "right" => Some(false),
+        _ => None,
+    };
+    let (Some(from_head), Some(to_head)) = (side(&args[2]), side(&args[3])) else {
+        return Ok(RespType::Error("ERR syntax error".to_string()));
+    };
+    match db.lmove(&args[0], &args[1], from_head, to_head) {
+        Ok(value) => Ok(bulk_or_null(value)),
+        Err(DataStoreError::WrongType) => Ok(wrong_type()),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// ZADD key score member [score member ...]
+fn zadd(db: &datastore::Db, key: &str, pairs: &[Bytes]) -> Result<RespType, UserInputError> {
+    if !pairs.len().is_multiple_of(2) {
+        return Ok(RespType::Error("ERR syntax error".to_string()));
+    }
+    let mut members = vec![];
+    for pair in pairs.chunks(2) {
+        match lossy(&pair[0]).parse::<f64>() {
+            Ok(score) if !score.is_nan() => members.push((score, pair[1].clone())),
+            _ => {
+                return Ok(RespType::Error(
+                    "ERR value is not a valid float".to_string(),
+                ))
+            }
+        }
+    }
+    match db.zadd(key, &members) {
+        Ok(added) => Ok(RespType::Integer(added as i64)),
+        Err(DataStoreError::WrongType) => Ok(wrong_type()),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// ZPOPMIN and ZPOPMAX: members and scores, flattened
+fn zpop(
+    db: &datastore::Db,
+    key: &str,
+    count: Option<&str>,
+    max: bool,
+) -> Result<RespType, UserInputError> {
+    let count = match count.map(|c| c.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            return Ok(RespType::Error(
+                "ERR value is out of range, must be positive".to_string(),
+            ))
+        }
+    };
+    match db.zpop(key, count, max) {
+        Ok(members) => Ok(RespType::Array(Some(
+            members
+                .into_iter()
+                .flat_map(|(member, score)| {
+                    [
+                        bulk_or_null(Some(member)),
+                        bulk_or_null(Some(Bytes::from(score.to_string()))),
+                    ]
+                })
+                .collect(),
+        ))),
+        Err(DataStoreError::WrongType) => Ok(wrong_type()),
+        Err(e) => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+/// Runs a command from a client. Writes that changed something are appended
+/// to the AOF, under its lock, so the log follows the order they were applied.
+pub fn execute<T: AsRef<[u8]>>(
+    cmd: Vec<T>,
+    db: &mut datastore::Db,
+) -> Result<RespType, UserInputError> {
+    let exec_lock = db.exec_lock();
+    // MIGRATE takes it itself, only around its local deletes, so that a
+    // transaction never waits on its network I/O
+    let migrating = cmd.first().is_some_and(|name| {
+        redisconfig::resolve_command_name(&lossy(name.as_ref())).is_some_and(|n| n == "migrate")
+    });
+    if migrating {
+        return execute_exclusive(cmd, db);
+    }
+    let _running = exec_lock.read();
+    let reply = execute_exclusive(cmd, db);
+    // a push may have made keys ready for blocked clients
+    db.blocking().serve(db);
+    reply
+}
+
+/// Like [`execute`], for a caller that already holds the exec lock
+/// exclusively, as EXEC does while it runs a transaction.
+pub fn execute_exclusive<T: AsRef<[u8]>>(
+    cmd: Vec<T>,
+    db: &mut datastore::Db,
+) -> Result<RespType, UserInputError> {
+    let mut args: Vec<Bytes> = cmd
+        .iter()
+        .map(|x| Bytes::copy_from_slice(x.as_ref()))
+        .collect();
+    if let Some(name) = args.first_mut() {
+        match redisconfig::resolve_command_name(&lossy(name)) {
+            Some(resolved) if !resolved.eq_ignore_ascii_case(&lossy(name)) => {
+                *name = Bytes::from(resolved)
+            }
+            Some(_) => {}
+            None => return Err(UserInputError::UnknownCommand(lossy(name))),
+        }
+    }
+    Stats::add(&db.stats().commands_processed, 1);
+    let command = RedisCommand::from_args(&args);
+    if !args.first().is_some_and(|name| is_write(&lossy(name))) {
+        return handle_input_cmd(args, db);
+    }
+    let aof_db = db.clone();
+    let mut aof = aof_db.aof().lock();
+    let Some(aof) = aof.as_mut() else {
+        return handle_input_cmd(args, db);
+    };
+    let dirty = db.dirty();
+    let reply = handle_input_cmd(args.clone(), db)?;
+    let changed = db.dirty() != dirty
+        || matches!(
+            command,
+            RedisCommand::FtCreate(_) | RedisCommand::FtDropIndex(_)
+        );
+    if changed && !matches!(reply, RespType::Error(_)) {
+        if let Err(e) = aof.append(&aof_form(&command, args, db, &reply)) {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Failed to write to the AOF: {}", e);
             }
+            return Ok(RespType::Error(format!(
+                "MISCONF Errors writing to the AOF file: {}",
+                e
+            )));
         }
     }
+    Ok(reply)
 }
 
-pub fn handle_input_cmd(
-    cmd: Vec<&str>,
+pub fn handle_input_cmd<T: AsRef<[u8]>>(
+    cmd: Vec<T>,
     db: &mut datastore::Db,
 ) -> Result<RespType, UserInputError> {
-    let resp_cmd = RedisCommand::from_str(cmd);
+    let cmd: Vec<Bytes> = cmd
+        .iter()
+        .map(|x| Bytes::copy_from_slice(x.as_ref()))
+        .collect();
+    let resp_cmd = RedisCommand::from_args(&cmd);
     // println!("{:?}", cmd.join(" ").replace("\r\n", "\\r\\n"));
 
     match resp_cmd {
         RedisCommand::Ping => Ok(RespType::SimpleString("PONG".to_string())),
         RedisCommand::Echo(s) => {
-            if s.len() > 0 {
+            if !s.is_empty() {
                 Ok(RespType::SimpleString(s))
             } else {
                 Err(UserInputError::InvalidInput(
@@ -77,14 +547,13 @@ pub fn handle_input_cmd(
             }
         }
         RedisCommand::Get(key) => {
-            if key.len() > 0 {
+            if !key.is_empty() {
                 // let db = db.data.lock();
                 // let res = db.get(&key.to_string()).cloned();
                 let res = db.get(&key);
                 match res {
-                    Ok(val) => Ok(RespType::BulkString(Some(Bytes::from(
-                        val.clone().into_bytes(),
-                    )))),
+                    Ok(val) => Ok(RespType::BulkString(Some(val))),
+                    Err(DataStoreError::WrongType) => Ok(wrong_type()),
                     Err(_) => Ok(RespType::Null),
                 }
             } else {
@@ -94,51 +563,175 @@ pub fn handle_input_cmd(
             }
         }
         RedisCommand::Set(key, value, options) => {
-            if key.len() > 0 && value.len() > 0 {
-                // let mut db = db.data.lock();
-                let _ = db.set(&key, &value, options);
-                Ok(RespType::SimpleString("OK".to_string()))
+            if !key.is_empty() && !value.is_empty() {
+                match db.set(&key, value, options) {
+                    Ok(()) => Ok(RespType::SimpleString("OK".to_string())),
+                    Err(DataStoreError::InvalidInput(e)) => {
+                        Ok(RespType::Error(format!("ERR {}", e)))
+                    }
+                    Err(e) => Err(UserInputError::DataStoreError(e)),
+                }
             } else {
                 Err(UserInputError::InvalidInput(
                     "No key/value provided to SET".to_string(),
                 ))
             }
         }
-        RedisCommand::Config(ops) => {
-            Ok(RespType::Error("Unimplemented".to_string()))
-            //     match ops.get(0) {
-            //         Some(action) if action.to_lowercase() == "get" => {
-            //             let key = ops.get(1).ok_or(UserInputError::InvalidInput(
-            //                 "No key provided for CONFIG GET".to_string(),
-            //             ))?;
-            //             match redisconfig::get_config(key) {
-            //                 Some(value) => {
-            //                     let key_resp = RespType::BulkString(Some(Bytes::from(key.as_bytes())));
-            //                     let val_resp = RespType::BulkString(Some(Bytes::from(value)));
-            //                     let res_resp = RespType::Array(Some(vec![key_resp, val_resp]));
-            //                     Ok(res_resp)
-            //                 }
-            //                 None => Ok(RespType::Array(None)),
-            //             }
-            //         }
-            //         Some(action) if action.to_lowercase() == "set" => {
-            //             let key = cmd.get(2).ok_or(UserInputError::InvalidInput(
-            //                 "No key provided for CONFIG SET".to_string(),
-            //             ))?;
-            //             let val = cmd.get(3).ok_or(UserInputError::InvalidInput(
-            //                 "No value provided for CONFIG SET".to_string(),
-            //             ))?;
-            //             // TODO: set config value
-            //             Ok(RespType::SimpleString("OK".to_string()))
-            //         }
-            //         None => Err(UserInputError::InvalidInput(
-            //             "No action provided for CONFIG command".to_string(),
-            //         )),
-            //         _ => Err(UserInputError::InvalidInput(format!(
-            //             "Invalid action provided for CONFIG command: {}",
-            //             cmd[1]
-            //         ))),
-            //     }
+        RedisCommand::Del(keys) => {
+            if keys.is_empty() {
+                return Err(UserInputError::InvalidInput(
+                    "No key provided to DEL".to_string(),
+                ));
+            }
+            Ok(RespType::Integer(db.del(&keys) as i64))
+        }
+        RedisCommand::HSet(key, pairs) => {
+            if key.is_empty() || pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return Err(UserInputError::InvalidInput(
+                    "HSET expects a key and field/value pairs".to_string(),
+                ));
+            }
+            let pairs: Vec<(String, Bytes)> = pairs
+                .chunks(2)
+                .map(|p| (lossy(&p[0]), p[1].clone()))
+                .collect();
+            match db.hset(&key, &pairs) {
+                Ok(added) => Ok(RespType::Integer(added as i64)),
+                Err(DataStoreError::WrongType) => Ok(wrong_type()),
+                Err(e) => Err(UserInputError::DataStoreError(e)),
+            }
+        }
+        RedisCommand::HGet(key, field) => match db.hget(&key, &field) {
+            Ok(val) => Ok(bulk_or_null(val)),
+            Err(DataStoreError::WrongType) => Ok(wrong_type()),
+            Err(e) => Err(UserInputError::DataStoreError(e)),
+        },
+        RedisCommand::HGetAll(key) => match db.hgetall(&key) {
+            Ok(hash) => {
+                let mut pairs: Vec<_> = hash.into_iter().collect();
+                pairs.sort();
+                Ok(RespType::Array(Some(
+                    pairs
+                        .into_iter()
+                        .flat_map(|(f, v)| {
+                            [bulk_or_null(Some(Bytes::from(f))), bulk_or_null(Some(v))]
+                        })
+                        .collect(),
+                )))
+            }
+            Err(DataStoreError::WrongType) => Ok(wrong_type()),
+            Err(e) => Err(UserInputError::DataStoreError(e)),
+        },
+        RedisCommand::HDel(key, fields) => {
+            if fields.is_empty() {
+                return Err(UserInputError::InvalidInput(
+                    "No field provided to HDEL".to_string(),
+                ));
+            }
+            match db.hdel(&key, &fields) {
+                Ok(removed) => Ok(RespType::Integer(removed as i64)),
+                Err(DataStoreError::WrongType) => Ok(wrong_type()),
+                Err(e) => Err(UserInputError::DataStoreError(e)),
+            }
+        }
+        RedisCommand::LPush(key, values) => list_push(db, &key, &values, true),
+        RedisCommand::RPush(key, values) => list_push(db, &key, &values, false),
+        RedisCommand::LPop(key, count) => list_pop(db, &key, count.as_deref(), true),
+        RedisCommand::RPop(key, count) => list_pop(db, &key, count.as_deref(), false),
+        RedisCommand::LMove(args) => lmove(db, &args),
+        RedisCommand::LMPop(args) => Ok(blocking::mpop(db, &args, false)),
+        RedisCommand::ZAdd(key, pairs) => zadd(db, &key, &pairs),
+        RedisCommand::ZPopMin(key, count) => zpop(db, &key, count.as_deref(), false),
+        RedisCommand::ZPopMax(key, count) => zpop(db, &key, count.as_deref(), true),
+        RedisCommand::ZMPop(args) => Ok(blocking::mpop(db, &args, true)),
+        RedisCommand::XAdd(args) => stream::xadd(db, &args),
+        RedisCommand::XLen(key) => stream::xlen(db, &key),
+        RedisCommand::XRange(args) => stream::xrange(db, &args),
+        RedisCommand::XRead(args) => stream::xread(db, &args, false),
+        RedisCommand::XReadGroup(args) => stream::xread(db, &args, true),
+        RedisCommand::XAck(args) => stream::xack(db, &args),
+        RedisCommand::XGroup(args) => stream::xgroup(db, &args),
+        RedisCommand::FtCreate(args) => search::ft_create(db, &args),
+        RedisCommand::FtSearch(args) => search::ft_search(db, &args),
+        RedisCommand::FtInfo(args) => search::ft_info(db, &args),
+        RedisCommand::FtDropIndex(args) => search::ft_dropindex(db, &args),
+        RedisCommand::FtList => search::ft_list(db),
+        RedisCommand::Save => match persistence::save(db) {
+            Ok(()) => Ok(RespType::SimpleString("OK".to_string())),
+            Err(DataStoreError::InvalidInput(e)) => Ok(RespType::Error(format!("ERR {}", e))),
+            Err(e) => Err(UserInputError::DataStoreError(e)),
+        },
+        RedisCommand::BgSave(args) => {
+            let schedule = match args.first().map(|a| a.to_lowercase()) {
+                None => false,
+                Some(arg) if arg == "schedule" && args.len() == 1 => true,
+                Some(_) => {
+                    return Err(UserInputError::InvalidInput(
+                        "BGSAVE takes only an optional SCHEDULE argument".to_string(),
+                    ))
+                }
+            };
+            Ok(match persistence::bgsave(db, schedule) {
+                persistence::BgsaveStart::Started => {
+                    RespType::SimpleString("Background saving started".to_string())
+                }
+                persistence::BgsaveStart::Scheduled => {
+                    RespType::SimpleString("Background saving scheduled".to_string())
+                }
+                persistence::BgsaveStart::AlreadyRunning => {
+                    RespType::Error("ERR Background save already in progress".to_string())
+                }
+            })
+        }
+        RedisCommand::Dump(key) => Ok(bulk_or_null(db.dump(&key).map(Bytes::from))),
+        RedisCommand::Restore(key, ttl, payload, options) => {
+            if key.is_empty() || payload.is_empty() {
+                return Err(UserInputError::InvalidInput(
+                    "RESTORE expects a key, a ttl and a payload".to_string(),
+                ));
+            }
+            restore(db, &key, &ttl, &payload, &options)
+        }
+        RedisCommand::Migrate(args) => migrate::migrate(db, &args),
+        RedisCommand::BgRewriteAof => Ok(match aof::rewrite(db) {
+            Ok(aof::RewriteStart::Started) => {
+                RespType::SimpleString("Background append only file rewriting started".to_string())
+            }
+            Ok(aof::RewriteStart::AlreadyRunning) => RespType::Error(
+                "ERR Background append only file rewriting already in progress".to_string(),
+            ),
+            Ok(aof::RewriteStart::NotEnabled) => {
+                RespType::Error("ERR Append only file is not enabled".to_string())
+            }
+            Err(e) => RespType::Error(format!(
+                "ERR Failed to start the append only file rewrite: {}",
+                e
+            )),
+        }),
+        RedisCommand::LastSave => Ok(RespType::Integer(db.save_status().lock().lastsave)),
+        RedisCommand::Info(sections) => {
+            let wants = |name: &str| {
+                sections.is_empty()
+                    || sections.iter().any(|s| {
+                        ["all", "everything", "default", name].contains(&s.to_lowercase().as_str())
+                    })
+            };
+            let mut info = String::new();
+            if wants("persistence") {
+                info.push_str(&persistence::info(db));
+            }
+            if wants("stats") {
+                if !info.is_empty() {
+                    info.push_str("\r\n");
+                }
+                info.push_str(&db.stats().info());
+            }
+            Ok(RespType::BulkString(Some(Bytes::from(info))))
+        }
+        RedisCommand::Config(ops) => redisconfig::config_command(db, &ops),
+        RedisCommand::Command(args) => {
+            let args: Vec<Bytes> = [Bytes::from("COMMAND")].into_iter().chain(args).collect();
+            Ok(command_table::command_command(&args))
         }
         RedisCommand::Unknown(cmd) => Err(UserInputError::UnknownCommand(cmd)),
     }
@@ -146,21 +739,25 @@ pub fn handle_input_cmd(
 
 // ===== tests =====
 
-static mut REDIS_CONN: Lazy<Connection> = Lazy::new(|| {
-    let client = Client::open("redis://127.0.0.1/").expect("Failed to connect to redis");
-    client
-        .get_connection()
-        .expect("Failed to get redis connection")
-});
-
 #[cfg(test)]
 mod tests {
     use std::str::from_utf8;
 
-    use redis::Value;
+    use once_cell::sync::Lazy;
+    use parking_lot::Mutex;
+    use redis::{Client, Connection, Value};
 
     use super::*;
 
+    static REDIS_CONN: Lazy<Mutex<Connection>> = Lazy::new(|| {
+        let client = Client::open("redis://127.0.0.1/").expect("Failed to connect to redis");
+        Mutex::new(
+            client
+                .get_connection()
+                .expect("Failed to get redis connection"),
+        )
+    });
+
     fn redis_value_to_string(val: &Value) -> String {
         match val {
             Value::SimpleString(s) => s.to_string(),
@@ -171,7 +768,7 @@ mod tests {
                 let mut res = String::new();
                 for v in arr.iter() {
                     res.push_str(&redis_value_to_string(v));
-                    res.push_str(" ");
+                    res.push(' ');
                 }
                 res
             }
@@ -184,7 +781,7 @@ mod tests {
     fn test_get_config() {
         let mut cmd = redis::cmd("CONFIG");
         cmd.arg("GET").arg("save");
-        let redis_res: Value = cmd.query(unsafe { &mut REDIS_CONN }).expect("ERR");
+        let redis_res: Value = cmd.query(&mut *REDIS_CONN.lock()).expect("ERR");
 
         let test_db = &mut datastore::Db::new(1);
         let rust_redis_res = handle_input_cmd(vec!["config", "get", "save"], test_db)
@@ -210,5 +807,118 @@ mod tests {
     }
 
     #[test]
-    fn test_set_with_expire() {}
+    fn test_set_with_expire() {
+        let test_db = &mut datastore::Db::new(1);
+        let res = handle_input_cmd(vec!["set", "k", "v", "EX", "100"], test_db).unwrap();
+        assert_eq!(res, RespType::SimpleString("OK".to_string()));
+        let ttl = test_db.expire_at("k").unwrap() - chrono::Utc::now().timestamp_millis();
+        assert!(ttl > 99_000 && ttl <= 100_000);
+
+        for options in [
+            ["EX", "abc"],
+            ["PX", "0"],
+            ["EXAT", "-5"],
+            ["EX", "9223372036854775807"],
+            ["PX", "9223372036854775807"],
+            ["EXAT", "9223372036854775807"],
+        ] {
+            let mut args = vec!["set", "other", "v"];
+            args.extend(options);
+            let res = handle_input_cmd(args, test_db).unwrap();
+            assert_eq!(
+                res,
+                RespType::Error("ERR invalid expire time in 'set' command".to_string())
+            );
+        }
+        assert_eq!(
+            handle_input_cmd(vec!["get", "other"], test_db).unwrap(),
+            RespType::Null
+        );
+        // a bad expiry leaves the old value and ttl alone
+        let res = handle_input_cmd(vec!["set", "k", "w", "PX", "x"], test_db).unwrap();
+        assert!(matches!(res, RespType::Error(_)));
+        assert_eq!(
+            handle_input_cmd(vec!["get", "k"], test_db).unwrap(),
+            RespType::BulkString(Some(Bytes::from("v")))
+        );
+        assert!(test_db.expire_at("k").is_some());
+    }
+
+    #[test]
+    fn test_hash_commands() {
+        let test_db = &mut datastore::Db::new(1);
+        let res = handle_input_cmd(vec!["hset", "h", "a", "1", "b", "2"], test_db).unwrap();
+        assert_eq!(res, RespType::Integer(2));
+        let res = handle_input_cmd(vec!["hget", "h", "b"], test_db).unwrap();
+        assert_eq!(res, RespType::BulkString(Some(Bytes::from("2"))));
+        let res = handle_input_cmd(vec!["hdel", "h", "a", "c"], test_db).unwrap();
+        assert_eq!(res, RespType::Integer(1));
+        let res = handle_input_cmd(vec!["get", "h"], test_db).unwrap();
+        assert_eq!(res, wrong_type());
+        let res = handle_input_cmd(vec!["del", "h", "missing"], test_db).unwrap();
+        assert_eq!(res, RespType::Integer(1));
+    }
+
+    #[test]
+    fn test_dump_restore() {
+        let test_db = &mut datastore::Db::new(1);
+        handle_input_cmd(vec!["hset", "h", "a", "1", "b", "2"], test_db).unwrap();
+        let payload = match handle_input_cmd(vec!["dump", "h"], test_db).unwrap() {
+            RespType::BulkString(Some(payload)) => payload,
+            res => panic!("unexpected DUMP reply {:?}", res),
+        };
+        assert_eq!(
+            handle_input_cmd(vec!["dump", "missing"], test_db).unwrap(),
+            RespType::BulkString(None)
+        );
+
+        let restore = |db: &mut datastore::Db, args: &[&[u8]]| {
+            let mut cmd: Vec<&[u8]> = vec![b"restore"];
+            cmd.extend_from_slice(args);
+            handle_input_cmd(cmd, db).unwrap()
+        };
+        let ok = RespType::SimpleString("OK".to_string());
+        assert_eq!(restore(test_db, &[b"copy", b"0", &payload]), ok);
+        assert_eq!(
+            handle_input_cmd(vec!["hget", "copy", "b"], test_db).unwrap(),
+            RespType::BulkString(Some(Bytes::from("2")))
+        );
+        assert_eq!(test_db.expire_at("copy"), None);
+        assert_eq!(
+            restore(test_db, &[b"copy", b"0", &payload]),
+            RespType::Error("BUSYKEY Target key name already exists.".to_string())
+        );
+        assert_eq!(
+            restore(
+                test_db,
+                &[b"copy", b"5000", &payload, b"replace", b"idletime", b"10"]
+            ),
+            ok
+        );
+        assert!(test_db.expire_at("copy").is_some());
+        // an absolute ttl in the past drops the key instead of storing it
+        assert_eq!(
+            restore(test_db, &[b"copy", b"1", &payload, b"REPLACE", b"ABSTTL"]),
+            ok
+        );
+        assert_eq!(test_db.key_type("copy"), None);
+
+        let mut bad = payload.to_vec();
+        bad[1] ^= 0xff;
+        assert_eq!(
+            restore(test_db, &[b"other", b"0", &bad]),
+            RespType::Error("ERR DUMP payload version or checksum are wrong".to_string())
+        );
+        assert_eq!(
+            restore(test_db, &[b"other", b"0", &payload, b"freq", b"300"]),
+            RespType::Error("ERR Invalid FREQ value, must be >= 0 and <= 255".to_string())
+        );
+        assert_eq!(
+            restore(
+                test_db,
+                &[b"other", b"0", &payload, b"idletime", b"1", b"freq", b"1"]
+            ),
+            RespType::Error("ERR syntax error".to_string())
+        );
+    }
 }
@@ -6,6 +6,5 @@ pub const ARRAY_PREFIX: u8 = b'*';
 pub const NULL_BULK_STRING: &[u8] = b"$-1\r\n";
 pub const NULL_ARRAY: &[u8] = b"*-1\r\n";
 
-pub const DATA_FILE_PATH: &'static str = ".data.json";
-pub const DATA_SAVE_INTERVAL_SECS: u64 = 5;
-pub const CONFIG_FILE_PATH: &'static str = "redis.conf";
+pub const SERVER_CRON_INTERVAL_MS: u64 = 100;
+pub const CONFIG_FILE_PATH: &str = "redis.conf";
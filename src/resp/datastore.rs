@@ -1,14 +1,19 @@
 use bytes::Bytes;
 use chrono::Utc;
 use lazy_static::lazy_static;
-use parking_lot::Mutex;
-use std::hash::Hash;
-use std::io::Read;
+use parking_lot::{Mutex, RwLock};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
-use std::{collections::HashMap, fs::OpenOptions, io::Write, time::Duration};
+use std::{collections::HashMap, time::Duration};
 
-use crate::resp::constants::DATA_FILE_PATH;
 use crate::resp::errors::DataStoreError;
+use crate::resp::{
+    aof, blocking, notify, persistence, pubsub, rdb, redisconfig, search,
+    stats::Stats,
+    stream::{self, Fields, NewId, Stream, StreamId, Trim},
+};
 
 use serde_derive::{Deserialize, Serialize};
 
@@ -20,69 +25,1273 @@ pub struct MapValue {
     expire_at: Option<i64>,
 }
 
-type Shard = Mutex<HashMap<String, String>>;
+/// f64 with a total order so it can be used as a sort key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
 
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by (score, member), with a lookup from member to score.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    /// Adds or updates a member, returning true if it was new.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        old.is_none()
+    }
+
+    pub fn remove(&mut self, member: &Bytes) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.clone())),
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &Bytes) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members in ascending score order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(s, m)| (m, s.0))
+    }
+
+    /// Removes the member with the lowest score, or the highest with `max`.
+    pub fn pop(&mut self, max: bool) -> Option<(Bytes, f64)> {
+        let (score, member) = if max {
+            self.ordered.pop_last()?
+        } else {
+            self.ordered.pop_first()?
+        };
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(SortedSet),
+    Hash(HashMap<String, Bytes>),
+    Stream(Stream),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Hash(_) => "hash",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+/// The keys held by one shard. Expiry times are kept next to the values,
+/// as unix timestamps in milliseconds, the way redis keeps a separate expires dict.
+///
+/// Both maps are persistent (structurally shared), so cloning a keyspace is
+/// O(1) and later writes copy only the nodes they touch. That is what lets
+/// a snapshot be taken without stalling writers for the length of a save.
+#[derive(Debug, Default, Clone)]
+pub struct Keyspace {
+    pub entries: im::HashMap<String, Value>,
+    pub expires: im::HashMap<String, i64>,
+}
+
+impl Keyspace {
+    fn is_expired(&self, key: &str, now: i64) -> bool {
+        self.expires.get(key).is_some_and(|at| *at <= now)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Value> {
+        self.expires.remove(key);
+        self.entries.remove(key)
+    }
+}
+
+type Shard = Mutex<Keyspace>;
+
+#[derive(Debug, Default)]
+struct WatchedVersion {
+    version: u64,
+    watchers: usize,
+}
+
+/// The keys clients WATCH, each with a version that goes up whenever the key
+/// changes or expires. Only watched keys are tracked, and a key is forgotten
+/// once nobody watches it.
+#[derive(Debug, Default)]
+struct Watches {
+    keys: Mutex<HashMap<String, WatchedVersion>>,
+}
+
+impl Watches {
+    fn touch(&self, key: &str) {
+        if let Some(watched) = self.keys.lock().get_mut(key) {
+            watched.version += 1;
+        }
+    }
+}
+
+/// A key a client WATCHes, with the version it had then. Dropping it stops
+/// watching the key.
+#[derive(Debug)]
+pub struct WatchedKey {
+    watches: Arc<Watches>,
+    key: String,
+    version: u64,
+}
+
+impl Drop for WatchedKey {
+    fn drop(&mut self) {
+        let mut keys = self.watches.keys.lock();
+        if let Some(watched) = keys.get_mut(&self.key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                keys.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Db {
     pub data: Arc<Vec<Shard>>,
+    // held shared while a command runs and exclusively by EXEC
+    exec_lock: Arc<RwLock<()>>,
+    watches: Arc<Watches>,
+    indexes: Arc<RwLock<search::Indexes>>,
+    // writes since the last successful save
+    dirty: Arc<AtomicU64>,
+    save_status: Arc<persistence::SaveState>,
+    aof: Arc<Mutex<Option<aof::Aof>>>,
+    stats: Arc<Stats>,
+    pubsub: Arc<pubsub::Broker>,
+    // the notify-keyspace-events classes, parsed
+    notify_flags: Arc<AtomicU32>,
+    blocking: Arc<blocking::Blocking>,
 }
 
 impl Db {
     pub fn new(num_shards: usize) -> Self {
         let mut db_with_shards = Vec::with_capacity(num_shards);
         for _ in 0..num_shards {
-            db_with_shards.push(Mutex::new(HashMap::new()));
+            db_with_shards.push(Mutex::new(Keyspace::default()));
         }
         Self {
             data: Arc::new(db_with_shards),
+            exec_lock: Arc::new(RwLock::new(())),
+            watches: Arc::new(Watches::default()),
+            indexes: Arc::new(RwLock::new(search::Indexes::default())),
+            dirty: Arc::new(AtomicU64::new(0)),
+            save_status: Arc::new(persistence::SaveState::default()),
+            aof: Arc::new(Mutex::new(None)),
+            stats: Arc::new(Stats::default()),
+            pubsub: Arc::new(pubsub::Broker::default()),
+            notify_flags: Arc::new(AtomicU32::new(
+                notify::parse(
+                    &redisconfig::get_config("notify-keyspace-events").unwrap_or_default(),
+                )
+                .unwrap_or(0),
+            )),
+            blocking: Arc::new(blocking::Blocking::default()),
         }
     }
 
-    pub fn clone(&self) -> Self {
-        Self {
-            data: self.data.clone(),
-        }
+    fn shard_index(&self, key: &str) -> usize {
+        key.len() % self.data.len()
     }
 
     fn get_shard_for_key(&self, key: &str) -> &Shard {
-        let i = key.len() % self.data.len();
-        &self.data[i]
+        &self.data[self.shard_index(key)]
+    }
+
+    // drops the key if its ttl has passed, so callers never see stale values
+    fn purge_if_expired(&self, key: &str, data: &mut Keyspace) {
+        if data.is_expired(key, Utc::now().timestamp_millis()) {
+            if let Some(Value::Stream(_)) = data.remove(key) {
+                self.blocking.signal(key);
+            }
+            self.reindex(key, None);
+            self.watches.touch(key);
+            Stats::add(&self.stats.expired_keys, 1);
+            self.notify(notify::EXPIRED, "expired", key);
+        }
+    }
+
+    // every write goes through here so secondary indexes see the new value
+    // while the shard is still locked, keeping them in step with the data.
+    // `f` also tells whether it modified the key: like redis, any write that
+    // does so invalidates a WATCH on it, even one storing the same value,
+    // while one refused or finding nothing to do doesn't
+    fn mutate<R>(&self, key: &str, f: impl FnOnce(&mut Keyspace) -> (R, bool)) -> R {
+        let mut data = self.get_shard_for_key(key).lock();
+        self.purge_if_expired(key, &mut data);
+        let was_stream = matches!(data.entries.get(key), Some(Value::Stream(_)));
+        let (res, modified) = f(&mut data);
+        // the group readers blocked on a stream are told it is gone
+        if was_stream && !matches!(data.entries.get(key), Some(Value::Stream(_))) {
+            self.blocking.signal(key);
+        }
+        if modified {
+            self.watches.touch(key);
+        }
+        self.reindex(key, data.entries.get(key));
+        res
     }
 
-    pub fn get(&self, key: &str) -> Result<String, DataStoreError> {
-        let data = self.get_shard_for_key(key).lock();
-        data.get(key)
-            .map(|x| x.clone())
-            .ok_or(DataStoreError::KeyNotFound)
+    // most keys are covered by no index, which the shared lock is enough to
+    // tell, so writers on different shards don't queue for the exclusive one
+    fn reindex(&self, key: &str, value: Option<&Value>) {
+        if self.indexes.read().covers(key) {
+            self.indexes.write().on_key_changed(key, value);
+        }
     }
 
-    pub fn set(&self, key: &str, val: &str, ops: Vec<String>) -> Result<(), DataStoreError> {
+    /// Held shared while a command runs, and exclusively by EXEC so the
+    /// commands of a transaction run with nothing in between. It is taken
+    /// before the aof lock.
+    pub fn exec_lock(&self) -> Arc<RwLock<()>> {
+        self.exec_lock.clone()
+    }
+
+    /// Starts watching a key for changes, as WATCH does.
+    pub fn watch(&self, key: &str) -> WatchedKey {
         let mut data = self.get_shard_for_key(key).lock();
-        data.insert(key.to_string(), val.to_string());
+        self.purge_if_expired(key, &mut data);
+        let mut keys = self.watches.keys.lock();
+        let watched = keys.entry(key.to_string()).or_default();
+        watched.watchers += 1;
+        WatchedKey {
+            watches: self.watches.clone(),
+            key: key.to_string(),
+            version: watched.version,
+        }
+    }
+
+    /// How many distinct keys clients are watching.
+    pub fn watched_keys(&self) -> usize {
+        self.watches.keys.lock().len()
+    }
+
+    /// Whether a watched key was changed, or has expired, since it was
+    /// watched.
+    pub fn is_touched(&self, watched: &WatchedKey) -> bool {
+        let mut data = self.get_shard_for_key(&watched.key).lock();
+        self.purge_if_expired(&watched.key, &mut data);
+        let keys = self.watches.keys.lock();
+        keys.get(&watched.key)
+            .is_none_or(|w| w.version != watched.version)
+    }
+
+    fn add_dirty(&self, changes: usize) {
+        self.dirty
+            .fetch_add(changes as u64, AtomicOrdering::Relaxed);
+    }
+
+    /// Number of writes since the last successful save.
+    pub fn dirty(&self) -> u64 {
+        self.dirty.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Forgets the changes a finished save covered. Writes that landed while
+    /// it was running stay counted.
+    pub fn clear_dirty(&self, saved: u64) {
+        // saturating, as saves that overlap can both clear the same changes
+        let _ =
+            self.dirty
+                .fetch_update(AtomicOrdering::Relaxed, AtomicOrdering::Relaxed, |dirty| {
+                    Some(dirty.saturating_sub(saved))
+                });
+    }
+
+    pub fn save_status(&self) -> &persistence::SaveState {
+        &self.save_status
+    }
+
+    /// The append-only file, when `appendonly` is on. Writers hold this lock
+    /// across executing and logging a command, so the log has the order in
+    /// which writes were applied.
+    pub fn aof(&self) -> &Mutex<Option<aof::Aof>> {
+        &self.aof
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn pubsub(&self) -> &Arc<pubsub::Broker> {
+        &self.pubsub
+    }
+
+    /// The clients blocked on keys by BLPOP and the like.
+    pub fn blocking(&self) -> &Arc<blocking::Blocking> {
+        &self.blocking
+    }
+
+    /// Changes which keyspace events are published, as CONFIG SET
+    /// notify-keyspace-events does.
+    pub fn set_notify_flags(&self, flags: u32) {
+        self.notify_flags.store(flags, AtomicOrdering::Relaxed);
+    }
+
+    // publishes a keyspace event, when notify-keyspace-events asks for its class
+    fn notify(&self, class: u32, event: &str, key: &str) {
+        let flags = self.notify_flags.load(AtomicOrdering::Relaxed);
+        notify::publish(&self.pubsub, flags, class, event, key);
+    }
+
+    // reads for a command, so a missing key is a key miss event
+    fn read<R>(&self, key: &str, f: impl FnOnce(Option<&Value>) -> R) -> R {
+        let mut data = self.get_shard_for_key(key).lock();
+        self.purge_if_expired(key, &mut data);
+        let value = data.entries.get(key);
+        if value.is_none() {
+            self.notify(notify::KEY_MISS, "keymiss", key);
+        }
+        f(value)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Bytes, DataStoreError> {
+        self.read(key, |value| match value {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(_) => Err(DataStoreError::WrongType),
+            None => Err(DataStoreError::KeyNotFound),
+        })
+    }
+
+    /// Sets a string value. Supports the expiry options of SET:
+    /// EX seconds, PX milliseconds, EXAT/PXAT unix time and KEEPTTL.
+    pub fn set(&self, key: &str, val: Bytes, ops: Vec<String>) -> Result<(), DataStoreError> {
+        let expire_at = parse_expiry(&ops)?;
+        let expires = matches!(expire_at, Expiry::At(_));
+        let created = self.mutate(key, |data| {
+            let created = data
+                .entries
+                .insert(key.to_string(), Value::String(val))
+                .is_none();
+            match expire_at {
+                Expiry::At(at) => {
+                    data.expires.insert(key.to_string(), at);
+                }
+                Expiry::Persist => {
+                    data.expires.remove(key);
+                }
+                Expiry::Keep => {}
+            }
+            (created, true)
+        });
+        if created {
+            self.notify(notify::NEW, "new", key);
+        }
+        self.notify(notify::STRING, "set", key);
+        if expires {
+            self.notify(notify::GENERIC, "expire", key);
+        }
+        self.add_dirty(1);
         Ok(())
     }
 
-    // TOD: save & load with .rdb file
-    pub fn save(&self) -> Result<(), DataStoreError> {
-        let mut json_data = String::new();
-        for shard in self.data.iter() {
-            let data = shard.lock();
-            let conetent =
-                serde_json::to_string(&*data).map_err(|_| DataStoreError::SerializeError)?;
-            json_data.push_str(conetent.as_str());
-        }
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(DATA_FILE_PATH)
-            .map_err(|_| DataStoreError::FileIOError)?;
-        file.write_all(json_data.as_bytes())
-            .map_err(|_| DataStoreError::FileIOError)?;
+    /// Expiry of the key as a unix timestamp in milliseconds.
+    pub fn expire_at(&self, key: &str) -> Option<i64> {
+        let mut data = self.get_shard_for_key(key).lock();
+        self.purge_if_expired(key, &mut data);
+        data.expires.get(key).copied()
+    }
+
+    pub fn key_type(&self, key: &str) -> Option<&'static str> {
+        let mut data = self.get_shard_for_key(key).lock();
+        self.purge_if_expired(key, &mut data);
+        data.entries.get(key).map(|v| v.type_name())
+    }
+
+    pub fn del(&self, keys: &[String]) -> usize {
+        let removed = keys
+            .iter()
+            .filter(|key| {
+                self.mutate(key, |data| {
+                    let removed = data.remove(key).is_some();
+                    (removed, removed)
+                })
+            })
+            .inspect(|key| self.notify(notify::GENERIC, "del", key))
+            .count();
+        self.add_dirty(removed);
+        removed
+    }
+
+    /// Sets the given fields, returning how many of them were newly added.
+    pub fn hset(&self, key: &str, pairs: &[(String, Bytes)]) -> Result<usize, DataStoreError> {
+        let mut created = false;
+        let res = self.mutate(key, |data| {
+            let value = data.entries.entry(key.to_string()).or_insert_with(|| {
+                created = true;
+                Value::Hash(HashMap::new())
+            });
+            match value {
+                Value::Hash(h) => {
+                    let added = pairs
+                        .iter()
+                        .filter(|(f, v)| h.insert(f.clone(), v.clone()).is_none())
+                        .count();
+                    (Ok(added), true)
+                }
+                _ => (Err(DataStoreError::WrongType), false),
+            }
+        });
+        if res.is_ok() {
+            if created {
+                self.notify(notify::NEW, "new", key);
+            }
+            self.notify(notify::HASH, "hset", key);
+            self.add_dirty(pairs.len());
+        }
+        res
+    }
+
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<Bytes>, DataStoreError> {
+        self.read(key, |value| match value {
+            Some(Value::Hash(h)) => Ok(h.get(field).cloned()),
+            Some(_) => Err(DataStoreError::WrongType),
+            None => Ok(None),
+        })
+    }
+
+    pub fn hgetall(&self, key: &str) -> Result<HashMap<String, Bytes>, DataStoreError> {
+        self.read(key, |value| match value {
+            Some(Value::Hash(h)) => Ok(h.clone()),
+            Some(_) => Err(DataStoreError::WrongType),
+            None => Ok(HashMap::new()),
+        })
+    }
+
+    /// Removes the given fields, dropping the key once the hash is empty.
+    pub fn hdel(&self, key: &str, fields: &[String]) -> Result<usize, DataStoreError> {
+        let mut emptied = false;
+        let res = self.mutate(key, |data| {
+            let removed = match data.entries.get_mut(key) {
+                Some(Value::Hash(h)) => fields.iter().filter(|f| h.remove(*f).is_some()).count(),
+                Some(_) => return (Err(DataStoreError::WrongType), false),
+                None => return (Ok(0), false),
+            };
+            if matches!(data.entries.get(key), Some(Value::Hash(h)) if h.is_empty()) {
+                data.remove(key);
+                emptied = true;
+            }
+            (Ok(removed), removed > 0)
+        });
+        if res.as_ref().is_ok_and(|removed| *removed > 0) {
+            self.notify(notify::HASH, "hdel", key);
+            if emptied {
+                self.notify(notify::GENERIC, "del", key);
+            }
+        }
+        self.add_dirty(*res.as_ref().unwrap_or(&0));
+        res
+    }
+
+    /// Recreates the indexes recorded in an rdb file's aux fields. Called
+    /// once its keys are loaded, so the indexes are backfilled with them.
+    pub fn restore_indexes(&self, aux: &[(String, String)]) {
+        for (_, definition) in aux.iter().filter(|(key, _)| key == INDEX_AUX) {
+            let res = serde_json::from_str::<Vec<String>>(definition)
+                .map_err(|e| e.to_string())
+                .and_then(|args| search::ft_create(self, &args).map_err(|e| e.to_string()));
+            if let Err(e) = res {
+                eprintln!("Skipping index {}: {}", definition, e);
+            }
+        }
+    }
+
+    /// Pushes values to the head or the tail of a list, creating it when
+    /// needed, and returns the new length.
+    pub fn push(&self, key: &str, values: &[Bytes], head: bool) -> Result<usize, DataStoreError> {
+        let mut created = false;
+        let res = self.mutate(key, |data| {
+            let value = data.entries.entry(key.to_string()).or_insert_with(|| {
+                created = true;
+                Value::List(VecDeque::new())
+            });
+            let Value::List(list) = value else {
+                return (Err(DataStoreError::WrongType), false);
+            };
+            for value in values {
+                if head {
+                    list.push_front(value.clone());
+                } else {
+                    list.push_back(value.clone());
+                }
+            }
+            let len = list.len();
+            self.blocking.signal(key);
+            (Ok(len), true)
+        });
+        if res.is_ok() {
+            if created {
+                self.notify(notify::NEW, "new", key);
+            }
+            self.notify(notify::LIST, if head { "lpush" } else { "rpush" }, key);
+            self.add_dirty(values.len());
+        }
+        res
+    }
+
+    /// Pops up to `count` values from the head or the tail of a list,
+    /// dropping the key once it is empty. A missing key has none.
+    pub fn pop(&self, key: &str, count: usize, head: bool) -> Result<Vec<Bytes>, DataStoreError> {
+        let mut emptied = false;
+        let res = self.mutate(key, |data| {
+            let list = match data.entries.get_mut(key) {
+                Some(Value::List(list)) => list,
+                Some(_) => return (Err(DataStoreError::WrongType), false),
+                None => return (Ok(vec![]), false),
+            };
+            let n = count.min(list.len());
+            let popped: Vec<Bytes> = if head {
+                list.drain(..n).collect()
+            } else {
+                list.drain(list.len() - n..).rev().collect()
+            };
+            if list.is_empty() {
+                data.remove(key);
+                emptied = true;
+            }
+            let modified = !popped.is_empty();
+            (Ok(popped), modified)
+        });
+        if let Ok(popped) = &res {
+            if !popped.is_empty() {
+                self.notify(notify::LIST, if head { "lpop" } else { "rpop" }, key);
+                if emptied {
+                    self.notify(notify::GENERIC, "del", key);
+                }
+            }
+            self.add_dirty(popped.len());
+        }
+        res
+    }
+
+    /// Pops a value off one list and pushes it onto another, as LMOVE does.
+    /// Nothing is popped when the destination holds something other than a
+    /// list.
+    pub fn lmove(
+        &self,
+        source: &str,
+        destination: &str,
+        from_head: bool,
+        to_head: bool,
+    ) -> Result<Option<Bytes>, DataStoreError> {
+        // both shards stay locked for the whole move, taken in index order
+        // so that moves between the same two shards can't deadlock
+        let (from, to) = (self.shard_index(source), self.shard_index(destination));
+        let mut shards = vec![self.data[from.min(to)].lock()];
+        if from != to {
+            shards.push(self.data[from.max(to)].lock());
+        }
+        let at = |i: usize| usize::from(i > from.min(to));
+        let (src, dst) = (at(from), at(to));
+        self.purge_if_expired(source, &mut shards[src]);
+        self.purge_if_expired(destination, &mut shards[dst]);
+        match shards[src].entries.get(source) {
+            None => return Ok(None),
+            Some(Value::List(_)) => {}
+            Some(_) => return Err(DataStoreError::WrongType),
+        }
+        if shards[dst]
+            .entries
+            .get(destination)
+            .is_some_and(|value| !matches!(value, Value::List(_)))
+        {
+            return Err(DataStoreError::WrongType);
+        }
+        let Some(Value::List(list)) = shards[src].entries.get_mut(source) else {
+            return Ok(None);
+        };
+        let popped = if from_head {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        let Some(value) = popped else {
+            return Ok(None);
+        };
+        // rotating a list of one leaves it as it was
+        let emptied = list.is_empty() && source != destination;
+        if emptied {
+            shards[src].remove(source);
+        }
+        let mut created = false;
+        let entry = shards[dst]
+            .entries
+            .entry(destination.to_string())
+            .or_insert_with(|| {
+                created = true;
+                Value::List(VecDeque::new())
+            });
+        if let Value::List(list) = entry {
+            if to_head {
+                list.push_front(value.clone());
+            } else {
+                list.push_back(value.clone());
+            }
+        }
+        for (key, i) in [(source, src), (destination, dst)] {
+            self.watches.touch(key);
+            self.reindex(key, shards[i].entries.get(key));
+        }
+        self.blocking.signal(destination);
+        drop(shards);
+
+        self.notify(
+            notify::LIST,
+            if from_head { "lpop" } else { "rpop" },
+            source,
+        );
+        if emptied {
+            self.notify(notify::GENERIC, "del", source);
+        }
+        if created {
+            self.notify(notify::NEW, "new", destination);
+        }
+        self.notify(
+            notify::LIST,
+            if to_head { "lpush" } else { "rpush" },
+            destination,
+        );
+        self.add_dirty(2);
+        Ok(Some(value))
+    }
+
+    /// Adds members to a sorted set or updates their scores, returning how
+    /// many were new.
+    pub fn zadd(&self, key: &str, members: &[(f64, Bytes)]) -> Result<usize, DataStoreError> {
+        let mut created = false;
+        let res = self.mutate(key, |data| {
+            let value = data.entries.entry(key.to_string()).or_insert_with(|| {
+                created = true;
+                Value::ZSet(SortedSet::default())
+            });
+            let Value::ZSet(zset) = value else {
+                return (Err(DataStoreError::WrongType), false);
+            };
+            let added = members
+                .iter()
+                .filter(|(score, member)| zset.insert(member.clone(), *score))
+                .count();
+            self.blocking.signal(key);
+            (Ok(added), true)
+        });
+        if res.is_ok() {
+            if created {
+                self.notify(notify::NEW, "new", key);
+            }
+            self.notify(notify::ZSET, "zadd", key);
+            self.add_dirty(members.len());
+        }
+        res
+    }
+
+    /// Pops up to `count` members with the lowest scores, or the highest with
+    /// `max`, dropping the key once the sorted set is empty.
+    pub fn zpop(
+        &self,
+        key: &str,
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<(Bytes, f64)>, DataStoreError> {
+        let mut emptied = false;
+        let res = self.mutate(key, |data| {
+            let zset = match data.entries.get_mut(key) {
+                Some(Value::ZSet(zset)) => zset,
+                Some(_) => return (Err(DataStoreError::WrongType), false),
+                None => return (Ok(vec![]), false),
+            };
+            let popped: Vec<_> = (0..count).map_while(|_| zset.pop(max)).collect();
+            if zset.is_empty() {
+                data.remove(key);
+                emptied = true;
+            }
+            let modified = !popped.is_empty();
+            (Ok(popped), modified)
+        });
+        if let Ok(popped) = &res {
+            if !popped.is_empty() {
+                self.notify(notify::ZSET, if max { "zpopmax" } else { "zpopmin" }, key);
+                if emptied {
+                    self.notify(notify::GENERIC, "del", key);
+                }
+            }
+            self.add_dirty(popped.len());
+        }
+        res
+    }
+
+    /// Runs `f` on the stream at `key`, None when the key is missing.
+    pub fn read_stream<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(Option<&Stream>) -> R,
+    ) -> Result<R, DataStoreError> {
+        self.read(key, |value| match value {
+            Some(Value::Stream(stream)) => Ok(f(Some(stream))),
+            Some(_) => Err(DataStoreError::WrongType),
+            None => Ok(f(None)),
+        })
+    }
+
+    // runs `f` on the stream at `key` for a write, None when the key is
+    // missing; `f` also tells whether it modified the stream
+    fn update_stream<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(Option<&mut Stream>) -> (Result<R, DataStoreError>, bool),
+    ) -> Result<R, DataStoreError> {
+        self.mutate(key, |data| match data.entries.get_mut(key) {
+            Some(Value::Stream(stream)) => f(Some(stream)),
+            Some(_) => (Err(DataStoreError::WrongType), false),
+            None => f(None),
+        })
+    }
+
+    /// Appends an entry to a stream, creating the stream unless `nomkstream`,
+    /// and trims it if asked to. Returns the ID of the entry, or None when
+    /// the key is missing and may not be created.
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: NewId,
+        fields: Fields,
+        nomkstream: bool,
+        trim: Option<Trim>,
+    ) -> Result<Option<StreamId>, DataStoreError> {
+        let now = Utc::now().timestamp_millis() as u64;
+        let mut created = false;
+        let mut trimmed = 0;
+        let res = self.mutate(key, |data| {
+            let next = match data.entries.get(key) {
+                Some(Value::Stream(stream)) => stream.next_id(id, now),
+                Some(_) => return (Err(DataStoreError::WrongType), false),
+                None if nomkstream => return (Ok(None), false),
+                None => Stream::default().next_id(id, now),
+            };
+            let id = match next {
+                Ok(id) => id,
+                Err(e) => return (Err(DataStoreError::InvalidInput(e.to_string())), false),
+            };
+            let value = data.entries.entry(key.to_string()).or_insert_with(|| {
+                created = true;
+                Value::Stream(Stream::default())
+            });
+            let Value::Stream(stream) = value else {
+                return (Err(DataStoreError::WrongType), false);
+            };
+            stream.add(id, fields);
+            trimmed = trim.map_or(0, |trim| stream.trim(trim));
+            self.blocking.signal(key);
+            (Ok(Some(id)), true)
+        });
+        if res.as_ref().is_ok_and(|id| id.is_some()) {
+            if created {
+                self.notify(notify::NEW, "new", key);
+            }
+            self.notify(notify::STREAM, "xadd", key);
+            if trimmed > 0 {
+                self.notify(notify::STREAM, "xtrim", key);
+            }
+            self.add_dirty(1);
+        }
+        res
+    }
+
+    /// Reads for a consumer of a group, as XREADGROUP does; see
+    /// [`Stream::read_group`]. New entries delivered count as writes.
+    pub fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> Result<Vec<(StreamId, Option<Fields>)>, DataStoreError> {
+        let now = Utc::now().timestamp_millis();
+        let (read, created) = self.update_stream(key, |stream| {
+            match stream.and_then(|s| s.read_group(group, consumer, after, count, noack, now)) {
+                Some((read, created)) => {
+                    let modified = created || (after.is_none() && !read.is_empty());
+                    (Ok((read, created)), modified)
+                }
+                None => (
+                    Err(DataStoreError::InvalidInput(stream::no_group(key, group))),
+                    false,
+                ),
+            }
+        })?;
+        if created {
+            self.notify(notify::STREAM, "xgroup-createconsumer", key);
+        }
+        let delivered = if after.is_none() { read.len() } else { 0 };
+        self.add_dirty(created as usize + delivered);
+        Ok(read)
+    }
+
+    /// Acknowledges entries of a group, returning how many were pending.
+    pub fn xack(&self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, DataStoreError> {
+        let acked = self.update_stream(key, |stream| {
+            let Some(group) = stream.and_then(|s| s.groups.get_mut(group)) else {
+                return (Ok(0), false);
+            };
+            let acked = ids
+                .iter()
+                .filter(|id| group.pending.remove(id).is_some())
+                .count();
+            (Ok(acked), acked > 0)
+        })?;
+        self.add_dirty(acked);
+        Ok(acked)
+    }
+
+    /// Creates a consumer group that has read up to `start`, or up to the
+    /// last entry without one. A missing key is created as an empty stream
+    /// with `mkstream`.
+    pub fn xgroup_create(
+        &self,
+        key: &str,
+        group: &str,
+        start: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<(), DataStoreError> {
+        let mut created = false;
+        self.mutate(key, |data| {
+            if mkstream && !data.entries.contains_key(key) {
+                data.entries
+                    .insert(key.to_string(), Value::Stream(Stream::default()));
+                created = true;
+            }
+            let stream = match data.entries.get_mut(key) {
+                Some(Value::Stream(stream)) => stream,
+                Some(_) => return (Err(DataStoreError::WrongType), false),
+                None => {
+                    let err = DataStoreError::InvalidInput(XGROUP_NO_KEY.to_string());
+                    return (Err(err), false);
+                }
+            };
+            if stream.groups.contains_key(group) {
+                let err = DataStoreError::InvalidInput(
+                    "BUSYGROUP Consumer Group name already exists".to_string(),
+                );
+                return (Err(err), created);
+            }
+            let last_id = start.unwrap_or(stream.last_id);
+            stream.groups.insert(
+                group.to_string(),
+                stream::ConsumerGroup {
+                    last_id,
+                    ..Default::default()
+                },
+            );
+            (Ok(()), true)
+        })?;
+        if created {
+            self.notify(notify::NEW, "new", key);
+        }
+        self.notify(notify::STREAM, "xgroup-create", key);
+        self.add_dirty(1);
         Ok(())
     }
 
+    /// Moves a group back or forth to have read up to `start`, or up to the
+    /// last entry without one.
+    pub fn xgroup_setid(
+        &self,
+        key: &str,
+        group: &str,
+        start: Option<StreamId>,
+    ) -> Result<(), DataStoreError> {
+        self.update_group(key, group, |group, last_id| {
+            group.last_id = start.unwrap_or(last_id);
+            ((), true)
+        })?;
+        self.notify(notify::STREAM, "xgroup-setid", key);
+        self.add_dirty(1);
+        Ok(())
+    }
+
+    /// Drops a consumer group, returning whether there was one. Clients
+    /// blocked reading with it are woken up.
+    pub fn xgroup_destroy(&self, key: &str, group: &str) -> Result<bool, DataStoreError> {
+        let destroyed = self.update_stream(key, |stream| {
+            let Some(stream) = stream else {
+                let err = DataStoreError::InvalidInput(XGROUP_NO_KEY.to_string());
+                return (Err(err), false);
+            };
+            let destroyed = stream.groups.remove(group).is_some();
+            if destroyed {
+                self.blocking.signal(key);
+            }
+            (Ok(destroyed), destroyed)
+        })?;
+        if destroyed {
+            self.notify(notify::STREAM, "xgroup-destroy", key);
+            self.add_dirty(1);
+        }
+        Ok(destroyed)
+    }
+
+    /// Adds a consumer to a group, returning whether it is new.
+    pub fn xgroup_createconsumer(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<bool, DataStoreError> {
+        let now = Utc::now().timestamp_millis();
+        let created = self.update_group(key, group, |group, _| {
+            match group.consumers.entry(consumer.to_string()) {
+                std::collections::btree_map::Entry::Occupied(_) => (false, false),
+                std::collections::btree_map::Entry::Vacant(entry) => {
+                    entry.insert(stream::Consumer { seen_time: now });
+                    (true, true)
+                }
+            }
+        })?;
+        if created {
+            self.notify(notify::STREAM, "xgroup-createconsumer", key);
+            self.add_dirty(1);
+        }
+        Ok(created)
+    }
+
+    /// Removes a consumer from a group along with its pending entries,
+    /// returning how many it had.
+    pub fn xgroup_delconsumer(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+    ) -> Result<usize, DataStoreError> {
+        let removed = self.update_group(key, group, |group, _| {
+            if group.consumers.remove(consumer).is_none() {
+                return (None, false);
+            }
+            let before = group.pending.len();
+            group.pending.retain(|_, p| p.consumer != consumer);
+            (Some(before - group.pending.len()), true)
+        })?;
+        let Some(pending) = removed else {
+            return Ok(0);
+        };
+        self.notify(notify::STREAM, "xgroup-delconsumer", key);
+        self.add_dirty(1);
+        Ok(pending)
+    }
+
+    // runs `f` on a consumer group for XGROUP, along with the stream's last
+    // ID; like `update_stream`, `f` tells whether it modified the group
+    fn update_group<R>(
+        &self,
+        key: &str,
+        group: &str,
+        f: impl FnOnce(&mut stream::ConsumerGroup, StreamId) -> (R, bool),
+    ) -> Result<R, DataStoreError> {
+        self.update_stream(key, |stream| {
+            let Some(stream) = stream else {
+                let err = DataStoreError::InvalidInput(XGROUP_NO_KEY.to_string());
+                return (Err(err), false);
+            };
+            match stream.groups.get_mut(group) {
+                Some(group) => {
+                    let (res, modified) = f(group, stream.last_id);
+                    (Ok(res), modified)
+                }
+                None => (
+                    Err(DataStoreError::InvalidInput(format!(
+                        "NOGROUP No such consumer group '{}' for key name '{}'",
+                        group, key
+                    ))),
+                    false,
+                ),
+            }
+        })
+    }
+
+    /// Stores a value as-is, replacing whatever the key held. Used when
+    /// loading snapshots, where values arrive already built, so no keyspace
+    /// event is published.
+    pub fn restore(&self, key: &str, value: Value, expire_at: Option<i64>) {
+        self.mutate(key, |data| {
+            data.entries.insert(key.to_string(), value);
+            match expire_at {
+                Some(at) => data.expires.insert(key.to_string(), at),
+                None => data.expires.remove(key),
+            };
+            ((), true)
+        });
+    }
+
+    /// Serializes a key's value in the DUMP payload format.
+    pub fn dump(&self, key: &str) -> Option<Vec<u8>> {
+        self.read(key, |value| value.map(rdb::dump_payload))
+    }
+
+    /// Stores a value for RESTORE. An existing key is only overwritten with
+    /// `replace`, and a value whose expiry has already passed is not stored.
+    pub fn restore_key(
+        &self,
+        key: &str,
+        value: Value,
+        expire_at: Option<i64>,
+        replace: bool,
+    ) -> Result<(), DataStoreError> {
+        let expired = expire_at.is_some_and(|at| at <= Utc::now().timestamp_millis());
+        // whether the value was stored, and whether the key held one before
+        let (stored, existed) = self.mutate(key, |data| {
+            if !replace && data.entries.contains_key(key) {
+                return (Err(DataStoreError::BusyKey), false);
+            }
+            if expired {
+                let removed = data.remove(key).is_some();
+                return (Ok((false, removed)), removed);
+            }
+            let existed = data.entries.insert(key.to_string(), value).is_some();
+            match expire_at {
+                Some(at) => data.expires.insert(key.to_string(), at),
+                None => data.expires.remove(key),
+            };
+            // a restored list or sorted set can serve blocked clients
+            self.blocking.signal(key);
+            (Ok((true, existed)), true)
+        })?;
+        if stored {
+            if !existed {
+                self.notify(notify::NEW, "new", key);
+            }
+            self.notify(notify::GENERIC, "restore", key);
+        } else if existed {
+            self.notify(notify::GENERIC, "del", key);
+        }
+        self.add_dirty(1);
+        Ok(())
+    }
+
+    /// Removes every key whose ttl has passed, returning how many were dropped.
+    pub fn purge_expired(&self) -> usize {
+        let now = Utc::now().timestamp_millis();
+        let mut purged = 0;
+        for shard in self.data.iter() {
+            let mut data = shard.lock();
+            let expired: Vec<String> = data
+                .expires
+                .iter()
+                .filter(|(_, at)| **at <= now)
+                .map(|(k, _)| k.clone())
+                .collect();
+            for key in expired {
+                if let Some(Value::Stream(_)) = data.remove(&key) {
+                    self.blocking.signal(&key);
+                }
+                self.reindex(&key, None);
+                self.watches.touch(&key);
+                self.notify(notify::EXPIRED, "expired", &key);
+                purged += 1;
+            }
+        }
+        self.add_dirty(purged);
+        Stats::add(&self.stats.expired_keys, purged as u64);
+        purged
+    }
+
+    /// Takes a point-in-time view of the whole keyspace. Every shard is locked
+    /// at once, so no write can land between shards, but only for as long as
+    /// it takes to clone the persistent maps.
+    pub fn snapshot(&self) -> Snapshot {
+        let shards: Vec<_> = self.data.iter().map(|s| s.lock()).collect();
+        Snapshot {
+            shards: shards.iter().map(|s| Keyspace::clone(s)).collect(),
+            indexes: self.indexes.read().definitions(),
+            taken_at: Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Registers a new secondary index and backfills it from the existing keys.
+    /// All shards are held while the index is built so no write can slip in
+    /// between the backfill and the index going live.
+    pub fn create_index(&self, index: search::Index) -> Result<(), DataStoreError> {
+        let shards: Vec<_> = self.data.iter().map(|s| s.lock()).collect();
+        let mut indexes = self.indexes.write();
+        indexes
+            .create(index, shards.iter().flat_map(|s| s.entries.iter()))
+            .map_err(DataStoreError::InvalidInput)
+    }
+
+    pub fn indexes(&self) -> &RwLock<search::Indexes> {
+        &self.indexes
+    }
+
+    pub fn save_to(&self, path: &str) -> Result<(), DataStoreError> {
+        self.snapshot().save_to(path)
+    }
+
     pub fn load(&self) -> Result<(), DataStoreError> {
-        todo!()
+        self.load_from(&persistence::rdb_filename())
+    }
+
+    /// Loads an rdb file into db 0. A missing file means a fresh start.
+    pub fn load_from(&self, path: &str) -> Result<(), DataStoreError> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(_) => return Err(DataStoreError::FileIOError),
+        };
+        let file = rdb::decode(&bytes).map_err(|e| {
+            eprintln!("Failed to load {}: {}", path, e);
+            DataStoreError::DataLoadError
+        })?;
+        let now = Utc::now().timestamp_millis();
+        let mut skipped = 0;
+        for entry in file.entries {
+            if entry.db != 0 {
+                skipped += 1;
+                continue;
+            }
+            if entry.expire_at.is_some_and(|at| at <= now) {
+                continue;
+            }
+            let key = String::from_utf8_lossy(&entry.key).to_string();
+            self.restore(&key, entry.value, entry.expire_at);
+        }
+        if skipped > 0 {
+            eprintln!("Skipped {} keys stored outside db 0", skipped);
+        }
+        self.restore_indexes(&file.aux);
+        Ok(())
+    }
+}
+
+// the rdb aux field holding an index definition, as JSON, one per index
+const INDEX_AUX: &str = "ft-index";
+
+/// A consistent copy of every shard, as returned by [`Db::snapshot`].
+/// It shares structure with the live data, so holding one is cheap.
+pub struct Snapshot {
+    pub shards: Vec<Keyspace>,
+    /// the FT.CREATE arguments of the indexes
+    pub indexes: Vec<Vec<String>>,
+    /// unix time in milliseconds; keys expired by then are left out
+    pub taken_at: i64,
+}
+
+impl Snapshot {
+    /// Live keys with their values and expiry times.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Value, Option<i64>)> {
+        self.shards.iter().flat_map(move |data| {
+            data.entries
+                .iter()
+                .filter(move |(k, _)| !data.is_expired(k, self.taken_at))
+                .map(move |(k, v)| (k, v, data.expires.get(k).copied()))
+        })
+    }
+
+    pub fn to_rdb(&self) -> Vec<u8> {
+        let mut writer = rdb::RdbWriter::new(true);
+        writer.aux("redis-ver", env!("CARGO_PKG_VERSION"));
+        writer.aux("redis-bits", &(usize::BITS).to_string());
+        writer.aux("ctime", &(self.taken_at / 1000).to_string());
+        writer.aux("aof-base", "0");
+        for definition in &self.indexes {
+            let definition = serde_json::to_string(definition).unwrap_or_default();
+            writer.aux(INDEX_AUX, &definition);
+        }
+        let (keys, expires) = self.entries().fold((0, 0), |(keys, expires), (_, _, e)| {
+            (keys + 1, expires + e.is_some() as usize)
+        });
+        writer.select_db(0, keys, expires);
+        for (key, value, expire_at) in self.entries() {
+            writer.entry(key.as_bytes(), value, expire_at);
+        }
+        writer.finish()
+    }
+
+    /// Writes the snapshot as an rdb file. The previous file at `path` stays
+    /// intact until the new one is complete and on disk.
+    pub fn save_to(&self, path: &str) -> Result<(), DataStoreError> {
+        persistence::write_atomically(path, &self.to_rdb()).map_err(|e| {
+            eprintln!("Failed to write {}: {}", path, e);
+            DataStoreError::FileIOError
+        })
+    }
+}
+
+enum Expiry {
+    At(i64),
+    Persist,
+    Keep,
+}
+
+const XGROUP_NO_KEY: &str = "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+fn parse_expiry(ops: &[String]) -> Result<Expiry, DataStoreError> {
+    let mut expiry = Expiry::Persist;
+    let mut i = 0;
+    while i < ops.len() {
+        let op = ops[i].to_uppercase();
+        if op == "KEEPTTL" {
+            expiry = Expiry::Keep;
+        } else if matches!(op.as_str(), "EX" | "PX" | "EXAT" | "PXAT") {
+            let invalid =
+                || DataStoreError::InvalidInput("invalid expire time in 'set' command".to_string());
+            let n: i64 = ops
+                .get(i + 1)
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .ok_or_else(invalid)?;
+            let now = Utc::now().timestamp_millis();
+            let at = match op.as_str() {
+                "EX" => n.checked_mul(1000).and_then(|ms| ms.checked_add(now)),
+                "PX" => n.checked_add(now),
+                "EXAT" => n.checked_mul(1000),
+                _ => Some(n),
+            };
+            expiry = Expiry::At(at.ok_or_else(invalid)?);
+            i += 1;
+        }
+        i += 1;
     }
+    Ok(expiry)
 }
 
 lazy_static! {
@@ -101,7 +1310,7 @@ pub fn get_value(key: &str) -> Result<String, DataStoreError> {
         Some(mut data) => match data.get(key) {
             Some(v) if v.expire_at.is_none() => Ok(v.value.clone()),
             Some(v) if v.expire_at.is_some() && v.expire_at.unwrap() > now => Ok(v.value.clone()),
-            Some(v) => {
+            Some(_) => {
                 data.remove(key);
                 Err(DataStoreError::ExpiredKey)
             }
@@ -182,7 +1391,7 @@ pub fn set_value(
 
     let nx = options.iter().find(|x| x.to_uppercase() == "NX");
     let xx = options.iter().find(|x| x.to_uppercase() == "XX");
-    let keepttl = options.iter().find(|x| x.to_uppercase() == "KEEPTTL");
+    let _keepttl = options.iter().find(|x| x.to_uppercase() == "KEEPTTL");
     let get = options.iter().find(|x| x.to_uppercase() == "GET");
 
     match data {
@@ -265,7 +1474,7 @@ mod tests {
         let key = "key".to_string();
         let value = "value".to_string();
 
-        let lock = DATA.lock();
+        let _lock = DATA.lock();
         let result = set_value(key.clone(), value.clone(), vec![]);
         assert_eq!(result, Err(DataStoreError::LockError));
     }
@@ -287,4 +1496,73 @@ mod tests {
         let result = get_value(&key);
         assert_eq!(result, Err(DataStoreError::ExpiredKey));
     }
+
+    #[test]
+    fn test_save_and_load_rdb() {
+        let path = std::env::temp_dir().join(format!("dump-{}.rdb", std::process::id()));
+        let path = path.to_str().unwrap();
+        let db = Db::new(4);
+        db.set("plain", Bytes::from("value"), vec![]).unwrap();
+        db.set(
+            "ttl",
+            Bytes::from("soon"),
+            vec!["EX".to_string(), "100".to_string()],
+        )
+        .unwrap();
+        db.hset("doc", &[("field".to_string(), Bytes::from("42"))])
+            .unwrap();
+        let definition: Vec<String> = "idx PREFIX 1 doc SCHEMA field NUMERIC"
+            .split(' ')
+            .map(str::to_string)
+            .collect();
+        search::ft_create(&db, &definition).unwrap();
+        db.save_to(path).unwrap();
+
+        let loaded = Db::new(2);
+        loaded.load_from(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.get("plain"), Ok(Bytes::from("value")));
+        assert_eq!(loaded.expire_at("ttl"), db.expire_at("ttl"));
+        assert_eq!(loaded.hget("doc", "field"), Ok(Some(Bytes::from("42"))));
+        // indexes come back too
+        assert_eq!(loaded.indexes().read().definitions(), vec![definition]);
+    }
+
+    #[test]
+    fn test_lmove() {
+        let db = Db::new(4);
+        let values = |items: &[&str]| {
+            items
+                .iter()
+                .map(|i| Bytes::from(i.to_string()))
+                .collect::<Vec<_>>()
+        };
+        db.push("src", &values(&["a", "b"]), false).unwrap();
+        // a key in another shard, then one in the same shard
+        assert_eq!(
+            db.lmove("src", "dst1", true, false),
+            Ok(Some(Bytes::from("a")))
+        );
+        assert_eq!(
+            db.lmove("src", "dst", true, true),
+            Ok(Some(Bytes::from("b")))
+        );
+        assert_eq!(db.key_type("src"), None);
+        assert_eq!(db.lmove("src", "dst", true, true), Ok(None));
+
+        // rotating a list of one keeps it
+        assert_eq!(
+            db.lmove("dst", "dst", false, true),
+            Ok(Some(Bytes::from("b")))
+        );
+        assert_eq!(db.pop("dst", 10, true), Ok(values(&["b"])));
+
+        // nothing is popped when the destination isn't a list
+        db.set("str", Bytes::from("x"), vec![]).unwrap();
+        assert_eq!(
+            db.lmove("dst1", "str", true, true),
+            Err(DataStoreError::WrongType)
+        );
+        assert_eq!(db.pop("dst1", 10, true), Ok(values(&["a"])));
+    }
 }
@@ -7,7 +7,7 @@ use super::resp_value::RespType;
 // return (RespType, number of bytes consumed)
 pub fn deserialize(input: &[u8]) -> Result<(RespType, usize), DeserializeError> {
     let res;
-    match input.get(0) {
+    match input.first() {
         Some(&SIMPLE_STRING_PREFIX) => {
             let end_idx = input.iter().position(|&c| c == b'\r').unwrap();
             res = Ok(RespType::SimpleString(u8_to_string(&input[1..end_idx])));
@@ -35,13 +35,9 @@ pub fn deserialize(input: &[u8]) -> Result<(RespType, usize), DeserializeError>
             )))
         }
     }
-    if res.is_err() {
-        return Err(res.err().unwrap());
-    } else {
-        let res = res.unwrap();
-        let len = res.get_byte_length();
-        Ok((res, len))
-    }
+    let res = res?;
+    let len = res.get_byte_length();
+    Ok((res, len))
 }
 
 pub fn deserialize_bulk_string(input: &[u8]) -> Result<RespType, DeserializeError> {
@@ -80,7 +76,7 @@ pub fn deserialize_array(input: &[u8]) -> Result<RespType, DeserializeError> {
     if input.len() == NULL_ARRAY.len() && input[1] == b'-' && input[2] == b'1' {
         return Ok(RespType::Array(None));
     }
-    let (len, mut content_start_idx) = match get_length_and_content_start_idx(&input) {
+    let (len, mut content_start_idx) = match get_length_and_content_start_idx(input) {
         Ok((len, content_start_idx)) => (len, content_start_idx),
         Err(e) => return Err(e),
     };
@@ -139,6 +135,160 @@ fn get_length_and_content_start_idx(input: &[u8]) -> Result<(usize, usize), Dese
     Ok((len, crlf_idx + 2))
 }
 
+/// Largest bulk string accepted, redis's default proto-max-bulk-len.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// Parses one value from the front of a buffer that may hold only part of it,
+/// as read from a socket. Returns None until the whole value has arrived,
+/// otherwise the value and the number of bytes it took.
+pub fn parse_partial(input: &[u8]) -> Result<Option<(RespType, usize)>, DeserializeError> {
+    parse_partial_at(input, 0)
+}
+
+/// Longest line a client may send without its CRLF, redis's
+/// PROTO_INLINE_MAX_SIZE.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Most arguments a request may have.
+const MAX_REQUEST_ARGS: i64 = 1024 * 1024;
+
+// whether the line at `pos` has its CRLF yet, failing once it has grown too
+// long to be waited on
+fn line_complete(input: &[u8], pos: usize) -> Result<bool, DeserializeError> {
+    let window = &input[pos..input.len().min(pos + MAX_LINE_LEN + 2)];
+    if window.windows(2).any(|w| w == b"\r\n") {
+        return Ok(true);
+    }
+    if input.len() - pos > MAX_LINE_LEN {
+        return Err(DeserializeError::InvalidInput(
+            "too big request line".to_string(),
+        ));
+    }
+    Ok(false)
+}
+
+/// Parses one client request from the front of the buffer, like
+/// `parse_partial`, but only in the shape clients send: an array of bulk
+/// strings. Anything else inside the array is an error, so a request can't
+/// nest without bound, and neither can a line grow without its CRLF.
+pub fn parse_request(input: &[u8]) -> Result<Option<(RespType, usize)>, DeserializeError> {
+    if input.is_empty() || !line_complete(input, 0)? {
+        return Ok(None);
+    }
+    if input[0] != ARRAY_PREFIX {
+        return parse_partial_at(input, 0);
+    }
+    let crlf_idx = input.windows(2).position(|w| w == b"\r\n").unwrap_or(0);
+    let line = String::from_utf8_lossy(&input[1..crlf_idx]);
+    let len = match line.parse::<i64>() {
+        Ok(len) if len <= MAX_REQUEST_ARGS => len,
+        _ => {
+            return Err(DeserializeError::InvalidInput(
+                "invalid multibulk length".to_string(),
+            ))
+        }
+    };
+    let mut pos = crlf_idx + 2;
+    if len < 0 {
+        return Ok(Some((RespType::Array(None), pos)));
+    }
+    let mut items = Vec::with_capacity((len as usize).min(1024));
+    for _ in 0..len {
+        match input.get(pos) {
+            None => return Ok(None),
+            Some(&BULK_STRING_PREFIX) => {}
+            Some(&other) => {
+                return Err(DeserializeError::InvalidInput(format!(
+                    "expected '$', got '{}'",
+                    other as char
+                )))
+            }
+        }
+        if !line_complete(input, pos)? {
+            return Ok(None);
+        }
+        match parse_partial_at(input, pos)? {
+            Some((item, end)) => {
+                items.push(item);
+                pos = end;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((RespType::Array(Some(items)), pos)))
+}
+
+// returns the value and the position after it
+fn parse_partial_at(
+    input: &[u8],
+    pos: usize,
+) -> Result<Option<(RespType, usize)>, DeserializeError> {
+    let Some(&prefix) = input.get(pos) else {
+        return Ok(None);
+    };
+    let Some(crlf_idx) = input[pos..].windows(2).position(|w| w == b"\r\n") else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(&input[pos + 1..pos + crlf_idx]).to_string();
+    let next = pos + crlf_idx + 2;
+    let number = || {
+        line.parse::<i64>().map_err(|_| {
+            DeserializeError::InvalidInput(format!("Failed to parse {:?} into integer", line))
+        })
+    };
+    let value = match prefix {
+        SIMPLE_STRING_PREFIX => (RespType::SimpleString(line.clone()), next),
+        ERROR_PREFIX => (RespType::Error(line.clone()), next),
+        INTEGER_PREFIX => (RespType::Integer(number()?), next),
+        BULK_STRING_PREFIX => {
+            let len = number()?;
+            if len < 0 {
+                return Ok(Some((RespType::BulkString(None), next)));
+            }
+            if len > MAX_BULK_LEN {
+                return Err(DeserializeError::InvalidInput(
+                    "invalid bulk length".to_string(),
+                ));
+            }
+            let end = next + len as usize;
+            if input.len() < end + 2 {
+                return Ok(None);
+            }
+            if &input[end..end + 2] != b"\r\n" {
+                return Err(DeserializeError::LengthMismatch(
+                    "Bulk string length does not match content length".to_string(),
+                ));
+            }
+            let content = Bytes::copy_from_slice(&input[next..end]);
+            (RespType::BulkString(Some(content)), end + 2)
+        }
+        ARRAY_PREFIX => {
+            let len = number()?;
+            if len < 0 {
+                return Ok(Some((RespType::Array(None), next)));
+            }
+            let mut items = Vec::with_capacity((len as usize).min(1024));
+            let mut pos = next;
+            for _ in 0..len {
+                match parse_partial_at(input, pos)? {
+                    Some((item, item_end)) => {
+                        items.push(item);
+                        pos = item_end;
+                    }
+                    None => return Ok(None),
+                }
+            }
+            (RespType::Array(Some(items)), pos)
+        }
+        _ => {
+            return Err(DeserializeError::InvalidInput(format!(
+                "unexpected '{}'",
+                prefix as char
+            )))
+        }
+    };
+    Ok(Some(value))
+}
+
 fn u8_to_string(input: &[u8]) -> String {
     match std::str::from_utf8(input) {
         Ok(s) => s.to_string(),
@@ -253,6 +403,60 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_parse_partial() {
+            let input = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n+OK\r\n";
+            for end in 0..24 {
+                assert_eq!(parse_partial(&input[..end]).unwrap(), None);
+            }
+            let expected = RespType::Array(Some(vec![
+                RespType::BulkString(Some(Bytes::from("GET"))),
+                RespType::BulkString(Some(Bytes::from("hello"))),
+            ]));
+            assert_eq!(parse_partial(input).unwrap(), Some((expected, 24)));
+            assert_eq!(
+                parse_partial(&input[24..]).unwrap(),
+                Some((RespType::SimpleString("OK".to_string()), 5))
+            );
+            assert!(parse_partial(b"$3\r\nhello\r\n").is_err());
+            assert!(parse_partial(b"!oops\r\n").is_err());
+        }
+
+        #[test]
+        fn test_parse_request() {
+            let input = b"*2\r\n$3\r\nGET\r\n$5\r\nhello\r\n";
+            for end in 0..input.len() {
+                assert_eq!(parse_request(&input[..end]).unwrap(), None);
+            }
+            let expected = RespType::Array(Some(vec![
+                RespType::BulkString(Some(Bytes::from("GET"))),
+                RespType::BulkString(Some(Bytes::from("hello"))),
+            ]));
+            assert_eq!(parse_request(input).unwrap(), Some((expected, 24)));
+
+            // nesting is refused at the first element that isn't a bulk
+            // string, however deep it would go
+            let nested = b"*1\r\n".repeat(200_000);
+            assert_eq!(
+                parse_request(&nested),
+                Err(DeserializeError::InvalidInput(
+                    "expected '$', got '*'".to_string()
+                ))
+            );
+            assert!(parse_request(b"*2\r\n$3\r\nGET\r\n:1\r\n").is_err());
+            assert!(parse_request(b"*99999999\r\n").is_err());
+
+            // a line without its CRLF is waited on up to the limit
+            let mut line = b"*".to_vec();
+            line.extend(b"1".repeat(MAX_LINE_LEN - 1));
+            assert_eq!(parse_request(&line).unwrap(), None);
+            line.extend(b"11");
+            assert!(parse_request(&line).is_err());
+            let mut bulk = b"*1\r\n$".to_vec();
+            bulk.extend(b"1".repeat(MAX_LINE_LEN + 1));
+            assert!(parse_request(&bulk).is_err());
+        }
+
         #[test]
         fn test_deserialize_array_actual_length_too_short() {
             let input = b"*5\r\n+foo\r\n-bar\r\n+foo\r\n";
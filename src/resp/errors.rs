@@ -31,6 +31,8 @@ pub enum DataStoreError {
     FileIOError,
     DataLoadError,
     ExpiredKey,
+    WrongType,
+    BusyKey,
     InvalidInput(String),
 }
 
@@ -45,20 +47,20 @@ impl Display for DataStoreError {
             DataStoreError::FileIOError => write!(f, "Failed to read/write file"),
             DataStoreError::DataLoadError => write!(f, "Failed to load data"),
             DataStoreError::ExpiredKey => write!(f, "Key has expired"),
+            DataStoreError::WrongType => {
+                write!(f, "Operation against a key holding the wrong kind of value")
+            }
+            DataStoreError::BusyKey => write!(f, "Target key name already exists"),
             DataStoreError::InvalidInput(s) => write!(f, "Invalid input: {}", s),
         }
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq)]
 pub enum ServerError {
-    AcceptError,
-    ReadError,
-    RespParseError(String),
     TypeError,
-    DataStoreError(DataStoreError),
     UserInputError(UserInputError),
-    ConfigError(String),
 }
 
 impl Error for ServerError {}
@@ -66,13 +68,8 @@ impl Error for ServerError {}
 impl Display for ServerError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            ServerError::AcceptError => write!(f, "Failed to accept connection"),
-            ServerError::ReadError => write!(f, "Failed to read from stream"),
-            ServerError::RespParseError(s) => write!(f, "Failed to parse RESP {}", s),
             ServerError::TypeError => write!(f, "Type error"),
-            ServerError::DataStoreError(e) => write!(f, "Data store error: {}", e),
             ServerError::UserInputError(e) => write!(f, "User input error: {}", e),
-            ServerError::ConfigError(e) => write!(f, "Config error: {}", e),
         }
     }
 }
@@ -95,3 +92,139 @@ impl Display for UserInputError {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RdbError {
+    Corrupt { offset: usize, reason: String },
+    Unsupported { offset: usize, reason: String },
+    ChecksumMismatch { expected: u64, actual: u64 },
+}
+
+impl RdbError {
+    /// Byte offset in the file where decoding stopped, when known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            RdbError::Corrupt { offset, .. } | RdbError::Unsupported { offset, .. } => {
+                Some(*offset)
+            }
+            RdbError::ChecksumMismatch { .. } => None,
+        }
+    }
+}
+
+impl Error for RdbError {}
+
+impl Display for RdbError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RdbError::Corrupt { offset, reason } => {
+                write!(f, "Corrupt rdb at offset {}: {}", offset, reason)
+            }
+            RdbError::Unsupported { offset, reason } => {
+                write!(
+                    f,
+                    "Unsupported rdb content at offset {}: {}",
+                    offset, reason
+                )
+            }
+            RdbError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Rdb checksum mismatch: expected {:016x}, got {:016x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AofError {
+    Io(String),
+    /// the file ends in the middle of a command
+    Truncated {
+        offset: usize,
+    },
+    Corrupt {
+        offset: usize,
+        reason: String,
+    },
+    /// a logged command failed when replayed
+    Command {
+        offset: usize,
+        reason: String,
+    },
+    Rdb(RdbError),
+}
+
+impl AofError {
+    /// Byte offset in the file where loading stopped, when known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            AofError::Truncated { offset }
+            | AofError::Corrupt { offset, .. }
+            | AofError::Command { offset, .. } => Some(*offset),
+            AofError::Rdb(e) => e.offset(),
+            AofError::Io(_) => None,
+        }
+    }
+}
+
+impl Error for AofError {}
+
+impl Display for AofError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AofError::Io(e) => write!(f, "Aof io error: {}", e),
+            AofError::Truncated { offset } => {
+                write!(f, "Unexpected end of aof file at offset {}", offset)
+            }
+            AofError::Corrupt { offset, reason } => {
+                write!(f, "Bad aof format at offset {}: {}", offset, reason)
+            }
+            AofError::Command { offset, reason } => {
+                write!(
+                    f,
+                    "Failed to replay aof command at offset {}: {}",
+                    offset, reason
+                )
+            }
+            AofError::Rdb(e) => write!(f, "Bad rdb preamble in aof: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Io {
+        path: String,
+        reason: String,
+    },
+    /// a directive that could not be applied, with where it was read from
+    Directive {
+        path: String,
+        line: usize,
+        text: String,
+        reason: String,
+    },
+}
+
+impl Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, reason } => {
+                write!(f, "Fatal error, can't open config file '{}': {}", path, reason)
+            }
+            ConfigError::Directive {
+                path,
+                line,
+                text,
+                reason,
+            } => write!(
+                f,
+                "*** FATAL CONFIG FILE ERROR ***\nReading the configuration file {}, at line {}\n>>> '{}'\n{}",
+                path, line, text, reason
+            ),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+/// Matches `text` against a redis-style glob pattern: `*` matches any run of
+/// bytes, `?` any single byte, `[abc]`, `[^abc]` and `[a-z]` a set of bytes,
+/// and `\` escapes the next byte.
+pub fn glob_match(pattern: &[u8], text: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    let (mut p, mut t) = (0, 0);
+    // where to resume after the last `*`: pattern position and text position
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                star = Some((p, t));
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p, text[t], nocase),
+            Some(b'\\') if p + 1 < pattern.len() => eq(pattern[p + 1], text[t]).then_some(p + 2),
+            Some(&c) => eq(c, text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (matched, star) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            // let the last `*` take one more byte and try again from there
+            (None, Some((star_p, star_t))) => {
+                p = star_p;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+// matches one byte against the `[...]` class starting at `start`, returning the
+// pattern position after the class
+fn match_class(pattern: &[u8], start: usize, c: u8, nocase: bool) -> Option<usize> {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c = fold(c);
+    let mut p = start + 1;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+    let mut found = false;
+    loop {
+        match pattern.get(p) {
+            // an unterminated class ends with the pattern, as in redis
+            None => break,
+            Some(b']') => {
+                p += 1;
+                break;
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                found |= fold(pattern[p + 1]) == c;
+                p += 2;
+            }
+            Some(&low) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let (low, high) = (fold(low), fold(pattern[p + 2]));
+                found |= (low.min(high)..=low.max(high)).contains(&c);
+                p += 3;
+            }
+            Some(&member) => {
+                found |= fold(member) == c;
+                p += 1;
+            }
+        }
+    }
+    (found != negate).then_some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        let matches =
+            |pattern: &str, text: &str| glob_match(pattern.as_bytes(), text.as_bytes(), false);
+        assert!(matches("*", ""));
+        assert!(matches("h?llo", "hello"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(matches("*max*", "maxmemory-policy"));
+        assert!(matches("a\\*b", "a*b"));
+        assert!(!matches("a\\*b", "axb"));
+        assert!(matches("*a*b*", "xxaxxbxx"));
+        assert!(!matches("*a*b", "xxaxxbxx"));
+        assert!(glob_match(b"MAX*", b"maxmemory", true));
+        assert!(!glob_match(b"MAX*", b"maxmemory", false));
+    }
+}
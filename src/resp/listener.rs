@@ -0,0 +1,210 @@
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::unix::fs::PermissionsExt,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, UnixListener};
+
+use super::{acl, redisconfig};
+
+/// What a client connecting from outside gets while protected mode is on.
+pub const PROTECTED_MODE_ERROR: &str = "DENIED Redis is running in protected mode because protected mode is enabled and no password is set for the default user. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. 4) Set up an authentication password for the default user. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.";
+
+/// One address from the `bind` directive.
+#[derive(Debug, PartialEq)]
+pub struct BindAddress {
+    pub ip: IpAddr,
+    /// a `-` prefix: the server starts anyway if the address isn't available,
+    /// as on hosts without IPv6
+    pub optional: bool,
+}
+
+/// Reads the `bind` directive, where `*` stands for every IPv4 address and
+/// `::*` for every IPv6 one.
+pub fn parse_bind(value: &str) -> Result<Vec<BindAddress>, String> {
+    value
+        .split_whitespace()
+        .map(|word| {
+            let (optional, address) = match word.strip_prefix('-') {
+                Some(address) => (true, address),
+                None => (false, word),
+            };
+            let ip = match address {
+                "*" => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                "::*" => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                _ => address
+                    .parse()
+                    .map_err(|_| format!("Invalid bind address '{}'", word))?,
+            };
+            Ok(BindAddress { ip, optional })
+        })
+        .collect()
+}
+
+/// Opens a listener on `port` for every configured bind address, reporting
+/// the ones that fail. A port of 0 means not listening on TCP at all.
+pub fn listen_tcp(port: u16) -> io::Result<Vec<TcpListener>> {
+    if port == 0 {
+        return Ok(vec![]);
+    }
+    let bind = redisconfig::get_config("bind").unwrap_or_default();
+    let addresses = parse_bind(&bind).map_err(io::Error::other)?;
+    let backlog = redisconfig::get_int("tcp-backlog") as i32;
+    let mut listeners = vec![];
+    for address in addresses {
+        let addr = SocketAddr::new(address.ip, port);
+        match listen_on(addr, backlog) {
+            Ok(listener) => listeners.push(listener),
+            // a missing address family or address, not one that is taken
+            Err(e)
+                if address.optional
+                    && !matches!(
+                        e.kind(),
+                        io::ErrorKind::AddrInUse | io::ErrorKind::PermissionDenied
+                    ) =>
+            {
+                if redisconfig::log_enabled("warning") {
+                    eprintln!(
+                        "Warning: Could not create server TCP listening socket {}: {}",
+                        addr, e
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "Could not create server TCP listening socket {}: {}",
+                    addr, e
+                );
+                return Err(e);
+            }
+        }
+    }
+    Ok(listeners)
+}
+
+fn listen_on(addr: SocketAddr, backlog: i32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // like redis, keep IPv6 sockets to IPv6 so `* ::*` can bind both
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Opens the `unixsocket` listener, if one is configured. A file left at the
+/// path by an earlier run is replaced, and the socket gets `unixsocketperm`
+/// when that is set.
+pub fn listen_unix() -> io::Result<Option<UnixListener>> {
+    let path = redisconfig::get_config("unixsocket").unwrap_or_default();
+    if path.is_empty() {
+        return Ok(None);
+    }
+    let perm = redisconfig::get_config("unixsocketperm")
+        .and_then(|perm| u32::from_str_radix(&perm, 8).ok())
+        .unwrap_or(0);
+    bind_unix(&path, perm).map(Some).inspect_err(|e| {
+        eprintln!("Failed opening Unix socket {}: {}", path, e);
+    })
+}
+
+fn bind_unix(path: &str, perm: u32) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if perm != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(perm))?;
+    }
+    Ok(listener)
+}
+
+/// Whether protected mode turns away a client connecting from `peer`: it is
+/// on and the default user has no password, and the client is not on the
+/// loopback interface.
+pub fn protected_mode_refuses(peer: IpAddr) -> bool {
+    redisconfig::get_bool("protected-mode")
+        && acl::default_user_nopass()
+        && !peer.to_canonical().is_loopback()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bind() {
+        assert_eq!(
+            parse_bind("* -::*").unwrap(),
+            vec![
+                BindAddress {
+                    ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    optional: false
+                },
+                BindAddress {
+                    ip: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                    optional: true
+                },
+            ]
+        );
+        assert_eq!(
+            parse_bind("127.0.0.1 -::1").unwrap(),
+            vec![
+                BindAddress {
+                    ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    optional: false
+                },
+                BindAddress {
+                    ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    optional: true
+                },
+            ]
+        );
+        assert_eq!(
+            parse_bind("10.0.0.1 example.com"),
+            Err("Invalid bind address 'example.com'".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_listen_on() {
+        let listener = listen_on("127.0.0.1:0".parse().unwrap(), 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        // the same port is still free on IPv6, where the host has it
+        if let Ok(v6) = listen_on(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port()), 16) {
+            assert!(v6.local_addr().unwrap().is_ipv6());
+        }
+        let client = tokio::net::TcpStream::connect(addr);
+        let (accepted, _) = tokio::join!(listener.accept(), client);
+        assert!(accepted.unwrap().1.ip().is_loopback());
+    }
+
+    #[tokio::test]
+    async fn test_bind_unix() {
+        let path = std::env::temp_dir().join(format!("listener-test-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, "stale").unwrap();
+        let listener = bind_unix(path, 0o700).unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let client = tokio::net::UnixStream::connect(path);
+        let (accepted, connected) = tokio::join!(listener.accept(), client);
+        assert!(accepted.is_ok() && connected.is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_protected_mode() {
+        assert!(protected_mode_refuses("10.1.2.3".parse().unwrap()));
+        assert!(protected_mode_refuses("2001:db8::1".parse().unwrap()));
+        assert!(!protected_mode_refuses("127.0.0.1".parse().unwrap()));
+        assert!(!protected_mode_refuses("::1".parse().unwrap()));
+        assert!(!protected_mode_refuses("::ffff:127.0.0.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,153 @@
+// LZF compression in the liblzf format redis uses for rdb strings.
+//
+// The stream is a sequence of chunks, each starting with a control byte:
+//   000lllll                      literal run of l+1 bytes
+//   lllooooo oooooooo             back reference, l = length-2 (1..=6)
+//   111ooooo llllllll oooooooo    back reference, length-2 = 7 + l
+// where the offset o points o+1 bytes back into the output.
+
+const HASH_LOG: usize = 14;
+const MAX_LITERAL: usize = 32;
+const MAX_OFFSET: usize = 1 << 13;
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+
+fn hash(b: &[u8]) -> usize {
+    let v = ((b[0] as usize) << 16) | ((b[1] as usize) << 8) | b[2] as usize;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) & ((1 << HASH_LOG) - 1)
+}
+
+/// Compresses `input`, returning None when the result would not be smaller.
+pub fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    if input.len() < 4 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(input.len());
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    // index of the control byte for the literal run being built
+    let mut lit_ctrl = 0;
+    let mut lit = 0;
+    out.push(0);
+    let mut i = 0;
+    while i < input.len() {
+        let candidate = if i + 2 < input.len() {
+            let h = hash(&input[i..]);
+            let r = table[h];
+            table[h] = i;
+            (r != usize::MAX && i - r - 1 < MAX_OFFSET && input[r..r + 3] == input[i..i + 3])
+                .then_some(r)
+        } else {
+            None
+        };
+        match candidate {
+            Some(r) => {
+                let max_len = MAX_REF.min(input.len() - i);
+                let mut len = 3;
+                while len < max_len && input[r + len] == input[i + len] {
+                    len += 1;
+                }
+                if lit > 0 {
+                    out[lit_ctrl] = (lit - 1) as u8;
+                } else {
+                    out.pop();
+                }
+                let off = i - r - 1;
+                let l = len - 2;
+                if l < 7 {
+                    out.push(((l << 5) | (off >> 8)) as u8);
+                } else {
+                    out.push(((7 << 5) | (off >> 8)) as u8);
+                    out.push((l - 7) as u8);
+                }
+                out.push(off as u8);
+                i += len;
+                lit_ctrl = out.len();
+                lit = 0;
+                out.push(0);
+            }
+            None => {
+                out.push(input[i]);
+                lit += 1;
+                i += 1;
+                if lit == MAX_LITERAL {
+                    out[lit_ctrl] = (lit - 1) as u8;
+                    lit_ctrl = out.len();
+                    lit = 0;
+                    out.push(0);
+                }
+            }
+        }
+        if out.len() >= input.len() {
+            return None;
+        }
+    }
+    if lit > 0 {
+        out[lit_ctrl] = (lit - 1) as u8;
+    } else {
+        out.pop();
+    }
+    (out.len() < input.len()).then_some(out)
+}
+
+/// Decompresses `input`, which must expand to exactly `out_len` bytes.
+pub fn decompress(input: &[u8], out_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(out_len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < MAX_LITERAL {
+            let run = input.get(i..i + ctrl + 1)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += *input.get(i)? as usize;
+                i += 1;
+            }
+            let off = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+            let start = out.len().checked_sub(off)?;
+            // byte by byte, as a reference may overlap the bytes it produces
+            for k in 0..len + 2 {
+                out.push(out[start + k]);
+            }
+        }
+        if out.len() > out_len {
+            return None;
+        }
+    }
+    (out.len() == out_len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+            b"hello world, hello world, hello world, hello again".repeat(40),
+            (0..5000u32).map(|i| (i * 7 % 13) as u8).collect(),
+        ];
+        for input in inputs {
+            let compressed = compress(&input).expect("input should compress");
+            assert!(compressed.len() < input.len());
+            assert_eq!(decompress(&compressed, input.len()), Some(input));
+        }
+    }
+
+    #[test]
+    fn test_incompressible() {
+        assert_eq!(compress(b"abcdefgh"), None);
+    }
+
+    #[test]
+    fn test_decompress_overlapping_reference() {
+        // "abc" literal, an 8 byte overlapping reference 3 back, then "c"
+        let compressed = [0x02, b'a', b'b', b'c', 0xc0, 0x02, 0x00, b'c'];
+        assert_eq!(decompress(&compressed, 12), Some(b"abcabcabcabc".to_vec()));
+        assert_eq!(decompress(&compressed, 11), None);
+    }
+}
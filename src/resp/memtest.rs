@@ -0,0 +1,70 @@
+use std::hint::black_box;
+
+/// A test pattern: the word to write at an index, given the pass number.
+type Pattern = fn(u64, usize) -> u64;
+
+const PATTERNS: [(&str, Pattern); 4] = [
+    ("addressing", |_, i| i as u64),
+    ("solid 0x55", |_, _| 0x5555_5555_5555_5555),
+    ("solid 0xaa", |_, _| 0xaaaa_aaaa_aaaa_aaaa),
+    ("random", |pass, i| xorshift(pass ^ i as u64)),
+];
+
+/// Writes patterns over `megabytes` of memory and reads them back, like
+/// `redis-server --test-memory`, printing what it does. Returns whether
+/// every pass read back what it wrote.
+pub fn test_memory(megabytes: usize, passes: usize) -> bool {
+    let len = megabytes * 1024 * 1024 / 8;
+    let mut words: Vec<u64> = Vec::new();
+    if words.try_reserve_exact(len).is_err() {
+        eprintln!("Unable to allocate {} megabytes", megabytes);
+        return false;
+    }
+    words.resize(len, 0);
+    for pass in 1..=passes {
+        println!("Pass {}/{}", pass, passes);
+        for (name, pattern) in PATTERNS {
+            if let Some(at) = fill_and_check(&mut words, |i| pattern(pass as u64, i)) {
+                println!(
+                    "*** MEMORY ERROR DETECTED: {} test failed at word {}",
+                    name, at
+                );
+                return false;
+            }
+        }
+    }
+    println!("Your memory passed this test.");
+    true
+}
+
+// writes the pattern over every word, then returns the first word that does
+// not read back as written; black_box keeps the compiler from answering from
+// what it knows was just stored
+fn fill_and_check(words: &mut [u64], pattern: impl Fn(usize) -> u64) -> Option<usize> {
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = pattern(i);
+    }
+    let words = black_box(words);
+    words
+        .iter()
+        .enumerate()
+        .position(|(i, word)| *word != pattern(i))
+}
+
+fn xorshift(seed: u64) -> u64 {
+    let mut x = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_passes() {
+        assert!(test_memory(1, 1));
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use super::{
+    datastore::Db, deserialize::parse_partial, errors::UserInputError, redisconfig,
+    resp_value::RespType,
+};
+
+/// Cached connections unused for this long are closed by the cron.
+const CONN_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Most connections kept around across all targets, as in redis.
+const MAX_CACHED_CONNS: usize = 64;
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+lazy_static! {
+    static ref CONNECTIONS: ConnectionPool = ConnectionPool::default();
+}
+
+/// A blocking RESP connection to another instance.
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    last_used: Instant,
+}
+
+impl Connection {
+    fn connect(addr: &str, timeout: Duration) -> io::Result<Self> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return Ok(Connection {
+                        stream,
+                        buf: Vec::new(),
+                        last_used: Instant::now(),
+                    });
+                }
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Writes all the commands in one go, leaving the replies to be read.
+    fn send(&mut self, commands: &[Vec<Bytes>], timeout: Duration) -> io::Result<()> {
+        let mut out = Vec::new();
+        for args in commands {
+            let args = args
+                .iter()
+                .map(|a| RespType::BulkString(Some(a.clone())))
+                .collect();
+            out.extend_from_slice(&RespType::Array(Some(args)).serialize());
+        }
+        self.stream.set_write_timeout(Some(timeout))?;
+        self.stream.write_all(&out)
+    }
+
+    fn read_reply(&mut self, timeout: Duration) -> io::Result<RespType> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            match parse_partial(&self.buf) {
+                Ok(Some((reply, consumed))) => {
+                    self.buf.drain(..consumed);
+                    return Ok(reply);
+                }
+                Ok(None) => {}
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            }
+            match self.stream.read(&mut chunk)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+}
+
+/// Idle connections to other instances, keyed by "host:port". A connection is
+/// taken out while in use so concurrent migrations never share one.
+#[derive(Default)]
+struct ConnectionPool {
+    idle: Mutex<HashMap<String, Vec<Connection>>>,
+}
+
+impl ConnectionPool {
+    /// Returns a cached connection, flagged as such, or opens a new one.
+    fn get(&self, addr: &str, timeout: Duration) -> io::Result<(Connection, bool)> {
+        if let Some(conn) = self.idle.lock().get_mut(addr).and_then(|conns| conns.pop()) {
+            return Ok((conn, true));
+        }
+        Ok((Connection::connect(addr, timeout)?, false))
+    }
+
+    fn put_back(&self, addr: &str, mut conn: Connection) {
+        let mut idle = self.idle.lock();
+        if idle.values().map(Vec::len).sum::<usize>() >= MAX_CACHED_CONNS {
+            return;
+        }
+        conn.last_used = Instant::now();
+        idle.entry(addr.to_string()).or_default().push(conn);
+    }
+
+    fn close_idle(&self) {
+        let mut idle = self.idle.lock();
+        for conns in idle.values_mut() {
+            conns.retain(|c| c.last_used.elapsed() < CONN_IDLE_TIMEOUT);
+        }
+        idle.retain(|_, conns| !conns.is_empty());
+    }
+}
+
+/// Closes connections to migration targets that have been idle for a while.
+pub fn cron() {
+    CONNECTIONS.close_idle();
+}
+
+struct Options {
+    addr: String,
+    keys: Vec<String>,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    auth: Option<Vec<Bytes>>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, RespType> {
+    let error = |msg: &str| RespType::Error(format!("ERR {}", msg));
+    let not_an_integer = || error("value is not an integer or out of range");
+    let port: u16 = args[1].parse().map_err(|_| not_an_integer())?;
+    let db: i64 = args[3].parse().map_err(|_| not_an_integer())?;
+    let timeout: i64 = args[4].parse().map_err(|_| not_an_integer())?;
+    // there is a single database on either side
+    if db != 0 {
+        return Err(error("DB index is out of range"));
+    }
+    let timeout = match timeout {
+        t if t <= 0 => DEFAULT_TIMEOUT_MS,
+        t => t as u64,
+    };
+    let mut options = Options {
+        addr: format!("{}:{}", args[0], port),
+        keys: vec![args[2].clone()],
+        timeout: Duration::from_millis(timeout),
+        copy: false,
+        replace: false,
+        auth: None,
+    };
+    let mut rest = args[5..].iter();
+    while let Some(option) = rest.next() {
+        let mut value = || rest.next().cloned().ok_or_else(|| error("syntax error"));
+        match option.to_lowercase().as_str() {
+            "copy" => options.copy = true,
+            "replace" => options.replace = true,
+            "auth" => options.auth = Some(vec![Bytes::from("AUTH"), Bytes::from(value()?)]),
+            "auth2" => {
+                let user = value()?;
+                let password = value()?;
+                options.auth = Some(vec![
+                    Bytes::from("AUTH"),
+                    Bytes::from(user),
+                    Bytes::from(password),
+                ]);
+            }
+            "keys" => {
+                if !args[2].is_empty() {
+                    return Err(error(
+                        "When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                    ));
+                }
+                options.keys = rest.by_ref().cloned().collect();
+            }
+            _ => return Err(error("syntax error")),
+        }
+    }
+    Ok(options)
+}
+
+enum Failure {
+    /// the connection broke before any reply came back; safe to retry
+    Send(io::Error),
+    Io(RespType),
+    Target(String),
+}
+
+/// Sends the keys as RESTORE commands on one connection and collects the ones
+/// the target accepted in `accepted`.
+fn transfer(
+    options: &Options,
+    conn: &mut Connection,
+    batch: &[(String, Vec<Bytes>)],
+    accepted: &mut Vec<String>,
+) -> Result<(), Failure> {
+    let mut commands: Vec<Vec<Bytes>> = options.auth.iter().cloned().collect();
+    commands.extend(batch.iter().map(|(_, restore)| restore.clone()));
+    conn.send(&commands, options.timeout)
+        .map_err(Failure::Send)?;
+    let mut first_error = None;
+    if options.auth.is_some() {
+        let reply = conn.read_reply(options.timeout).map_err(Failure::Send)?;
+        if let RespType::Error(e) = reply {
+            first_error = Some(e);
+        }
+    }
+    let mut replied = false;
+    for (key, _) in batch {
+        let reply = match conn.read_reply(options.timeout) {
+            Ok(reply) => reply,
+            Err(e) if !replied => return Err(Failure::Send(e)),
+            Err(_) => {
+                return Err(Failure::Io(RespType::Error(
+                    "IOERR error or timeout reading to target instance".to_string(),
+                )))
+            }
+        };
+        replied = true;
+        match reply {
+            RespType::Error(e) => {
+                first_error.get_or_insert(e);
+            }
+            _ => accepted.push(key.clone()),
+        }
+    }
+    match first_error {
+        Some(e) => Err(Failure::Target(e)),
+        None => Ok(()),
+    }
+}
+
+/// Runs blocking network I/O without stalling the other tasks of the tokio
+/// worker it is called on, such as the one serving the RESTOREs when the
+/// target is this very instance.
+fn blocking<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Deletes the keys the target accepted and logs the deletes, under the AOF
+/// lock so the log follows the order writes were applied, and outside any
+/// transaction.
+fn delete_migrated(db: &Db, keys: &[String]) -> Option<RespType> {
+    let exec_lock = db.exec_lock();
+    let _running = exec_lock.read();
+    let mut aof = db.aof().lock();
+    if db.del(keys) == 0 {
+        return None;
+    }
+    let args: Vec<Bytes> = [Bytes::from("DEL")]
+        .into_iter()
+        .chain(keys.iter().map(|key| Bytes::from(key.clone())))
+        .collect();
+    match aof.as_mut()?.append(&args) {
+        Ok(()) => None,
+        Err(e) => {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Failed to write to the AOF: {}", e);
+            }
+            Some(RespType::Error(format!(
+                "MISCONF Errors writing to the AOF file: {}",
+                e
+            )))
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+/// [AUTH password] [AUTH2 username password] [KEYS key ...]
+///
+/// Each key is sent to the target with its remaining ttl as a RESTORE, over a
+/// pooled connection, and only removed here once the target replied OK. No
+/// lock is held while talking to the target.
+pub fn migrate(db: &Db, args: &[String]) -> Result<RespType, UserInputError> {
+    if args.len() < 5 {
+        return Err(UserInputError::InvalidInput(
+            "wrong number of arguments for 'migrate' command".to_string(),
+        ));
+    }
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(reply) => return Ok(reply),
+    };
+    let now = Utc::now().timestamp_millis();
+    let batch: Vec<(String, Vec<Bytes>)> = options
+        .keys
+        .iter()
+        .filter_map(|key| {
+            let payload = db.dump(key)?;
+            let ttl = match db.expire_at(key) {
+                Some(at) if at <= now => return None,
+                Some(at) => at - now,
+                None => 0,
+            };
+            let mut restore = vec![
+                Bytes::from("RESTORE"),
+                Bytes::from(key.clone()),
+                Bytes::from(ttl.to_string()),
+                Bytes::from(payload),
+            ];
+            if options.replace {
+                restore.push(Bytes::from("REPLACE"));
+            }
+            Some((key.clone(), restore))
+        })
+        .collect();
+    if batch.is_empty() {
+        return Ok(RespType::SimpleString("NOKEY".to_string()));
+    }
+
+    let mut accepted = Vec::new();
+    let reply = blocking(|| send_batch(&options, &batch, &mut accepted));
+    if options.copy {
+        return Ok(reply);
+    }
+    Ok(delete_migrated(db, &accepted).unwrap_or(reply))
+}
+
+fn send_batch(
+    options: &Options,
+    batch: &[(String, Vec<Bytes>)],
+    accepted: &mut Vec<String>,
+) -> RespType {
+    let mut may_retry = true;
+    loop {
+        let (mut conn, cached) = match CONNECTIONS.get(&options.addr, options.timeout) {
+            Ok(conn) => conn,
+            Err(_) => {
+                return RespType::Error(
+                    "IOERR error or timeout connecting to the client".to_string(),
+                )
+            }
+        };
+        return match transfer(options, &mut conn, batch, accepted) {
+            Ok(()) => {
+                CONNECTIONS.put_back(&options.addr, conn);
+                RespType::SimpleString("OK".to_string())
+            }
+            Err(Failure::Target(e)) => {
+                CONNECTIONS.put_back(&options.addr, conn);
+                RespType::Error(format!("ERR Target instance replied with error: {}", e))
+            }
+            // a cached connection may have been closed by the other side
+            // while idle, so one that fails straight away is retried once
+            Err(Failure::Send(e)) if cached && may_retry && !is_timeout(&e) => {
+                may_retry = false;
+                continue;
+            }
+            Err(Failure::Send(_)) => {
+                RespType::Error("IOERR error or timeout writing to target instance".to_string())
+            }
+            Err(Failure::Io(reply)) => reply,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::resp::{
+        aof::{self, parse_command, AofPaths, FsyncPolicy},
+        commands::{execute, handle_input_cmd},
+        server,
+    };
+
+    /// Starts a server for `db` on an ephemeral port, returning the port.
+    fn start_target(db: Db) -> u16 {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                tx.send(listener.local_addr().unwrap().port()).unwrap();
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    tokio::spawn(server::process(stream, db.clone()));
+                }
+            });
+        });
+        rx.recv().unwrap()
+    }
+
+    fn run(db: &mut Db, cmd: Vec<String>) -> RespType {
+        handle_input_cmd(cmd, db).unwrap()
+    }
+
+    fn cmd(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_migrate() {
+        let target = Db::new(1);
+        let port = start_target(target.clone()).to_string();
+        let source = &mut Db::new(1);
+        run(source, cmd(&["set", "a", "1", "px", "60000"]));
+        run(source, cmd(&["hset", "h", "f", "v"]));
+        // a large value has to arrive over several reads
+        let big = "x".repeat(100_000);
+        run(source, cmd(&["set", "big", &big]));
+
+        let ok = RespType::SimpleString("OK".to_string());
+        let res = run(
+            source,
+            cmd(&["migrate", "127.0.0.1", &port, "a", "0", "1000"]),
+        );
+        assert_eq!(res, ok);
+        assert_eq!(source.key_type("a"), None);
+        assert_eq!(target.get("a").unwrap(), Bytes::from("1"));
+        assert!(target.expire_at("a").is_some());
+
+        let res = run(
+            source,
+            cmd(&[
+                "migrate",
+                "127.0.0.1",
+                &port,
+                "",
+                "0",
+                "1000",
+                "copy",
+                "keys",
+                "h",
+                "big",
+                "missing",
+            ]),
+        );
+        assert_eq!(res, ok);
+        assert_eq!(source.key_type("h"), Some("hash"));
+        assert_eq!(target.hget("h", "f").unwrap(), Some(Bytes::from("v")));
+        assert_eq!(target.get("big").unwrap(), Bytes::from(big));
+
+        // the target already holds h, so it refuses without REPLACE
+        let res = run(
+            source,
+            cmd(&["migrate", "127.0.0.1", &port, "h", "0", "1000"]),
+        );
+        assert_eq!(
+            res,
+            RespType::Error(
+                "ERR Target instance replied with error: BUSYKEY Target key name already exists."
+                    .to_string()
+            )
+        );
+        assert_eq!(source.key_type("h"), Some("hash"));
+        let res = run(
+            source,
+            cmd(&["migrate", "127.0.0.1", &port, "h", "0", "1000", "replace"]),
+        );
+        assert_eq!(res, ok);
+        assert_eq!(source.key_type("h"), None);
+
+        let res = run(
+            source,
+            cmd(&["migrate", "127.0.0.1", &port, "h", "0", "1000"]),
+        );
+        assert_eq!(res, RespType::SimpleString("NOKEY".to_string()));
+        let res = run(
+            source,
+            cmd(&["migrate", "127.0.0.1", &port, "h", "1", "1000"]),
+        );
+        assert_eq!(
+            res,
+            RespType::Error("ERR DB index is out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn test_migrate_with_aof() {
+        let dir = std::env::temp_dir().join(format!("migrate-aof-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths = AofPaths::new(&dir, "appendonly.aof");
+        let mut db = Db::new(1);
+        aof::enable(&db, &paths, FsyncPolicy::Always).unwrap();
+        let port = start_target(db.clone()).to_string();
+        execute(cmd(&["SET", "a", "1"]), &mut db).unwrap();
+
+        // the target is this very instance, whose RESTORE has to get past
+        // the AOF lock
+        let res = execute(
+            cmd(&["migrate", "127.0.0.1", &port, "a", "0", "5000"]),
+            &mut db,
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            RespType::Error(
+                "ERR Target instance replied with error: BUSYKEY Target key name already exists."
+                    .to_string()
+            )
+        );
+
+        // writes go on while a migration waits for a silent target
+        let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_port = silent.local_addr().unwrap().port().to_string();
+        let mut migrating = db.clone();
+        let migration = std::thread::spawn(move || {
+            execute(
+                cmd(&["migrate", "127.0.0.1", &silent_port, "a", "0", "2000"]),
+                &mut migrating,
+            )
+            .unwrap()
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        execute(cmd(&["SET", "b", "2"]), &mut db).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(matches!(migration.join().unwrap(), RespType::Error(e) if e.starts_with("IOERR")));
+
+        let target = Db::new(1);
+        let port = start_target(target.clone()).to_string();
+        let res = execute(
+            cmd(&["migrate", "127.0.0.1", &port, "a", "0", "1000"]),
+            &mut db,
+        )
+        .unwrap();
+        assert_eq!(res, RespType::SimpleString("OK".to_string()));
+        assert_eq!(db.key_type("a"), None);
+
+        let data = std::fs::read(paths.file("appendonly.aof.1.incr.aof")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let mut logged = Vec::new();
+        let mut pos = 0;
+        while let Some((args, next)) = parse_command(&data, pos).unwrap() {
+            logged.push(args);
+            pos = next;
+        }
+        let bytes = |args: &[&str]| {
+            args.iter()
+                .map(|a| Bytes::from(a.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            logged,
+            vec![
+                bytes(&["SET", "a", "1"]),
+                bytes(&["SET", "b", "2"]),
+                bytes(&["DEL", "a"])
+            ]
+        );
+    }
+}
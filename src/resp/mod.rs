@@ -1,8 +1,33 @@
+pub mod acl;
+pub mod aof;
+pub mod blocking;
+pub mod check;
+pub mod cli;
+pub mod client;
+pub mod command_table;
 pub mod commands;
 pub mod constants;
 pub mod datastore;
 pub mod deserialize;
 mod errors;
+pub mod glob;
+pub mod listener;
+pub mod lzf;
+pub mod memtest;
+pub mod migrate;
+pub mod notify;
+pub mod persistence;
+pub mod pubsub;
+pub mod rdb;
 pub mod redisconfig;
 pub mod resp_value;
+pub mod search;
 pub mod server;
+pub mod slots;
+pub mod stats;
+pub mod stream;
+#[cfg(test)]
+mod test_helpers;
+pub mod tls;
+pub mod transaction;
+pub mod vector;
@@ -0,0 +1,192 @@
+use super::pubsub::Broker;
+
+// The event classes of notify-keyspace-events, as bits.
+pub const KEYSPACE: u32 = 1 << 0;
+pub const KEYEVENT: u32 = 1 << 1;
+pub const GENERIC: u32 = 1 << 2;
+pub const STRING: u32 = 1 << 3;
+pub const LIST: u32 = 1 << 4;
+pub const SET: u32 = 1 << 5;
+pub const HASH: u32 = 1 << 6;
+pub const ZSET: u32 = 1 << 7;
+pub const EXPIRED: u32 = 1 << 8;
+/// Accepted but never published, as nothing evicts keys.
+pub const EVICTED: u32 = 1 << 9;
+pub const STREAM: u32 = 1 << 10;
+pub const KEY_MISS: u32 = 1 << 11;
+pub const NEW: u32 = 1 << 12;
+
+/// What "A" stands for. Like redis, key misses and new keys are left out
+/// and have to be asked for on their own.
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+const CLASSES: &[(char, u32)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('n', NEW),
+];
+
+/// Reads the class characters of notify-keyspace-events, such as "KEA" or
+/// "Kx". Returns None on an unknown character.
+pub fn parse(classes: &str) -> Option<u32> {
+    classes.chars().try_fold(0, |flags, c| {
+        let class = match c {
+            'A' => ALL,
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            'm' => KEY_MISS,
+            _ => CLASSES.iter().find(|(name, _)| *name == c)?.1,
+        };
+        Some(flags | class)
+    })
+}
+
+/// The flags in the canonical form redis shows them in, "A" standing for
+/// all of its classes.
+pub fn describe(flags: u32) -> String {
+    let mut classes = String::new();
+    if flags & ALL == ALL {
+        classes.push('A');
+    }
+    for (name, class) in CLASSES {
+        if flags & class != 0 && (flags & ALL != ALL || class & ALL == 0) {
+            classes.push(*name);
+        }
+    }
+    for (name, class) in [('K', KEYSPACE), ('E', KEYEVENT), ('m', KEY_MISS)] {
+        if flags & class != 0 {
+            classes.push(name);
+        }
+    }
+    classes
+}
+
+/// Publishes an event of the given class on a key, if the flags ask for
+/// that class: to `__keyspace@0__:<key>` with the event as the message,
+/// and to `__keyevent@0__:<event>` with the key as the message.
+pub fn publish(broker: &Broker, flags: u32, class: u32, event: &str, key: &str) {
+    if flags & class == 0 {
+        return;
+    }
+    if flags & KEYSPACE != 0 {
+        let channel = format!("__keyspace@0__:{}", key);
+        broker.publish(channel.as_bytes(), event.as_bytes());
+    }
+    if flags & KEYEVENT != 0 {
+        let channel = format!("__keyevent@0__:{}", event);
+        broker.publish(channel.as_bytes(), key.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{client::Client, datastore::Db, pubsub, resp_value::RespType};
+    use bytes::Bytes;
+
+    // subscribes a client to every key event
+    fn listen(db: &Db) -> Client {
+        let mut client = Client::new();
+        let args = [Bytes::from("PSUBSCRIBE"), Bytes::from("__keyevent@0__:*")];
+        pubsub::pubsub_command(&mut client, &args, db);
+        client.subscriber.as_mut().unwrap().pending();
+        client
+    }
+
+    // the events received since last time, as (event, key)
+    fn events(client: &mut Client) -> Vec<(String, String)> {
+        let text = |item: &RespType| match item {
+            RespType::BulkString(Some(b)) => String::from_utf8_lossy(b).to_string(),
+            _ => panic!("not a bulk string"),
+        };
+        let pending = client.subscriber.as_mut().unwrap().pending();
+        pending
+            .unwrap_or_default()
+            .iter()
+            .map(|message| match message {
+                RespType::Array(Some(items)) => {
+                    let channel = text(&items[2]);
+                    let event = channel.trim_start_matches("__keyevent@0__:");
+                    (event.to_string(), text(&items[3]))
+                }
+                _ => panic!("not a message"),
+            })
+            .collect()
+    }
+
+    fn event(event: &str, key: &str) -> (String, String) {
+        (event.to_string(), key.to_string())
+    }
+
+    #[test]
+    fn test_keyspace_events() {
+        let db = Db::new(1);
+        let mut client = listen(&db);
+        db.set("k", Bytes::from("v"), vec![]).unwrap();
+        assert_eq!(events(&mut client), vec![]);
+
+        db.set_notify_flags(parse("Eg$hxnm").unwrap());
+        db.set(
+            "k",
+            Bytes::from("v"),
+            vec!["PX".to_string(), "20".to_string()],
+        )
+        .unwrap();
+        db.set("new", Bytes::from("v"), vec![]).unwrap();
+        db.hset("h", &[("f".to_string(), Bytes::from("v"))])
+            .unwrap();
+        db.hdel("h", &["f".to_string()]).unwrap();
+        db.del(&["new".to_string(), "missing".to_string()]);
+        let _ = db.get("missing");
+        assert_eq!(
+            events(&mut client),
+            vec![
+                event("set", "k"),
+                event("expire", "k"),
+                event("new", "new"),
+                event("set", "new"),
+                event("new", "h"),
+                event("hset", "h"),
+                event("hdel", "h"),
+                event("del", "h"),
+                event("del", "new"),
+                event("keymiss", "missing"),
+            ]
+        );
+
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        db.purge_expired();
+        assert_eq!(events(&mut client), vec![event("expired", "k")]);
+
+        // only the classes asked for
+        db.set_notify_flags(parse("Ex").unwrap());
+        db.set(
+            "k",
+            Bytes::from("v"),
+            vec!["PX".to_string(), "1".to_string()],
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let _ = db.get("k");
+        assert_eq!(events(&mut client), vec![event("expired", "k")]);
+    }
+
+    #[test]
+    fn test_parse_and_describe() {
+        assert_eq!(parse(""), Some(0));
+        assert_eq!(parse("KEA"), Some(KEYSPACE | KEYEVENT | ALL));
+        assert_eq!(parse("Ex"), Some(KEYEVENT | EXPIRED));
+        assert_eq!(parse("Kq"), None);
+        assert_eq!(describe(parse("KEA").unwrap()), "AKE");
+        assert_eq!(describe(parse("EKg$lshzxet").unwrap()), "AKE");
+        assert_eq!(describe(parse("xKmn$").unwrap()), "$xnKm");
+        assert_eq!(describe(parse("AnE").unwrap()), "AnE");
+    }
+}
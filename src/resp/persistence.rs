@@ -0,0 +1,413 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::Utc;
+use parking_lot::Mutex;
+
+use super::{
+    aof,
+    datastore::{Db, Snapshot},
+    errors::DataStoreError,
+    redisconfig,
+};
+
+/// After a failed background save, automatic saves wait this long before retrying.
+const BGSAVE_RETRY_DELAY_SECS: i64 = 5;
+
+/// Snapshot when at least `changes` writes happened in the last `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaveRule {
+    pub seconds: i64,
+    pub changes: u64,
+}
+
+/// Parses the `save` config value: pairs of "seconds changes". An empty
+/// value disables automatic snapshots.
+pub fn parse_save_rules(value: &str) -> Result<Vec<SaveRule>, String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if !parts.len().is_multiple_of(2) {
+        return Err("Invalid save parameters".to_string());
+    }
+    parts
+        .chunks(2)
+        .map(|pair| match (pair[0].parse(), pair[1].parse()) {
+            (Ok(seconds), Ok(changes)) if seconds > 0 => Ok(SaveRule { seconds, changes }),
+            _ => Err("Invalid save parameters".to_string()),
+        })
+        .collect()
+}
+
+fn save_rules() -> Vec<SaveRule> {
+    let value = redisconfig::get_config("save").unwrap_or_default();
+    parse_save_rules(&value).unwrap_or_else(|e| {
+        if redisconfig::log_enabled("warning") {
+            eprintln!("Ignoring save config {:?}: {}", value, e);
+        }
+        vec![]
+    })
+}
+
+/// Bookkeeping shared by SAVE, BGSAVE, LASTSAVE and INFO persistence.
+#[derive(Debug)]
+pub struct SaveStatus {
+    /// unix time of the last successful save
+    pub lastsave: i64,
+    /// unix time the running background save started, if any
+    pub bgsave_started: Option<i64>,
+    pub bgsave_scheduled: bool,
+    pub last_bgsave_ok: bool,
+    pub last_bgsave_secs: Option<i64>,
+    pub last_bgsave_try: i64,
+}
+
+impl Default for SaveStatus {
+    fn default() -> Self {
+        Self {
+            lastsave: Utc::now().timestamp(),
+            bgsave_started: None,
+            bgsave_scheduled: false,
+            last_bgsave_ok: true,
+            last_bgsave_secs: None,
+            last_bgsave_try: 0,
+        }
+    }
+}
+
+pub type SaveState = Mutex<SaveStatus>;
+
+/// What BGSAVE did with the request.
+#[derive(Debug, PartialEq)]
+pub enum BgsaveStart {
+    Started,
+    Scheduled,
+    AlreadyRunning,
+}
+
+/// Numbers the temp files, so concurrent writers never share one.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// Replaces `path` with `data` so that a crash at any point leaves either the
+/// old file or the new one: write a temp file of its own next to it, fsync,
+/// rename over the target, then fsync the directory so the rename itself is
+/// durable.
+pub fn write_atomically(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let target = path.as_ref();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = target
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let tmp = dir.join(format!(
+        "temp-{}-{}-{}",
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed),
+        name.to_string_lossy()
+    ));
+    let res = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, target)?;
+        File::open(dir)?.sync_all()
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res
+}
+
+// writes the snapshot and clears the changes it covered; `dirty_before` is
+// the counter as it was when the snapshot was taken
+fn save_and_mark(db: &Db, snapshot: Snapshot, dirty_before: u64) -> Result<(), DataStoreError> {
+    snapshot.save_to(&rdb_filename())?;
+    db.clear_dirty(dirty_before);
+    db.save_status().lock().lastsave = Utc::now().timestamp();
+    Ok(())
+}
+
+/// Synchronous SAVE. Refused while a background save is running, since
+/// both would write the same file, and the status stays locked until it is
+/// done so that no background save starts meanwhile.
+pub fn save(db: &Db) -> Result<(), DataStoreError> {
+    let mut status = db.save_status().lock();
+    if status.bgsave_started.is_some() {
+        return Err(DataStoreError::InvalidInput(
+            "Background save already in progress".to_string(),
+        ));
+    }
+    let dirty_before = db.dirty();
+    db.snapshot().save_to(&rdb_filename())?;
+    db.clear_dirty(dirty_before);
+    status.lastsave = Utc::now().timestamp();
+    Ok(())
+}
+
+/// Takes a snapshot and writes it out on a separate thread, so only the
+/// brief snapshot itself happens on the caller's path. With `schedule`, a
+/// request made while another save is running is queued for when it finishes.
+pub fn bgsave(db: &Db, schedule: bool) -> BgsaveStart {
+    let now = Utc::now().timestamp();
+    {
+        let mut status = db.save_status().lock();
+        if status.bgsave_started.is_some() {
+            if schedule {
+                status.bgsave_scheduled = true;
+                return BgsaveStart::Scheduled;
+            }
+            return BgsaveStart::AlreadyRunning;
+        }
+        status.bgsave_started = Some(now);
+        status.bgsave_scheduled = false;
+        status.last_bgsave_try = now;
+    }
+    let dirty_before = db.dirty();
+    let snapshot = db.snapshot();
+    let db = db.clone();
+    std::thread::spawn(move || {
+        let res = save_and_mark(&db, snapshot, dirty_before);
+        if let Err(e) = &res {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Background saving error: {}", e);
+            }
+        }
+        let mut status = db.save_status().lock();
+        status.bgsave_started = None;
+        status.last_bgsave_ok = res.is_ok();
+        status.last_bgsave_secs = Some(Utc::now().timestamp() - now);
+    });
+    BgsaveStart::Started
+}
+
+/// Called periodically by the server: runs scheduled saves and any
+/// save rule whose thresholds have been reached.
+pub fn cron(db: &Db) {
+    let now = Utc::now().timestamp();
+    let (lastsave, can_retry, scheduled) = {
+        let status = db.save_status().lock();
+        if status.bgsave_started.is_some() {
+            return;
+        }
+        let can_retry =
+            status.last_bgsave_ok || now - status.last_bgsave_try > BGSAVE_RETRY_DELAY_SECS;
+        (status.lastsave, can_retry, status.bgsave_scheduled)
+    };
+    if scheduled {
+        bgsave(db, false);
+        return;
+    }
+    let dirty = db.dirty();
+    let due = save_rules()
+        .into_iter()
+        .find(|rule| dirty >= rule.changes && now - lastsave >= rule.seconds);
+    if let Some(rule) = due {
+        if can_retry {
+            if redisconfig::log_enabled("notice") {
+                println!(
+                    "{} changes in {} seconds. Saving...",
+                    rule.changes, rule.seconds
+                );
+            }
+            bgsave(db, false);
+        }
+    }
+}
+
+/// Whether the configured rules ask for snapshots at all, e.g. on shutdown.
+pub fn saving_enabled() -> bool {
+    !save_rules().is_empty()
+}
+
+/// The rdb file snapshots are written to and loaded from.
+pub fn rdb_filename() -> String {
+    redisconfig::get_config("dbfilename").unwrap_or_default()
+}
+
+/// Loads the dataset at startup. Like redis, the AOF is the source of truth
+/// when `appendonly` is on; otherwise the rdb dump is read. Afterwards the
+/// AOF, if enabled, is opened for appending.
+pub fn load_data(db: &mut Db) -> Result<(), DataStoreError> {
+    if !redisconfig::get_bool("appendonly") {
+        return db.load();
+    }
+    let paths = aof::AofPaths::from_config();
+    let policy = redisconfig::get_config("appendfsync")
+        .and_then(|p| aof::FsyncPolicy::parse(&p))
+        .ok_or(DataStoreError::InvalidInput(
+            "appendfsync must be always, everysec or no".to_string(),
+        ))?;
+    let loaded =
+        aof::load(&paths, db, redisconfig::get_bool("aof-load-truncated")).map_err(|e| {
+            eprintln!("Failed to load the AOF in {}: {}", paths.dir.display(), e);
+            DataStoreError::DataLoadError
+        })?;
+    match loaded {
+        Some(stats) => {
+            if redisconfig::log_enabled("notice") {
+                println!(
+                    "AOF loaded: {} keys from the preamble, {} commands",
+                    stats.preamble_keys, stats.commands
+                );
+            }
+        }
+        None => db.load()?,
+    }
+    // replayed commands are already on disk
+    db.clear_dirty(db.dirty());
+    aof::enable(db, &paths, policy).map_err(|e| {
+        eprintln!("Failed to open the AOF in {}: {}", paths.dir.display(), e);
+        DataStoreError::FileIOError
+    })
+}
+
+/// The "# Persistence" section of INFO.
+pub fn info(db: &Db) -> String {
+    let aof_fields = db.aof().lock().as_ref().map(|aof| aof.info_fields());
+    let status = db.save_status().lock();
+    let now = Utc::now().timestamp();
+    let mut fields = vec![
+        ("loading", "0".to_string()),
+        ("rdb_changes_since_last_save", db.dirty().to_string()),
+        (
+            "rdb_bgsave_in_progress",
+            (status.bgsave_started.is_some() as u8).to_string(),
+        ),
+        ("rdb_last_save_time", status.lastsave.to_string()),
+        (
+            "rdb_last_bgsave_status",
+            if status.last_bgsave_ok { "ok" } else { "err" }.to_string(),
+        ),
+        (
+            "rdb_last_bgsave_time_sec",
+            status.last_bgsave_secs.unwrap_or(-1).to_string(),
+        ),
+        (
+            "rdb_current_bgsave_time_sec",
+            status
+                .bgsave_started
+                .map_or(-1, |started| now - started)
+                .to_string(),
+        ),
+        (
+            "rdb_saves_scheduled",
+            (status.bgsave_scheduled as u8).to_string(),
+        ),
+        ("aof_enabled", (aof_fields.is_some() as u8).to_string()),
+    ];
+    fields.extend(aof_fields.unwrap_or_default());
+    let mut out = "# Persistence\r\n".to_string();
+    for (name, value) in fields {
+        out.push_str(&format!("{}:{}\r\n", name, value));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::resp::datastore::Value;
+
+    #[test]
+    fn test_parse_save_rules() {
+        assert_eq!(
+            parse_save_rules("3600 1 300 100"),
+            Ok(vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 300,
+                    changes: 100
+                },
+            ])
+        );
+        assert_eq!(parse_save_rules(""), Ok(vec![]));
+        assert!(parse_save_rules("3600").is_err());
+        assert!(parse_save_rules("0 1").is_err());
+        assert!(parse_save_rules("soon 1").is_err());
+    }
+
+    #[test]
+    fn test_dirty_counter() {
+        let db = Db::new(2);
+        db.set("a", Bytes::from("1"), vec![]).unwrap();
+        db.hset(
+            "h",
+            &[
+                ("x".to_string(), Bytes::from("1")),
+                ("y".to_string(), Bytes::from("2")),
+            ],
+        )
+        .unwrap();
+        db.del(&["a".to_string(), "missing".to_string()]);
+        assert_eq!(db.dirty(), 4);
+        db.clear_dirty(3);
+        assert_eq!(db.dirty(), 1);
+        assert!(info(&db).contains("rdb_changes_since_last_save:1\r\n"));
+        // two saves covering the same writes don't wrap it around
+        db.clear_dirty(3);
+        assert_eq!(db.dirty(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_is_point_in_time() {
+        let db = Db::new(4);
+        for i in 0..100 {
+            db.set(&format!("key:{}", i), Bytes::from("before"), vec![])
+                .unwrap();
+        }
+        let snapshot = db.snapshot();
+        for i in 0..100 {
+            db.set(&format!("key:{}", i), Bytes::from("after"), vec![])
+                .unwrap();
+        }
+        db.set("new", Bytes::from("after"), vec![]).unwrap();
+        let values: Vec<_> = snapshot.entries().map(|(_, v, _)| v.clone()).collect();
+        assert_eq!(values.len(), 100);
+        assert!(values
+            .iter()
+            .all(|v| *v == Value::String(Bytes::from("before"))));
+        assert_eq!(db.get("key:7"), Ok(Bytes::from("after")));
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+        let path = path.to_str().unwrap();
+        write_atomically(path, b"first").unwrap();
+        write_atomically(path, b"second").unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"second");
+        // only the target is left behind, no temp files
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let missing = dir.join("no-such-dir").join("dump.rdb");
+        assert!(write_atomically(missing.to_str().unwrap(), b"third").is_err());
+        assert_eq!(fs::read(path).unwrap(), b"second");
+
+        // concurrent writers each rename a whole file of their own
+        let writers: Vec<_> = (0..8u8)
+            .map(|i| {
+                let path = path.to_string();
+                std::thread::spawn(move || write_atomically(&path, &[i; 100_000]).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let data = fs::read(path).unwrap();
+        assert_eq!(data.len(), 100_000);
+        assert!(data.iter().all(|b| *b == data[0]));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,833 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::mpsc;
+
+use super::{
+    client::Client,
+    datastore::Db,
+    glob::glob_match,
+    redisconfig,
+    resp_value::{bulk, RespType},
+    slots::key_hash_slot,
+};
+
+/// What a subscription is to: a channel, the channels matching a glob
+/// pattern, or a shard channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+impl Kind {
+    fn subscribe_reply(self) -> &'static [u8] {
+        match self {
+            Kind::Channel => b"subscribe",
+            Kind::Pattern => b"psubscribe",
+            Kind::Shard => b"ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static [u8] {
+        match self {
+            Kind::Channel => b"unsubscribe",
+            Kind::Pattern => b"punsubscribe",
+            Kind::Shard => b"sunsubscribe",
+        }
+    }
+}
+
+// how far a subscriber is behind on reading its messages
+#[derive(Debug, Default)]
+struct Backlog {
+    bytes: usize,
+    over_soft_limit_since: Option<Instant>,
+    overflowed: bool,
+}
+
+/// Where the messages for one subscriber are queued until its connection
+/// writes them out. Publishers never wait on it: a subscriber that falls
+/// behind by more than the pubsub class of `client-output-buffer-limit`
+/// allows is disconnected instead, like redis does.
+#[derive(Debug, Clone)]
+struct Mailbox {
+    tx: mpsc::UnboundedSender<RespType>,
+    backlog: Arc<Mutex<Backlog>>,
+}
+
+impl Mailbox {
+    // queues a message, returning false once the subscriber is over its limit
+    fn push(&self, message: RespType, limits: &Limits) -> bool {
+        let mut backlog = self.backlog.lock();
+        if backlog.overflowed {
+            return false;
+        }
+        backlog.bytes += message.get_byte_length();
+        if limits.soft == 0 || backlog.bytes <= limits.soft {
+            backlog.over_soft_limit_since = None;
+        } else {
+            backlog
+                .over_soft_limit_since
+                .get_or_insert_with(Instant::now);
+        }
+        let over_soft = backlog
+            .over_soft_limit_since
+            .is_some_and(|since| since.elapsed() >= limits.soft_seconds);
+        if (limits.hard > 0 && backlog.bytes > limits.hard) || over_soft {
+            backlog.overflowed = true;
+            return false;
+        }
+        // the receiver only goes away with the subscriber, which
+        // unsubscribes everything first
+        let _ = self.tx.send(message);
+        true
+    }
+}
+
+/// The pubsub class of `client-output-buffer-limit`, in bytes; 0 is no limit.
+struct Limits {
+    hard: usize,
+    soft: usize,
+    soft_seconds: Duration,
+}
+
+impl Limits {
+    fn from_config() -> Limits {
+        let value = redisconfig::get_config("client-output-buffer-limit").unwrap_or_default();
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let at = fields.iter().position(|f| *f == "pubsub");
+        let number = |i: usize| {
+            at.and_then(|at| fields.get(at + i))
+                .and_then(|n| n.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        Limits {
+            hard: number(1) as usize,
+            soft: number(2) as usize,
+            soft_seconds: Duration::from_secs(number(3)),
+        }
+    }
+}
+
+/// The subscribers of each channel or pattern.
+type Channels = HashMap<Bytes, HashMap<u64, Mailbox>>;
+
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: Channels,
+    patterns: Channels,
+    /// shard channels by hash slot, apart from the global ones so that
+    /// publishing to one never looks at the patterns or other slots
+    shard_channels: HashMap<u16, Channels>,
+}
+
+impl Subscriptions {
+    fn of(&mut self, kind: Kind, name: &[u8]) -> &mut Channels {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => self.shard_channels.entry(key_hash_slot(name)).or_default(),
+        }
+    }
+
+    fn remove(&mut self, kind: Kind, name: &Bytes, id: u64) {
+        let subscribers = self.of(kind, name);
+        if let Some(ids) = subscribers.get_mut(name) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                subscribers.remove(name);
+            }
+        }
+        if kind == Kind::Shard {
+            self.shard_channels
+                .retain(|_, channels| !channels.is_empty());
+        }
+    }
+
+    fn remove_subscriber(&mut self, id: u64) {
+        let shards = self.shard_channels.values_mut();
+        for subscribers in [&mut self.channels, &mut self.patterns]
+            .into_iter()
+            .chain(shards)
+        {
+            subscribers.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+        self.shard_channels
+            .retain(|_, channels| !channels.is_empty());
+    }
+
+    fn shard_channels(&self) -> impl Iterator<Item = (&Bytes, &HashMap<u64, Mailbox>)> {
+        self.shard_channels.values().flatten()
+    }
+}
+
+/// Routes published messages to the clients subscribed to their channel,
+/// directly or through a pattern.
+#[derive(Debug, Default)]
+pub struct Broker {
+    subscriptions: RwLock<Subscriptions>,
+}
+
+impl Broker {
+    /// Sends a message to every subscriber of the channel, returning how
+    /// many received it. Subscribers that overflow are dropped.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        self.deliver(|subscriptions, deliver| {
+            if let Some(subscribers) = subscriptions.channels.get(channel) {
+                let message = vec![bulk(b"message"), bulk(channel), bulk(message)];
+                for (id, mailbox) in subscribers {
+                    deliver(*id, mailbox, RespType::Array(Some(message.clone())));
+                }
+            }
+            for (pattern, subscribers) in &subscriptions.patterns {
+                if !glob_match(pattern, channel, false) {
+                    continue;
+                }
+                let message = vec![
+                    bulk(b"pmessage"),
+                    bulk(pattern),
+                    bulk(channel),
+                    bulk(message),
+                ];
+                for (id, mailbox) in subscribers {
+                    deliver(*id, mailbox, RespType::Array(Some(message.clone())));
+                }
+            }
+        })
+    }
+
+    /// Sends a message to the subscribers of a shard channel, which only
+    /// involves the channel's hash slot.
+    pub fn spublish(&self, channel: &[u8], message: &[u8]) -> usize {
+        self.deliver(|subscriptions, deliver| {
+            let subscribers = subscriptions
+                .shard_channels
+                .get(&key_hash_slot(channel))
+                .and_then(|channels| channels.get(channel));
+            let message = vec![bulk(b"smessage"), bulk(channel), bulk(message)];
+            for (id, mailbox) in subscribers.into_iter().flatten() {
+                deliver(*id, mailbox, RespType::Array(Some(message.clone())));
+            }
+        })
+    }
+
+    // Runs `route`, which hands each message to `deliver` with its
+    // subscriber, and counts the subscribers that got one. Those that
+    // overflowed are dropped afterwards.
+    fn deliver(
+        &self,
+        route: impl FnOnce(&Subscriptions, &mut dyn FnMut(u64, &Mailbox, RespType)),
+    ) -> usize {
+        let limits = Limits::from_config();
+        let mut received = 0;
+        let mut overflowed = vec![];
+        route(
+            &self.subscriptions.read(),
+            &mut |id: u64, mailbox: &Mailbox, message: RespType| {
+                if mailbox.push(message, &limits) {
+                    received += 1;
+                } else {
+                    overflowed.push(id);
+                }
+            },
+        );
+        if !overflowed.is_empty() {
+            let mut subscriptions = self.subscriptions.write();
+            for id in overflowed {
+                subscriptions.remove_subscriber(id);
+            }
+        }
+        received
+    }
+
+    /// The channels with at least one subscriber, optionally only those
+    /// matching a glob pattern.
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let mut channels: Vec<Bytes> = self
+            .subscriptions
+            .read()
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|p| glob_match(p, channel, false)))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    /// How many clients subscribe to the channel, not counting patterns.
+    pub fn numsub(&self, channel: &[u8]) -> usize {
+        self.subscriptions
+            .read()
+            .channels
+            .get(channel)
+            .map_or(0, |ids| ids.len())
+    }
+
+    /// How many distinct patterns clients subscribe to.
+    pub fn numpat(&self) -> usize {
+        self.subscriptions.read().patterns.len()
+    }
+
+    /// The shard channels with at least one subscriber, optionally only
+    /// those matching a glob pattern.
+    pub fn shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let mut channels: Vec<Bytes> = self
+            .subscriptions
+            .read()
+            .shard_channels()
+            .map(|(channel, _)| channel)
+            .filter(|channel| pattern.is_none_or(|p| glob_match(p, channel, false)))
+            .cloned()
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    /// How many clients subscribe to the shard channel.
+    pub fn shard_numsub(&self, channel: &[u8]) -> usize {
+        self.subscriptions
+            .read()
+            .shard_channels
+            .get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+            .map_or(0, |ids| ids.len())
+    }
+}
+
+/// A client's subscriptions, and the messages published to them that it
+/// has yet to write out. Dropping it, as when the client disconnects,
+/// unsubscribes from everything.
+#[derive(Debug)]
+pub struct Subscriber {
+    id: u64,
+    broker: Arc<Broker>,
+    mailbox: Mailbox,
+    messages: mpsc::UnboundedReceiver<RespType>,
+    channels: BTreeSet<Bytes>,
+    patterns: BTreeSet<Bytes>,
+    shard_channels: BTreeSet<Bytes>,
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let mut subscriptions = self.broker.subscriptions.write();
+        for name in &self.channels {
+            subscriptions.remove(Kind::Channel, name, self.id);
+        }
+        for name in &self.patterns {
+            subscriptions.remove(Kind::Pattern, name, self.id);
+        }
+        for name in &self.shard_channels {
+            subscriptions.remove(Kind::Shard, name, self.id);
+        }
+    }
+}
+
+impl Subscriber {
+    fn new(id: u64, broker: Arc<Broker>) -> Subscriber {
+        let (tx, messages) = mpsc::unbounded_channel();
+        Subscriber {
+            id,
+            broker,
+            mailbox: Mailbox {
+                tx,
+                backlog: Arc::default(),
+            },
+            messages,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            shard_channels: BTreeSet::new(),
+        }
+    }
+
+    /// How many channels, patterns and shard channels the client subscribes
+    /// to. While it is more than zero the client is in subscriber mode.
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.shard_channels.len()
+    }
+
+    fn names(&mut self, kind: Kind) -> &mut BTreeSet<Bytes> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    // The confirmations are queued like messages, and under the broker's
+    // lock, so a message on a channel is never written before the
+    // confirmation of subscribing to it, nor after the one of leaving it.
+    fn subscribe(&mut self, kind: Kind, names: &[Bytes]) {
+        let broker = self.broker.clone();
+        let mut subscriptions = broker.subscriptions.write();
+        for name in names {
+            if self.names(kind).insert(name.clone()) {
+                subscriptions
+                    .of(kind, name)
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(self.id, self.mailbox.clone());
+            }
+            self.confirm(kind, kind.subscribe_reply(), Some(name));
+        }
+    }
+
+    // without names, leaves every subscription of the kind
+    fn unsubscribe(&mut self, kind: Kind, names: &[Bytes]) {
+        let broker = self.broker.clone();
+        let mut subscriptions = broker.subscriptions.write();
+        let names = match names {
+            [] => self.names(kind).iter().cloned().collect(),
+            names => names.to_vec(),
+        };
+        if names.is_empty() {
+            self.confirm(kind, kind.unsubscribe_reply(), None);
+        }
+        for name in names {
+            if self.names(kind).remove(&name) {
+                subscriptions.remove(kind, &name, self.id);
+            }
+            self.confirm(kind, kind.unsubscribe_reply(), Some(&name));
+        }
+    }
+
+    // like redis, shard subscriptions are counted apart from the others
+    fn confirm(&self, kind: Kind, reply: &[u8], name: Option<&Bytes>) {
+        let count = match kind {
+            Kind::Shard => self.shard_channels.len(),
+            _ => self.channels.len() + self.patterns.len(),
+        };
+        let confirmation = vec![
+            bulk(reply),
+            RespType::BulkString(name.cloned()),
+            RespType::Integer(count as i64),
+        ];
+        self.mailbox
+            .push(RespType::Array(Some(confirmation)), &Limits::from_config());
+    }
+
+    /// Takes the messages queued so far, or None when the client fell too
+    /// far behind and has to be disconnected.
+    pub fn pending(&mut self) -> Option<Vec<RespType>> {
+        let mut messages = vec![];
+        while let Ok(message) = self.messages.try_recv() {
+            messages.push(message);
+        }
+        self.received(&messages)?;
+        Some(messages)
+    }
+
+    /// Waits for the next message, returning None when the client fell too
+    /// far behind and has to be disconnected.
+    pub async fn next(&mut self) -> Option<RespType> {
+        let message = self.messages.recv().await?;
+        self.received(std::slice::from_ref(&message))?;
+        Some(message)
+    }
+
+    fn received(&self, messages: &[RespType]) -> Option<()> {
+        let mut backlog = self.mailbox.backlog.lock();
+        if backlog.overflowed {
+            return None;
+        }
+        backlog.bytes -= messages.iter().map(|m| m.get_byte_length()).sum::<usize>();
+        Some(())
+    }
+}
+
+/// Whether the command subscribes or unsubscribes, which is all a client
+/// in subscriber mode may do besides PING and QUIT.
+pub fn is_subscribe_command(name: &str) -> bool {
+    matches!(
+        name,
+        "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ssubscribe" | "sunsubscribe"
+    )
+}
+
+/// Answers SUBSCRIBE and the other pub/sub commands, and PING in subscriber
+/// mode, returning None for any other command. The replies of subscribing
+/// and unsubscribing are queued with the client's messages, so for those
+/// the reply is empty.
+pub fn pubsub_command(client: &mut Client, args: &[Bytes], db: &Db) -> Option<Vec<RespType>> {
+    let name = redisconfig::resolve_command_name(&String::from_utf8_lossy(&args[0]))?;
+    let kind = match name.as_str() {
+        "subscribe" | "unsubscribe" => Kind::Channel,
+        "psubscribe" | "punsubscribe" => Kind::Pattern,
+        "ssubscribe" | "sunsubscribe" => Kind::Shard,
+        "publish" => {
+            let received = db.pubsub().publish(&args[1], &args[2]);
+            return Some(vec![RespType::Integer(received as i64)]);
+        }
+        "spublish" => {
+            let received = db.pubsub().spublish(&args[1], &args[2]);
+            return Some(vec![RespType::Integer(received as i64)]);
+        }
+        "pubsub" => return Some(vec![pubsub_subcommand(args, db.pubsub())]),
+        "ping" if client.is_subscribed() => {
+            let message = args.get(1).map_or(&b""[..], |m| m);
+            return Some(vec![RespType::Array(Some(vec![
+                bulk(b"pong"),
+                bulk(message),
+            ]))]);
+        }
+        _ => return None,
+    };
+    let subscriber = client
+        .subscriber
+        .get_or_insert_with(|| Subscriber::new(client.id, db.pubsub().clone()));
+    if name.ends_with("unsubscribe") {
+        subscriber.unsubscribe(kind, &args[1..]);
+    } else {
+        subscriber.subscribe(kind, &args[1..]);
+    }
+    Some(vec![])
+}
+
+// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT |
+// SHARDCHANNELS [pattern] | SHARDNUMSUB [channel ...] | HELP
+fn pubsub_subcommand(args: &[Bytes], broker: &Broker) -> RespType {
+    let sub = String::from_utf8_lossy(&args[1]).to_lowercase();
+    let counts = |count: &dyn Fn(&[u8]) -> usize| {
+        RespType::Array(Some(
+            args[2..]
+                .iter()
+                .flat_map(|channel| [bulk(channel), RespType::Integer(count(channel) as i64)])
+                .collect(),
+        ))
+    };
+    let names = |names: Vec<Bytes>| RespType::Array(Some(names.iter().map(bulk).collect()));
+    let pattern = args.get(2).map(|p| &p[..]);
+    match sub.as_str() {
+        "channels" => names(broker.channels(pattern)),
+        "numsub" => counts(&|channel| broker.numsub(channel)),
+        "numpat" => RespType::Integer(broker.numpat() as i64),
+        "shardchannels" => names(broker.shard_channels(pattern)),
+        "shardnumsub" => counts(&|channel| broker.shard_numsub(channel)),
+        "help" => RespType::Array(Some(
+            [
+                "PUBSUB <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "CHANNELS [<pattern>]",
+                "    Return the currently active channels matching a <pattern> (default: '*').",
+                "NUMPAT",
+                "    Return number of subscriptions to patterns.",
+                "NUMSUB [<channel> ...]",
+                "    Return the number of subscribers for the specified channels, excluding",
+                "    pattern subscriptions(default: no channels).",
+                "SHARDCHANNELS [<pattern>]",
+                "    Return the currently active shard level channels matching a <pattern> (default: '*').",
+                "SHARDNUMSUB [<shardchannel> ...]",
+                "    Return the number of subscribers for the specified shard level channel(s)",
+                "HELP",
+                "    Print this help.",
+            ]
+            .iter()
+            .map(|line| RespType::SimpleString(line.to_string()))
+            .collect(),
+        )),
+        _ => RespType::Error(format!(
+            "ERR unknown subcommand '{}'. Try PUBSUB HELP.",
+            String::from_utf8_lossy(&args[1])
+        )),
+    }
+}
+
+/// The channels a command publishes or subscribes to, which ACL channel
+/// permissions apply to, and whether they are patterns.
+pub fn channel_args<'a>(name: &str, args: &'a [Bytes]) -> (&'a [Bytes], bool) {
+    match name {
+        "publish" | "spublish" => (&args[1..2.min(args.len())], false),
+        "subscribe" | "ssubscribe" => (&args[1..], false),
+        "psubscribe" => (&args[1..], true),
+        _ => (&[], false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::server;
+    use crate::resp::test_helpers::send;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn array(items: &[&str]) -> RespType {
+        RespType::Array(Some(items.iter().map(|i| bulk(i.as_bytes())).collect()))
+    }
+
+    fn confirmation(kind: &str, name: Option<&str>, count: i64) -> RespType {
+        RespType::Array(Some(vec![
+            bulk(kind.as_bytes()),
+            RespType::BulkString(name.map(|n| Bytes::from(n.to_string()))),
+            RespType::Integer(count),
+        ]))
+    }
+
+    fn pending(client: &mut Client) -> Vec<RespType> {
+        client.subscriber.as_mut().unwrap().pending().unwrap()
+    }
+
+    #[test]
+    fn test_publish_and_subscribe() {
+        let mut db = Db::new(1);
+        let mut client = Client::new();
+        let mut publisher = Client::new();
+        assert_eq!(send(&mut client, &mut db, "SUBSCRIBE news sport"), vec![]);
+        assert_eq!(send(&mut client, &mut db, "PSUBSCRIBE n*"), vec![]);
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                confirmation("subscribe", Some("news"), 1),
+                confirmation("subscribe", Some("sport"), 2),
+                confirmation("psubscribe", Some("n*"), 3),
+            ]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBLISH news hello"),
+            vec![RespType::Integer(2)]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBLISH weather rain"),
+            vec![RespType::Integer(0)]
+        );
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                array(&["message", "news", "hello"]),
+                array(&["pmessage", "n*", "news", "hello"]),
+            ]
+        );
+
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB CHANNELS"),
+            vec![array(&["news", "sport"])]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB CHANNELS s*"),
+            vec![array(&["sport"])]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB NUMSUB news other"),
+            vec![RespType::Array(Some(vec![
+                bulk(b"news"),
+                RespType::Integer(1),
+                bulk(b"other"),
+                RespType::Integer(0),
+            ]))]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB NUMPAT"),
+            vec![RespType::Integer(1)]
+        );
+
+        send(&mut client, &mut db, "UNSUBSCRIBE");
+        send(&mut client, &mut db, "PUNSUBSCRIBE n* other");
+        send(&mut client, &mut db, "PUNSUBSCRIBE");
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                confirmation("unsubscribe", Some("news"), 2),
+                confirmation("unsubscribe", Some("sport"), 1),
+                confirmation("punsubscribe", Some("n*"), 0),
+                confirmation("punsubscribe", Some("other"), 0),
+                confirmation("punsubscribe", None, 0),
+            ]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBLISH news hello"),
+            vec![RespType::Integer(0)]
+        );
+
+        // disconnecting unsubscribes
+        send(&mut client, &mut db, "SUBSCRIBE news");
+        drop(client);
+        assert_eq!(db.pubsub().channels(None), Vec::<Bytes>::new());
+    }
+
+    #[test]
+    fn test_sharded_pubsub() {
+        let mut db = Db::new(1);
+        let mut client = Client::new();
+        let mut publisher = Client::new();
+        send(&mut client, &mut db, "SUBSCRIBE news");
+        send(&mut client, &mut db, "SSUBSCRIBE {user1}.a {user1}.b news");
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                confirmation("subscribe", Some("news"), 1),
+                confirmation("ssubscribe", Some("{user1}.a"), 1),
+                confirmation("ssubscribe", Some("{user1}.b"), 2),
+                confirmation("ssubscribe", Some("news"), 3),
+            ]
+        );
+
+        // shard channels and global channels of the same name are apart
+        assert_eq!(
+            send(&mut publisher, &mut db, "SPUBLISH {user1}.a hi"),
+            vec![RespType::Integer(1)]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "SPUBLISH news shard"),
+            vec![RespType::Integer(1)]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBLISH news global"),
+            vec![RespType::Integer(1)]
+        );
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                array(&["smessage", "{user1}.a", "hi"]),
+                array(&["smessage", "news", "shard"]),
+                array(&["message", "news", "global"]),
+            ]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB SHARDCHANNELS"),
+            vec![array(&["news", "{user1}.a", "{user1}.b"])]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB SHARDCHANNELS {*"),
+            vec![array(&["{user1}.a", "{user1}.b"])]
+        );
+        assert_eq!(
+            send(
+                &mut publisher,
+                &mut db,
+                "PUBSUB SHARDNUMSUB {user1}.b other"
+            ),
+            vec![RespType::Array(Some(vec![
+                bulk(b"{user1}.b"),
+                RespType::Integer(1),
+                bulk(b"other"),
+                RespType::Integer(0),
+            ]))]
+        );
+        assert_eq!(
+            send(&mut publisher, &mut db, "PUBSUB CHANNELS"),
+            vec![array(&["news"])]
+        );
+
+        send(&mut client, &mut db, "UNSUBSCRIBE");
+        assert!(client.is_subscribed());
+        send(&mut client, &mut db, "SUNSUBSCRIBE");
+        assert!(!client.is_subscribed());
+        assert_eq!(
+            pending(&mut client),
+            vec![
+                confirmation("unsubscribe", Some("news"), 0),
+                confirmation("sunsubscribe", Some("news"), 2),
+                confirmation("sunsubscribe", Some("{user1}.a"), 1),
+                confirmation("sunsubscribe", Some("{user1}.b"), 0),
+            ]
+        );
+        assert_eq!(db.pubsub().shard_channels(None), Vec::<Bytes>::new());
+
+        send(&mut client, &mut db, "SSUBSCRIBE news");
+        drop(client);
+        assert_eq!(db.pubsub().shard_numsub(b"news"), 0);
+    }
+
+    #[test]
+    fn test_subscriber_mode() {
+        let mut db = Db::new(1);
+        let mut client = Client::new();
+        send(&mut client, &mut db, "SUBSCRIBE news");
+        assert!(client.is_subscribed());
+        assert_eq!(
+            send(&mut client, &mut db, "GET k"),
+            vec![RespType::Error(
+                "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT are allowed in this context"
+                    .to_string()
+            )]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "PING"),
+            vec![array(&["pong", ""])]
+        );
+        assert_eq!(
+            send(&mut client, &mut db, "PING hi"),
+            vec![array(&["pong", "hi"])]
+        );
+        send(&mut client, &mut db, "UNSUBSCRIBE news");
+        assert!(!client.is_subscribed());
+
+        client.transaction = Some(Default::default());
+        assert_eq!(
+            send(&mut client, &mut db, "SUBSCRIBE news"),
+            vec![RespType::Error(
+                "ERR Command not allowed inside a transaction".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_slow_subscriber_overflows() {
+        let (tx, _messages) = mpsc::unbounded_channel();
+        let mailbox = Mailbox {
+            tx,
+            backlog: Arc::default(),
+        };
+        let message = array(&["message", "news", "0123456789"]);
+        let size = message.get_byte_length();
+        let hard = Limits {
+            hard: size * 2,
+            soft: 0,
+            soft_seconds: Duration::ZERO,
+        };
+        assert!(mailbox.push(message.clone(), &hard));
+        assert!(mailbox.push(message.clone(), &hard));
+        assert!(!mailbox.push(message.clone(), &hard));
+        // once over the limit, it stays disconnected
+        mailbox.backlog.lock().bytes = 0;
+        assert!(!mailbox.push(message.clone(), &hard));
+
+        let (tx, _messages) = mpsc::unbounded_channel();
+        let mailbox = Mailbox {
+            tx,
+            backlog: Arc::default(),
+        };
+        let soft = Limits {
+            hard: 0,
+            soft: size,
+            soft_seconds: Duration::from_millis(20),
+        };
+        assert!(mailbox.push(message.clone(), &soft));
+        assert!(mailbox.push(message.clone(), &soft));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!mailbox.push(message, &soft));
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_pushed() {
+        let db = Db::new(1);
+        let (mut subscriber, server_end) = tokio::io::duplex(4096);
+        tokio::spawn(server::process(server_end, db.clone()));
+        subscriber
+            .write_all(b"*2\r\n$9\r\nSUBSCRIBE\r\n$4\r\nnews\r\n")
+            .await
+            .unwrap();
+        let mut reply = vec![0; 256];
+        let n = subscriber.read(&mut reply).await.unwrap();
+        assert_eq!(
+            &reply[..n],
+            b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+
+        assert_eq!(db.pubsub().publish(b"news", b"hello"), 1);
+        let n = subscriber.read(&mut reply).await.unwrap();
+        assert_eq!(
+            &reply[..n],
+            b"*3\r\n$7\r\nmessage\r\n$4\r\nnews\r\n$5\r\nhello\r\n"
+        );
+    }
+}
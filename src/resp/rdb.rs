@@ -0,0 +1,1424 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use bytes::Bytes;
+
+use super::{
+    datastore::{SortedSet, Value},
+    errors::RdbError,
+    lzf,
+    stream::{Consumer, ConsumerGroup, Fields, PendingEntry, Stream, StreamId},
+};
+
+/// Version written to new files. 10 is the format of redis 7.0, so every
+/// 7.x server can load our dumps.
+pub const RDB_VERSION: u16 = 10;
+/// Newest format we can read (redis 7.4).
+pub const MAX_RDB_VERSION: u16 = 12;
+
+// value types
+pub const TYPE_STRING: u8 = 0;
+pub const TYPE_LIST: u8 = 1;
+pub const TYPE_SET: u8 = 2;
+pub const TYPE_ZSET: u8 = 3;
+pub const TYPE_HASH: u8 = 4;
+pub const TYPE_ZSET_2: u8 = 5;
+pub const TYPE_MODULE_PRE_GA: u8 = 6;
+pub const TYPE_MODULE_2: u8 = 7;
+pub const TYPE_HASH_ZIPMAP: u8 = 9;
+pub const TYPE_LIST_ZIPLIST: u8 = 10;
+pub const TYPE_SET_INTSET: u8 = 11;
+pub const TYPE_ZSET_ZIPLIST: u8 = 12;
+pub const TYPE_HASH_ZIPLIST: u8 = 13;
+pub const TYPE_LIST_QUICKLIST: u8 = 14;
+pub const TYPE_STREAM_LISTPACKS: u8 = 15;
+pub const TYPE_HASH_LISTPACK: u8 = 16;
+pub const TYPE_ZSET_LISTPACK: u8 = 17;
+pub const TYPE_LIST_QUICKLIST_2: u8 = 18;
+pub const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+pub const TYPE_SET_LISTPACK: u8 = 20;
+pub const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// opcodes
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_FUNCTION_PRE_GA: u8 = 0xF6;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+// special string encodings, flagged by a length byte of 11xxxxxx
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+// quicklist 2 node containers
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// stream node entry flags
+const STREAM_ITEM_DELETED: i64 = 1;
+const STREAM_ITEM_SAMEFIELDS: i64 = 2;
+/// Entries per stream node, redis's default stream-node-max-entries.
+const STREAM_NODE_ENTRIES: usize = 100;
+
+// ===== crc64 =====
+
+// crc-64-jones, reflected, as used by redis for rdb trailers and DUMP payloads
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC64_TABLE: [u64; 256] = crc64_table();
+
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for b in data {
+        crc = CRC64_TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+// ===== writer =====
+
+pub fn write_length(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.push(0x40 | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+// strings holding small integers in canonical form are stored as integers
+fn as_encoded_int(s: &[u8]) -> Option<i64> {
+    if s.is_empty() || s.len() > 11 {
+        return None;
+    }
+    let n: i64 = std::str::from_utf8(s).ok()?.parse().ok()?;
+    (n.to_string().as_bytes() == s).then_some(n)
+}
+
+pub fn write_string(buf: &mut Vec<u8>, s: &[u8], compress: bool) {
+    if let Some(n) = as_encoded_int(s) {
+        if let Ok(n) = i8::try_from(n) {
+            buf.push(0xC0 | ENC_INT8);
+            buf.push(n as u8);
+            return;
+        } else if let Ok(n) = i16::try_from(n) {
+            buf.push(0xC0 | ENC_INT16);
+            buf.extend_from_slice(&n.to_le_bytes());
+            return;
+        } else if let Ok(n) = i32::try_from(n) {
+            buf.push(0xC0 | ENC_INT32);
+            buf.extend_from_slice(&n.to_le_bytes());
+            return;
+        }
+    }
+    if compress && s.len() > 20 {
+        if let Some(compressed) = lzf::compress(s) {
+            buf.push(0xC0 | ENC_LZF);
+            write_length(buf, compressed.len() as u64);
+            write_length(buf, s.len() as u64);
+            buf.extend_from_slice(&compressed);
+            return;
+        }
+    }
+    write_length(buf, s.len() as u64);
+    buf.extend_from_slice(s);
+}
+
+pub fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::ZSet(_) => TYPE_ZSET_2,
+        Value::Hash(_) => TYPE_HASH,
+        Value::Stream(_) => TYPE_STREAM_LISTPACKS_2,
+    }
+}
+
+/// Writes the payload of a value, without its type byte. Values use the plain
+/// (non-packed) encodings, which every redis version can read; streams have
+/// none, so they are written as redis 7.0 writes them.
+pub fn write_value(buf: &mut Vec<u8>, value: &Value, compress: bool) {
+    match value {
+        Value::String(s) => write_string(buf, s, compress),
+        Value::List(items) => {
+            write_length(buf, items.len() as u64);
+            for item in items {
+                write_string(buf, item, compress);
+            }
+        }
+        Value::Set(members) => {
+            write_length(buf, members.len() as u64);
+            for member in members {
+                write_string(buf, member, compress);
+            }
+        }
+        Value::ZSet(zset) => {
+            write_length(buf, zset.len() as u64);
+            // redis writes zsets from the highest score down so loading appends in order
+            for (member, score) in zset.iter().rev() {
+                write_string(buf, member, compress);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        Value::Hash(fields) => {
+            write_length(buf, fields.len() as u64);
+            for (field, val) in fields {
+                write_string(buf, field.as_bytes(), compress);
+                write_string(buf, val, compress);
+            }
+        }
+        Value::Stream(stream) => write_stream(buf, stream, compress),
+    }
+}
+
+fn write_stream_id(buf: &mut Vec<u8>, id: StreamId) {
+    write_length(buf, id.ms);
+    write_length(buf, id.seq);
+}
+
+// Streams are stored as nodes keyed by their first ID, each a listpack of a
+// master entry holding the first entry's fields, then the entries as deltas
+// from the node ID, with only the values when their fields match the master.
+fn write_stream(buf: &mut Vec<u8>, stream: &Stream, compress: bool) {
+    let entries: Vec<_> = stream.entries.iter().collect();
+    let nodes = entries.chunks(STREAM_NODE_ENTRIES);
+    write_length(buf, nodes.len() as u64);
+    for node in nodes {
+        let (master_id, master_fields) = node[0];
+        // deltas are stored as signed integers, wrapping like redis's
+        let int = |n: u64| Bytes::from((n as i64).to_string());
+        let mut items = vec![
+            int(node.len() as u64),
+            int(0),
+            int(master_fields.len() as u64),
+        ];
+        items.extend(master_fields.iter().map(|(field, _)| field.clone()));
+        items.push(int(0));
+        for (id, fields) in node {
+            let same_fields = fields.len() == master_fields.len()
+                && fields
+                    .iter()
+                    .zip(master_fields.iter())
+                    .all(|((a, _), (b, _))| a == b);
+            let flags = if same_fields {
+                STREAM_ITEM_SAMEFIELDS
+            } else {
+                0
+            };
+            items.push(int(flags as u64));
+            items.push(int(id.ms.wrapping_sub(master_id.ms)));
+            items.push(int(id.seq.wrapping_sub(master_id.seq)));
+            if same_fields {
+                items.extend(fields.iter().map(|(_, value)| value.clone()));
+                items.push(int(3 + fields.len() as u64));
+            } else {
+                items.push(int(fields.len() as u64));
+                for (field, value) in fields.iter() {
+                    items.push(field.clone());
+                    items.push(value.clone());
+                }
+                items.push(int(4 + 2 * fields.len() as u64));
+            }
+        }
+        write_string(buf, &master_id.to_be_bytes(), false);
+        write_string(buf, &write_listpack(&items), compress);
+    }
+    write_length(buf, stream.entries.len() as u64);
+    write_stream_id(buf, stream.last_id);
+    let first_id = stream.entries.keys().next().copied().unwrap_or_default();
+    write_stream_id(buf, first_id);
+    write_stream_id(buf, stream.max_deleted_id);
+    write_length(buf, stream.entries_added);
+    write_length(buf, stream.groups.len() as u64);
+    for (name, group) in &stream.groups {
+        write_string(buf, name.as_bytes(), compress);
+        write_stream_id(buf, group.last_id);
+        // entries-read unknown, redis works it out again on load
+        write_length(buf, u64::MAX);
+        write_length(buf, group.pending.len() as u64);
+        for (id, pending) in &group.pending {
+            buf.extend_from_slice(&id.to_be_bytes());
+            buf.extend_from_slice(&pending.delivery_time.to_le_bytes());
+            write_length(buf, pending.delivery_count);
+        }
+        write_length(buf, group.consumers.len() as u64);
+        for (name, consumer) in &group.consumers {
+            write_string(buf, name.as_bytes(), compress);
+            buf.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            let pending: Vec<_> = group.pending_of(name).collect();
+            write_length(buf, pending.len() as u64);
+            for id in pending {
+                buf.extend_from_slice(&id.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Builds a listpack of the items, those in canonical integer form stored
+/// as integers, like redis does.
+pub fn write_listpack(items: &[Bytes]) -> Vec<u8> {
+    let mut buf = vec![0; 6];
+    for item in items {
+        let start = buf.len();
+        match std::str::from_utf8(item)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|n| n.to_string().as_bytes() == &item[..])
+        {
+            Some(n @ 0..=127) => buf.push(n as u8),
+            Some(n @ -4096..=4095) => {
+                let raw = (n as u16) & 0x1fff;
+                buf.extend_from_slice(&[0xC0 | (raw >> 8) as u8, raw as u8]);
+            }
+            Some(n) if i16::try_from(n).is_ok() => {
+                buf.push(0xF1);
+                buf.extend_from_slice(&(n as i16).to_le_bytes());
+            }
+            Some(n) if (-(1 << 23)..1 << 23).contains(&n) => {
+                buf.push(0xF2);
+                buf.extend_from_slice(&(n as i32).to_le_bytes()[..3]);
+            }
+            Some(n) if i32::try_from(n).is_ok() => {
+                buf.push(0xF3);
+                buf.extend_from_slice(&(n as i32).to_le_bytes());
+            }
+            Some(n) => {
+                buf.push(0xF4);
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+            None if item.len() < 64 => buf.push(0x80 | item.len() as u8),
+            None if item.len() < 4096 => {
+                buf.extend_from_slice(&[0xE0 | (item.len() >> 8) as u8, item.len() as u8])
+            }
+            None => {
+                buf.push(0xF0);
+                buf.extend_from_slice(&(item.len() as u32).to_le_bytes());
+            }
+        }
+        if !matches!(buf[start], 0..=0x7F | 0xC0..=0xDF | 0xF1..=0xF4) {
+            buf.extend_from_slice(item);
+        }
+        // the entry length again, 7 bits a byte, for walking backwards
+        let len = buf.len() - start;
+        let groups = (0..5).rev().skip_while(|i| *i > 0 && len >> (7 * i) == 0);
+        for i in groups {
+            let bits = ((len >> (7 * i)) & 0x7f) as u8;
+            buf.push(if len >> (7 * (i + 1)) != 0 {
+                bits | 0x80
+            } else {
+                bits
+            });
+        }
+    }
+    buf.push(0xFF);
+    let total = buf.len() as u32;
+    let count = u16::try_from(items.len()).unwrap_or(u16::MAX);
+    buf[..4].copy_from_slice(&total.to_le_bytes());
+    buf[4..6].copy_from_slice(&count.to_le_bytes());
+    buf
+}
+
+/// Builds an rdb file in memory: header, aux fields, one db section and the
+/// crc64 trailer.
+pub struct RdbWriter {
+    buf: Vec<u8>,
+    compress: bool,
+}
+
+impl RdbWriter {
+    pub fn new(compress: bool) -> Self {
+        let mut buf = b"REDIS".to_vec();
+        buf.extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+        Self { buf, compress }
+    }
+
+    pub fn aux(&mut self, key: &str, value: &str) {
+        self.buf.push(OPCODE_AUX);
+        write_string(&mut self.buf, key.as_bytes(), false);
+        write_string(&mut self.buf, value.as_bytes(), false);
+    }
+
+    pub fn select_db(&mut self, db: u64, keys: usize, expires: usize) {
+        self.buf.push(OPCODE_SELECTDB);
+        write_length(&mut self.buf, db);
+        self.buf.push(OPCODE_RESIZEDB);
+        write_length(&mut self.buf, keys as u64);
+        write_length(&mut self.buf, expires as u64);
+    }
+
+    pub fn entry(&mut self, key: &[u8], value: &Value, expire_at: Option<i64>) {
+        if let Some(at) = expire_at {
+            self.buf.push(OPCODE_EXPIRETIME_MS);
+            self.buf.extend_from_slice(&at.to_le_bytes());
+        }
+        self.buf.push(value_type(value));
+        write_string(&mut self.buf, key, self.compress);
+        write_value(&mut self.buf, value, self.compress);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.push(OPCODE_EOF);
+        let checksum = crc64(0, &self.buf);
+        self.buf.extend_from_slice(&checksum.to_le_bytes());
+        self.buf
+    }
+}
+
+// ===== reader =====
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RdbEntry {
+    pub db: u64,
+    pub key: Bytes,
+    pub value: Value,
+    pub expire_at: Option<i64>,
+    pub idle: Option<u64>,
+    pub freq: Option<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct RdbFile {
+    pub version: u16,
+    pub aux: Vec<(String, String)>,
+    pub entries: Vec<RdbEntry>,
+    /// the stored checksum, None when the file was written with checksums disabled
+    pub checksum: Option<u64>,
+}
+
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+/// Cursor over an rdb byte buffer that reports errors with their offset.
+pub struct RdbReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn corrupt(&self, reason: &str) -> RdbError {
+        RdbError::Corrupt {
+            offset: self.pos,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn unsupported(&self, reason: &str) -> RdbError {
+        RdbError::Unsupported {
+            offset: self.pos,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], RdbError> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(self.corrupt("unexpected end of file"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, RdbError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], RdbError> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    fn read_length_or_encoding(&mut self) -> Result<Length, RdbError> {
+        let first = self.read_u8()?;
+        Ok(match first >> 6 {
+            0 => Length::Len((first & 0x3f) as u64),
+            1 => Length::Len((((first & 0x3f) as u64) << 8) | self.read_u8()? as u64),
+            2 => match first {
+                0x80 => Length::Len(u32::from_be_bytes(self.read_array()?) as u64),
+                0x81 => Length::Len(u64::from_be_bytes(self.read_array()?)),
+                _ => return Err(self.corrupt("unknown length encoding")),
+            },
+            _ => Length::Encoded(first & 0x3f),
+        })
+    }
+
+    pub fn read_length(&mut self) -> Result<u64, RdbError> {
+        match self.read_length_or_encoding()? {
+            Length::Len(n) => Ok(n),
+            Length::Encoded(_) => Err(self.corrupt("expected a length, found an encoded string")),
+        }
+    }
+
+    // lengths that size an allocation are checked against what is left in the file
+    fn read_count(&mut self) -> Result<usize, RdbError> {
+        let n = self.read_length()?;
+        if n > (self.data.len() - self.pos) as u64 {
+            return Err(self.corrupt("length larger than the rest of the file"));
+        }
+        Ok(n as usize)
+    }
+
+    pub fn read_string(&mut self) -> Result<Bytes, RdbError> {
+        match self.read_length_or_encoding()? {
+            Length::Len(n) => {
+                let n = usize::try_from(n).map_err(|_| self.corrupt("string too long"))?;
+                Ok(Bytes::copy_from_slice(self.read_bytes(n)?))
+            }
+            Length::Encoded(ENC_INT8) => Ok(Bytes::from((self.read_u8()? as i8).to_string())),
+            Length::Encoded(ENC_INT16) => Ok(Bytes::from(
+                i16::from_le_bytes(self.read_array()?).to_string(),
+            )),
+            Length::Encoded(ENC_INT32) => Ok(Bytes::from(
+                i32::from_le_bytes(self.read_array()?).to_string(),
+            )),
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.read_count()?;
+                let len = self.read_length()?;
+                if len > 512 * 1024 * 1024 {
+                    return Err(self.corrupt("lzf string too long"));
+                }
+                let start = self.pos;
+                let compressed = self.read_bytes(compressed_len)?;
+                lzf::decompress(compressed, len as usize)
+                    .map(Bytes::from)
+                    .ok_or(RdbError::Corrupt {
+                        offset: start,
+                        reason: "invalid lzf data".to_string(),
+                    })
+            }
+            Length::Encoded(_) => Err(self.corrupt("unknown string encoding")),
+        }
+    }
+
+    fn read_string_lossy(&mut self) -> Result<String, RdbError> {
+        Ok(String::from_utf8_lossy(&self.read_string()?).to_string())
+    }
+
+    // old zset encoding: score as a length-prefixed ascii string
+    fn read_text_double(&mut self) -> Result<f64, RdbError> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let raw = self.read_bytes(len as usize)?;
+                std::str::from_utf8(raw)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(self.corrupt("invalid double"))
+            }
+        }
+    }
+
+    fn read_binary_double(&mut self) -> Result<f64, RdbError> {
+        Ok(f64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_stream_id(&mut self) -> Result<StreamId, RdbError> {
+        Ok(StreamId {
+            ms: self.read_length()?,
+            seq: self.read_length()?,
+        })
+    }
+
+    fn read_raw_stream_id(&mut self) -> Result<StreamId, RdbError> {
+        Ok(StreamId::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_stream(&mut self, value_type: u8) -> Result<Stream, RdbError> {
+        let mut stream = Stream::default();
+        let nodes = self.read_count()?;
+        for _ in 0..nodes {
+            let master_key = self.read_string()?;
+            let master_id = <[u8; 16]>::try_from(&master_key[..])
+                .map(StreamId::from_be_bytes)
+                .map_err(|_| self.corrupt("invalid stream node key"))?;
+            let node_start = self.pos;
+            let blob = self.read_string()?;
+            let entries = parse_listpack(&blob)
+                .and_then(|items| parse_stream_node(master_id, &items))
+                .ok_or(RdbError::Corrupt {
+                    offset: node_start,
+                    reason: "invalid stream node".to_string(),
+                })?;
+            stream.entries.extend(entries);
+        }
+        let length = self.read_length()?;
+        if length != stream.entries.len() as u64 {
+            return Err(self.corrupt("stream length does not match its entries"));
+        }
+        stream.last_id = self.read_stream_id()?;
+        stream.entries_added = length;
+        if value_type != TYPE_STREAM_LISTPACKS {
+            self.read_stream_id()?; // first ID, known from the entries
+            stream.max_deleted_id = self.read_stream_id()?;
+            stream.entries_added = self.read_length()?;
+        }
+        let groups = self.read_count()?;
+        for _ in 0..groups {
+            let name = self.read_string_lossy()?;
+            let mut group = ConsumerGroup {
+                last_id: self.read_stream_id()?,
+                ..Default::default()
+            };
+            if value_type != TYPE_STREAM_LISTPACKS {
+                self.read_length()?; // entries read
+            }
+            let mut deliveries = BTreeMap::new();
+            for _ in 0..self.read_count()? {
+                let id = self.read_raw_stream_id()?;
+                let time = i64::from_le_bytes(self.read_array()?);
+                deliveries.insert(id, (time, self.read_length()?));
+            }
+            for _ in 0..self.read_count()? {
+                let consumer = self.read_string_lossy()?;
+                let seen_time = i64::from_le_bytes(self.read_array()?);
+                if value_type == TYPE_STREAM_LISTPACKS_3 {
+                    self.read_array::<8>()?; // active time
+                }
+                for _ in 0..self.read_count()? {
+                    let id = self.read_raw_stream_id()?;
+                    let (delivery_time, delivery_count) = deliveries
+                        .remove(&id)
+                        .ok_or(self.corrupt("consumer entry missing from the group"))?;
+                    group.pending.insert(
+                        id,
+                        PendingEntry {
+                            consumer: consumer.clone(),
+                            delivery_time,
+                            delivery_count,
+                        },
+                    );
+                }
+                group.consumers.insert(consumer, Consumer { seen_time });
+            }
+            if !deliveries.is_empty() {
+                return Err(self.corrupt("group entry without a consumer"));
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+
+    /// Reads a value payload of the given type.
+    pub fn read_value(&mut self, value_type: u8) -> Result<Value, RdbError> {
+        let start = self.pos;
+        let at = |reason: &str| RdbError::Corrupt {
+            offset: start,
+            reason: reason.to_string(),
+        };
+        Ok(match value_type {
+            TYPE_STRING => Value::String(self.read_string()?),
+            TYPE_LIST => {
+                let n = self.read_count()?;
+                let mut items = VecDeque::with_capacity(n);
+                for _ in 0..n {
+                    items.push_back(self.read_string()?);
+                }
+                Value::List(items)
+            }
+            TYPE_SET => {
+                let n = self.read_count()?;
+                let mut members = HashSet::with_capacity(n);
+                for _ in 0..n {
+                    members.insert(self.read_string()?);
+                }
+                Value::Set(members)
+            }
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let n = self.read_count()?;
+                let mut zset = SortedSet::default();
+                for _ in 0..n {
+                    let member = self.read_string()?;
+                    let score = if value_type == TYPE_ZSET {
+                        self.read_text_double()?
+                    } else {
+                        self.read_binary_double()?
+                    };
+                    if score.is_nan() {
+                        return Err(self.corrupt("zset score is NaN"));
+                    }
+                    zset.insert(member, score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_HASH => {
+                let n = self.read_count()?;
+                let mut fields = HashMap::with_capacity(n);
+                for _ in 0..n {
+                    let field = self.read_string_lossy()?;
+                    fields.insert(field, self.read_string()?);
+                }
+                Value::Hash(fields)
+            }
+            TYPE_HASH_ZIPMAP => {
+                let blob = self.read_string()?;
+                let items = parse_zipmap(&blob).ok_or(at("invalid zipmap"))?;
+                Value::Hash(hash_from_pairs(items).ok_or(at("odd zipmap entries"))?)
+            }
+            TYPE_LIST_ZIPLIST => {
+                let blob = self.read_string()?;
+                Value::List(parse_ziplist(&blob).ok_or(at("invalid ziplist"))?.into())
+            }
+            TYPE_SET_INTSET => {
+                let blob = self.read_string()?;
+                Value::Set(
+                    parse_intset(&blob)
+                        .ok_or(at("invalid intset"))?
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            TYPE_SET_LISTPACK => {
+                let blob = self.read_string()?;
+                Value::Set(
+                    parse_listpack(&blob)
+                        .ok_or(at("invalid listpack"))?
+                        .into_iter()
+                        .collect(),
+                )
+            }
+            TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+                let blob = self.read_string()?;
+                let items = if value_type == TYPE_ZSET_ZIPLIST {
+                    parse_ziplist(&blob).ok_or(at("invalid ziplist"))?
+                } else {
+                    parse_listpack(&blob).ok_or(at("invalid listpack"))?
+                };
+                if !items.len().is_multiple_of(2) {
+                    return Err(at("odd number of zset entries"));
+                }
+                let mut zset = SortedSet::default();
+                for pair in items.chunks(2) {
+                    let score = std::str::from_utf8(&pair[1])
+                        .ok()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or(at("invalid zset score"))?;
+                    zset.insert(pair[0].clone(), score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+                let blob = self.read_string()?;
+                let items = if value_type == TYPE_HASH_ZIPLIST {
+                    parse_ziplist(&blob).ok_or(at("invalid ziplist"))?
+                } else {
+                    parse_listpack(&blob).ok_or(at("invalid listpack"))?
+                };
+                Value::Hash(hash_from_pairs(items).ok_or(at("odd number of hash entries"))?)
+            }
+            TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_count()?;
+                let mut items = VecDeque::new();
+                for _ in 0..nodes {
+                    let container = if value_type == TYPE_LIST_QUICKLIST_2 {
+                        self.read_length()?
+                    } else {
+                        QUICKLIST_NODE_PACKED
+                    };
+                    let node_start = self.pos;
+                    let blob = self.read_string()?;
+                    let node_err = || RdbError::Corrupt {
+                        offset: node_start,
+                        reason: "invalid quicklist node".to_string(),
+                    };
+                    match container {
+                        QUICKLIST_NODE_PLAIN => items.push_back(blob),
+                        QUICKLIST_NODE_PACKED if value_type == TYPE_LIST_QUICKLIST => {
+                            items.extend(parse_ziplist(&blob).ok_or_else(node_err)?)
+                        }
+                        QUICKLIST_NODE_PACKED => {
+                            items.extend(parse_listpack(&blob).ok_or_else(node_err)?)
+                        }
+                        _ => return Err(node_err()),
+                    }
+                }
+                Value::List(items)
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Value::Stream(self.read_stream(value_type)?)
+            }
+            TYPE_MODULE_PRE_GA | TYPE_MODULE_2 => {
+                return Err(self.unsupported("module values are not supported"))
+            }
+            other => {
+                return Err(self.unsupported(&format!("unknown value type {}", other)));
+            }
+        })
+    }
+}
+
+fn hash_from_pairs(items: Vec<Bytes>) -> Option<HashMap<String, Bytes>> {
+    if !items.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(
+        items
+            .chunks(2)
+            .map(|p| (String::from_utf8_lossy(&p[0]).to_string(), p[1].clone()))
+            .collect(),
+    )
+}
+
+// The live entries of a stream node's listpack; see write_stream.
+fn parse_stream_node(master_id: StreamId, items: &[Bytes]) -> Option<Vec<(StreamId, Fields)>> {
+    let mut items = items.iter();
+    let mut next = || items.next().cloned();
+    let int = |item: Option<Bytes>| -> Option<u64> {
+        let n: i64 = std::str::from_utf8(&item?).ok()?.parse().ok()?;
+        Some(n as u64)
+    };
+    let count = int(next())?.checked_add(int(next())?)?;
+    let master_fields = (0..int(next())?)
+        .map(|_| next())
+        .collect::<Option<Vec<_>>>()?;
+    int(next())?;
+    let mut entries = vec![];
+    for _ in 0..count {
+        let flags = int(next())? as i64;
+        let id = StreamId {
+            ms: master_id.ms.wrapping_add(int(next())?),
+            seq: master_id.seq.wrapping_add(int(next())?),
+        };
+        let fields: Fields = if flags & STREAM_ITEM_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), next()?)))
+                .collect::<Option<_>>()?
+        } else {
+            (0..int(next())?)
+                .map(|_| Some((next()?, next()?)))
+                .collect::<Option<_>>()?
+        };
+        int(next())?;
+        if flags & STREAM_ITEM_DELETED == 0 {
+            entries.push((id, fields));
+        }
+    }
+    Some(entries)
+}
+
+fn le_int(bytes: &[u8]) -> i64 {
+    // sign-extend a little-endian integer of 1 to 8 bytes
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let shift = 64 - 8 * bytes.len() as u32;
+    (i64::from_le_bytes(buf) << shift) >> shift
+}
+
+/// Entries of a ziplist (redis < 7 compact lists, hashes and zsets).
+pub fn parse_ziplist(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let count = u16::from_le_bytes(blob.get(8..10)?.try_into().ok()?);
+    let mut items = Vec::with_capacity(count as usize);
+    let mut i = 10;
+    loop {
+        if *blob.get(i)? == 0xFF {
+            break;
+        }
+        // previous entry length: 1 byte, or 0xFE followed by 4 bytes
+        i += if blob[i] == 0xFE { 5 } else { 1 };
+        let enc = *blob.get(i)?;
+        let item = match enc >> 6 {
+            0 => {
+                let len = (enc & 0x3f) as usize;
+                i += 1;
+                blob.get(i..i + len)?.to_vec()
+            }
+            1 => {
+                let len = (((enc & 0x3f) as usize) << 8) | *blob.get(i + 1)? as usize;
+                i += 2;
+                blob.get(i..i + len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(blob.get(i + 1..i + 5)?.try_into().ok()?) as usize;
+                i += 5;
+                blob.get(i..i + len)?.to_vec()
+            }
+            _ => {
+                i += 1;
+                let (width, value) = match enc {
+                    0xC0 => (2, None),
+                    0xD0 => (4, None),
+                    0xE0 => (8, None),
+                    0xF0 => (3, None),
+                    0xFE => (1, None),
+                    0xF1..=0xFD => (0, Some((enc & 0x0f) as i64 - 1)),
+                    _ => return None,
+                };
+                let n = match value {
+                    Some(n) => n,
+                    None => le_int(blob.get(i..i + width)?),
+                };
+                i += width;
+                items.push(Bytes::from(n.to_string()));
+                continue;
+            }
+        };
+        i += item.len();
+        items.push(Bytes::from(item));
+    }
+    Some(items)
+}
+
+/// Entries of a listpack (redis 7 compact encodings).
+pub fn parse_listpack(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut items = vec![];
+    let mut i = 6;
+    loop {
+        let enc = *blob.get(i)?;
+        if enc == 0xFF {
+            break;
+        }
+        let (item, entry_len) = if enc & 0x80 == 0 {
+            (Bytes::from((enc & 0x7f).to_string()), 1)
+        } else if enc & 0xC0 == 0x80 {
+            let len = (enc & 0x3f) as usize;
+            (
+                Bytes::copy_from_slice(blob.get(i + 1..i + 1 + len)?),
+                1 + len,
+            )
+        } else if enc & 0xE0 == 0xC0 {
+            // 13 bit signed integer
+            let raw = (((enc & 0x1f) as u16) << 8) | *blob.get(i + 1)? as u16;
+            let n = ((raw << 3) as i16) >> 3;
+            (Bytes::from(n.to_string()), 2)
+        } else if enc & 0xF0 == 0xE0 {
+            let len = (((enc & 0x0f) as usize) << 8) | *blob.get(i + 1)? as usize;
+            (
+                Bytes::copy_from_slice(blob.get(i + 2..i + 2 + len)?),
+                2 + len,
+            )
+        } else {
+            match enc {
+                0xF0 => {
+                    let len = u32::from_le_bytes(blob.get(i + 1..i + 5)?.try_into().ok()?) as usize;
+                    (
+                        Bytes::copy_from_slice(blob.get(i + 5..i + 5 + len)?),
+                        5 + len,
+                    )
+                }
+                0xF1 => (Bytes::from(le_int(blob.get(i + 1..i + 3)?).to_string()), 3),
+                0xF2 => (Bytes::from(le_int(blob.get(i + 1..i + 4)?).to_string()), 4),
+                0xF3 => (Bytes::from(le_int(blob.get(i + 1..i + 5)?).to_string()), 5),
+                0xF4 => (Bytes::from(le_int(blob.get(i + 1..i + 9)?).to_string()), 9),
+                _ => return None,
+            }
+        };
+        // each entry is followed by its own length, encoded in 1 to 5 bytes
+        let backlen = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        i += entry_len + backlen;
+        items.push(item);
+    }
+    Some(items)
+}
+
+/// Members of an intset (small sets of integers).
+pub fn parse_intset(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let width = u32::from_le_bytes(blob.get(0..4)?.try_into().ok()?) as usize;
+    let len = u32::from_le_bytes(blob.get(4..8)?.try_into().ok()?) as usize;
+    if !matches!(width, 2 | 4 | 8) || blob.len() != 8 + width * len {
+        return None;
+    }
+    Some(
+        blob[8..]
+            .chunks(width)
+            .map(|c| Bytes::from(le_int(c).to_string()))
+            .collect(),
+    )
+}
+
+/// Entries of a zipmap (hashes written by redis < 2.6).
+fn parse_zipmap(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut items = vec![];
+    let mut i = 1;
+    let read_len = |i: &mut usize| -> Option<usize> {
+        let first = *blob.get(*i)?;
+        match first {
+            0..=253 => {
+                *i += 1;
+                Some(first as usize)
+            }
+            254 => {
+                let len = u32::from_le_bytes(blob.get(*i + 1..*i + 5)?.try_into().ok()?);
+                *i += 5;
+                Some(len as usize)
+            }
+            255 => None,
+        }
+    };
+    loop {
+        if *blob.get(i)? == 0xFF {
+            break;
+        }
+        let key_len = read_len(&mut i)?;
+        items.push(Bytes::copy_from_slice(blob.get(i..i + key_len)?));
+        i += key_len;
+        let val_len = read_len(&mut i)?;
+        let free = *blob.get(i)? as usize;
+        i += 1;
+        items.push(Bytes::copy_from_slice(blob.get(i..i + val_len)?));
+        i += val_len + free;
+    }
+    Some(items)
+}
+
+/// Decodes a whole rdb file, verifying its checksum when one was written.
+pub fn decode(data: &[u8]) -> Result<RdbFile, RdbError> {
+    decode_prefix(data).map(|(file, _)| file)
+}
+
+/// Decodes an rdb file at the start of `data`, also returning how many bytes
+/// it took. Used for AOF files that begin with an rdb preamble.
+pub fn decode_prefix(data: &[u8]) -> Result<(RdbFile, usize), RdbError> {
+    let mut file = RdbFile::default();
+    let mut entries = vec![];
+    let consumed = decode_into(data, &mut file, |entry| entries.push(entry))?;
+    file.entries = entries;
+    Ok((file, consumed))
+}
+
+/// Streams the entries of an rdb file to `on_entry` instead of collecting
+/// them, filling in the header fields of `file` as they are read. On error,
+/// everything up to the failure has already been passed on, which is what
+/// the offline checker reports from.
+pub fn decode_into(
+    data: &[u8],
+    file: &mut RdbFile,
+    mut on_entry: impl FnMut(RdbEntry),
+) -> Result<usize, RdbError> {
+    let mut reader = RdbReader::new(data);
+    let magic = reader.read_bytes(9)?;
+    if &magic[..5] != b"REDIS" {
+        return Err(RdbError::Corrupt {
+            offset: 0,
+            reason: "missing REDIS magic".to_string(),
+        });
+    }
+    let version: u16 = std::str::from_utf8(&magic[5..])
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or(RdbError::Corrupt {
+            offset: 5,
+            reason: "invalid version".to_string(),
+        })?;
+    if version == 0 || version > MAX_RDB_VERSION {
+        return Err(RdbError::Unsupported {
+            offset: 5,
+            reason: format!("rdb version {}", version),
+        });
+    }
+    file.version = version;
+    let mut db = 0;
+    let mut expire_at = None;
+    let (mut idle, mut freq) = (None, None);
+    loop {
+        let opcode = reader.read_u8()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => db = reader.read_length()?,
+            OPCODE_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            OPCODE_AUX => {
+                let key = reader.read_string_lossy()?;
+                let value = reader.read_string_lossy()?;
+                file.aux.push((key, value));
+            }
+            OPCODE_EXPIRETIME_MS => {
+                expire_at = Some(i64::from_le_bytes(reader.read_array()?));
+            }
+            OPCODE_EXPIRETIME => {
+                expire_at = Some(i32::from_le_bytes(reader.read_array()?) as i64 * 1000);
+            }
+            OPCODE_IDLE => idle = Some(reader.read_length()?),
+            OPCODE_FREQ => freq = Some(reader.read_u8()?),
+            OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.read_length()?;
+                }
+            }
+            // function libraries are kept as source; we have no scripting, so skip them
+            OPCODE_FUNCTION2 => {
+                reader.read_string()?;
+            }
+            OPCODE_FUNCTION_PRE_GA | OPCODE_MODULE_AUX => {
+                return Err(RdbError::Unsupported {
+                    offset: reader.position() - 1,
+                    reason: format!("opcode {:#x}", opcode),
+                })
+            }
+            value_type => {
+                let key = reader.read_string()?;
+                let value = reader.read_value(value_type)?;
+                on_entry(RdbEntry {
+                    db,
+                    key,
+                    value,
+                    expire_at: expire_at.take(),
+                    idle: idle.take(),
+                    freq: freq.take(),
+                });
+            }
+        }
+    }
+    // versions >= 5 end with a crc64 of everything before it; zero means checksums were off
+    if version >= 5 {
+        let body_len = reader.position();
+        let stored = u64::from_le_bytes(reader.read_array()?);
+        if stored != 0 {
+            let actual = crc64(0, &data[..body_len]);
+            if actual != stored {
+                return Err(RdbError::ChecksumMismatch {
+                    expected: stored,
+                    actual,
+                });
+            }
+            file.checksum = Some(stored);
+        }
+    }
+    Ok(reader.position())
+}
+
+// ===== DUMP payloads =====
+
+/// Serializes one value the way DUMP does: the type byte and value, then a
+/// footer of the rdb version as a little-endian u16 and a crc64 of everything
+/// before the checksum.
+pub fn dump_payload(value: &Value) -> Vec<u8> {
+    let mut buf = vec![value_type(value)];
+    write_value(&mut buf, value, true);
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Reads a DUMP payload back, refusing ones from a newer rdb version or whose
+/// checksum does not match.
+pub fn parse_payload(payload: &[u8]) -> Result<Value, RdbError> {
+    if payload.len() < 11 {
+        return Err(RdbError::Corrupt {
+            offset: 0,
+            reason: "payload too short".to_string(),
+        });
+    }
+    let (body, stored) = payload.split_at(payload.len() - 8);
+    let (data, version) = body.split_at(body.len() - 2);
+    let version = u16::from_le_bytes([version[0], version[1]]);
+    if version > MAX_RDB_VERSION {
+        return Err(RdbError::Unsupported {
+            offset: data.len(),
+            reason: format!("rdb version {}", version),
+        });
+    }
+    let stored = u64::from_le_bytes(stored.try_into().unwrap());
+    let actual = crc64(0, body);
+    if actual != stored {
+        return Err(RdbError::ChecksumMismatch {
+            expected: stored,
+            actual,
+        });
+    }
+    let mut reader = RdbReader::new(data);
+    let value_type = reader.read_u8()?;
+    let value = reader.read_value(value_type)?;
+    if reader.position() != data.len() {
+        return Err(reader.corrupt("trailing bytes after the value"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::stream;
+
+    fn sample_stream() -> Stream {
+        let mut stream = Stream::default();
+        let fields = |pairs: &[(&str, &str)]| -> Fields {
+            pairs
+                .iter()
+                .map(|(f, v)| (Bytes::from(f.to_string()), Bytes::from(v.to_string())))
+                .collect()
+        };
+        stream.add(
+            StreamId { ms: 5, seq: 3 },
+            fields(&[("f", "1"), ("g", "x")]),
+        );
+        stream.add(
+            StreamId { ms: 5, seq: 4 },
+            fields(&[("f", "-70000"), ("g", "y")]),
+        );
+        stream.add(StreamId { ms: 9, seq: 0 }, fields(&[("other", "z")]));
+        stream.max_deleted_id = StreamId { ms: 1, seq: 0 };
+        stream.entries_added = 5;
+        let mut group = ConsumerGroup {
+            last_id: StreamId { ms: 5, seq: 4 },
+            ..Default::default()
+        };
+        group
+            .consumers
+            .insert("c".to_string(), Consumer { seen_time: 1234 });
+        group
+            .consumers
+            .insert("idle".to_string(), Consumer { seen_time: 99 });
+        group.pending.insert(
+            StreamId { ms: 5, seq: 3 },
+            PendingEntry {
+                consumer: "c".to_string(),
+                delivery_time: 1000,
+                delivery_count: 2,
+            },
+        );
+        stream.groups.insert("g".to_string(), group);
+        stream
+    }
+
+    fn sample_values() -> Vec<(&'static str, Value, Option<i64>)> {
+        let mut zset = SortedSet::default();
+        zset.insert(Bytes::from("a"), 1.5);
+        zset.insert(Bytes::from("b"), -3.0);
+        vec![
+            ("str", Value::String(Bytes::from("hello")), None),
+            (
+                "int",
+                Value::String(Bytes::from("-12345")),
+                Some(1_900_000_000_000),
+            ),
+            ("big", Value::String(Bytes::from("x".repeat(1000))), None),
+            (
+                "list",
+                Value::List(VecDeque::from([Bytes::from("1"), Bytes::from("two")])),
+                None,
+            ),
+            (
+                "set",
+                Value::Set(HashSet::from([Bytes::from("m1"), Bytes::from("m2")])),
+                None,
+            ),
+            ("zset", Value::ZSet(zset), None),
+            (
+                "hash",
+                Value::Hash(HashMap::from([("f".to_string(), Bytes::from("v"))])),
+                None,
+            ),
+            ("stream", Value::Stream(sample_stream()), None),
+        ]
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = RdbWriter::new(true);
+        writer.aux("redis-ver", "7.0.0");
+        writer.select_db(0, 8, 1);
+        for (key, value, expire_at) in sample_values() {
+            writer.entry(key.as_bytes(), &value, expire_at);
+        }
+        let data = writer.finish();
+        let file = decode(&data).unwrap();
+        assert_eq!(file.version, RDB_VERSION);
+        assert_eq!(
+            file.aux,
+            vec![("redis-ver".to_string(), "7.0.0".to_string())]
+        );
+        let decoded: Vec<_> = file
+            .entries
+            .into_iter()
+            .map(|e| (e.key, e.value, e.expire_at))
+            .collect();
+        let expected: Vec<_> = sample_values()
+            .into_iter()
+            .map(|(k, v, e)| (Bytes::from(k), v, e))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_listpack_and_stream_nodes() {
+        let items: Vec<Bytes> = [
+            "0",
+            "127",
+            "128",
+            "-4096",
+            "4095",
+            "-32768",
+            "8388607",
+            "-2147483648",
+            "9223372036854775807",
+            "007",
+            "",
+            "text",
+        ]
+        .iter()
+        .map(|s| Bytes::from(s.to_string()))
+        .chain([Bytes::from("y".repeat(100)), Bytes::from("z".repeat(5000))])
+        .collect();
+        let listpack = write_listpack(&items);
+        assert_eq!(parse_listpack(&listpack), Some(items));
+        assert_eq!(
+            u32::from_le_bytes(listpack[..4].try_into().unwrap()) as usize,
+            listpack.len()
+        );
+
+        // enough entries for several nodes, with the sequence going back
+        // down within a node
+        let mut stream = Stream::default();
+        for i in 0..250 {
+            let id = StreamId {
+                ms: i / 2,
+                seq: (i % 2) * 10,
+            };
+            stream.add(id, vec![(Bytes::from("n"), Bytes::from(i.to_string()))]);
+        }
+        stream.trim(stream::Trim::MaxLen(240));
+        let value = Value::Stream(stream);
+        let mut buf = vec![];
+        write_value(&mut buf, &value, false);
+        assert_eq!(buf[0], 3);
+        let mut reader = RdbReader::new(&buf);
+        assert_eq!(reader.read_value(TYPE_STREAM_LISTPACKS_2), Ok(value));
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let mut writer = RdbWriter::new(false);
+        writer.entry(b"key", &Value::String(Bytes::from("value")), None);
+        let mut data = writer.finish();
+        let last = data.len() - 9;
+        data[last - 1] ^= 0xff;
+        assert!(matches!(
+            decode(&data),
+            Err(RdbError::ChecksumMismatch { .. })
+        ));
+        // the 5 byte value body starts at offset 15 and now runs past the end
+        let truncated = &data[..last - 3];
+        assert!(matches!(
+            decode(truncated),
+            Err(RdbError::Corrupt { offset: 15, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dump_payload() {
+        for (_, value, _) in sample_values() {
+            let payload = dump_payload(&value);
+            assert_eq!(parse_payload(&payload), Ok(value));
+        }
+        let mut payload = dump_payload(&Value::String(Bytes::from("hello")));
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(matches!(
+            parse_payload(&payload),
+            Err(RdbError::ChecksumMismatch { .. })
+        ));
+        let mut payload = dump_payload(&Value::String(Bytes::from("hello")));
+        // the version footer follows the type byte and the 6-byte string
+        payload[7] = 0xff;
+        assert!(matches!(
+            parse_payload(&payload),
+            Err(RdbError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_redis_dump() {
+        // written by redis 7.2: a listpack hash, a quicklist, an intset and a
+        // listpack zset, with the crc left at zero
+        let mut data = b"REDIS0011".to_vec();
+        data.extend_from_slice(&[OPCODE_SELECTDB, 0]);
+        // hash {f: v} as listpack
+        let hash_lp = [0x0d, 0, 0, 0, 2, 0, 0x81, b'f', 2, 0x81, b'v', 2, 0xff];
+        data.push(TYPE_HASH_LISTPACK);
+        write_string(&mut data, b"h", false);
+        write_string(&mut data, &hash_lp, false);
+        // list [7, -300] as quicklist 2 with one packed node
+        let list_lp = [0x0c, 0, 0, 0, 2, 0, 0x07, 1, 0xde, 0xd4, 2, 0xff];
+        data.push(TYPE_LIST_QUICKLIST_2);
+        write_string(&mut data, b"l", false);
+        data.extend_from_slice(&[1, QUICKLIST_NODE_PACKED as u8]);
+        write_string(&mut data, &list_lp, false);
+        // set {1, 2} as intset
+        let intset = [2, 0, 0, 0, 2, 0, 0, 0, 1, 0, 2, 0];
+        data.push(TYPE_SET_INTSET);
+        write_string(&mut data, b"s", false);
+        write_string(&mut data, &intset, false);
+        // zset {m: 3} as listpack
+        let zset_lp = [0x0d, 0, 0, 0, 2, 0, 0x81, b'm', 2, 0x03, 1, 0xff];
+        data.push(TYPE_ZSET_LISTPACK);
+        write_string(&mut data, b"z", false);
+        write_string(&mut data, &zset_lp, false);
+        data.push(OPCODE_EOF);
+        data.extend_from_slice(&[0; 8]);
+
+        let file = decode(&data).unwrap();
+        assert_eq!(file.checksum, None);
+        let values: Vec<Value> = file.entries.into_iter().map(|e| e.value).collect();
+        let mut zset = SortedSet::default();
+        zset.insert(Bytes::from("m"), 3.0);
+        assert_eq!(
+            values,
+            vec![
+                Value::Hash(HashMap::from([("f".to_string(), Bytes::from("v"))])),
+                Value::List(VecDeque::from([Bytes::from("7"), Bytes::from("-300")])),
+                Value::Set(HashSet::from([Bytes::from("1"), Bytes::from("2")])),
+                Value::ZSet(zset),
+            ]
+        );
+    }
+}
@@ -1,37 +1,1669 @@
 use lazy_static::lazy_static;
+use parking_lot::RwLock;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader},
+    fs,
+    path::{Path, PathBuf},
 };
 
-use super::{constants::CONFIG_FILE_PATH, errors::ServerError};
+use super::{
+    acl,
+    aof::{self, AofPaths, FsyncPolicy},
+    command_table,
+    datastore::Db,
+    errors::{ConfigError, UserInputError},
+    glob::glob_match,
+    listener, notify,
+    persistence::write_atomically,
+    resp_value::{bulk, RespType},
+    tls,
+};
+
+/// Includes nested deeper than this are taken to be an include loop.
+const MAX_INCLUDE_DEPTH: usize = 16;
 
-// TODO: parse config file
 lazy_static! {
-    static ref REDIS_CONFIG: HashMap<String, String> = {
-        let mut m = HashMap::new();
-        m.insert("port".to_string(), "6379".to_string());
-        m.insert("save".to_string(), "3600 1 300 100 60 10000".to_string());
-        m.insert("appendonly".to_string(), "no".to_string());
-        m
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+}
+
+/// How a directive's arguments are checked, and the form they are kept in.
+enum Kind {
+    /// yes or no
+    Bool,
+    /// an integer within the bounds
+    Int(i64, i64),
+    /// a memory amount with an optional unit, kept in bytes
+    Memory,
+    /// a memory amount or a percentage, like maxmemory-clients
+    MemoryOrPercent,
+    /// file permissions in octal, like unixsocketperm
+    Octal,
+    /// one of the listed words
+    Enum(&'static [&'static str]),
+    /// one or more of the listed words, like the shutdown flags
+    Flags(&'static [&'static str]),
+    /// any single argument, including an empty one
+    Str,
+    /// a single argument that names a file in the working directory
+    FileName,
+    /// arguments checked and normalized by the function
+    Args(fn(&[String]) -> Result<String, String>),
+    /// pairs of seconds and changes, or "" to turn snapshots off
+    Save,
+    /// groups of class, hard limit, soft limit and seconds
+    ClientOutputBufferLimit,
+}
+
+struct Spec {
+    name: &'static str,
+    kind: Kind,
+    /// default value, already in the stored form
+    default: &'static str,
+}
+
+const fn spec(name: &'static str, kind: Kind, default: &'static str) -> Spec {
+    Spec {
+        name,
+        kind,
+        default,
+    }
+}
+
+const LOG_LEVELS: &[&str] = &["debug", "verbose", "notice", "warning", "nothing"];
+const SUPERVISED: &[&str] = &["upstart", "systemd", "auto", "no"];
+const SYSLOG_FACILITIES: &[&str] = &[
+    "user", "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+const MAXMEMORY_POLICIES: &[&str] = &[
+    "volatile-lru",
+    "volatile-lfu",
+    "volatile-random",
+    "volatile-ttl",
+    "allkeys-lru",
+    "allkeys-lfu",
+    "allkeys-random",
+    "noeviction",
+];
+const FSYNC_POLICIES: &[&str] = &["always", "everysec", "no"];
+const DISKLESS_LOAD: &[&str] = &["disabled", "on-empty-db", "swapdb"];
+const OOM_SCORE_ADJ: &[&str] = &["no", "yes", "relative", "absolute"];
+const PUBSUB_DEFAULTS: &[&str] = &["allchannels", "resetchannels"];
+const SANITIZE_PAYLOAD: &[&str] = &["no", "yes", "clients"];
+const ENDPOINT_TYPES: &[&str] = &["ip", "hostname", "unknown-endpoint"];
+const ERROR_BEHAVIORS: &[&str] = &["ignore", "panic", "panic-on-replicas"];
+const PROTECTED_CONFIGS: &[&str] = &["no", "yes", "local"];
+const SHUTDOWN_FLAGS: &[&str] = &["default", "save", "nosave", "now", "force"];
+
+const MAX_INT: i64 = i32::MAX as i64;
+
+/// Every directive redis.conf may hold. Values are all checked and kept,
+/// though many tune features this server does not have.
+static SPECS: &[Spec] = &[
+    // network
+    spec("bind", Kind::Args(bind_addresses), "* -::*"),
+    spec("bind-source-addr", Kind::Str, ""),
+    spec("protected-mode", Kind::Bool, "yes"),
+    spec(
+        "enable-protected-configs",
+        Kind::Enum(PROTECTED_CONFIGS),
+        "no",
+    ),
+    spec("enable-debug-command", Kind::Enum(PROTECTED_CONFIGS), "no"),
+    spec("enable-module-command", Kind::Enum(PROTECTED_CONFIGS), "no"),
+    spec("port", Kind::Int(0, 65535), "6379"),
+    spec("tcp-backlog", Kind::Int(0, MAX_INT), "511"),
+    spec("unixsocket", Kind::Str, ""),
+    spec("unixsocketperm", Kind::Octal, "0"),
+    spec("timeout", Kind::Int(0, MAX_INT), "0"),
+    spec("tcp-keepalive", Kind::Int(0, MAX_INT), "300"),
+    spec("socket-mark-id", Kind::Int(0, u32::MAX as i64), "0"),
+    spec("max-new-connections-per-cycle", Kind::Int(1, 1000), "10"),
+    spec("max-new-tls-connections-per-cycle", Kind::Int(1, 1000), "1"),
+    // tls
+    spec("tls-port", Kind::Int(0, 65535), "0"),
+    spec("tls-cert-file", Kind::Str, ""),
+    spec("tls-key-file", Kind::Str, ""),
+    spec("tls-key-file-pass", Kind::Str, ""),
+    spec("tls-client-cert-file", Kind::Str, ""),
+    spec("tls-client-key-file", Kind::Str, ""),
+    spec("tls-client-key-file-pass", Kind::Str, ""),
+    spec("tls-dh-params-file", Kind::Str, ""),
+    spec("tls-ca-cert-file", Kind::Str, ""),
+    spec("tls-ca-cert-dir", Kind::Str, ""),
+    spec(
+        "tls-auth-clients",
+        Kind::Enum(&["no", "yes", "optional"]),
+        "yes",
+    ),
+    spec("tls-replication", Kind::Bool, "no"),
+    spec("tls-cluster", Kind::Bool, "no"),
+    spec("tls-protocols", Kind::Str, ""),
+    spec("tls-ciphers", Kind::Str, ""),
+    spec("tls-ciphersuites", Kind::Str, ""),
+    spec("tls-prefer-server-ciphers", Kind::Bool, "no"),
+    spec("tls-session-caching", Kind::Bool, "yes"),
+    spec("tls-session-cache-size", Kind::Int(0, MAX_INT), "20480"),
+    spec("tls-session-cache-timeout", Kind::Int(0, MAX_INT), "300"),
+    // general
+    spec("daemonize", Kind::Bool, "no"),
+    spec("supervised", Kind::Enum(SUPERVISED), "no"),
+    spec("pidfile", Kind::Str, ""),
+    spec("loglevel", Kind::Enum(LOG_LEVELS), "notice"),
+    spec("logfile", Kind::Str, ""),
+    spec("syslog-enabled", Kind::Bool, "no"),
+    spec("syslog-ident", Kind::Str, "redis"),
+    spec("syslog-facility", Kind::Enum(SYSLOG_FACILITIES), "local0"),
+    spec("crash-log-enabled", Kind::Bool, "yes"),
+    spec("crash-memcheck-enabled", Kind::Bool, "yes"),
+    spec("databases", Kind::Int(1, MAX_INT), "16"),
+    spec("always-show-logo", Kind::Bool, "no"),
+    spec("set-proc-title", Kind::Bool, "yes"),
+    spec(
+        "proc-title-template",
+        Kind::Str,
+        "{title} {listen-addr} {server-mode}",
+    ),
+    spec("locale-collate", Kind::Str, ""),
+    spec("hide-user-data-from-log", Kind::Bool, "no"),
+    // snapshotting
+    spec("save", Kind::Save, "3600 1 300 100 60 10000"),
+    spec("stop-writes-on-bgsave-error", Kind::Bool, "yes"),
+    spec("rdbcompression", Kind::Bool, "yes"),
+    spec("rdbchecksum", Kind::Bool, "yes"),
+    spec("sanitize-dump-payload", Kind::Enum(SANITIZE_PAYLOAD), "no"),
+    spec("dbfilename", Kind::FileName, "dump.rdb"),
+    spec("rdb-del-sync-files", Kind::Bool, "no"),
+    spec("dir", Kind::Str, "./"),
+    // replication
+    spec("masterauth", Kind::Str, ""),
+    spec("masteruser", Kind::Str, ""),
+    spec("replica-serve-stale-data", Kind::Bool, "yes"),
+    spec("replica-read-only", Kind::Bool, "yes"),
+    spec("repl-diskless-sync", Kind::Bool, "yes"),
+    spec("repl-diskless-sync-delay", Kind::Int(0, MAX_INT), "5"),
+    spec(
+        "repl-diskless-sync-max-replicas",
+        Kind::Int(0, MAX_INT),
+        "0",
+    ),
+    spec("repl-diskless-load", Kind::Enum(DISKLESS_LOAD), "disabled"),
+    spec("repl-ping-replica-period", Kind::Int(1, MAX_INT), "10"),
+    spec("repl-timeout", Kind::Int(1, MAX_INT), "60"),
+    spec("repl-disable-tcp-nodelay", Kind::Bool, "no"),
+    spec("repl-backlog-size", Kind::Memory, "1048576"),
+    spec("repl-backlog-ttl", Kind::Int(0, MAX_INT), "3600"),
+    spec("replica-priority", Kind::Int(0, MAX_INT), "100"),
+    spec("replica-announced", Kind::Bool, "yes"),
+    spec("min-replicas-to-write", Kind::Int(0, MAX_INT), "0"),
+    spec("min-replicas-max-lag", Kind::Int(0, MAX_INT), "10"),
+    spec("replica-announce-ip", Kind::Str, ""),
+    spec("replica-announce-port", Kind::Int(0, 65535), "0"),
+    spec("replica-ignore-maxmemory", Kind::Bool, "yes"),
+    spec("replica-ignore-disk-write-errors", Kind::Bool, "no"),
+    spec("replica-lazy-flush", Kind::Bool, "no"),
+    spec(
+        "propagation-error-behavior",
+        Kind::Enum(ERROR_BEHAVIORS),
+        "ignore",
+    ),
+    // keys tracking
+    spec("tracking-table-max-keys", Kind::Int(0, i64::MAX), "1000000"),
+    // security
+    spec("acllog-max-len", Kind::Int(0, i64::MAX), "128"),
+    spec("aclfile", Kind::Str, ""),
+    spec(
+        "acl-pubsub-default",
+        Kind::Enum(PUBSUB_DEFAULTS),
+        "resetchannels",
+    ),
+    spec("requirepass", Kind::Str, ""),
+    // clients and memory
+    spec("maxclients", Kind::Int(1, MAX_INT), "10000"),
+    spec("maxmemory", Kind::Memory, "0"),
+    spec(
+        "maxmemory-policy",
+        Kind::Enum(MAXMEMORY_POLICIES),
+        "noeviction",
+    ),
+    spec("maxmemory-samples", Kind::Int(1, 64), "5"),
+    spec("maxmemory-eviction-tenacity", Kind::Int(0, 100), "10"),
+    spec("maxmemory-clients", Kind::MemoryOrPercent, "0"),
+    spec("active-expire-effort", Kind::Int(1, 10), "1"),
+    // lazy freeing
+    spec("lazyfree-lazy-eviction", Kind::Bool, "no"),
+    spec("lazyfree-lazy-expire", Kind::Bool, "no"),
+    spec("lazyfree-lazy-server-del", Kind::Bool, "no"),
+    spec("lazyfree-lazy-user-del", Kind::Bool, "no"),
+    spec("lazyfree-lazy-user-flush", Kind::Bool, "no"),
+    // threads and kernel
+    spec("io-threads", Kind::Int(1, 128), "1"),
+    spec("io-threads-do-reads", Kind::Bool, "no"),
+    spec("oom-score-adj", Kind::Enum(OOM_SCORE_ADJ), "no"),
+    spec(
+        "oom-score-adj-values",
+        Kind::Args(oom_score_adj_values),
+        "0 200 800",
+    ),
+    spec("disable-thp", Kind::Bool, "yes"),
+    spec("server-cpulist", Kind::Str, ""),
+    spec("bio-cpulist", Kind::Str, ""),
+    spec("aof-rewrite-cpulist", Kind::Str, ""),
+    spec("bgsave-cpulist", Kind::Str, ""),
+    spec("ignore-warnings", Kind::Str, ""),
+    // append only mode
+    spec("appendonly", Kind::Bool, "no"),
+    spec("appendfilename", Kind::FileName, "appendonly.aof"),
+    spec("appenddirname", Kind::FileName, "appendonlydir"),
+    spec("appendfsync", Kind::Enum(FSYNC_POLICIES), "everysec"),
+    spec("no-appendfsync-on-rewrite", Kind::Bool, "no"),
+    spec("auto-aof-rewrite-percentage", Kind::Int(0, MAX_INT), "100"),
+    spec("auto-aof-rewrite-min-size", Kind::Memory, "67108864"),
+    spec("aof-load-truncated", Kind::Bool, "yes"),
+    spec("aof-use-rdb-preamble", Kind::Bool, "yes"),
+    spec("aof-timestamp-enabled", Kind::Bool, "no"),
+    spec("aof-rewrite-incremental-fsync", Kind::Bool, "yes"),
+    spec("rdb-save-incremental-fsync", Kind::Bool, "yes"),
+    // shutdown
+    spec("shutdown-timeout", Kind::Int(0, MAX_INT), "10"),
+    spec("shutdown-on-sigint", Kind::Flags(SHUTDOWN_FLAGS), "default"),
+    spec(
+        "shutdown-on-sigterm",
+        Kind::Flags(SHUTDOWN_FLAGS),
+        "default",
+    ),
+    // scripting
+    spec("busy-reply-threshold", Kind::Int(0, i64::MAX), "5000"),
+    // cluster
+    spec("cluster-enabled", Kind::Bool, "no"),
+    spec("cluster-config-file", Kind::Str, "nodes.conf"),
+    spec("cluster-node-timeout", Kind::Int(0, i64::MAX), "15000"),
+    spec("cluster-port", Kind::Int(0, 65535), "0"),
+    spec(
+        "cluster-replica-validity-factor",
+        Kind::Int(0, MAX_INT),
+        "10",
+    ),
+    spec("cluster-migration-barrier", Kind::Int(0, MAX_INT), "1"),
+    spec("cluster-allow-replica-migration", Kind::Bool, "yes"),
+    spec("cluster-require-full-coverage", Kind::Bool, "yes"),
+    spec("cluster-replica-no-failover", Kind::Bool, "no"),
+    spec("cluster-allow-reads-when-down", Kind::Bool, "no"),
+    spec("cluster-allow-pubsubshard-when-down", Kind::Bool, "yes"),
+    spec("cluster-link-sendbuf-limit", Kind::Memory, "0"),
+    spec("cluster-announce-hostname", Kind::Str, ""),
+    spec("cluster-announce-human-nodename", Kind::Str, ""),
+    spec("cluster-announce-ip", Kind::Str, ""),
+    spec("cluster-announce-port", Kind::Int(0, 65535), "0"),
+    spec("cluster-announce-bus-port", Kind::Int(0, 65535), "0"),
+    spec("cluster-announce-tls-port", Kind::Int(0, 65535), "0"),
+    spec(
+        "cluster-preferred-endpoint-type",
+        Kind::Enum(ENDPOINT_TYPES),
+        "ip",
+    ),
+    // monitoring
+    spec("slowlog-log-slower-than", Kind::Int(-1, i64::MAX), "10000"),
+    spec("slowlog-max-len", Kind::Int(0, i64::MAX), "128"),
+    spec("latency-monitor-threshold", Kind::Int(0, i64::MAX), "0"),
+    spec("latency-tracking", Kind::Bool, "yes"),
+    spec(
+        "latency-tracking-info-percentiles",
+        Kind::Args(percentiles),
+        "50 99 99.9",
+    ),
+    spec("notify-keyspace-events", Kind::Args(keyspace_events), ""),
+    // data structures
+    spec("hash-max-listpack-entries", Kind::Int(0, i64::MAX), "128"),
+    spec("hash-max-listpack-value", Kind::Memory, "64"),
+    spec("list-max-listpack-size", Kind::Int(-5, MAX_INT), "-2"),
+    spec("list-compress-depth", Kind::Int(0, MAX_INT), "0"),
+    spec("set-max-intset-entries", Kind::Int(0, i64::MAX), "512"),
+    spec("set-max-listpack-entries", Kind::Int(0, i64::MAX), "128"),
+    spec("set-max-listpack-value", Kind::Memory, "64"),
+    spec("zset-max-listpack-entries", Kind::Int(0, i64::MAX), "128"),
+    spec("zset-max-listpack-value", Kind::Memory, "64"),
+    spec("hll-sparse-max-bytes", Kind::Memory, "3000"),
+    spec("stream-node-max-bytes", Kind::Memory, "4096"),
+    spec("stream-node-max-entries", Kind::Int(0, i64::MAX), "100"),
+    // advanced
+    spec("activerehashing", Kind::Bool, "yes"),
+    spec(
+        "client-output-buffer-limit",
+        Kind::ClientOutputBufferLimit,
+        "normal 0 0 0 replica 268435456 67108864 60 pubsub 33554432 8388608 60",
+    ),
+    spec("client-query-buffer-limit", Kind::Memory, "1073741824"),
+    spec("proto-max-bulk-len", Kind::Memory, "536870912"),
+    spec("hz", Kind::Int(0, MAX_INT), "10"),
+    spec("dynamic-hz", Kind::Bool, "yes"),
+    spec("jemalloc-bg-thread", Kind::Bool, "yes"),
+    spec("lfu-log-factor", Kind::Int(0, MAX_INT), "10"),
+    spec("lfu-decay-time", Kind::Int(0, MAX_INT), "1"),
+    spec("activedefrag", Kind::Bool, "no"),
+    spec("active-defrag-ignore-bytes", Kind::Memory, "104857600"),
+    spec("active-defrag-threshold-lower", Kind::Int(0, 1000), "10"),
+    spec("active-defrag-threshold-upper", Kind::Int(0, 1000), "100"),
+    spec("active-defrag-cycle-min", Kind::Int(1, 99), "1"),
+    spec("active-defrag-cycle-max", Kind::Int(1, 99), "25"),
+    spec(
+        "active-defrag-max-scan-fields",
+        Kind::Int(1, i64::MAX),
+        "1000",
+    ),
+];
+
+/// Old names redis still accepts for renamed directives.
+const ALIASES: &[(&str, &str)] = &[
+    ("slave-serve-stale-data", "replica-serve-stale-data"),
+    ("slave-read-only", "replica-read-only"),
+    ("repl-ping-slave-period", "repl-ping-replica-period"),
+    ("slave-priority", "replica-priority"),
+    ("slave-announce-ip", "replica-announce-ip"),
+    ("slave-announce-port", "replica-announce-port"),
+    ("slave-ignore-maxmemory", "replica-ignore-maxmemory"),
+    ("slave-lazy-flush", "replica-lazy-flush"),
+    ("min-slaves-to-write", "min-replicas-to-write"),
+    ("min-slaves-max-lag", "min-replicas-max-lag"),
+    (
+        "cluster-slave-validity-factor",
+        "cluster-replica-validity-factor",
+    ),
+    ("cluster-slave-no-failover", "cluster-replica-no-failover"),
+    ("hash-max-ziplist-entries", "hash-max-listpack-entries"),
+    ("hash-max-ziplist-value", "hash-max-listpack-value"),
+    ("list-max-ziplist-size", "list-max-listpack-size"),
+    ("zset-max-ziplist-entries", "zset-max-listpack-entries"),
+    ("zset-max-ziplist-value", "zset-max-listpack-value"),
+    ("lua-time-limit", "busy-reply-threshold"),
+];
+
+/// Directives of features this server does not have, where going on without
+/// them would quietly run something else than what was configured.
+const UNSUPPORTED: &[(&str, &str)] = &[
+    ("replicaof", "replication is not supported by this server"),
+    ("slaveof", "replication is not supported by this server"),
+    ("loadmodule", "modules are not supported by this server"),
+];
+
+/// The comment CONFIG REWRITE puts before the directives it adds to a file.
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+/// Directives CONFIG SET refuses, since they only take effect at startup.
+const IMMUTABLE: &[&str] = &[
+    "daemonize",
+    "supervised",
+    "databases",
+    "io-threads",
+    "io-threads-do-reads",
+    "port",
+    "tls-port",
+    "bind",
+    "unixsocket",
+    "unixsocketperm",
+    "logfile",
+    "syslog-enabled",
+    "syslog-ident",
+    "syslog-facility",
+    "pidfile",
+    "tcp-backlog",
+    "cluster-enabled",
+    "cluster-config-file",
+    "cluster-port",
+    "appenddirname",
+    "aclfile",
+    "disable-thp",
+    "always-show-logo",
+];
+
+fn find_spec(name: &str) -> Option<&'static Spec> {
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, canonical)| canonical);
+    SPECS.iter().find(|spec| spec.name == name)
+}
+
+fn bind_addresses(args: &[String]) -> Result<String, String> {
+    if args.is_empty() || args.len() > 16 || args.iter().any(|a| a.is_empty()) {
+        return Err("Too many bind addresses specified.".to_string());
+    }
+    let value = args.join(" ");
+    listener::parse_bind(&value)?;
+    Ok(value)
+}
+
+fn oom_score_adj_values(args: &[String]) -> Result<String, String> {
+    let values: Vec<&str> = match args {
+        [one] => one.split_whitespace().collect(),
+        _ => args.iter().map(String::as_str).collect(),
     };
+    let valid = values.len() == 3
+        && values
+            .iter()
+            .all(|v| v.parse::<i64>().is_ok_and(|v| (-2000..=2000).contains(&v)));
+    if !valid {
+        return Err(
+            "Invalid oom-score-adj-values, elements must be between -2000 and 2000.".to_string(),
+        );
+    }
+    Ok(values.join(" "))
 }
 
-pub fn parse_config_file() -> Option<ServerError> {
-    let file = File::open(CONFIG_FILE_PATH).ok()?;
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.ok()?;
-        if line.starts_with('#') {
-            continue;
+fn percentiles(args: &[String]) -> Result<String, String> {
+    let values: Vec<&str> = args.iter().flat_map(|a| a.split_whitespace()).collect();
+    let valid = values
+        .iter()
+        .all(|v| v.parse::<f64>().is_ok_and(|v| (0.0..=100.0).contains(&v)));
+    if !valid {
+        return Err(
+            "latency-tracking-info-percentiles must be numbers between 0 and 100".to_string(),
+        );
+    }
+    Ok(values.join(" "))
+}
+
+fn keyspace_events(args: &[String]) -> Result<String, String> {
+    notify::parse(single(args)?)
+        .map(notify::describe)
+        .ok_or_else(|| "Invalid event class character. Use 'Ag$lshzxeKEtmn'.".to_string())
+}
+
+fn single(args: &[String]) -> Result<&str, String> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err("wrong number of arguments".to_string()),
+    }
+}
+
+fn normalize_memory(arg: &str) -> Result<String, String> {
+    parse_memory(arg)
+        .map(|bytes| bytes.to_string())
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+fn normalize_save(args: &[String]) -> Result<String, String> {
+    let words: Vec<&str> = args.iter().flat_map(|a| a.split_whitespace()).collect();
+    let valid = words.len().is_multiple_of(2)
+        && words.chunks(2).all(|pair| {
+            pair[0].parse::<u64>().is_ok_and(|seconds| seconds > 0)
+                && pair[1].parse::<u64>().is_ok()
+        });
+    if !valid {
+        return Err("Invalid save parameters".to_string());
+    }
+    Ok(words.join(" "))
+}
+
+/// Sets the limits of the classes given, keeping the others from `current`.
+fn merge_client_output_buffer_limit(current: &str, args: &[String]) -> Result<String, String> {
+    let words: Vec<&str> = args.iter().flat_map(|a| a.split_whitespace()).collect();
+    if words.is_empty() || !words.len().is_multiple_of(4) {
+        return Err("wrong number of arguments".to_string());
+    }
+    let mut limits: Vec<[String; 4]> = current
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|c| [c[0], c[1], c[2], c[3]].map(str::to_string))
+        .collect();
+    for group in words.chunks(4) {
+        let class = match group[0].to_lowercase().as_str() {
+            "normal" => "normal",
+            "replica" | "slave" => "replica",
+            "pubsub" => "pubsub",
+            _ => {
+                return Err(
+                    "Invalid client class specified in buffer limit configuration.".to_string(),
+                )
+            }
+        };
+        let (Some(hard), Some(soft), Ok(seconds)) = (
+            parse_memory(group[1]),
+            parse_memory(group[2]),
+            group[3].parse::<u64>(),
+        ) else {
+            return Err(
+                "Error in hard, soft or soft_seconds setting in buffer limit configuration."
+                    .to_string(),
+            );
+        };
+        let entry = [
+            class.to_string(),
+            hard.to_string(),
+            soft.to_string(),
+            seconds.to_string(),
+        ];
+        match limits.iter_mut().find(|l| l[0] == class) {
+            Some(limit) => *limit = entry,
+            None => limits.push(entry),
+        }
+    }
+    Ok(limits.concat().join(" "))
+}
+
+fn normalize(spec: &Spec, current: &str, args: &[String]) -> Result<String, String> {
+    match spec.kind {
+        Kind::Bool => match single(args)?.to_lowercase().as_str() {
+            "yes" => Ok("yes".to_string()),
+            "no" => Ok("no".to_string()),
+            _ => Err("argument must be 'yes' or 'no'".to_string()),
+        },
+        Kind::Int(min, max) => {
+            let value: i64 = single(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer".to_string())?;
+            if !(min..=max).contains(&value) {
+                return Err(format!(
+                    "argument must be between {} and {} inclusive",
+                    min, max
+                ));
+            }
+            Ok(value.to_string())
+        }
+        Kind::Memory => normalize_memory(single(args)?),
+        Kind::MemoryOrPercent => {
+            let arg = single(args)?;
+            match arg.strip_suffix('%') {
+                Some(percent) => match percent.parse::<u64>() {
+                    Ok(p) if p <= 100 => Ok(format!("{}%", p)),
+                    _ => Err("argument must be a memory or percent value".to_string()),
+                },
+                None => normalize_memory(arg),
+            }
+        }
+        Kind::Octal => {
+            let arg = single(args)?;
+            u32::from_str_radix(arg, 8)
+                .map(|_| arg.to_string())
+                .map_err(|_| "argument must be an octal number".to_string())
+        }
+        Kind::Enum(options) => {
+            let arg = single(args)?.to_lowercase();
+            if options.contains(&arg.as_str()) {
+                Ok(arg)
+            } else {
+                Err(format!(
+                    "argument(s) must be one of the following: {}",
+                    options.join(", ")
+                ))
+            }
+        }
+        Kind::Flags(options) => {
+            let words: Vec<String> = args
+                .iter()
+                .flat_map(|a| a.split_whitespace())
+                .map(str::to_lowercase)
+                .collect();
+            if words.is_empty() || words.iter().any(|w| !options.contains(&w.as_str())) {
+                return Err(format!(
+                    "argument(s) must be one of the following: {}",
+                    options.join(", ")
+                ));
+            }
+            Ok(words.join(" "))
+        }
+        Kind::Str => single(args).map(str::to_string),
+        Kind::FileName => {
+            let arg = single(args)?;
+            if arg.is_empty() || arg.contains('/') || arg.contains('\\') {
+                return Err(format!("{} can't be a path, just a filename", spec.name));
+            }
+            Ok(arg.to_string())
+        }
+        Kind::Args(check) => check(args),
+        Kind::Save => match args {
+            [arg] if arg.is_empty() => Ok(String::new()),
+            _ => normalize_save(args),
+        },
+        Kind::ClientOutputBufferLimit => merge_client_output_buffer_limit(current, args),
+    }
+}
+
+/// Splits a config line into arguments the way redis does. Arguments may be
+/// "double quoted", with C-like escapes such as \n and \xff, or 'single
+/// quoted', where only \' is special. A closing quote must end the argument.
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let unbalanced = || "Unbalanced quotes in configuration line".to_string();
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+        let mut arg = String::new();
+        if first == '"' || first == '\'' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    None => return Err(unbalanced()),
+                    Some(c) if c == first => break,
+                    Some('\\') if first == '\'' => match chars.next_if_eq(&'\'') {
+                        Some(quote) => arg.push(quote),
+                        None => arg.push('\\'),
+                    },
+                    Some('\\') => match chars.next() {
+                        Some('n') => arg.push('\n'),
+                        Some('r') => arg.push('\r'),
+                        Some('t') => arg.push('\t'),
+                        Some('b') => arg.push('\u{8}'),
+                        Some('a') => arg.push('\u{7}'),
+                        Some('x') => {
+                            let hex: String = chars.clone().take(2).collect();
+                            match u8::from_str_radix(&hex, 16) {
+                                Ok(byte) if hex.len() == 2 => {
+                                    arg.push(byte as char);
+                                    chars.nth(1);
+                                }
+                                _ => arg.push('x'),
+                            }
+                        }
+                        Some(c) => arg.push(c),
+                        None => return Err(unbalanced()),
+                    },
+                    Some(c) => arg.push(c),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return Err(unbalanced());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Files an include directive names: the path itself, or every file that
+/// matches a wildcard in its last component, in sorted order.
+fn include_paths(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let path = Path::new(pattern);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| format!("Failed opening the directory {}: {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .filter(|entry| {
+            glob_match(
+                name.as_bytes(),
+                entry.file_name().to_string_lossy().as_bytes(),
+                false,
+            )
+        })
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// The server configuration: every directive's value in a normalized form
+/// (memory in bytes, yes/no, lowercased enums), plus the file-only ones.
+#[derive(Debug, Clone)]
+pub struct Config {
+    values: HashMap<&'static str, String>,
+    /// rename-command directives as (command, new name); an empty new name
+    /// disables the command
+    pub renamed_commands: Vec<(String, String)>,
+    /// `user` directives, each the user name followed by its ACL rules
+    pub users: Vec<Vec<String>>,
+    /// the file the configuration was read from, for CONFIG REWRITE
+    pub file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            values: SPECS
+                .iter()
+                .map(|spec| (spec.name, spec.default.to_string()))
+                .collect(),
+            renamed_commands: vec![],
+            users: vec![],
+            file: None,
+        }
+    }
+}
+
+// state carried across the files of one load
+struct Loader {
+    config: Config,
+    /// the first save line replaces the default rules, later ones add to them
+    save_seen: bool,
+    depth: usize,
+}
+
+impl Config {
+    /// Reads a config file on top of the defaults.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        Config::load(Some(path.as_ref()), &[])
+    }
+
+    /// Reads the config file, if any, then the directives given on the
+    /// command line, each a name followed by its arguments. Like redis, the
+    /// command line comes last and so overrides the file.
+    pub fn load(file: Option<&Path>, options: &[Vec<String>]) -> Result<Config, ConfigError> {
+        let mut loader = Loader {
+            config: Config::default(),
+            save_seen: false,
+            depth: 0,
+        };
+        if let Some(path) = file {
+            loader.load_file(path)?;
+            // absolute, as the server changes to `dir` after reading it
+            loader.config.file = fs::canonicalize(path).ok();
+        }
+        for (i, option) in options.iter().enumerate() {
+            let Some(name) = option.first() else {
+                continue;
+            };
+            loader
+                .apply(&name.to_lowercase(), &option[1..])
+                .map_err(|e| match e {
+                    Applied::Nested(e) => e,
+                    Applied::Invalid(reason) => ConfigError::Directive {
+                        path: "(command line)".to_string(),
+                        line: i + 1,
+                        text: option
+                            .iter()
+                            .map(|a| quote(a))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        reason,
+                    },
+                })?;
+        }
+        Ok(loader.config)
+    }
+
+    /// Reads config text, as found in a file at `path`, on top of the defaults.
+    pub fn parse(text: &str, path: &str) -> Result<Config, ConfigError> {
+        let mut loader = Loader {
+            config: Config::default(),
+            save_seen: false,
+            depth: 0,
+        };
+        loader.load_str(text, path)?;
+        Ok(loader.config)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let spec = find_spec(&name.to_lowercase())?;
+        self.values.get(spec.name).map(String::as_str)
+    }
+
+    /// Checks and stores one directive, replacing its previous value.
+    pub fn set(&mut self, name: &str, args: &[String]) -> Result<(), String> {
+        let spec = find_spec(&name.to_lowercase())
+            .ok_or_else(|| "Bad directive or wrong number of arguments".to_string())?;
+        let current = self.values.get(spec.name).cloned().unwrap_or_default();
+        let value = normalize(spec, &current, args)?;
+        self.values.insert(spec.name, value);
+        Ok(())
+    }
+
+    /// The text of a config file whose directives give `on_file`, changed to
+    /// give this configuration instead. The first line of a directive whose
+    /// value changed is replaced and any later ones dropped; directives the
+    /// file doesn't mention are added at the end. Everything else, comments
+    /// included, is kept as it was. The `user` lines are handled as one
+    /// directive.
+    fn rewrite_text(&self, text: &str, on_file: &Config) -> String {
+        let changed = |spec: &Spec| self.values.get(spec.name) != on_file.values.get(spec.name);
+        let users_changed = self.users != on_file.users;
+        let mut users_written = false;
+        let mut written: Vec<&'static str> = vec![];
+        let mut lines: Vec<String> = vec![];
+        let mut signature_at = None;
+        for line in text.lines() {
+            // like redis, move the signature to before what this rewrite
+            // adds, or keep it where it was when nothing is added
+            if line == REWRITE_SIGNATURE {
+                signature_at.get_or_insert(lines.len());
+                continue;
+            }
+            let name = tokenize(line)
+                .ok()
+                .and_then(|args| args.first().map(|name| name.to_lowercase()));
+            if users_changed && name.as_deref() == Some("user") {
+                if !users_written {
+                    users_written = true;
+                    lines.extend(self.user_lines());
+                }
+                continue;
+            }
+            match name.and_then(|name| find_spec(&name)) {
+                Some(spec) if changed(spec) => {
+                    if !written.contains(&spec.name) {
+                        written.push(spec.name);
+                        lines.extend(render(spec, &self.values[spec.name]));
+                    }
+                }
+                _ => lines.push(line.to_string()),
+            }
+        }
+        let missing: Vec<&Spec> = SPECS
+            .iter()
+            .filter(|spec| changed(spec) && !written.contains(&spec.name))
+            .collect();
+        let missing_users = users_changed && !users_written;
+        if !missing.is_empty() || missing_users {
+            lines.push(REWRITE_SIGNATURE.to_string());
+            for spec in missing {
+                lines.extend(render(spec, &self.values[spec.name]));
+            }
+            if missing_users {
+                lines.extend(self.user_lines());
+            }
+        } else if let Some(at) = signature_at {
+            lines.insert(at, REWRITE_SIGNATURE.to_string());
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    fn user_lines(&self) -> Vec<String> {
+        self.users
+            .iter()
+            .map(|user| {
+                let args: Vec<String> = user.iter().map(|a| quote(a)).collect();
+                format!("user {}", args.join(" "))
+            })
+            .collect()
+    }
+}
+
+/// The config file lines for a directive's stored value.
+fn render(spec: &Spec, value: &str) -> Vec<String> {
+    let line = |value: &str| format!("{} {}", spec.name, value);
+    match spec.kind {
+        Kind::Memory => vec![line(&human_memory(value))],
+        Kind::MemoryOrPercent if !value.ends_with('%') => vec![line(&human_memory(value))],
+        Kind::Save if value.is_empty() => vec![line("\"\"")],
+        Kind::ClientOutputBufferLimit => value
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .chunks(4)
+            .map(|c| {
+                line(&format!(
+                    "{} {} {} {}",
+                    c[0],
+                    human_memory(c[1]),
+                    human_memory(c[2]),
+                    c[3]
+                ))
+            })
+            .collect(),
+        Kind::Str | Kind::FileName => vec![line(&quote(value))],
+        _ => vec![line(value)],
+    }
+}
+
+/// A byte count in the largest of gb, mb and kb that divides it.
+fn human_memory(bytes: &str) -> String {
+    let Ok(n) = bytes.parse::<u64>() else {
+        return bytes.to_string();
+    };
+    for (unit, size) in [("gb", 1 << 30), ("mb", 1 << 20), ("kb", 1 << 10)] {
+        if n != 0 && n % size == 0 {
+            return format!("{}{}", n / size, unit);
+        }
+    }
+    n.to_string()
+}
+
+/// An argument as it must be written for `tokenize` to read it back.
+pub fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && !matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return arg.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() && (c as u32) < 0x100 => {
+                quoted.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        self.load_str(&text, &path.display().to_string())
+    }
+
+    fn load_str(&mut self, text: &str, path: &str) -> Result<(), ConfigError> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |reason: String| ConfigError::Directive {
+                path: path.to_string(),
+                line: i + 1,
+                text: line.to_string(),
+                reason,
+            };
+            let args = tokenize(line).map_err(error)?;
+            let name = args[0].to_lowercase();
+            self.apply(&name, &args[1..]).map_err(|e| match e {
+                Applied::Nested(e) => e,
+                Applied::Invalid(reason) => error(reason),
+            })?;
         }
-        // let (k, v) = line.split_once(" ").unwrap();
-        // unsafe { REDIS_CONFIG.insert(k.to_string(), v.to_string()) };
+        Ok(())
     }
-    None
+
+    fn apply(&mut self, name: &str, args: &[String]) -> Result<(), Applied> {
+        if let Some((_, reason)) = UNSUPPORTED.iter().find(|(n, _)| *n == name) {
+            return Err(Applied::Invalid(reason.to_string()));
+        }
+        match name {
+            "include" => {
+                let pattern = single(args).map_err(Applied::Invalid)?;
+                if self.depth >= MAX_INCLUDE_DEPTH {
+                    return Err(Applied::Invalid(
+                        "includes nested too deep, is a file including itself?".to_string(),
+                    ));
+                }
+                self.depth += 1;
+                for path in include_paths(pattern).map_err(Applied::Invalid)? {
+                    self.load_file(&path).map_err(|e| match e {
+                        ConfigError::Io { path, reason } => {
+                            Applied::Invalid(format!("Failed to open {}: {}", path, reason))
+                        }
+                        e => Applied::Nested(e),
+                    })?;
+                }
+                self.depth -= 1;
+                Ok(())
+            }
+            "rename-command" => match args {
+                [command, new_name] => {
+                    self.config
+                        .renamed_commands
+                        .push((command.to_lowercase(), new_name.to_lowercase()));
+                    Ok(())
+                }
+                _ => Err(Applied::Invalid("wrong number of arguments".to_string())),
+            },
+            "user" => {
+                acl::parse_user(args).map_err(Applied::Invalid)?;
+                self.config.users.push(args.to_vec());
+                Ok(())
+            }
+            "save" => {
+                let mut merged = args.to_vec();
+                let current = self.config.get("save").unwrap_or_default().to_string();
+                if self.save_seen && !current.is_empty() && args.iter().all(|a| !a.is_empty()) {
+                    merged.insert(0, current);
+                }
+                self.save_seen = true;
+                self.config.set(name, &merged).map_err(Applied::Invalid)
+            }
+            _ => self.config.set(name, args).map_err(Applied::Invalid),
+        }
+    }
+}
+
+enum Applied {
+    /// the error came from an included file and already says where
+    Nested(ConfigError),
+    Invalid(String),
+}
+
+/// Reads the config file and command-line directives, as `Config::load`
+/// does, and makes them the running configuration.
+pub fn load_config(file: Option<&Path>, options: &[Vec<String>]) -> Result<(), ConfigError> {
+    let config = Config::load(file, options)?;
+    *CONFIG.write() = config;
+    Ok(())
+}
+
+/// The running configuration.
+pub fn config() -> Config {
+    CONFIG.read().clone()
 }
 
 pub fn get_config(key: &str) -> Option<String> {
-    REDIS_CONFIG.get(key).cloned()
+    CONFIG.read().get(key).map(str::to_string)
+}
+
+pub fn get_bool(key: &str) -> bool {
+    get_config(key).is_some_and(|v| v == "yes")
+}
+
+pub fn get_int(key: &str) -> i64 {
+    get_config(key).and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+/// Whether a message of the given level is logged under the running
+/// `loglevel`.
+pub fn log_enabled(level: &str) -> bool {
+    let rank = |level: &str| LOG_LEVELS.iter().position(|&l| l == level);
+    rank(level) >= rank(&get_config("loglevel").unwrap_or_default())
+}
+
+/// A memory directive in bytes.
+pub fn get_memory(key: &str) -> u64 {
+    get_config(key).and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+/// CONFIG GET, SET, RESETSTAT and REWRITE; `args` starts at the subcommand.
+pub fn config_command(db: &Db, args: &[String]) -> Result<RespType, UserInputError> {
+    let Some(subcommand) = args.first() else {
+        return Err(UserInputError::InvalidInput(
+            "wrong number of arguments for 'config' command".to_string(),
+        ));
+    };
+    let spec = command_table::lookup("config").and_then(|config| {
+        config
+            .subcommands
+            .iter()
+            .find(|s| s.name == subcommand.to_lowercase())
+    });
+    let Some(spec) = spec else {
+        return Ok(RespType::Error(format!(
+            "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+            subcommand
+        )));
+    };
+    // CONFIG SET also takes its arguments in pairs
+    if !spec.arity_matches(args.len() + 1) || (spec.name == "set" && args.len().is_multiple_of(2)) {
+        return Ok(RespType::Error(format!(
+            "ERR wrong number of arguments for 'config|{}' command",
+            spec.name
+        )));
+    }
+    match spec.name {
+        "get" => Ok(config_get(&args[1..])),
+        "set" => Ok(config_set(db, &args[1..])),
+        "resetstat" => {
+            db.stats().reset();
+            Ok(RespType::SimpleString("OK".to_string()))
+        }
+        "rewrite" => Ok(config_rewrite()),
+        _ => Ok(RespType::Array(Some(
+            [
+                "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "GET <pattern>",
+                "    Return parameters matching the glob-like <pattern> and their values.",
+                "SET <directive> <value> [<directive> <value> ...]",
+                "    Set the configuration <directive> to <value>.",
+                "RESETSTAT",
+                "    Reset statistics reported by the INFO command.",
+                "REWRITE",
+                "    Rewrite the configuration file.",
+            ]
+            .iter()
+            .map(|line| RespType::SimpleString(line.to_string()))
+            .collect(),
+        ))),
+    }
+}
+
+// the directives matching any of the patterns, as name, value pairs; a name
+// without wildcards may also be an old alias
+fn config_get(patterns: &[String]) -> RespType {
+    let config = config();
+    let mut found: Vec<(String, &'static Spec)> = vec![];
+    for pattern in patterns {
+        let pattern = pattern.to_lowercase();
+        if !pattern.contains(['*', '?', '[']) {
+            if let Some(spec) = find_spec(&pattern) {
+                found.push((pattern, spec));
+            }
+            continue;
+        }
+        for spec in SPECS {
+            if glob_match(pattern.as_bytes(), spec.name.as_bytes(), true) {
+                found.push((spec.name.to_string(), spec));
+            }
+        }
+    }
+    let mut reply = vec![];
+    let mut seen: Vec<String> = vec![];
+    for (name, spec) in found {
+        if seen.contains(&name) {
+            continue;
+        }
+        let value = match spec.name {
+            // like redis, the directory actually in use
+            "dir" => std::env::current_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            _ => config.values[spec.name].clone(),
+        };
+        reply.push(bulk(&name));
+        reply.push(bulk(&value));
+        seen.push(name);
+    }
+    RespType::Array(Some(reply))
+}
+
+// sets every pair or none of them: the values are all checked first, and if
+// putting one into effect fails the previous configuration comes back
+fn config_set(db: &Db, pairs: &[String]) -> RespType {
+    let failed = |name: &str, reason: &str| {
+        RespType::Error(format!(
+            "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+            name, reason
+        ))
+    };
+    let old = config();
+    let mut new = old.clone();
+    let mut changed: Vec<&'static str> = vec![];
+    for pair in pairs.chunks(2) {
+        let Some(spec) = find_spec(&pair[0].to_lowercase()) else {
+            return RespType::Error(format!(
+                "ERR Unknown option or number of arguments for CONFIG SET - '{}'",
+                pair[0]
+            ));
+        };
+        if changed.contains(&spec.name) {
+            return RespType::Error(format!("ERR Duplicate parameter - {}", pair[0]));
+        }
+        if IMMUTABLE.contains(&spec.name) {
+            return failed(&pair[0], "can't set immutable config");
+        }
+        if let Err(reason) = new.set(spec.name, &pair[1..]) {
+            return failed(&pair[0], &reason);
+        }
+        changed.push(spec.name);
+    }
+    *CONFIG.write() = new;
+    for (i, name) in changed.iter().enumerate() {
+        if let Err(reason) = take_effect(db, name) {
+            *CONFIG.write() = old;
+            for name in &changed[..i] {
+                let _ = take_effect(db, name);
+            }
+            return failed(name, &reason);
+        }
+    }
+    RespType::SimpleString("OK".to_string())
+}
+
+// puts a directive's running value into effect where it isn't just read
+// when needed, as the save rules, timeout and loglevel are
+fn take_effect(db: &Db, name: &str) -> Result<(), String> {
+    let value = get_config(name).unwrap_or_default();
+    match name {
+        // nothing evicts keys, so a limit couldn't be kept
+        "maxmemory" if value != "0" => Err("maxmemory is not supported".to_string()),
+        "dir" => std::env::set_current_dir(&value).map_err(|e| e.to_string()),
+        "appendonly" if value == "yes" => {
+            let policy = FsyncPolicy::parse(&get_config("appendfsync").unwrap_or_default())
+                .unwrap_or(FsyncPolicy::EverySec);
+            aof::start(db, &AofPaths::from_config(), policy).map_err(|e| e.to_string())
+        }
+        "appendonly" => aof::stop(db).map_err(|e| e.to_string()),
+        "requirepass" => {
+            acl::set_requirepass();
+            Ok(())
+        }
+        "notify-keyspace-events" => {
+            db.set_notify_flags(notify::parse(&value).unwrap_or(0));
+            Ok(())
+        }
+        // like redis, setting any of them reloads the certificates
+        _ if name.starts_with("tls-") => tls::configure(),
+        "appendfsync" => {
+            if let (Some(aof), Some(policy)) =
+                (db.aof().lock().as_mut(), FsyncPolicy::parse(&value))
+            {
+                aof.set_policy(policy);
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn config_rewrite() -> RespType {
+    let mut config = config();
+    let Some(path) = config.file.clone() else {
+        return RespType::Error("ERR The server is running without a config file".to_string());
+    };
+    let res = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| {
+            let on_file = Config::from_file(&path).map_err(|e| match e {
+                ConfigError::Io { reason, .. } => reason,
+                ConfigError::Directive { line, reason, .. } => {
+                    format!("line {}: {}", line, reason)
+                }
+            })?;
+            // like redis, the users are kept in the config file unless there
+            // is an aclfile for them
+            if config.get("aclfile").unwrap_or_default().is_empty() {
+                let users = acl::describe_users();
+                config.users = if acl::describe_config_users(&on_file)? == users {
+                    on_file.users.clone()
+                } else {
+                    users
+                };
+            }
+            Ok(config.rewrite_text(&text, &on_file))
+        })
+        .and_then(|text| write_atomically(&path, text.as_bytes()).map_err(|e| e.to_string()));
+    match res {
+        Ok(()) => RespType::SimpleString("OK".to_string()),
+        Err(e) => RespType::Error(format!("ERR Rewriting config file: {}", e)),
+    }
+}
+
+/// The name a client-sent command runs as after rename-command: the command
+/// it was renamed from, or None when its own name was renamed away.
+pub fn resolve_command_name(name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    let config = CONFIG.read();
+    if let Some((command, _)) = config
+        .renamed_commands
+        .iter()
+        .find(|(_, new_name)| *new_name == name)
+    {
+        return Some(command.clone());
+    }
+    if config
+        .renamed_commands
+        .iter()
+        .any(|(command, _)| *command == name)
+    {
+        return None;
+    }
+    Some(name)
+}
+
+/// Parses a memory amount like redis.conf does: a plain number of bytes or one
+/// with a unit, where k/m/g are powers of 1000 and kb/mb/gb powers of 1024.
+pub fn parse_memory(value: &str) -> Option<u64> {
+    let value = value.to_lowercase();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("1024"), Some(1024));
+        assert_eq!(parse_memory("64mb"), Some(64 * 1024 * 1024));
+        assert_eq!(parse_memory("1k"), Some(1000));
+        assert_eq!(parse_memory("2GB"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_memory("10 mb"), None);
+        assert_eq!(parse_memory("mb"), None);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("  save 900 1 ").unwrap(),
+            strings(&["save", "900", "1"])
+        );
+        assert_eq!(
+            tokenize(r#"logfile """#).unwrap(),
+            strings(&["logfile", ""])
+        );
+        assert_eq!(
+            tokenize(r#"a "b c\n\x41" 'it\'s'"#).unwrap(),
+            strings(&["a", "b c\nA", "it's"])
+        );
+        assert!(tokenize(r#"a "b"#).is_err());
+        assert!(tokenize(r#"a "b"c"#).is_err());
+    }
+
+    #[test]
+    fn test_shipped_config() {
+        let config = Config::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/redis.conf")).unwrap();
+        assert_eq!(config.get("port"), Some("6379"));
+        assert_eq!(config.get("save"), Some(""));
+        assert_eq!(config.get("auto-aof-rewrite-min-size"), Some("67108864"));
+        assert_eq!(config.get("bind"), Some("127.0.0.1 -::1"));
+        assert_eq!(
+            config.get("client-output-buffer-limit"),
+            Some("normal 0 0 0 replica 268435456 67108864 60 pubsub 33554432 8388608 60")
+        );
+    }
+
+    #[test]
+    fn test_directives() {
+        let text = "\
+# comment
+port 7000
+maxmemory 1gb
+APPENDONLY Yes
+maxmemory-policy allkeys-LRU
+save 900 1
+save 300 10
+slave-read-only no
+client-output-buffer-limit pubsub 1mb 0 0
+rename-command FLUSHALL \"\"
+";
+        let config = Config::parse(text, "test.conf").unwrap();
+        assert_eq!(config.get("port"), Some("7000"));
+        assert_eq!(config.get("maxmemory"), Some("1073741824"));
+        assert_eq!(config.get("appendonly"), Some("yes"));
+        assert_eq!(config.get("maxmemory-policy"), Some("allkeys-lru"));
+        assert_eq!(config.get("save"), Some("900 1 300 10"));
+        assert_eq!(config.get("replica-read-only"), Some("no"));
+        assert_eq!(
+            config.get("client-output-buffer-limit"),
+            Some("normal 0 0 0 replica 268435456 67108864 60 pubsub 1048576 0 0")
+        );
+        assert_eq!(
+            config.renamed_commands,
+            vec![("flushall".to_string(), String::new())]
+        );
+
+        let error = |text: &str| match Config::parse(text, "test.conf") {
+            Err(ConfigError::Directive { line, reason, .. }) => (line, reason),
+            res => panic!("expected an error, got {:?}", res),
+        };
+        assert_eq!(
+            error("port 6379\n\nno-such-thing 1"),
+            (3, "Bad directive or wrong number of arguments".to_string())
+        );
+        assert_eq!(
+            error("port 70000"),
+            (
+                1,
+                "argument must be between 0 and 65535 inclusive".to_string()
+            )
+        );
+        assert_eq!(
+            error("appendonly maybe"),
+            (1, "argument must be 'yes' or 'no'".to_string())
+        );
+        assert_eq!(
+            error("appendfsync sometimes"),
+            (
+                1,
+                "argument(s) must be one of the following: always, everysec, no".to_string()
+            )
+        );
+        assert_eq!(
+            error("save 900"),
+            (1, "Invalid save parameters".to_string())
+        );
+        assert_eq!(
+            error("dbfilename /tmp/dump.rdb"),
+            (1, "dbfilename can't be a path, just a filename".to_string())
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("redisconfig-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("conf.d/a.conf"), "port 7001\n").unwrap();
+        fs::write(dir.join("conf.d/b.conf"), "port 7002\nmaxmemory 10mb\n").unwrap();
+        fs::write(dir.join("conf.d/skip.txt"), "port 1\n").unwrap();
+        fs::write(dir.join("bad.conf"), "\nhz lots\n").unwrap();
+        let main = dir.join("redis.conf");
+        fs::write(
+            &main,
+            format!("port 7000\ninclude {}/conf.d/*.conf\n", dir.display()),
+        )
+        .unwrap();
+        let config = Config::from_file(&main).unwrap();
+        assert_eq!(config.get("port"), Some("7002"));
+        assert_eq!(config.get("maxmemory"), Some("10485760"));
+
+        // errors in an included file point into that file
+        fs::write(&main, format!("include {}/bad.conf\n", dir.display())).unwrap();
+        match Config::from_file(&main) {
+            Err(ConfigError::Directive { path, line, .. }) => {
+                assert!(path.ends_with("bad.conf"));
+                assert_eq!(line, 2);
+            }
+            res => panic!("expected an error, got {:?}", res),
+        }
+        fs::write(&main, format!("include {}\n", main.display())).unwrap();
+        assert!(Config::from_file(&main).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_command_line_options() {
+        let dir = std::env::temp_dir().join(format!("redisconfig-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(&path, "port 7000\nsave 900 1\nhz 20\n").unwrap();
+        let options = vec![strings(&["port", "7001"]), strings(&["save", "60", "5"])];
+        let config = Config::load(Some(&path), &options).unwrap();
+        assert_eq!(config.get("port"), Some("7001"));
+        assert_eq!(config.get("save"), Some("900 1 60 5"));
+        assert_eq!(config.get("hz"), Some("20"));
+        assert_eq!(config.file, Some(fs::canonicalize(&path).unwrap()));
+
+        let config = Config::load(None, &[strings(&["dir", "/tmp"])]).unwrap();
+        assert_eq!(config.get("dir"), Some("/tmp"));
+        assert_eq!(config.file, None);
+        match Config::load(None, &[strings(&["port", "7000"]), strings(&["port"])]) {
+            Err(ConfigError::Directive { path, line, .. }) => {
+                assert_eq!(path, "(command line)");
+                assert_eq!(line, 2);
+            }
+            res => panic!("expected an error, got {:?}", res),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn pairs(reply: RespType) -> Vec<(String, String)> {
+        let RespType::Array(Some(items)) = reply else {
+            panic!("expected an array, got {:?}", reply);
+        };
+        let text = |item: &RespType| match item {
+            RespType::BulkString(Some(b)) => String::from_utf8_lossy(b).to_string(),
+            item => panic!("expected a bulk string, got {:?}", item),
+        };
+        items
+            .chunks(2)
+            .map(|pair| (text(&pair[0]), text(&pair[1])))
+            .collect()
+    }
+
+    #[test]
+    fn test_config_get() {
+        let db = Db::new(1);
+        let reply = config_command(&db, &strings(&["GET", "MAXMEMORY*", "maxmemory"])).unwrap();
+        let names: Vec<String> = pairs(reply).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(
+            names,
+            strings(&[
+                "maxmemory",
+                "maxmemory-policy",
+                "maxmemory-samples",
+                "maxmemory-eviction-tenacity",
+                "maxmemory-clients"
+            ])
+        );
+        let reply = config_command(&db, &strings(&["get", "slave-lazy-flush", "no-such"])).unwrap();
+        assert_eq!(
+            pairs(reply),
+            vec![("slave-lazy-flush".to_string(), "no".to_string())]
+        );
+        assert_eq!(
+            config_command(&db, &strings(&["get"])),
+            Ok(RespType::Error(
+                "ERR wrong number of arguments for 'config|get' command".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_config_set() {
+        let db = Db::new(1);
+        let set = |args: &[&str]| {
+            let mut command = strings(&["SET"]);
+            command.extend(strings(args));
+            config_command(&db, &command).unwrap()
+        };
+        assert_eq!(
+            set(&["lfu-log-factor", "20", "lfu-decay-time", "5"]),
+            RespType::SimpleString("OK".to_string())
+        );
+        assert_eq!(get_config("lfu-log-factor").as_deref(), Some("20"));
+        // one bad value and nothing is set
+        assert_eq!(
+            set(&["lfu-log-factor", "30", "lfu-decay-time", "soon"]),
+            RespType::Error(
+                "ERR CONFIG SET failed (possibly related to argument 'lfu-decay-time') - argument couldn't be parsed into an integer"
+                    .to_string()
+            )
+        );
+        assert_eq!(get_config("lfu-log-factor").as_deref(), Some("20"));
+        assert_eq!(
+            set(&["lfu-log-factor", "1", "LFU-LOG-FACTOR", "2"]),
+            RespType::Error("ERR Duplicate parameter - LFU-LOG-FACTOR".to_string())
+        );
+        assert_eq!(
+            set(&["daemonize", "yes"]),
+            RespType::Error(
+                "ERR CONFIG SET failed (possibly related to argument 'daemonize') - can't set immutable config"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            set(&["no-such", "1"]),
+            RespType::Error(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'no-such'".to_string()
+            )
+        );
+        assert_eq!(
+            set(&["maxmemory", "1mb"]),
+            RespType::Error(
+                "ERR CONFIG SET failed (possibly related to argument 'maxmemory') - maxmemory is not supported"
+                    .to_string()
+            )
+        );
+        assert_eq!(get_config("maxmemory").as_deref(), Some("0"));
+        assert_eq!(
+            set(&["maxmemory", "0"]),
+            RespType::SimpleString("OK".to_string())
+        );
+        assert!(log_enabled("notice") && !log_enabled("verbose"));
+        set(&["loglevel", "warning"]);
+        assert!(log_enabled("warning") && !log_enabled("notice"));
+        set(&[
+            "lfu-log-factor",
+            "10",
+            "lfu-decay-time",
+            "1",
+            "loglevel",
+            "notice",
+        ]);
+    }
+
+    #[test]
+    fn test_rewrite_text() {
+        let text = "\
+# the port
+port 7000
+save 900 1
+save 300 10
+
+maxmemory 1gb # not a comment, but an argument
+";
+        let on_file = Config::parse("port 7000\nsave 900 1\nsave 300 10\n", "test.conf").unwrap();
+        let mut config = on_file.clone();
+        config.set("save", &strings(&["60 100"])).unwrap();
+        config.set("maxmemory", &strings(&["2mb"])).unwrap();
+        config
+            .set("dbfilename", &strings(&["my dump.rdb"]))
+            .unwrap();
+        assert_eq!(
+            config.rewrite_text(text, &on_file),
+            "\
+# the port
+port 7000
+save 60 100
+
+maxmemory 2mb
+# Generated by CONFIG REWRITE
+dbfilename \"my dump.rdb\"
+"
+        );
+        assert_eq!(on_file.rewrite_text(text, &on_file), text);
+
+        // a second rewrite moves the signature instead of adding another
+        let text = config.rewrite_text(text, &on_file);
+        let on_file = config.clone();
+        config.set("hz", &strings(&["20"])).unwrap();
+        assert!(config
+            .rewrite_text(&text, &on_file)
+            .ends_with("dbfilename \"my dump.rdb\"\n# Generated by CONFIG REWRITE\nhz 20\n"));
+        // and keeps it when it adds nothing
+        let mut config = on_file.clone();
+        config.set("dbfilename", &strings(&["dump.rdb"])).unwrap();
+        assert!(config
+            .rewrite_text(&text, &on_file)
+            .ends_with("maxmemory 2mb\n# Generated by CONFIG REWRITE\ndbfilename dump.rdb\n"));
+
+        // the user lines are replaced together
+        let text = "user alice on ~*\nport 7000\nuser bob off\n";
+        let on_file = Config::parse(text, "test.conf").unwrap();
+        assert_eq!(
+            on_file.users,
+            vec![strings(&["alice", "on", "~*"]), strings(&["bob", "off"])]
+        );
+        let mut config = on_file.clone();
+        config.users = vec![strings(&["alice", "off"])];
+        assert_eq!(
+            config.rewrite_text(text, &on_file),
+            "user alice off\nport 7000\n"
+        );
+        assert!(Config::parse("user alice +nosuch\n", "test.conf").is_err());
+    }
 }
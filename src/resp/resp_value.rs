@@ -2,7 +2,7 @@ use bytes::Bytes;
 
 use super::constants::{NULL_ARRAY, NULL_BULK_STRING};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RespType {
     SimpleString(String),
     Error(String),
@@ -68,6 +68,11 @@ impl RespType {
     }
 }
 
+/// A bulk string reply holding a copy of `value`.
+pub fn bulk(value: impl AsRef<[u8]>) -> RespType {
+    RespType::BulkString(Some(Bytes::copy_from_slice(value.as_ref())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
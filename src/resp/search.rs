@@ -0,0 +1,1676 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ops::Bound,
+};
+
+use bytes::Bytes;
+
+use super::{
+    datastore::{Db, Score, Value},
+    errors::UserInputError,
+    resp_value::{bulk, RespType},
+    vector::{Algorithm, VectorIndex, VectorSpec},
+};
+
+// ===== index definition =====
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Text {
+        weight: f64,
+    },
+    Tag {
+        separator: char,
+        case_sensitive: bool,
+    },
+    Numeric,
+    Vector(VectorSpec),
+}
+
+impl FieldType {
+    fn name(&self) -> &'static str {
+        match self {
+            FieldType::Text { .. } => "TEXT",
+            FieldType::Tag { .. } => "TAG",
+            FieldType::Numeric => "NUMERIC",
+            FieldType::Vector(_) => "VECTOR",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldSpec {
+    /// name of the hash field
+    pub identifier: String,
+    /// name used to refer to the field in queries
+    pub alias: String,
+    pub kind: FieldType,
+    pub sortable: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexSpec {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub fields: Vec<FieldSpec>,
+    /// the FT.CREATE arguments it was made from, to recreate it on load
+    pub definition: Vec<String>,
+}
+
+impl IndexSpec {
+    // FT.CREATE index [ON HASH] [PREFIX count prefix ...] SCHEMA field [AS alias] type [opts] ...
+    pub fn parse(args: &[String]) -> Result<Self, UserInputError> {
+        let name = args
+            .first()
+            .ok_or(invalid("No index name provided to FT.CREATE"))?
+            .to_string();
+        let mut prefixes = vec![];
+        let mut i = 1;
+        loop {
+            let opt = args
+                .get(i)
+                .ok_or(invalid("No SCHEMA provided to FT.CREATE"))?;
+            i += 1;
+            match opt.to_uppercase().as_str() {
+                "SCHEMA" => break,
+                "ON" => match args.get(i).map(|s| s.to_uppercase()).as_deref() {
+                    Some("HASH") => i += 1,
+                    Some("JSON") => {
+                        return Err(invalid(
+                            "JSON indexes need a JSON value type, only HASH is supported",
+                        ))
+                    }
+                    _ => return Err(invalid("ON expects HASH")),
+                },
+                "PREFIX" => {
+                    let n = parse_count(args.get(i))?;
+                    i += 1;
+                    let end = i + n;
+                    if end > args.len() {
+                        return Err(invalid("Not enough prefixes provided"));
+                    }
+                    prefixes.extend(args[i..end].iter().cloned());
+                    i = end;
+                }
+                "LANGUAGE" | "SCORE" | "LANGUAGE_FIELD" | "SCORE_FIELD" => i += 1,
+                "NOOFFSETS" | "NOHL" | "NOFIELDS" | "NOFREQS" | "SKIPINITIALSCAN" => {}
+                other => return Err(invalid(&format!("Unknown FT.CREATE option: {}", other))),
+            }
+        }
+        // PREFIX "" is the same as indexing every key
+        prefixes.retain(|p| !p.is_empty());
+
+        let mut fields: Vec<FieldSpec> = vec![];
+        while i < args.len() {
+            let identifier = args[i].clone();
+            i += 1;
+            let mut alias = identifier.clone();
+            if args.get(i).map(|s| s.to_uppercase()).as_deref() == Some("AS") {
+                alias = args
+                    .get(i + 1)
+                    .ok_or(invalid("No alias provided after AS"))?
+                    .clone();
+                i += 2;
+            }
+            let type_name = args
+                .get(i)
+                .ok_or(invalid(&format!(
+                    "No type provided for field {}",
+                    identifier
+                )))?
+                .to_uppercase();
+            i += 1;
+            let mut kind = match type_name.as_str() {
+                "TEXT" => FieldType::Text { weight: 1.0 },
+                "TAG" => FieldType::Tag {
+                    separator: ',',
+                    case_sensitive: false,
+                },
+                "NUMERIC" => FieldType::Numeric,
+                "VECTOR" => {
+                    let (spec, consumed) = VectorSpec::parse(&args[i..])?;
+                    i += consumed;
+                    FieldType::Vector(spec)
+                }
+                other => return Err(invalid(&format!("Unknown field type: {}", other))),
+            };
+            let mut sortable = false;
+            while let Some(opt) = args.get(i) {
+                match (opt.to_uppercase().as_str(), &mut kind) {
+                    ("SORTABLE", _) => sortable = true,
+                    (
+                        "UNF" | "NOINDEX" | "NOSTEM" | "WITHSUFFIXTRIE" | "INDEXEMPTY"
+                        | "INDEXMISSING",
+                        _,
+                    ) => {}
+                    ("PHONETIC", FieldType::Text { .. }) => i += 1,
+                    ("WEIGHT", FieldType::Text { weight }) => {
+                        *weight = args
+                            .get(i + 1)
+                            .and_then(|w| w.parse().ok())
+                            .ok_or(invalid("WEIGHT expects a number"))?;
+                        i += 1;
+                    }
+                    ("SEPARATOR", FieldType::Tag { separator, .. }) => {
+                        *separator = args
+                            .get(i + 1)
+                            .and_then(|s| s.chars().next())
+                            .ok_or(invalid("SEPARATOR expects a character"))?;
+                        i += 1;
+                    }
+                    ("CASESENSITIVE", FieldType::Tag { case_sensitive, .. }) => {
+                        *case_sensitive = true
+                    }
+                    _ => break,
+                }
+                i += 1;
+            }
+            if fields.iter().any(|f| f.alias == alias) {
+                return Err(invalid(&format!("Duplicate field in schema: {}", alias)));
+            }
+            fields.push(FieldSpec {
+                identifier,
+                alias,
+                kind,
+                sortable,
+            });
+        }
+        if fields.is_empty() {
+            return Err(invalid("No fields provided in SCHEMA"));
+        }
+        Ok(Self {
+            name,
+            prefixes,
+            fields,
+            definition: args.to_vec(),
+        })
+    }
+
+    fn covers(&self, key: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p.as_str()))
+    }
+
+    /// Looks a field up by alias first, then by hash field name.
+    fn field(&self, name: &str) -> Option<&FieldSpec> {
+        self.fields
+            .iter()
+            .find(|f| f.alias == name)
+            .or_else(|| self.fields.iter().find(|f| f.identifier == name))
+    }
+}
+
+// ===== index =====
+
+#[derive(Debug, Default)]
+struct Doc {
+    fields: HashMap<String, Bytes>,
+    tokens: HashMap<String, Vec<String>>,
+    tags: HashMap<String, Vec<String>>,
+    numbers: HashMap<String, f64>,
+}
+
+type Postings = HashMap<String, HashMap<String, HashSet<String>>>;
+
+#[derive(Debug)]
+pub struct Index {
+    spec: IndexSpec,
+    docs: HashMap<String, Doc>,
+    // field alias -> term -> keys
+    terms: Postings,
+    // field alias -> tag -> keys
+    tags: Postings,
+    numbers: HashMap<String, BTreeSet<(Score, String)>>,
+    // field alias -> vectors
+    vectors: HashMap<String, VectorIndex>,
+    failures: usize,
+}
+
+impl Index {
+    pub fn new(spec: IndexSpec) -> Self {
+        let vectors = spec
+            .fields
+            .iter()
+            .filter_map(|f| match &f.kind {
+                FieldType::Vector(v) => Some((f.alias.clone(), VectorIndex::new(v.clone()))),
+                _ => None,
+            })
+            .collect();
+        Self {
+            spec,
+            docs: HashMap::new(),
+            terms: HashMap::new(),
+            tags: HashMap::new(),
+            numbers: HashMap::new(),
+            vectors,
+            failures: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    fn update(&mut self, key: &str, value: Option<&Value>) {
+        if !self.spec.covers(key) {
+            return;
+        }
+        self.remove_doc(key);
+        if let Some(Value::Hash(h)) = value {
+            self.add_doc(key, h);
+        }
+    }
+
+    fn add_doc(&mut self, key: &str, hash: &HashMap<String, Bytes>) {
+        let mut doc = Doc {
+            fields: hash.clone(),
+            ..Default::default()
+        };
+        let mut vectors = vec![];
+        for field in self.spec.fields.iter() {
+            let Some(blob) = hash.get(&field.identifier) else {
+                continue;
+            };
+            let raw = String::from_utf8_lossy(blob);
+            match &field.kind {
+                FieldType::Text { .. } => {
+                    doc.tokens.insert(field.alias.clone(), tokenize(&raw));
+                }
+                FieldType::Tag {
+                    separator,
+                    case_sensitive,
+                } => {
+                    let tags = raw
+                        .split(*separator)
+                        .map(|t| normalize_tag(t, *case_sensitive))
+                        .filter(|t| !t.is_empty())
+                        .collect();
+                    doc.tags.insert(field.alias.clone(), tags);
+                }
+                FieldType::Numeric => match raw.trim().parse::<f64>() {
+                    Ok(n) if !n.is_nan() => {
+                        doc.numbers.insert(field.alias.clone(), n);
+                    }
+                    _ => {
+                        // like redis, a hash with an unparsable numeric field is left out
+                        self.failures += 1;
+                        return;
+                    }
+                },
+                FieldType::Vector(spec) => match spec.decode(blob) {
+                    Some(v) => vectors.push((field.alias.clone(), v)),
+                    None => {
+                        self.failures += 1;
+                        return;
+                    }
+                },
+            }
+        }
+        for (alias, v) in vectors {
+            if let Some(index) = self.vectors.get_mut(&alias) {
+                index.insert(key, v);
+            }
+        }
+        for (alias, tokens) in doc.tokens.iter() {
+            let postings = self.terms.entry(alias.clone()).or_default();
+            for t in tokens {
+                postings
+                    .entry(t.clone())
+                    .or_default()
+                    .insert(key.to_string());
+            }
+        }
+        for (alias, tags) in doc.tags.iter() {
+            let postings = self.tags.entry(alias.clone()).or_default();
+            for t in tags {
+                postings
+                    .entry(t.clone())
+                    .or_default()
+                    .insert(key.to_string());
+            }
+        }
+        for (alias, n) in doc.numbers.iter() {
+            self.numbers
+                .entry(alias.clone())
+                .or_default()
+                .insert((Score(*n), key.to_string()));
+        }
+        self.docs.insert(key.to_string(), doc);
+    }
+
+    fn remove_doc(&mut self, key: &str) {
+        let Some(doc) = self.docs.remove(key) else {
+            return;
+        };
+        for (alias, tokens) in doc.tokens.iter() {
+            remove_postings(&mut self.terms, alias, tokens, key);
+        }
+        for (alias, tags) in doc.tags.iter() {
+            remove_postings(&mut self.tags, alias, tags, key);
+        }
+        for (alias, n) in doc.numbers.iter() {
+            if let Some(set) = self.numbers.get_mut(alias) {
+                set.remove(&(Score(*n), key.to_string()));
+            }
+        }
+        for index in self.vectors.values_mut() {
+            index.remove(key);
+        }
+    }
+
+    fn num_terms(&self) -> usize {
+        self.terms.values().map(|t| t.len()).sum()
+    }
+}
+
+fn remove_postings(postings: &mut Postings, alias: &str, values: &[String], key: &str) {
+    let Some(field_postings) = postings.get_mut(alias) else {
+        return;
+    };
+    for v in values {
+        if let Some(keys) = field_postings.get_mut(v) {
+            keys.remove(key);
+            if keys.is_empty() {
+                field_postings.remove(v);
+            }
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+fn normalize_tag(tag: &str, case_sensitive: bool) -> String {
+    let tag = tag.trim();
+    if case_sensitive {
+        tag.to_string()
+    } else {
+        tag.to_lowercase()
+    }
+}
+
+/// All secondary indexes, kept up to date by `Db` on every write.
+#[derive(Debug, Default)]
+pub struct Indexes {
+    indexes: BTreeMap<String, Index>,
+}
+
+impl Indexes {
+    /// Whether any index covers the key, so has to hear of its changes.
+    pub fn covers(&self, key: &str) -> bool {
+        self.indexes.values().any(|index| index.spec.covers(key))
+    }
+
+    pub fn on_key_changed(&mut self, key: &str, value: Option<&Value>) {
+        for index in self.indexes.values_mut() {
+            index.update(key, value);
+        }
+    }
+
+    pub fn create<'a>(
+        &mut self,
+        mut index: Index,
+        entries: impl Iterator<Item = (&'a String, &'a Value)>,
+    ) -> Result<(), String> {
+        if self.indexes.contains_key(index.name()) {
+            return Err("Index already exists".to_string());
+        }
+        for (key, value) in entries {
+            index.update(key, Some(value));
+        }
+        self.indexes.insert(index.name().to_string(), index);
+        Ok(())
+    }
+
+    /// Removes the index, returning the keys it covered.
+    pub fn drop_index(&mut self, name: &str) -> Option<Vec<String>> {
+        self.indexes
+            .remove(name)
+            .map(|index| index.docs.into_keys().collect())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Index> {
+        self.indexes.get(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.indexes.keys().cloned().collect()
+    }
+
+    /// The FT.CREATE arguments of every index.
+    pub fn definitions(&self) -> Vec<Vec<String>> {
+        self.indexes
+            .values()
+            .map(|index| index.spec.definition.clone())
+            .collect()
+    }
+}
+
+// ===== query language =====
+
+#[derive(Debug, Clone, PartialEq)]
+enum Query {
+    All,
+    Term {
+        fields: Option<Vec<String>>,
+        term: String,
+    },
+    Prefix {
+        fields: Option<Vec<String>>,
+        prefix: String,
+    },
+    Phrase {
+        fields: Option<Vec<String>>,
+        terms: Vec<String>,
+    },
+    Numeric {
+        field: String,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    },
+    Tags {
+        field: String,
+        tags: Vec<String>,
+    },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+// query   := union
+// union   := inter ('|' inter)*
+// inter   := unary+
+// unary   := '-' unary | atom
+// atom    := '(' union ')' | '@' field ('|' field)* ':' (range | tags | unary)
+//          | '"' phrase '"' | '*' | word ['*']
+struct QueryParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    params: &'a HashMap<String, String>,
+}
+
+impl<'a> QueryParser<'a> {
+    fn parse(input: &str, params: &'a HashMap<String, String>) -> Result<Query, String> {
+        let mut parser = Self {
+            chars: input.chars().collect(),
+            pos: 0,
+            params,
+        };
+        let query = parser.union(None)?;
+        parser.skip_ws();
+        match parser.peek() {
+            None => Ok(query),
+            Some(c) => Err(format!(
+                "Syntax error at offset {} near '{}'",
+                parser.pos, c
+            )),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_ws();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(format!(
+                "Syntax error at offset {}: expected '{}'",
+                self.pos, expected
+            )),
+        }
+    }
+
+    fn union(&mut self, fields: Option<&[String]>) -> Result<Query, String> {
+        let mut branches = vec![self.intersect(fields)?];
+        loop {
+            self.skip_ws();
+            if self.peek() != Some('|') {
+                break;
+            }
+            self.pos += 1;
+            branches.push(self.intersect(fields)?);
+        }
+        Ok(collapse(branches, Query::Or))
+    }
+
+    fn intersect(&mut self, fields: Option<&[String]>) -> Result<Query, String> {
+        let mut parts = vec![];
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None | Some(')') | Some('|') => break,
+                _ => parts.push(self.unary(fields)?),
+            }
+        }
+        if parts.is_empty() {
+            return Err(format!(
+                "Syntax error at offset {}: empty expression",
+                self.pos
+            ));
+        }
+        Ok(collapse(parts, Query::And))
+    }
+
+    fn unary(&mut self, fields: Option<&[String]>) -> Result<Query, String> {
+        self.skip_ws();
+        if self.peek() == Some('-') {
+            self.pos += 1;
+            return Ok(Query::Not(Box::new(self.unary(fields)?)));
+        }
+        self.atom(fields)
+    }
+
+    fn atom(&mut self, fields: Option<&[String]>) -> Result<Query, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let query = self.union(fields)?;
+                self.expect(')')?;
+                Ok(query)
+            }
+            Some('@') => {
+                self.pos += 1;
+                let mut names = vec![self.word()?];
+                while self.peek() == Some('|') {
+                    self.pos += 1;
+                    names.push(self.word()?);
+                }
+                self.expect(':')?;
+                self.skip_ws();
+                match self.peek() {
+                    Some('[') => self.numeric_range(single_field(names)?),
+                    Some('{') => self.tag_set(single_field(names)?),
+                    _ => self.unary(Some(&names)),
+                }
+            }
+            Some('"') => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '"') {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                self.expect('"')?;
+                let terms = tokenize(&text);
+                let fields = fields.map(|f| f.to_vec());
+                Ok(match terms.len() {
+                    0 => return Err("Empty phrase".to_string()),
+                    1 => Query::Term {
+                        fields,
+                        term: terms[0].clone(),
+                    },
+                    _ => Query::Phrase { fields, terms },
+                })
+            }
+            Some('*') => {
+                self.pos += 1;
+                Ok(Query::All)
+            }
+            _ => {
+                let word = self.param_or_word()?;
+                let fields = fields.map(|f| f.to_vec());
+                if self.peek() == Some('*') {
+                    self.pos += 1;
+                    return Ok(Query::Prefix {
+                        fields,
+                        prefix: word.to_lowercase(),
+                    });
+                }
+                let mut terms: Vec<Query> = tokenize(&word)
+                    .into_iter()
+                    .map(|term| Query::Term {
+                        fields: fields.clone(),
+                        term,
+                    })
+                    .collect();
+                match terms.len() {
+                    0 => Err(format!("Syntax error at offset {}", self.pos)),
+                    1 => Ok(terms.remove(0)),
+                    _ => Ok(Query::And(terms)),
+                }
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.pos += 1;
+                if let Some(escaped) = self.peek() {
+                    word.push(escaped);
+                    self.pos += 1;
+                }
+            } else if c.is_alphanumeric() || c == '_' || c == '.' || c == '$' {
+                word.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if word.is_empty() {
+            return Err(format!("Syntax error at offset {}", self.pos));
+        }
+        Ok(word)
+    }
+
+    fn param_or_word(&mut self) -> Result<String, String> {
+        let word = self.word()?;
+        match word.strip_prefix('$') {
+            Some(name) => self
+                .params
+                .get(name)
+                .cloned()
+                .ok_or(format!("No such parameter `{}`", name)),
+            None => Ok(word),
+        }
+    }
+
+    fn numeric_range(&mut self, field: String) -> Result<Query, String> {
+        self.pos += 1;
+        let min = self.range_bound()?;
+        let max = self.range_bound()?;
+        self.expect(']')?;
+        Ok(Query::Numeric { field, min, max })
+    }
+
+    fn range_bound(&mut self) -> Result<Bound<f64>, String> {
+        self.skip_ws();
+        let exclusive = self.peek() == Some('(');
+        if exclusive {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && c != ']' && c != ',')
+        {
+            self.pos += 1;
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        if self.peek() == Some(',') {
+            self.pos += 1;
+        }
+        let raw = match raw.strip_prefix('$') {
+            Some(name) => self
+                .params
+                .get(name)
+                .cloned()
+                .ok_or(format!("No such parameter `{}`", name))?,
+            None => raw,
+        };
+        let n = match raw.to_lowercase().as_str() {
+            "-inf" => f64::NEG_INFINITY,
+            "inf" | "+inf" => f64::INFINITY,
+            other => other
+                .parse()
+                .map_err(|_| format!("Bad numeric range bound: {}", raw))?,
+        };
+        Ok(if exclusive {
+            Bound::Excluded(n)
+        } else {
+            Bound::Included(n)
+        })
+    }
+
+    fn tag_set(&mut self, field: String) -> Result<Query, String> {
+        self.pos += 1;
+        let mut tags = vec![];
+        let mut current = String::new();
+        loop {
+            match self.peek() {
+                None => return Err("Unterminated tag list".to_string()),
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some('|') => {
+                    tags.push(std::mem::take(&mut current));
+                    self.pos += 1;
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    if let Some(c) = self.peek() {
+                        current.push(c);
+                        self.pos += 1;
+                    }
+                }
+                Some(c) => {
+                    current.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        tags.push(current);
+        let tags: Vec<String> = tags
+            .iter()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .map(|t| match t.strip_prefix('$') {
+                Some(name) => self
+                    .params
+                    .get(name)
+                    .cloned()
+                    .ok_or(format!("No such parameter `{}`", name)),
+                None => Ok(t.to_string()),
+            })
+            .collect::<Result<_, _>>()?;
+        if tags.is_empty() {
+            return Err("Empty tag list".to_string());
+        }
+        Ok(Query::Tags { field, tags })
+    }
+}
+
+fn collapse(mut parts: Vec<Query>, combine: fn(Vec<Query>) -> Query) -> Query {
+    if parts.len() == 1 {
+        parts.remove(0)
+    } else {
+        combine(parts)
+    }
+}
+
+fn single_field(mut names: Vec<String>) -> Result<String, String> {
+    if names.len() != 1 {
+        return Err("Numeric and tag filters apply to a single field".to_string());
+    }
+    Ok(names.remove(0))
+}
+
+// ===== query evaluation =====
+
+type Scores = HashMap<String, f64>;
+
+impl Index {
+    fn field_of(&self, name: &str) -> Result<&FieldSpec, String> {
+        self.spec
+            .field(name)
+            .ok_or(format!("Unknown field `{}`", name))
+    }
+
+    fn text_fields(&self, fields: &Option<Vec<String>>) -> Result<Vec<(String, f64)>, String> {
+        match fields {
+            None => Ok(self
+                .spec
+                .fields
+                .iter()
+                .filter_map(|f| match f.kind {
+                    FieldType::Text { weight } => Some((f.alias.clone(), weight)),
+                    _ => None,
+                })
+                .collect()),
+            Some(names) => names
+                .iter()
+                .map(|name| {
+                    let field = self.field_of(name)?;
+                    match field.kind {
+                        FieldType::Text { weight } => Ok((field.alias.clone(), weight)),
+                        _ => Err(format!("Field `{}` is not a TEXT field", name)),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn idf(&self, num_docs_with_term: usize) -> f64 {
+        (1.0 + self.docs.len() as f64 / num_docs_with_term.max(1) as f64).ln()
+    }
+
+    fn term_scores(&self, fields: &[(String, f64)], matches: impl Fn(&str) -> bool) -> Scores {
+        let mut scores = Scores::new();
+        for (alias, weight) in fields {
+            let Some(postings) = self.terms.get(alias) else {
+                continue;
+            };
+            for (term, keys) in postings.iter().filter(|(t, _)| matches(t)) {
+                let idf = self.idf(keys.len());
+                for key in keys {
+                    let tf = self.docs[key].tokens[alias]
+                        .iter()
+                        .filter(|t| *t == term)
+                        .count();
+                    *scores.entry(key.clone()).or_default() += weight * tf as f64 * idf;
+                }
+            }
+        }
+        scores
+    }
+
+    fn eval(&self, query: &Query) -> Result<Scores, String> {
+        match query {
+            Query::All => Ok(self.docs.keys().map(|k| (k.clone(), 1.0)).collect()),
+            Query::Term { fields, term } => {
+                let fields = self.text_fields(fields)?;
+                Ok(self.term_scores(&fields, |t| t == term))
+            }
+            Query::Prefix { fields, prefix } => {
+                let fields = self.text_fields(fields)?;
+                Ok(self.term_scores(&fields, |t| t.starts_with(prefix.as_str())))
+            }
+            Query::Phrase { fields, terms } => {
+                let fields = self.text_fields(fields)?;
+                let mut scores = Scores::new();
+                for (alias, weight) in fields.iter() {
+                    for (key, doc) in self.docs.iter() {
+                        let Some(tokens) = doc.tokens.get(alias) else {
+                            continue;
+                        };
+                        let hits = tokens.windows(terms.len()).filter(|w| w == terms).count();
+                        if hits > 0 {
+                            *scores.entry(key.clone()).or_default() += weight * hits as f64;
+                        }
+                    }
+                }
+                Ok(scores)
+            }
+            Query::Numeric { field, min, max } => {
+                let field = self.field_of(field)?;
+                if field.kind != FieldType::Numeric {
+                    return Err(format!("Field `{}` is not a NUMERIC field", field.alias));
+                }
+                let Some(values) = self.numbers.get(&field.alias) else {
+                    return Ok(Scores::new());
+                };
+                let lower = match min {
+                    Bound::Included(n) | Bound::Excluded(n) => Score(*n),
+                    Bound::Unbounded => Score(f64::NEG_INFINITY),
+                };
+                Ok(values
+                    .range((lower, String::new())..)
+                    .take_while(|(n, _)| match max {
+                        Bound::Included(m) => n.0 <= *m,
+                        Bound::Excluded(m) => n.0 < *m,
+                        Bound::Unbounded => true,
+                    })
+                    .filter(|(n, _)| !matches!(min, Bound::Excluded(m) if n.0 == *m))
+                    .map(|(_, key)| (key.clone(), 0.0))
+                    .collect())
+            }
+            Query::Tags { field, tags } => {
+                let field = self.field_of(field)?;
+                let FieldType::Tag { case_sensitive, .. } = field.kind else {
+                    return Err(format!("Field `{}` is not a TAG field", field.alias));
+                };
+                let Some(postings) = self.tags.get(&field.alias) else {
+                    return Ok(Scores::new());
+                };
+                let mut scores = Scores::new();
+                for tag in tags {
+                    let tag = normalize_tag(tag, case_sensitive);
+                    let matching: Vec<&HashSet<String>> = match tag.strip_suffix('*') {
+                        Some(prefix) => postings
+                            .iter()
+                            .filter(|(t, _)| t.starts_with(prefix))
+                            .map(|(_, keys)| keys)
+                            .collect(),
+                        None => postings.get(&tag).into_iter().collect(),
+                    };
+                    for key in matching.into_iter().flatten() {
+                        scores.insert(key.clone(), 0.0);
+                    }
+                }
+                Ok(scores)
+            }
+            Query::And(parts) => {
+                let mut acc: Option<Scores> = None;
+                for part in parts {
+                    let scores = self.eval(part)?;
+                    acc = Some(match acc {
+                        None => scores,
+                        Some(acc) => acc
+                            .into_iter()
+                            .filter_map(|(k, s)| scores.get(&k).map(|s2| (k, s + s2)))
+                            .collect(),
+                    });
+                }
+                Ok(acc.unwrap_or_default())
+            }
+            Query::Or(parts) => {
+                let mut acc = Scores::new();
+                for part in parts {
+                    for (k, s) in self.eval(part)? {
+                        *acc.entry(k).or_default() += s;
+                    }
+                }
+                Ok(acc)
+            }
+            Query::Not(inner) => {
+                let excluded = self.eval(inner)?;
+                Ok(self
+                    .docs
+                    .keys()
+                    .filter(|k| !excluded.contains_key(*k))
+                    .map(|k| (k.clone(), 0.0))
+                    .collect())
+            }
+        }
+    }
+}
+
+// ===== FT.* commands =====
+
+fn invalid(msg: &str) -> UserInputError {
+    UserInputError::InvalidInput(msg.to_string())
+}
+
+fn parse_count(arg: Option<&String>) -> Result<usize, UserInputError> {
+    arg.and_then(|n| n.parse().ok())
+        .ok_or(invalid("Expected a count argument"))
+}
+
+pub fn ft_create(db: &Db, args: &[String]) -> Result<RespType, UserInputError> {
+    let spec = IndexSpec::parse(args)?;
+    db.create_index(Index::new(spec))
+        .map_err(UserInputError::DataStoreError)?;
+    Ok(RespType::SimpleString("OK".to_string()))
+}
+
+struct SearchOptions {
+    no_content: bool,
+    with_scores: bool,
+    return_fields: Option<Vec<String>>,
+    sort_by: Option<(String, bool)>,
+    offset: usize,
+    limit: usize,
+    params: HashMap<String, Bytes>,
+}
+
+impl SearchOptions {
+    // options are text, only PARAMS values are kept binary since they may carry vector blobs
+    fn parse(raw_args: &[Bytes]) -> Result<Self, UserInputError> {
+        let args: Vec<String> = raw_args
+            .iter()
+            .map(|a| String::from_utf8_lossy(a).to_string())
+            .collect();
+        let mut opts = Self {
+            no_content: false,
+            with_scores: false,
+            return_fields: None,
+            sort_by: None,
+            offset: 0,
+            limit: 10,
+            params: HashMap::new(),
+        };
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].to_uppercase().as_str() {
+                "NOCONTENT" => opts.no_content = true,
+                "WITHSCORES" => opts.with_scores = true,
+                "VERBATIM" | "NOSTOPWORDS" => {}
+                "RETURN" => {
+                    let n = parse_count(args.get(i + 1))?;
+                    let end = i + 2 + n;
+                    if end > args.len() {
+                        return Err(invalid("Not enough RETURN fields provided"));
+                    }
+                    opts.return_fields = Some(args[i + 2..end].to_vec());
+                    i = end - 1;
+                }
+                "SORTBY" => {
+                    let field = args
+                        .get(i + 1)
+                        .ok_or(invalid("No field provided to SORTBY"))?;
+                    let desc = match args.get(i + 2).map(|s| s.to_uppercase()).as_deref() {
+                        Some("DESC") => true,
+                        Some("ASC") => false,
+                        _ => {
+                            opts.sort_by = Some((field.clone(), false));
+                            i += 2;
+                            continue;
+                        }
+                    };
+                    opts.sort_by = Some((field.clone(), desc));
+                    i += 2;
+                }
+                "LIMIT" => {
+                    opts.offset = parse_count(args.get(i + 1))?;
+                    opts.limit = parse_count(args.get(i + 2))?;
+                    i += 2;
+                }
+                "PARAMS" => {
+                    let n = parse_count(args.get(i + 1))?;
+                    let end = i + 2 + n;
+                    if !n.is_multiple_of(2) || end > args.len() {
+                        return Err(invalid("PARAMS expects name/value pairs"));
+                    }
+                    for (j, name) in args[i + 2..end].iter().enumerate().step_by(2) {
+                        opts.params
+                            .insert(name.clone(), raw_args[i + 3 + j].clone());
+                    }
+                    i = end - 1;
+                }
+                "DIALECT" | "TIMEOUT" | "LANGUAGE" => i += 1,
+                other => return Err(invalid(&format!("Unknown FT.SEARCH option: {}", other))),
+            }
+            i += 1;
+        }
+        if opts.return_fields.as_ref().is_some_and(|f| f.is_empty()) {
+            opts.no_content = true;
+        }
+        Ok(opts)
+    }
+
+    fn text_params(&self) -> HashMap<String, String> {
+        self.params
+            .iter()
+            .map(|(k, v)| (k.clone(), String::from_utf8_lossy(v).to_string()))
+            .collect()
+    }
+}
+
+/// The `=>[KNN k @field $blob [EF_RUNTIME n] [AS name]]` part of a query.
+#[derive(Debug)]
+struct KnnClause {
+    k: usize,
+    field: String,
+    blob: Bytes,
+    ef_runtime: Option<usize>,
+    score_name: String,
+}
+
+impl KnnClause {
+    fn parse(clause: &str, params: &HashMap<String, Bytes>) -> Result<Self, String> {
+        let inner = clause
+            .trim()
+            .strip_prefix('[')
+            .and_then(|c| c.strip_suffix(']'))
+            .ok_or("Expected a [KNN ...] clause after =>")?;
+        let tokens: Vec<&str> = inner.split_whitespace().collect();
+        let param = |token: &str| -> Result<Bytes, String> {
+            match token.strip_prefix('$') {
+                Some(name) => params
+                    .get(name)
+                    .cloned()
+                    .ok_or(format!("No such parameter `{}`", name)),
+                None => Ok(Bytes::from(token.to_string())),
+            }
+        };
+        let (k, field, blob) = match tokens.as_slice() {
+            [knn, k, field, blob, ..] if knn.eq_ignore_ascii_case("KNN") => (*k, *field, *blob),
+            _ => return Err("Expected KNN k @field $blob".to_string()),
+        };
+        let k = String::from_utf8_lossy(&param(k)?)
+            .parse()
+            .map_err(|_| format!("Bad KNN count: {}", k))?;
+        let field = field
+            .strip_prefix('@')
+            .ok_or("Expected @field in KNN clause")?
+            .to_string();
+        let mut knn = Self {
+            k,
+            score_name: format!("__{}_score", field),
+            field,
+            blob: param(blob)?,
+            ef_runtime: None,
+        };
+        let mut rest = tokens[4..].iter();
+        while let Some(opt) = rest.next() {
+            let value = rest.next().ok_or(format!("Missing value for {}", opt))?;
+            match opt.to_uppercase().as_str() {
+                "EF_RUNTIME" => {
+                    let ef = String::from_utf8_lossy(&param(value)?).to_string();
+                    knn.ef_runtime =
+                        Some(ef.parse().map_err(|_| format!("Bad EF_RUNTIME: {}", ef))?)
+                }
+                "AS" => knn.score_name = value.to_string(),
+                other => return Err(format!("Unknown KNN option: {}", other)),
+            }
+        }
+        Ok(knn)
+    }
+}
+
+// FT.SEARCH index query [NOCONTENT] [WITHSCORES] [RETURN n f ...] [SORTBY f [ASC|DESC]]
+//           [LIMIT offset num] [PARAMS n name value ...]
+// where query may end in =>[KNN k @field $blob] to rank the filtered docs by vector distance
+pub fn ft_search(db: &Db, args: &[Bytes]) -> Result<RespType, UserInputError> {
+    let (name, query) = match args {
+        [name, query, ..] => (
+            String::from_utf8_lossy(name).to_string(),
+            String::from_utf8_lossy(query).to_string(),
+        ),
+        _ => return Err(invalid("FT.SEARCH expects an index name and a query")),
+    };
+    let opts = SearchOptions::parse(&args[2..])?;
+    let (filter, knn) = match query.split_once("=>") {
+        Some((filter, clause)) => (
+            filter,
+            Some(KnnClause::parse(clause, &opts.params).map_err(UserInputError::InvalidInput)?),
+        ),
+        None => (query.as_str(), None),
+    };
+    let filter =
+        QueryParser::parse(filter, &opts.text_params()).map_err(UserInputError::InvalidInput)?;
+
+    let indexes = db.indexes().read();
+    let index = indexes
+        .get(&name)
+        .ok_or(invalid(&format!("{}: no such index", name)))?;
+    let scores = index.eval(&filter).map_err(UserInputError::InvalidInput)?;
+    let mut hits: Vec<(String, f64)> = match &knn {
+        Some(knn) => {
+            let field = index
+                .field_of(&knn.field)
+                .map_err(UserInputError::InvalidInput)?;
+            let vectors = index.vectors.get(&field.alias).ok_or(invalid(&format!(
+                "Field `{}` is not a VECTOR field",
+                knn.field
+            )))?;
+            let query = vectors.spec().decode(&knn.blob).ok_or(invalid(
+                "Query vector does not match the field's TYPE and DIM",
+            ))?;
+            let allowed: Option<HashSet<&str>> =
+                (filter != Query::All).then(|| scores.keys().map(|k| k.as_str()).collect());
+            vectors
+                .knn(&query, knn.k, knn.ef_runtime, allowed.as_ref())
+                .into_iter()
+                .map(|(key, distance)| (key, distance as f64))
+                .collect()
+        }
+        None => scores.into_iter().collect(),
+    };
+
+    match &opts.sort_by {
+        Some((field, desc)) if knn.as_ref().is_some_and(|k| &k.score_name == field) => {
+            hits.sort_by(|(ka, a), (kb, b)| {
+                let ord = a.total_cmp(b);
+                let ord = if *desc { ord.reverse() } else { ord };
+                ord.then_with(|| ka.cmp(kb))
+            });
+        }
+        Some((field, desc)) => {
+            let field = index
+                .field_of(field)
+                .map_err(UserInputError::InvalidInput)?;
+            let sort_key = |key: &str| {
+                let doc = &index.docs[key];
+                (
+                    doc.numbers.get(&field.alias).copied(),
+                    doc.fields.get(&field.identifier).cloned(),
+                )
+            };
+            hits.sort_by(|(a, _), (b, _)| {
+                let (num_a, str_a) = sort_key(a);
+                let (num_b, str_b) = sort_key(b);
+                // documents missing the field always sort last
+                let ord = match (num_a, num_b, str_a, str_b) {
+                    (Some(x), Some(y), _, _) => x.total_cmp(&y),
+                    (_, _, Some(x), Some(y)) => x.cmp(&y),
+                    (_, _, Some(_), None) => return Ordering::Less,
+                    (_, _, None, Some(_)) => return Ordering::Greater,
+                    _ => Ordering::Equal,
+                };
+                let ord = if *desc { ord.reverse() } else { ord };
+                ord.then_with(|| a.cmp(b))
+            });
+        }
+        // knn hits already come closest first
+        None if knn.is_some() => {}
+        None => hits.sort_by(|(ka, a), (kb, b)| b.total_cmp(a).then_with(|| ka.cmp(kb))),
+    }
+
+    let mut res = vec![RespType::Integer(hits.len() as i64)];
+    for (key, score) in hits.iter().skip(opts.offset).take(opts.limit) {
+        res.push(bulk(key));
+        if opts.with_scores {
+            res.push(bulk(score.to_string()));
+        }
+        if opts.no_content {
+            continue;
+        }
+        let doc = &index.docs[key];
+        let distance = knn.as_ref().map(|k| {
+            (
+                k.score_name.clone(),
+                Bytes::from((*score as f32).to_string()),
+            )
+        });
+        let fields: Vec<(String, Bytes)> = match &opts.return_fields {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| match &distance {
+                    Some((score_name, d)) if score_name == name => Some((name.clone(), d.clone())),
+                    _ => {
+                        let identifier = index
+                            .spec
+                            .field(name)
+                            .map_or(name.as_str(), |f| f.identifier.as_str());
+                        doc.fields
+                            .get(identifier)
+                            .map(|v| (name.clone(), v.clone()))
+                    }
+                })
+                .collect(),
+            None => {
+                let mut all: Vec<_> = doc
+                    .fields
+                    .iter()
+                    .map(|(f, v)| (f.clone(), v.clone()))
+                    .chain(distance)
+                    .collect();
+                all.sort();
+                all
+            }
+        };
+        res.push(RespType::Array(Some(
+            fields
+                .into_iter()
+                .flat_map(|(f, v)| [bulk(&f), RespType::BulkString(Some(v))])
+                .collect(),
+        )));
+    }
+    Ok(RespType::Array(Some(res)))
+}
+
+pub fn ft_info(db: &Db, args: &[String]) -> Result<RespType, UserInputError> {
+    let name = args
+        .first()
+        .ok_or(invalid("No index name provided to FT.INFO"))?;
+    let indexes = db.indexes().read();
+    let index = indexes
+        .get(name)
+        .ok_or(invalid(&format!("{}: no such index", name)))?;
+    let attributes = index
+        .spec
+        .fields
+        .iter()
+        .map(|f| {
+            let mut attr = vec![
+                bulk("identifier"),
+                bulk(&f.identifier),
+                bulk("attribute"),
+                bulk(&f.alias),
+                bulk("type"),
+                bulk(f.kind.name()),
+            ];
+            match &f.kind {
+                FieldType::Text { weight } => {
+                    attr.extend([bulk("WEIGHT"), bulk(weight.to_string())]);
+                }
+                FieldType::Tag { separator, .. } => {
+                    attr.extend([bulk("SEPARATOR"), bulk(separator.to_string())]);
+                }
+                FieldType::Numeric => {}
+                FieldType::Vector(v) => {
+                    let algorithm = match v.algorithm {
+                        Algorithm::Flat => "FLAT",
+                        Algorithm::Hnsw { .. } => "HNSW",
+                    };
+                    attr.extend([
+                        bulk("algorithm"),
+                        bulk(algorithm),
+                        bulk("data_type"),
+                        bulk(v.data_type.name()),
+                        bulk("dim"),
+                        RespType::Integer(v.dim as i64),
+                        bulk("distance_metric"),
+                        bulk(v.metric.name()),
+                    ]);
+                }
+            }
+            if f.sortable {
+                attr.push(bulk("SORTABLE"));
+            }
+            RespType::Array(Some(attr))
+        })
+        .collect();
+    let prefixes = if index.spec.prefixes.is_empty() {
+        vec![bulk("")]
+    } else {
+        index.spec.prefixes.iter().map(bulk).collect()
+    };
+    Ok(RespType::Array(Some(vec![
+        bulk("index_name"),
+        bulk(index.name()),
+        bulk("index_definition"),
+        RespType::Array(Some(vec![
+            bulk("key_type"),
+            bulk("HASH"),
+            bulk("prefixes"),
+            RespType::Array(Some(prefixes)),
+        ])),
+        bulk("attributes"),
+        RespType::Array(Some(attributes)),
+        bulk("num_docs"),
+        RespType::Integer(index.docs.len() as i64),
+        bulk("num_terms"),
+        RespType::Integer(index.num_terms() as i64),
+        bulk("hash_indexing_failures"),
+        RespType::Integer(index.failures as i64),
+    ])))
+}
+
+// FT.DROPINDEX index [DD]
+pub fn ft_dropindex(db: &Db, args: &[String]) -> Result<RespType, UserInputError> {
+    let name = args
+        .first()
+        .ok_or(invalid("No index name provided to FT.DROPINDEX"))?;
+    let delete_docs = match args.get(1).map(|s| s.to_uppercase()).as_deref() {
+        Some("DD") => true,
+        None => false,
+        Some(other) => return Err(invalid(&format!("Unknown FT.DROPINDEX option: {}", other))),
+    };
+    let keys = db
+        .indexes()
+        .write()
+        .drop_index(name)
+        .ok_or(invalid("Unknown Index name"))?;
+    if delete_docs {
+        db.del(&keys);
+    }
+    Ok(RespType::SimpleString("OK".to_string()))
+}
+
+pub fn ft_list(db: &Db) -> Result<RespType, UserInputError> {
+    let names = db.indexes().read().names();
+    Ok(RespType::Array(Some(names.iter().map(bulk).collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::vector::DistanceMetric;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|x| x.to_string()).collect()
+    }
+
+    fn raw_args(s: &str) -> Vec<Bytes> {
+        s.split_whitespace()
+            .map(|x| Bytes::from(x.to_string()))
+            .collect()
+    }
+
+    fn hset(db: &Db, key: &str, pairs: &[(&str, &[u8])]) {
+        let pairs: Vec<(String, Bytes)> = pairs
+            .iter()
+            .map(|(f, v)| (f.to_string(), Bytes::copy_from_slice(v)))
+            .collect();
+        db.hset(key, &pairs).unwrap();
+    }
+
+    fn blob(v: &[f32]) -> Vec<u8> {
+        v.iter().flat_map(|f| f.to_le_bytes()).collect()
+    }
+
+    fn setup() -> Db {
+        let db = Db::new(4);
+        hset(
+            &db,
+            "book:1",
+            &[
+                ("title", b"The Rust Programming Language"),
+                ("year", b"2018"),
+                ("tags", b"rust,programming"),
+            ],
+        );
+        hset(
+            &db,
+            "book:2",
+            &[
+                ("title", b"Programming Rust"),
+                ("year", b"2021"),
+                ("tags", b"rust,systems"),
+            ],
+        );
+        hset(
+            &db,
+            "book:3",
+            &[
+                ("title", b"Learning Go"),
+                ("year", b"2021"),
+                ("tags", b"go,programming"),
+            ],
+        );
+        hset(&db, "movie:1", &[("title", b"Rust"), ("year", b"2024")]);
+        ft_create(
+            &db,
+            &args("idx ON HASH PREFIX 1 book: SCHEMA title TEXT WEIGHT 2 year NUMERIC SORTABLE tags TAG"),
+        )
+        .unwrap();
+        db
+    }
+
+    fn search_keys(db: &Db, query: &str, extra: &str) -> Vec<String> {
+        let mut a = vec![
+            Bytes::from("idx"),
+            Bytes::from(query.to_string()),
+            Bytes::from("NOCONTENT"),
+        ];
+        a.extend(raw_args(extra));
+        match ft_search(db, &a).unwrap() {
+            RespType::Array(Some(items)) => items[1..]
+                .iter()
+                .map(|i| match i {
+                    RespType::BulkString(Some(b)) => String::from_utf8(b.to_vec()).unwrap(),
+                    _ => panic!("Unexpected reply item"),
+                })
+                .collect(),
+            _ => panic!("Unexpected reply"),
+        }
+    }
+
+    fn sorted(mut keys: Vec<String>) -> Vec<String> {
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let params = HashMap::new();
+        let q = QueryParser::parse("@title:(rust|go) -@year:[(2000 +inf]", &params).unwrap();
+        assert_eq!(
+            q,
+            Query::And(vec![
+                Query::Or(vec![
+                    Query::Term {
+                        fields: Some(vec!["title".to_string()]),
+                        term: "rust".to_string()
+                    },
+                    Query::Term {
+                        fields: Some(vec!["title".to_string()]),
+                        term: "go".to_string()
+                    },
+                ]),
+                Query::Not(Box::new(Query::Numeric {
+                    field: "year".to_string(),
+                    min: Bound::Excluded(2000.0),
+                    max: Bound::Included(f64::INFINITY),
+                })),
+            ])
+        );
+        assert!(QueryParser::parse("(rust", &params).is_err());
+    }
+
+    #[test]
+    fn test_search_terms_and_boolean_ops() {
+        let db = setup();
+        assert_eq!(
+            sorted(search_keys(&db, "rust", "")),
+            vec!["book:1", "book:2"]
+        );
+        assert_eq!(
+            sorted(search_keys(&db, "rust | go", "")),
+            vec!["book:1", "book:2", "book:3"]
+        );
+        assert_eq!(
+            search_keys(&db, "programming -language", ""),
+            vec!["book:2"]
+        );
+        assert_eq!(search_keys(&db, "\"rust programming\"", ""), vec!["book:1"]);
+        assert_eq!(
+            sorted(search_keys(&db, "prog*", "")),
+            vec!["book:1", "book:2"]
+        );
+        assert_eq!(search_keys(&db, "*", "").len(), 3);
+    }
+
+    #[test]
+    fn test_search_numeric_and_tags() {
+        let db = setup();
+        assert_eq!(
+            sorted(search_keys(&db, "@year:[2020 2021]", "")),
+            vec!["book:2", "book:3"]
+        );
+        assert_eq!(search_keys(&db, "@year:[-inf (2021]", ""), vec!["book:1"]);
+        assert_eq!(
+            sorted(search_keys(&db, "@tags:{systems | go}", "")),
+            vec!["book:2", "book:3"]
+        );
+        assert_eq!(
+            search_keys(&db, "@tags:{programming} @year:[$y $y]", "PARAMS 2 y 2018"),
+            vec!["book:1"]
+        );
+    }
+
+    #[test]
+    fn test_search_sortby_limit_return() {
+        let db = setup();
+        assert_eq!(
+            search_keys(&db, "*", "SORTBY year DESC LIMIT 0 2"),
+            vec!["book:2", "book:3"]
+        );
+        let res = ft_search(&db, &raw_args("idx @title:go RETURN 1 year")).unwrap();
+        assert_eq!(
+            res,
+            RespType::Array(Some(vec![
+                RespType::Integer(1),
+                bulk("book:3"),
+                RespType::Array(Some(vec![bulk("year"), bulk("2021")])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_index_follows_writes() {
+        let db = setup();
+        hset(
+            &db,
+            "book:4",
+            &[("title", b"Rust in Action"), ("year", b"2021")],
+        );
+        assert_eq!(
+            sorted(search_keys(&db, "rust", "")),
+            vec!["book:1", "book:2", "book:4"]
+        );
+        hset(&db, "book:1", &[("title", b"Something Else")]);
+        db.del(&["book:2".to_string()]);
+        assert_eq!(search_keys(&db, "rust", ""), vec!["book:4"]);
+        db.set("book:4", Bytes::from("plain string"), vec![])
+            .unwrap();
+        assert!(search_keys(&db, "rust", "").is_empty());
+    }
+
+    #[test]
+    fn test_drop_index() {
+        let db = setup();
+        assert_eq!(
+            ft_list(&db).unwrap(),
+            RespType::Array(Some(vec![bulk("idx")]))
+        );
+        assert!(ft_create(&db, &args("idx SCHEMA title TEXT")).is_err());
+        ft_dropindex(&db, &args("idx DD")).unwrap();
+        assert_eq!(ft_list(&db).unwrap(), RespType::Array(Some(vec![])));
+        assert_eq!(db.key_type("book:1"), None);
+        assert_eq!(db.key_type("movie:1"), Some("hash"));
+    }
+
+    fn knn(db: &Db, query: &str, vector: &[f32]) -> RespType {
+        let mut a = raw_args("vidx");
+        a.push(Bytes::from(query.to_string()));
+        a.extend(raw_args("PARAMS 2 vec"));
+        a.push(Bytes::from(blob(vector)));
+        ft_search(db, &a).unwrap()
+    }
+
+    fn vector_setup(algorithm: &str) -> Db {
+        let db = Db::new(2);
+        let points: [(&str, [f32; 2], &[u8]); 4] = [
+            ("item:1", [0.0, 0.0], b"red"),
+            ("item:2", [1.0, 0.0], b"blue"),
+            ("item:3", [0.0, 3.0], b"red"),
+            ("item:4", [5.0, 5.0], b"red"),
+        ];
+        for (key, v, color) in points {
+            hset(&db, key, &[("color", color), ("embedding", &blob(&v))]);
+        }
+        let create = format!(
+            "vidx PREFIX 1 item: SCHEMA color TAG embedding VECTOR {} 6 TYPE FLOAT32 DIM 2 DISTANCE_METRIC L2",
+            algorithm
+        );
+        ft_create(&db, &args(&create)).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_knn_search() {
+        for algorithm in ["FLAT", "HNSW"] {
+            let db = vector_setup(algorithm);
+            let query = [0.9, 0.1];
+            let l2 = |v: &[f32]| DistanceMetric::L2.distance(&query, v);
+            let res = knn(&db, "*=>[KNN 2 @embedding $vec AS dist]", &query);
+            assert_eq!(
+                res,
+                RespType::Array(Some(vec![
+                    RespType::Integer(2),
+                    bulk("item:2"),
+                    RespType::Array(Some(vec![
+                        bulk("color"),
+                        bulk("blue"),
+                        bulk("dist"),
+                        bulk(l2(&[1.0, 0.0]).to_string()),
+                        bulk("embedding"),
+                        RespType::BulkString(Some(Bytes::from(blob(&[1.0, 0.0])))),
+                    ])),
+                    bulk("item:1"),
+                    RespType::Array(Some(vec![
+                        bulk("color"),
+                        bulk("red"),
+                        bulk("dist"),
+                        bulk(l2(&[0.0, 0.0]).to_string()),
+                        bulk("embedding"),
+                        RespType::BulkString(Some(Bytes::from(blob(&[0.0, 0.0])))),
+                    ])),
+                ]))
+            );
+        }
+    }
+
+    #[test]
+    fn test_hybrid_knn_follows_writes() {
+        let db = vector_setup("HNSW");
+        let nearest = |db: &Db| match knn(db, "@color:{red}=>[KNN 1 @embedding $vec]", &[1.0, 0.0])
+        {
+            RespType::Array(Some(items)) => items[1].clone(),
+            _ => panic!("Unexpected reply"),
+        };
+        assert_eq!(nearest(&db), bulk("item:1"));
+        hset(
+            &db,
+            "item:5",
+            &[("color", b"red"), ("embedding", &blob(&[1.0, 0.5]))],
+        );
+        assert_eq!(nearest(&db), bulk("item:5"));
+        db.del(&["item:5".to_string(), "item:1".to_string()]);
+        assert_eq!(nearest(&db), bulk("item:3"));
+        // a blob of the wrong size is not indexed
+        hset(&db, "item:6", &[("color", b"red"), ("embedding", b"xx")]);
+        assert_eq!(nearest(&db), bulk("item:3"));
+    }
+}
@@ -1,114 +1,374 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
+use bytes::{Buf, Bytes, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, Error},
-    net::{TcpListener, TcpStream},
-    time::timeout,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Error},
+    net::{TcpListener, UnixListener},
 };
 
 use crate::resp::{
-    commands::handle_input_cmd, datastore, deserialize::deserialize_array, redisconfig,
+    acl, aof, blocking,
+    cli::ServerArgs,
+    client::Client,
+    commands::execute,
+    constants::{CONFIG_FILE_PATH, SERVER_CRON_INTERVAL_MS},
+    datastore,
+    deserialize::parse_request,
+    listener, migrate, persistence, pubsub, redisconfig,
     resp_value::RespType,
+    stats::Stats,
+    tls, transaction,
 };
 
 use super::errors::ServerError;
 
-pub async fn run_server() -> Result<(), Error> {
-    let db = datastore::Db::new(1);
-    // db.load().unwrap();
-    let port = redisconfig::get_config("port")
-        .unwrap_or("6379".to_string())
-        .parse()
-        .unwrap();
-    let socket_sddr = SocketAddr::from(([127, 0, 0, 1], port));
-    let listener = TcpListener::bind(socket_sddr).await?;
-    println!("Server listening on port {}", port);
+pub async fn run_server(args: ServerArgs) -> Result<(), Error> {
+    // without a config file on the command line, use ./redis.conf if present
+    let config_file = args
+        .config_file
+        .or_else(|| Some(PathBuf::from(CONFIG_FILE_PATH)).filter(|path| path.exists()));
+    if let Err(e) = redisconfig::load_config(config_file.as_deref(), &args.options) {
+        eprintln!("{}", e);
+        return Err(Error::other("invalid configuration"));
+    }
+    if redisconfig::get_memory("maxmemory") > 0 && redisconfig::log_enabled("warning") {
+        eprintln!("maxmemory is not supported, no limit is kept");
+    }
+    let dir = redisconfig::get_config("dir").unwrap_or_default();
+    if let Err(e) = std::env::set_current_dir(&dir) {
+        eprintln!("Can't chdir to '{}': {}", dir, e);
+        return Err(e);
+    }
+    if let Err(e) = acl::init() {
+        eprintln!("{}", e);
+        return Err(Error::other("invalid ACL configuration"));
+    }
+    let mut db = datastore::Db::new(1);
+    if persistence::load_data(&mut db).is_err() {
+        eprintln!("Refusing to start without the persisted data");
+        return Err(Error::other("failed to load data"));
+    }
+    let cron_db = db.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(SERVER_CRON_INTERVAL_MS));
+        loop {
+            ticker.tick().await;
+            {
+                // not in the middle of a transaction
+                let exec_lock = cron_db.exec_lock();
+                let _running = exec_lock.read();
+                cron_db.purge_expired();
+                // group readers blocked on an expired stream are told it is gone
+                cron_db.blocking().serve(&cron_db);
+                persistence::cron(&cron_db);
+                aof::cron(&cron_db);
+            }
+            migrate::cron();
+        }
+    });
+    if let Err(e) = tls::configure() {
+        eprintln!("Failed to configure TLS: {}", e);
+        return Err(Error::other("invalid TLS configuration"));
+    }
+    let port = redisconfig::get_int("port") as u16;
+    let tls_port = redisconfig::get_int("tls-port") as u16;
+    let listeners = listener::listen_tcp(port)?;
+    let tls_listeners = listener::listen_tcp(tls_port)?;
+    let unix = listener::listen_unix()?;
+    if listeners.is_empty() && tls_listeners.is_empty() && unix.is_none() {
+        eprintln!("Configured to not listen anywhere, exiting.");
+        return Err(Error::other("no listening sockets"));
+    }
+    if !listeners.is_empty() && redisconfig::log_enabled("notice") {
+        println!("Server listening on port {}", port);
+    }
+    for tcp in listeners {
+        tokio::spawn(accept_tcp(tcp, db.clone(), false));
+    }
+    if !tls_listeners.is_empty() && redisconfig::log_enabled("notice") {
+        println!("Server listening on TLS port {}", tls_port);
+    }
+    for tcp in tls_listeners {
+        tokio::spawn(accept_tcp(tcp, db.clone(), true));
+    }
+    if let Some(unix) = unix {
+        if redisconfig::log_enabled("notice") {
+            println!(
+                "Server listening on Unix socket {}",
+                redisconfig::get_config("unixsocket").unwrap_or_default()
+            );
+        }
+        tokio::spawn(accept_unix(unix, db.clone()));
+    }
+    tokio::signal::ctrl_c().await?;
+    if let Some(aof) = db.aof().lock().as_mut() {
+        if let Err(e) = aof.fsync() {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Failed to fsync the AOF: {}", e);
+            }
+        }
+    }
+    // like redis on SIGINT, take a final snapshot when saving is configured
+    if persistence::saving_enabled() {
+        if redisconfig::log_enabled("notice") {
+            println!("Saving the final RDB snapshot before exiting.");
+        }
+        if let Err(e) = persistence::save(&db) {
+            if redisconfig::log_enabled("warning") {
+                eprintln!("Error trying to save the DB: {}", e);
+            }
+        }
+    }
+    if let Some(path) = redisconfig::get_config("unixsocket").filter(|p| !p.is_empty()) {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+async fn accept_tcp(tcp: TcpListener, db: datastore::Db, tls: bool) {
     loop {
+        let (stream, peer) = match tcp.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // e.g. out of file descriptors; give clients a moment to leave
+                eprintln!("Accepting client connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                continue;
+            }
+        };
         let db = db.clone();
-        let (stream, _) = listener.accept().await.unwrap();
         tokio::spawn(async move {
-            process(stream, db).await;
+            if !tls {
+                return serve_remote(stream, peer, db).await;
+            }
+            match tls::accept(stream).await {
+                Ok(stream) => serve_remote(stream, peer, db).await,
+                Err(e) => eprintln!("Error accepting a client connection: {} (addr={})", e, peer),
+            }
         });
     }
 }
 
-pub async fn process(mut stream: TcpStream, mut db: datastore::Db) {
+// serves a client that connected over the network, unless protected mode
+// turns it away
+async fn serve_remote<S>(mut stream: S, peer: SocketAddr, db: datastore::Db)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if listener::protected_mode_refuses(peer.ip()) {
+        let denied = RespType::Error(listener::PROTECTED_MODE_ERROR.to_string());
+        let _ = stream.write_all(&denied.serialize()).await;
+        return;
+    }
+    process(stream, db).await;
+}
+
+// clients on the Unix socket are local, so protected mode lets them in
+async fn accept_unix(unix: UnixListener, db: datastore::Db) {
+    loop {
+        match unix.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(process(stream, db.clone()));
+            }
+            Err(e) => {
+                eprintln!("Accepting client connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+    }
+}
+
+/// Serves one client, over TCP or a Unix socket, until it disconnects.
+pub async fn process<S>(mut stream: S, mut db: datastore::Db)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Stats::add(&db.stats().connections_received, 1);
+    let mut client = Client::new();
+    let mut buf = BytesMut::with_capacity(16 * 1024);
     // use loop to continue processing requests from the same client
     loop {
-        let input_arr_res = read_arr_from_stream(&mut stream).await;
-        let input_arr = match input_arr_res {
-            Ok(RespType::Quit) => {
-                db.save().unwrap();
+        // like redis, a blocked client's further requests wait until it is
+        // served, but a disconnect is noticed and unblocks it
+        if let Some(blocked) = client.blocked.as_mut() {
+            let res = tokio::select! {
+                res = blocked.wait() => Some(res),
+                read = stream.read_buf(&mut buf) => match read {
+                    Ok(0) => return,
+                    Ok(_) => None,
+                    Err(e) => {
+                        eprintln!("Failed to read from stream: {}", e);
+                        return;
+                    }
+                },
+            };
+            let Some(res) = res else {
+                continue;
+            };
+            client.blocked = None;
+            if let Err(e) = stream.write_all(&res.serialize()).await {
+                eprintln!("Failed to write to stream: {}", e);
                 return;
             }
-            Ok(res) => res,
+            continue;
+        }
+        // answer every complete request already buffered before reading more,
+        // so pipelined requests and ones split across reads both work
+        match parse_request(&buf) {
+            Ok(Some((request, consumed))) => {
+                buf.advance(consumed);
+                // like redis, drop the clients of a user that was deleted
+                if !acl::user_exists(&client.user) {
+                    return;
+                }
+                let replies =
+                    reply(request, &mut client, &mut db).unwrap_or_else(|e| vec![error_reply(e)]);
+                let quit = replies.contains(&RespType::Quit);
+                // messages published before the command finished, and the
+                // confirmations of its subscribing, go out ahead of its reply
+                let Some(mut out) = pending_messages(&mut client) else {
+                    return;
+                };
+                for res in replies {
+                    let res = if res == RespType::Quit {
+                        RespType::SimpleString("OK".to_string())
+                    } else {
+                        res
+                    };
+                    out.extend_from_slice(&res.serialize());
+                }
+                if let Err(e) = stream.write_all(&out).await {
+                    eprintln!("Failed to write to stream: {}", e);
+                    return;
+                }
+                if quit {
+                    return;
+                }
+                continue;
+            }
+            Ok(None) => {}
             Err(e) => {
-                eprintln!("Failed to read from stream: {}", e);
+                let res = RespType::Error(format!("ERR Protocol error: {}", e));
+                let _ = stream.write_all(&res.serialize()).await;
                 return;
             }
+        }
+        // like redis, close connections idle for longer than `timeout`
+        // seconds, except subscribers, which wait for messages
+        let timeout = redisconfig::get_int("timeout");
+        let idle_limit =
+            (timeout > 0 && !client.is_subscribed()).then(|| Duration::from_secs(timeout as u64));
+        let read = async {
+            match idle_limit {
+                Some(limit) => tokio::time::timeout(limit, stream.read_buf(&mut buf))
+                    .await
+                    .ok(),
+                None => Some(stream.read_buf(&mut buf).await),
+            }
         };
-        let res = reply(input_arr, &mut db).unwrap();
-
-        stream.writable().await.unwrap();
-        stream.write(&res.serialize()).await.unwrap();
-        stream.flush().await.unwrap();
+        let message = async {
+            match client.subscriber.as_mut() {
+                Some(subscriber) => subscriber.next().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            res = read => match res {
+                None | Some(Ok(0)) => return,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    eprintln!("Failed to read from stream: {}", e);
+                    return;
+                }
+            },
+            message = message => {
+                let Some(message) = message else {
+                    if redisconfig::log_enabled("warning") {
+                        eprintln!("Client id={} closed for overcoming of output buffer limits.", client.id);
+                    }
+                    return;
+                };
+                if let Err(e) = stream.write_all(&message.serialize()).await {
+                    eprintln!("Failed to write to stream: {}", e);
+                    return;
+                }
+            }
+        }
     }
 }
 
-async fn read_arr_from_stream(stream: &mut TcpStream) -> Result<RespType, ServerError> {
-    // put a timeout on the read operation
-    let stream_ready = timeout(Duration::from_secs(1), stream.readable());
-    match stream_ready.await {
-        Ok(Ok(_)) => {}
-        Ok(Err(e)) => {
-            eprintln!("Failed to check if stream is readable: {}", e);
-            return Err(ServerError::ReadError);
-        }
-        Err(_) => {
-            println!("Timeout waiting for data");
-            return Err(ServerError::ReadError);
-        }
-    };
-    let mut buf = [0u8; 1024];
-    let content_len = match stream.read(&mut buf).await {
-        Ok(content_len) => content_len,
-        Err(e) => {
-            eprintln!("Failed to read from stream: {}", e);
-            0
+// the serialized messages waiting for the client, or None when it fell too
+// far behind on them and is disconnected
+fn pending_messages(client: &mut Client) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    if let Some(subscriber) = client.subscriber.as_mut() {
+        let Some(messages) = subscriber.pending() else {
+            if redisconfig::log_enabled("warning") {
+                eprintln!(
+                    "Client id={} closed for overcoming of output buffer limits.",
+                    client.id
+                );
+            }
+            return None;
+        };
+        for message in messages {
+            out.extend_from_slice(&message.serialize());
         }
-    };
-    if content_len == 0 {
-        return Ok(RespType::Quit);
     }
-
-    let input_arr = deserialize_array(&buf[..content_len])
-        .map_err(|_| ServerError::RespParseError("Failed to deserialize input array".to_string()))
-        .unwrap();
-    Ok(input_arr)
+    Some(out)
 }
 
-fn reply(arr: RespType, db: &mut datastore::Db) -> Result<RespType, ServerError> {
+/// The replies to one request. Usually there is one; subscribing is
+/// answered through the client's messages instead.
+pub fn reply(
+    arr: RespType,
+    client: &mut Client,
+    db: &mut datastore::Db,
+) -> Result<Vec<RespType>, ServerError> {
     match arr {
         RespType::Array(arr) => {
             if arr.is_none() {
-                return Ok(RespType::Null);
+                return Ok(vec![RespType::Null]);
             }
             let bulk_string_arr = arr.unwrap();
-            if bulk_string_arr.len() == 0 {
+            if bulk_string_arr.is_empty() {
                 // TODO: handle empty array
-                return Ok(RespType::SimpleString("".to_string()));
+                return Ok(vec![RespType::SimpleString("".to_string())]);
             }
 
-            let str_arr: Vec<&str> = bulk_string_arr
-                .iter()
+            let args: Vec<Bytes> = bulk_string_arr
+                .into_iter()
                 .map(|bs| match bs {
-                    RespType::BulkString(Some(bs)) => std::str::from_utf8(bs).unwrap(),
-                    _ => "",
+                    RespType::BulkString(Some(bs)) => bs,
+                    _ => Bytes::new(),
                 })
                 .collect();
-            let res = handle_input_cmd(str_arr, db).map_err(|e| ServerError::UserInputError(e))?;
-            Ok(res)
+            if let Some(res) = client.connection_command(&args) {
+                return Ok(vec![res]);
+            }
+            if let Some(res) = client.client_command(&args, db) {
+                return Ok(vec![res]);
+            }
+            if let Some(res) = transaction::transaction_command(client, &args, db) {
+                return Ok(vec![res]);
+            }
+            if let Some(replies) = pubsub::pubsub_command(client, &args, db) {
+                return Ok(replies);
+            }
+            if let Some(replies) = blocking::blocking_command(client, &args, db, true) {
+                return Ok(replies);
+            }
+            let res = execute(args, db).map_err(ServerError::UserInputError)?;
+            Ok(vec![res])
         }
         _ => Err(ServerError::TypeError),
     }
 }
+
+// errors go back to the client instead of tearing down the connection
+fn error_reply(e: ServerError) -> RespType {
+    match e {
+        ServerError::UserInputError(e) => RespType::Error(format!("ERR {}", e)),
+        e => RespType::Error(format!("ERR {}", e)),
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Server counters for the "# Stats" section of INFO, zeroed by CONFIG RESETSTAT.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub connections_received: AtomicU64,
+    pub commands_processed: AtomicU64,
+    /// keys removed because their ttl passed, lazily or by the cron
+    pub expired_keys: AtomicU64,
+}
+
+impl Stats {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.connections_received,
+            &self.commands_processed,
+            &self.expired_keys,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn info(&self) -> String {
+        let fields = [
+            ("total_connections_received", &self.connections_received),
+            ("total_commands_processed", &self.commands_processed),
+            ("expired_keys", &self.expired_keys),
+        ];
+        let mut info = "# Stats\r\n".to_string();
+        for (name, counter) in fields {
+            info.push_str(&format!("{}:{}\r\n", name, counter.load(Ordering::Relaxed)));
+        }
+        info
+    }
+}
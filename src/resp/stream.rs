@@ -0,0 +1,1035 @@
+use std::{collections::BTreeMap, fmt, ops::Bound};
+
+use bytes::Bytes;
+
+use super::{
+    commands::wrong_type,
+    datastore::Db,
+    errors::{DataStoreError, UserInputError},
+    resp_value::{bulk, RespType},
+};
+
+/// An entry ID: a unix time in milliseconds, and a sequence number for the
+/// entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Reads "ms-seq", or "ms" alone with `seq` standing in for the sequence.
+    pub fn parse(s: &str, seq: u64) -> Option<Self> {
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse().ok()?),
+            None => (s, seq),
+        };
+        Some(Self {
+            ms: ms.parse().ok()?,
+            seq,
+        })
+    }
+
+    // the smallest ID after this one
+    fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => Some(Self {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    /// The ID as the 16 big-endian bytes rdb files key stream nodes by.
+    pub fn to_be_bytes(self) -> [u8; 16] {
+        let mut raw = [0; 16];
+        raw[..8].copy_from_slice(&self.ms.to_be_bytes());
+        raw[8..].copy_from_slice(&self.seq.to_be_bytes());
+        raw
+    }
+
+    pub fn from_be_bytes(raw: [u8; 16]) -> Self {
+        Self {
+            ms: u64::from_be_bytes(raw[..8].try_into().unwrap()),
+            seq: u64::from_be_bytes(raw[8..].try_into().unwrap()),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID XADD is asked for: `*`, `ms-*`, or an explicit one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NewId {
+    Auto,
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+/// How XADD trims the stream: down to a length, or to the entries from an
+/// ID on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trim {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// An entry delivered to a consumer that it hasn't acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    /// unix time in milliseconds of the last delivery
+    pub delivery_time: i64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    /// unix time in milliseconds the consumer last read
+    pub seen_time: i64,
+}
+
+/// A consumer group: the last entry delivered to any of its consumers, and
+/// the delivered entries still waiting to be acknowledged.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerGroup {
+    pub last_id: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    /// A consumer's pending entries, in ID order.
+    pub fn pending_of<'a>(&'a self, consumer: &'a str) -> impl Iterator<Item = StreamId> + 'a {
+        self.pending
+            .iter()
+            .filter(move |(_, p)| p.consumer == consumer)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// An append-only log of entries, each a list of field-value pairs, in ID
+/// order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Fields>,
+    /// the last ID ever added, even once trimmed away
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+}
+
+const ID_TOO_SMALL: &str =
+    "ERR The ID specified in XADD is equal or smaller than the target stream top item";
+
+impl Stream {
+    /// The ID an entry added at `now` gets, or the error when the one asked
+    /// for does not come after the last entry.
+    pub fn next_id(&self, id: NewId, now: u64) -> Result<StreamId, &'static str> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if now > last.ms => StreamId { ms: now, seq: 0 },
+            NewId::Auto => last.next().ok_or(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            )?,
+            NewId::AutoSeq(ms) if ms > last.ms => StreamId { ms, seq: 0 },
+            NewId::AutoSeq(ms) if ms == last.ms => StreamId {
+                ms,
+                seq: last.seq.checked_add(1).ok_or(ID_TOO_SMALL)?,
+            },
+            NewId::AutoSeq(_) => return Err(ID_TOO_SMALL),
+            NewId::Explicit(id) => id,
+        };
+        if id == StreamId::default() {
+            return Err("ERR The ID specified in XADD must be greater than 0-0");
+        }
+        if id <= last {
+            return Err(ID_TOO_SMALL);
+        }
+        Ok(id)
+    }
+
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Drops the oldest entries as asked, returning how many went.
+    pub fn trim(&mut self, trim: Trim) -> usize {
+        let kept = match trim {
+            Trim::MaxLen(len) if self.entries.len() <= len => return 0,
+            Trim::MaxLen(len) => match self.entries.keys().nth(self.entries.len() - len) {
+                Some(from) => self.entries.split_off(&from.clone()),
+                None => BTreeMap::new(),
+            },
+            Trim::MinId(from) => self.entries.split_off(&from),
+        };
+        let removed = std::mem::replace(&mut self.entries, kept);
+        if let Some(last) = removed.keys().next_back() {
+            self.max_deleted_id = self.max_deleted_id.max(*last);
+        }
+        removed.len()
+    }
+
+    /// The entries from `start` to `end`, at most `count` of them.
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Vec<(StreamId, Fields)> {
+        // walked from the start, since BTreeMap::range panics on an empty range
+        self.entries
+            .range((start, Bound::Unbounded))
+            .take_while(|(id, _)| match end {
+                Bound::Included(end) => **id <= end,
+                Bound::Excluded(end) => **id < end,
+                Bound::Unbounded => true,
+            })
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect()
+    }
+
+    /// Reads for a consumer of a group, creating the consumer if needed.
+    /// Without `after`, reads the entries never delivered to the group and
+    /// makes them pending, unless `noack`; with it, the consumer's pending
+    /// entries after that ID, those since trimmed away without fields.
+    ///
+    /// Returns None without such a group, and otherwise the entries and
+    /// whether the consumer is new.
+    #[allow(clippy::type_complexity)]
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        noack: bool,
+        now: i64,
+    ) -> Option<(Vec<(StreamId, Option<Fields>)>, bool)> {
+        let group = self.groups.get_mut(group)?;
+        let created = group
+            .consumers
+            .insert(consumer.to_string(), Consumer { seen_time: now })
+            .is_none();
+        let count = count.unwrap_or(usize::MAX);
+        let Some(after) = after else {
+            let read: Vec<_> = self
+                .entries
+                .range((Bound::Excluded(group.last_id), Bound::Unbounded))
+                .take(count)
+                .map(|(id, fields)| (*id, Some(fields.clone())))
+                .collect();
+            if let Some((last, _)) = read.last() {
+                group.last_id = *last;
+            }
+            if !noack {
+                for (id, _) in &read {
+                    let pending = PendingEntry {
+                        consumer: consumer.to_string(),
+                        delivery_time: now,
+                        delivery_count: 1,
+                    };
+                    group.pending.insert(*id, pending);
+                }
+            }
+            return Some((read, created));
+        };
+        let read = group
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .filter(|(_, p)| p.consumer == consumer)
+            .take(count)
+            .map(|(id, _)| (*id, self.entries.get(id).cloned()))
+            .collect();
+        Some((read, created))
+    }
+}
+
+fn error(msg: &str) -> RespType {
+    RespType::Error(msg.to_string())
+}
+
+fn text(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).to_string()
+}
+
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+fn parse_id(arg: &[u8], seq: u64) -> Result<StreamId, RespType> {
+    StreamId::parse(&text(arg), seq).ok_or_else(|| error(INVALID_ID))
+}
+
+// errors of the keyspace that go back to the client as they are
+fn error_reply(e: DataStoreError) -> Result<RespType, UserInputError> {
+    match e {
+        DataStoreError::WrongType => Ok(wrong_type()),
+        DataStoreError::InvalidInput(msg) => Ok(RespType::Error(msg)),
+        e => Err(UserInputError::DataStoreError(e)),
+    }
+}
+
+// [id, [field, value, ...]], or [id, nil] for an entry trimmed away
+fn entry_reply(id: StreamId, fields: Option<Fields>) -> RespType {
+    let fields = fields.map(|fields| {
+        fields
+            .iter()
+            .flat_map(|(field, value)| [bulk(field), bulk(value)])
+            .collect()
+    });
+    RespType::Array(Some(vec![
+        bulk(id.to_string().as_bytes()),
+        RespType::Array(fields),
+    ]))
+}
+
+// [key, [entry, ...]], one key's part of a read reply
+fn key_reply(key: &str, entries: Vec<(StreamId, Option<Fields>)>) -> RespType {
+    let entries = entries
+        .into_iter()
+        .map(|(id, fields)| entry_reply(id, fields))
+        .collect();
+    RespType::Array(Some(vec![
+        bulk(key.as_bytes()),
+        RespType::Array(Some(entries)),
+    ]))
+}
+
+/// What XADD was asked to do.
+struct XAdd {
+    nomkstream: bool,
+    trim: Option<Trim>,
+    id: NewId,
+    /// where the ID is in the arguments
+    id_at: usize,
+    fields: Fields,
+}
+
+// key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] *|id
+// field value [field value ...]
+fn parse_xadd(args: &[Bytes]) -> Result<XAdd, RespType> {
+    let (mut nomkstream, mut trim) = (false, None);
+    let mut i = 1;
+    while i < args.len() {
+        match text(&args[i]).to_lowercase().as_str() {
+            "nomkstream" => nomkstream = true,
+            option @ ("maxlen" | "minid") => {
+                let approx = args.get(i + 1).is_some_and(|a| a[..] == *b"~");
+                if args
+                    .get(i + 1)
+                    .is_some_and(|a| a[..] == *b"=" || a[..] == *b"~")
+                {
+                    i += 1;
+                }
+                let threshold = args.get(i + 1).ok_or_else(|| error("ERR syntax error"))?;
+                trim = Some(match option {
+                    "maxlen" => match text(threshold).parse::<i64>() {
+                        Ok(len) if len >= 0 => Trim::MaxLen(len as usize),
+                        Ok(_) => return Err(error("ERR The MAXLEN argument must be >= 0.")),
+                        Err(_) => return Err(error("ERR value is not an integer or out of range")),
+                    },
+                    _ => Trim::MinId(parse_id(threshold, 0)?),
+                });
+                i += 1;
+                // trimming is always exact, so the effort limit has nothing to bound
+                if args
+                    .get(i + 1)
+                    .is_some_and(|a| a.eq_ignore_ascii_case(b"limit"))
+                {
+                    if !approx {
+                        return Err(error(
+                            "ERR syntax error, LIMIT cannot be used without the special ~ option",
+                        ));
+                    }
+                    if args
+                        .get(i + 2)
+                        .is_none_or(|n| text(n).parse::<u64>().is_err())
+                    {
+                        return Err(error("ERR value is not an integer or out of range"));
+                    }
+                    i += 2;
+                }
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    let fields = args.get(i + 1..).unwrap_or_default();
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return Err(error("ERR wrong number of arguments for 'xadd' command"));
+    }
+    let id = match text(&args[i]).as_str() {
+        "*" => NewId::Auto,
+        id => match id.strip_suffix("-*") {
+            Some(ms) => NewId::AutoSeq(ms.parse().map_err(|_| error(INVALID_ID))?),
+            None => NewId::Explicit(parse_id(&args[i], 0)?),
+        },
+    };
+    Ok(XAdd {
+        nomkstream,
+        trim,
+        id,
+        id_at: i,
+        fields: fields
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect(),
+    })
+}
+
+/// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]]
+/// *|id field value [field value ...]; `args` start after the command name.
+pub fn xadd(db: &Db, args: &[Bytes]) -> Result<RespType, UserInputError> {
+    let xadd = match parse_xadd(args) {
+        Ok(xadd) => xadd,
+        Err(e) => return Ok(e),
+    };
+    let key = text(&args[0]);
+    match db.xadd(&key, xadd.id, xadd.fields, xadd.nomkstream, xadd.trim) {
+        Ok(id) => Ok(RespType::BulkString(
+            id.map(|id| Bytes::from(id.to_string())),
+        )),
+        Err(e) => error_reply(e),
+    }
+}
+
+/// XADD as it is logged, with the ID it added in place of the one asked
+/// for, so replaying it adds the same entry. `args` start with the name.
+pub fn xadd_aof_form(mut args: Vec<Bytes>, reply: &RespType) -> Vec<Bytes> {
+    if let (Ok(xadd), RespType::BulkString(Some(id))) = (parse_xadd(&args[1..]), reply) {
+        args[xadd.id_at + 1] = id.clone();
+    }
+    args
+}
+
+pub fn xlen(db: &Db, key: &str) -> Result<RespType, UserInputError> {
+    match db.read_stream(key, |stream| stream.map_or(0, |s| s.entries.len())) {
+        Ok(len) => Ok(RespType::Integer(len as i64)),
+        Err(e) => error_reply(e),
+    }
+}
+
+/// XRANGE key start end [COUNT count], where start may be `-`, end `+`, and
+/// either can be made exclusive with a `(`.
+pub fn xrange(db: &Db, args: &[Bytes]) -> Result<RespType, UserInputError> {
+    let bound = |arg: &[u8], seq: u64, open: &[u8]| {
+        if arg == open {
+            return Ok(Bound::Unbounded);
+        }
+        match arg.strip_prefix(b"(") {
+            Some(id) => parse_id(id, seq).map(Bound::Excluded),
+            None => parse_id(arg, seq).map(Bound::Included),
+        }
+    };
+    let (start, end) = match (bound(&args[1], 0, b"-"), bound(&args[2], u64::MAX, b"+")) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => return Ok(e),
+    };
+    let count = match &args[3..] {
+        [] => None,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => {
+            match text(count).parse::<i64>() {
+                Ok(count) => Some(count.max(0) as usize),
+                Err(_) => return Ok(error("ERR value is not an integer or out of range")),
+            }
+        }
+        _ => return Ok(error("ERR syntax error")),
+    };
+    let entries = db.read_stream(&text(&args[0]), |stream| {
+        stream.map_or(vec![], |s| s.range(start, end, count))
+    });
+    match entries {
+        Ok(entries) => Ok(RespType::Array(Some(
+            entries
+                .into_iter()
+                .map(|(id, fields)| entry_reply(id, Some(fields)))
+                .collect(),
+        ))),
+        Err(e) => error_reply(e),
+    }
+}
+
+/// Where a read starts on a key: after an ID, after the last entry when the
+/// read started (`$`), or at the entries never delivered to the group (`>`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadFrom {
+    After(StreamId),
+    Last,
+    New,
+}
+
+/// The options of XREAD and XREADGROUP.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadArgs {
+    /// the group and consumer of XREADGROUP
+    pub group: Option<(String, String)>,
+    pub count: Option<usize>,
+    /// BLOCK milliseconds, 0 waiting for ever
+    pub block: Option<u64>,
+    pub noack: bool,
+    pub streams: Vec<(String, ReadFrom)>,
+}
+
+/// Reads [COUNT count] [BLOCK ms] [NOACK] [GROUP group consumer] STREAMS
+/// key [key ...] id [id ...], the GROUP and NOACK options only for
+/// XREADGROUP. `args` start after the command name.
+pub fn parse_read(args: &[Bytes], group: bool) -> Result<ReadArgs, RespType> {
+    let name = if group { "xreadgroup" } else { "xread" };
+    let mut read = ReadArgs {
+        group: None,
+        count: None,
+        block: None,
+        noack: false,
+        streams: vec![],
+    };
+    let mut i = 0;
+    let streams = loop {
+        let Some(arg) = args.get(i) else {
+            return Err(error("ERR syntax error"));
+        };
+        let value = args.get(i + 1).map(|v| text(v));
+        match text(arg).to_lowercase().as_str() {
+            "count" => match value.map(|v| v.parse::<i64>()) {
+                Some(Ok(count)) => read.count = (count > 0).then_some(count as usize),
+                Some(Err(_)) => return Err(error("ERR value is not an integer or out of range")),
+                None => return Err(error("ERR syntax error")),
+            },
+            "block" => match value.map(|v| v.parse::<i64>()) {
+                Some(Ok(ms)) if ms < 0 => return Err(error("ERR timeout is negative")),
+                Some(Ok(ms)) => read.block = Some(ms as u64),
+                Some(Err(_)) => return Err(error("ERR timeout is not an integer or out of range")),
+                None => return Err(error("ERR syntax error")),
+            },
+            "group" if !group => return Err(error(
+                "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+            )),
+            "group" => match (value, args.get(i + 2)) {
+                (Some(name), Some(consumer)) => {
+                    read.group = Some((name, text(consumer)));
+                    i += 1;
+                }
+                _ => return Err(error("ERR syntax error")),
+            },
+            "noack" if group => {
+                read.noack = true;
+                i += 1;
+                continue;
+            }
+            "streams" => break &args[i + 1..],
+            _ => return Err(error("ERR syntax error")),
+        }
+        i += 2;
+    };
+    if group && read.group.is_none() {
+        return Err(error("ERR Missing GROUP option for XREADGROUP"));
+    }
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return Err(error(&format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.",
+            name,
+            if group { ">" } else { "$" }
+        )));
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    for (key, id) in keys.iter().zip(ids) {
+        let from = match (&id[..], group) {
+            (b"$", false) => ReadFrom::Last,
+            (b">", true) => ReadFrom::New,
+            (b"$", true) => {
+                return Err(error(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.",
+                ))
+            }
+            (b">", false) => {
+                return Err(error(
+                    "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.",
+                ))
+            }
+            (id, _) => ReadFrom::After(parse_id(id, 0)?),
+        };
+        read.streams.push((text(key), from));
+    }
+    Ok(read)
+}
+
+/// The error XREADGROUP gives for a key without the group, checked for
+/// every key before any is read.
+pub fn missing_group(db: &Db, read: &ReadArgs) -> Option<RespType> {
+    let (group, _) = read.group.as_ref()?;
+    read.streams.iter().find_map(|(key, _)| {
+        match db.read_stream(key, |s| s.is_some_and(|s| s.groups.contains_key(group))) {
+            Ok(true) => None,
+            Ok(false) => Some(error(&no_group(key, group))),
+            Err(DataStoreError::WrongType) => Some(wrong_type()),
+            Err(e) => Some(RespType::Error(format!("ERR {}", e))),
+        }
+    })
+}
+
+/// The error for reading with a group the key lacks, or a missing key.
+pub fn no_group(key: &str, group: &str) -> String {
+    format!(
+        "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+        key, group
+    )
+}
+
+/// The ID `$` stands for on a key: its last entry's, or 0-0 on a missing key.
+pub fn last_id(db: &Db, key: &str) -> Result<StreamId, DataStoreError> {
+    db.read_stream(key, |stream| stream.map(|s| s.last_id).unwrap_or_default())
+}
+
+/// One key's part of an XREAD reply: the entries after `after`, or None
+/// when there are none.
+pub fn read_key(
+    db: &Db,
+    key: &str,
+    after: StreamId,
+    count: Option<usize>,
+) -> Result<Option<RespType>, DataStoreError> {
+    let entries = db.read_stream(key, |stream| {
+        stream.map_or(vec![], |s| {
+            s.range(Bound::Excluded(after), Bound::Unbounded, count)
+        })
+    })?;
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let entries = entries
+        .into_iter()
+        .map(|(id, fields)| (id, Some(fields)))
+        .collect();
+    Ok(Some(key_reply(key, entries)))
+}
+
+/// One key's part of an XREADGROUP reply. Reading new entries replies None
+/// when there are none, while reading a consumer's pending entries always
+/// replies, with no entries if need be.
+pub fn read_group_key(
+    db: &Db,
+    key: &str,
+    (group, consumer): (&str, &str),
+    after: Option<StreamId>,
+    count: Option<usize>,
+    noack: bool,
+) -> Result<Option<RespType>, DataStoreError> {
+    let entries = db.xreadgroup(key, group, consumer, after, count, noack)?;
+    if entries.is_empty() && after.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(key_reply(key, entries)))
+}
+
+/// XREAD and XREADGROUP, with `group`, answered right away: BLOCK is left
+/// to the blocking commands. `args` start after the command name.
+pub fn xread(db: &Db, args: &[Bytes], group: bool) -> Result<RespType, UserInputError> {
+    let read = match parse_read(args, group) {
+        Ok(read) => read,
+        Err(e) => return Ok(e),
+    };
+    if let Some(e) = missing_group(db, &read) {
+        return Ok(e);
+    }
+    let mut replies = vec![];
+    for (key, from) in &read.streams {
+        let reply = match (&read.group, from) {
+            (Some((group, consumer)), from) => {
+                let after = match from {
+                    ReadFrom::After(id) => Some(*id),
+                    _ => None,
+                };
+                read_group_key(db, key, (group, consumer), after, read.count, read.noack)
+            }
+            (None, ReadFrom::After(id)) => read_key(db, key, *id, read.count),
+            // nothing comes after the last entry without waiting
+            (None, _) => Ok(None),
+        };
+        match reply {
+            Ok(Some(reply)) => replies.push(reply),
+            Ok(None) => {}
+            Err(e) => return error_reply(e),
+        }
+    }
+    Ok(RespType::Array((!replies.is_empty()).then_some(replies)))
+}
+
+/// XACK key group id [id ...]
+pub fn xack(db: &Db, args: &[Bytes]) -> Result<RespType, UserInputError> {
+    let ids = match args[2..]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(ids) => ids,
+        Err(e) => return Ok(e),
+    };
+    match db.xack(&text(&args[0]), &text(&args[1]), &ids) {
+        Ok(acked) => Ok(RespType::Integer(acked as i64)),
+        Err(e) => error_reply(e),
+    }
+}
+
+const XGROUP_HELP: &[&str] = &[
+    "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CREATE <key> <groupname> <id|$> [option]",
+    "    Create a new consumer group. Options are:",
+    "    * MKSTREAM",
+    "      Create the empty stream if it does not exist.",
+    "CREATECONSUMER <key> <groupname> <consumer>",
+    "    Create a new consumer in the specified group.",
+    "DELCONSUMER <key> <groupname> <consumer>",
+    "    Remove the specified consumer.",
+    "DESTROY <key> <groupname>",
+    "    Remove the specified group.",
+    "SETID <key> <groupname> <id|$>",
+    "    Set the current group ID.",
+    "HELP",
+    "    Print this help.",
+];
+
+/// XGROUP CREATE, CREATECONSUMER, DELCONSUMER, DESTROY, SETID and HELP.
+pub fn xgroup(db: &Db, args: &[Bytes]) -> Result<RespType, UserInputError> {
+    let subcommand = args
+        .first()
+        .map(|a| text(a).to_lowercase())
+        .unwrap_or_default();
+    let arity = match subcommand.as_str() {
+        "help" => 1,
+        "destroy" => 3,
+        "create" | "setid" | "createconsumer" | "delconsumer" => 4,
+        _ => {
+            return Ok(error(&format!(
+                "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+                args.first().map(|a| text(a)).unwrap_or_default()
+            )))
+        }
+    };
+    let extra_ok = matches!(subcommand.as_str(), "create" | "setid");
+    if args.len() < arity || (args.len() > arity && !extra_ok) {
+        return Ok(error(&format!(
+            "ERR wrong number of arguments for 'xgroup|{}' command",
+            subcommand
+        )));
+    }
+    if subcommand == "help" {
+        return Ok(RespType::Array(Some(
+            XGROUP_HELP
+                .iter()
+                .map(|line| RespType::SimpleString(line.to_string()))
+                .collect(),
+        )));
+    }
+    let (key, group) = (text(&args[1]), text(&args[2]));
+    // CREATE and SETID: `$` stands for the last entry
+    let start = || match &args[3][..] {
+        b"$" => Ok(None),
+        id => parse_id(id, 0).map(Some),
+    };
+    let res = match subcommand.as_str() {
+        "create" => {
+            let start = match start() {
+                Ok(start) => start,
+                Err(e) => return Ok(e),
+            };
+            let mut mkstream = false;
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match text(option).to_lowercase().as_str() {
+                    "mkstream" => mkstream = true,
+                    // read counts aren't tracked, only checked
+                    "entriesread"
+                        if options
+                            .next()
+                            .is_some_and(|n| text(n).parse::<i64>().is_ok()) => {}
+                    _ => return Ok(error("ERR syntax error")),
+                }
+            }
+            db.xgroup_create(&key, &group, start, mkstream)
+                .map(|()| RespType::SimpleString("OK".to_string()))
+        }
+        "setid" => {
+            let start = match start() {
+                Ok(start) => start,
+                Err(e) => return Ok(e),
+            };
+            match &args[4..] {
+                [] => {}
+                [option, n]
+                    if option.eq_ignore_ascii_case(b"entriesread")
+                        && text(n).parse::<i64>().is_ok() => {}
+                _ => return Ok(error("ERR syntax error")),
+            }
+            db.xgroup_setid(&key, &group, start)
+                .map(|()| RespType::SimpleString("OK".to_string()))
+        }
+        "destroy" => db
+            .xgroup_destroy(&key, &group)
+            .map(|destroyed| RespType::Integer(destroyed as i64)),
+        "createconsumer" => db
+            .xgroup_createconsumer(&key, &group, &text(&args[3]))
+            .map(|created| RespType::Integer(created as i64)),
+        _ => db
+            .xgroup_delconsumer(&key, &group, &text(&args[3]))
+            .map(|pending| RespType::Integer(pending as i64)),
+    };
+    res.or_else(error_reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::test_helpers::args;
+
+    fn id(s: &str) -> StreamId {
+        StreamId::parse(s, 0).unwrap()
+    }
+
+    // the IDs of the entries in a read reply, per key
+    fn read_ids(reply: &RespType) -> Vec<(String, Vec<String>)> {
+        let text = |r: &RespType| match r {
+            RespType::BulkString(Some(b)) => String::from_utf8_lossy(b).to_string(),
+            _ => panic!("not a bulk string: {:?}", r),
+        };
+        let RespType::Array(Some(keys)) = reply else {
+            return vec![];
+        };
+        keys.iter()
+            .map(|key| match key {
+                RespType::Array(Some(pair)) => match &pair[1] {
+                    RespType::Array(Some(entries)) => (
+                        text(&pair[0]),
+                        entries
+                            .iter()
+                            .map(|e| match e {
+                                RespType::Array(Some(e)) => text(&e[0]),
+                                _ => panic!("not an entry"),
+                            })
+                            .collect(),
+                    ),
+                    _ => panic!("no entries"),
+                },
+                _ => panic!("not a key"),
+            })
+            .collect()
+    }
+
+    fn ids(key: &str, ids: &[&str]) -> (String, Vec<String>) {
+        (key.to_string(), ids.iter().map(|i| i.to_string()).collect())
+    }
+
+    #[test]
+    fn test_next_id() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.next_id(NewId::Auto, 5), Ok(id("5-0")));
+        assert_eq!(stream.next_id(NewId::AutoSeq(0), 5), Ok(id("0-1")));
+        assert!(stream.next_id(NewId::Explicit(id("0-0")), 5).is_err());
+        stream.add(id("5-3"), vec![]);
+        // the clock went back, so the sequence goes on
+        assert_eq!(stream.next_id(NewId::Auto, 4), Ok(id("5-4")));
+        assert_eq!(stream.next_id(NewId::AutoSeq(5), 9), Ok(id("5-4")));
+        assert_eq!(stream.next_id(NewId::AutoSeq(4), 9), Err(ID_TOO_SMALL));
+        assert_eq!(
+            stream.next_id(NewId::Explicit(id("5-3")), 9),
+            Err(ID_TOO_SMALL)
+        );
+        assert_eq!(stream.next_id(NewId::Explicit(id("6")), 9), Ok(id("6-0")));
+    }
+
+    #[test]
+    fn test_xadd_and_xrange() {
+        let db = Db::new(4);
+        let add = |line: &str| xadd(&db, &args(line)[1..]).unwrap();
+        let bulk = |s: &str| RespType::BulkString(Some(Bytes::from(s.to_string())));
+        assert_eq!(add("XADD s 1-1 f v"), bulk("1-1"));
+        assert_eq!(add("XADD s 1-* f v"), bulk("1-2"));
+        assert_eq!(add("XADD s 3 f v g w"), bulk("3-0"));
+        assert_eq!(add("XADD s 2 f v"), error(ID_TOO_SMALL));
+        assert_eq!(
+            add("XADD s 4 f"),
+            error("ERR wrong number of arguments for 'xadd' command")
+        );
+        assert_eq!(add("XADD s x-1 f v"), error(INVALID_ID));
+        assert_eq!(
+            add("XADD missing NOMKSTREAM * f v"),
+            RespType::BulkString(None)
+        );
+        assert_eq!(db.key_type("missing"), None);
+        assert_eq!(xlen(&db, "s").unwrap(), RespType::Integer(3));
+
+        let range = |line: &str| {
+            let reply = xrange(&db, &args(line)[1..]).unwrap();
+            read_ids(&RespType::Array(Some(vec![RespType::Array(Some(vec![
+                bulk("s"),
+                reply,
+            ]))])))
+            .remove(0)
+            .1
+        };
+        assert_eq!(range("XRANGE s - +"), ["1-1", "1-2", "3-0"]);
+        assert_eq!(range("XRANGE s (1-1 3"), ["1-2", "3-0"]);
+        assert_eq!(range("XRANGE s 1 (3-0"), ["1-1", "1-2"]);
+        assert_eq!(range("XRANGE s - + COUNT 1"), ["1-1"]);
+        assert_eq!(range("XRANGE s 3 1"), Vec::<String>::new());
+
+        // trimming keeps the newest entries
+        assert_eq!(add("XADD s MAXLEN 2 4 f v"), bulk("4-0"));
+        assert_eq!(range("XRANGE s - +"), ["3-0", "4-0"]);
+        assert_eq!(add("XADD s MINID = 4 5 f v"), bulk("5-0"));
+        assert_eq!(range("XRANGE s - +"), ["4-0", "5-0"]);
+        assert_eq!(
+            add("XADD s MAXLEN 1 LIMIT 10 * f v"),
+            error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+        );
+
+        db.set("str", Bytes::from("v"), vec![]).unwrap();
+        assert_eq!(add("XADD str * f v"), wrong_type());
+
+        // logged with the ID it got, wherever the options put it
+        let logged = |line: &str, id: &str| {
+            let logged = xadd_aof_form(args(line), &bulk(id));
+            logged
+                .iter()
+                .map(|a| String::from_utf8_lossy(a).to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(logged("XADD s * f v", "6-0"), "XADD s 6-0 f v");
+        assert_eq!(
+            logged("XADD s NOMKSTREAM MAXLEN ~ 5 LIMIT 2 7-* * v", "7-1"),
+            "XADD s NOMKSTREAM MAXLEN ~ 5 LIMIT 2 7-1 * v"
+        );
+    }
+
+    #[test]
+    fn test_xread_and_groups() {
+        let db = Db::new(4);
+        for line in ["XADD a 1 f 1", "XADD a 2 f 2", "XADD b 1 f 1"] {
+            xadd(&db, &args(line)[1..]).unwrap();
+        }
+        let read = |line: &str| {
+            let args = args(line);
+            let group = args[0].eq_ignore_ascii_case(b"xreadgroup");
+            xread(&db, &args[1..], group).unwrap()
+        };
+        assert_eq!(
+            read_ids(&read("XREAD STREAMS a b 1 0")),
+            [ids("a", &["2-0"]), ids("b", &["1-0"])]
+        );
+        assert_eq!(
+            read_ids(&read("XREAD COUNT 1 STREAMS a 0")),
+            [ids("a", &["1-0"])]
+        );
+        assert_eq!(read("XREAD STREAMS a $"), RespType::Array(None));
+        assert_eq!(
+            read("XREAD STREAMS a b 0"),
+            error("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.")
+        );
+
+        let group = |line: &str| xgroup(&db, &args(line)[1..]).unwrap();
+        let ok = RespType::SimpleString("OK".to_string());
+        assert_eq!(group("XGROUP CREATE a g 0"), ok);
+        assert_eq!(
+            group("XGROUP CREATE a g 0"),
+            error("BUSYGROUP Consumer Group name already exists")
+        );
+        assert_eq!(
+            read("XREADGROUP GROUP g c STREAMS a b > >"),
+            error(&no_group("b", "g"))
+        );
+        assert_eq!(
+            read_ids(&read("XREADGROUP GROUP g c COUNT 1 STREAMS a >")),
+            [ids("a", &["1-0"])]
+        );
+        assert_eq!(
+            read_ids(&read("XREADGROUP GROUP g d STREAMS a >")),
+            [ids("a", &["2-0"])]
+        );
+        assert_eq!(
+            read("XREADGROUP GROUP g d STREAMS a >"),
+            RespType::Array(None)
+        );
+        // each consumer's history is its own pending entries
+        assert_eq!(
+            read_ids(&read("XREADGROUP GROUP g c STREAMS a 0")),
+            [ids("a", &["1-0"])]
+        );
+        assert_eq!(
+            xack(&db, &args("XACK a g 1-0 2-0 9-0")[1..]).unwrap(),
+            RespType::Integer(2)
+        );
+        assert_eq!(
+            read_ids(&read("XREADGROUP GROUP g c STREAMS a 0")),
+            [ids("a", &[])]
+        );
+
+        // SETID rewinds the group, so entries are delivered again
+        assert_eq!(group("XGROUP SETID a g 0"), ok);
+        assert_eq!(
+            read_ids(&read("XREADGROUP GROUP g c NOACK STREAMS a >")),
+            [ids("a", &["1-0", "2-0"])]
+        );
+        assert_eq!(group("XGROUP CREATECONSUMER a g e"), RespType::Integer(1));
+        assert_eq!(group("XGROUP DELCONSUMER a g e"), RespType::Integer(0));
+        assert_eq!(group("XGROUP DESTROY a g"), RespType::Integer(1));
+        assert_eq!(group("XGROUP DESTROY a g"), RespType::Integer(0));
+        assert_eq!(
+            group("XGROUP CREATE new g $"),
+            error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")
+        );
+        assert_eq!(group("XGROUP CREATE new g $ MKSTREAM"), ok);
+        assert_eq!(xlen(&db, "new").unwrap(), RespType::Integer(0));
+    }
+
+    #[test]
+    fn test_parse_read() {
+        let parse = |line: &str, group: bool| parse_read(&args(line), group);
+        let read = parse("COUNT 5 BLOCK 0 STREAMS a b $ 1-2", false).unwrap();
+        assert_eq!(read.count, Some(5));
+        assert_eq!(read.block, Some(0));
+        assert_eq!(
+            read.streams,
+            [
+                ("a".to_string(), ReadFrom::Last),
+                ("b".to_string(), ReadFrom::After(id("1-2")))
+            ]
+        );
+        let read = parse("GROUP g c NOACK STREAMS a >", true).unwrap();
+        assert_eq!(read.group, Some(("g".to_string(), "c".to_string())));
+        assert!(read.noack);
+        assert_eq!(read.streams, [("a".to_string(), ReadFrom::New)]);
+
+        assert_eq!(
+            parse("BLOCK -1 STREAMS a $", false),
+            Err(error("ERR timeout is negative"))
+        );
+        assert_eq!(
+            parse("STREAMS a", false),
+            Err(error(
+                "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+            ))
+        );
+        assert_eq!(
+            parse("STREAMS a >", true),
+            Err(error("ERR Missing GROUP option for XREADGROUP"))
+        );
+        assert!(parse("GROUP g c STREAMS a $", true).is_err());
+        assert!(parse("STREAMS a >", false).is_err());
+        assert!(parse("NOACK STREAMS a 0", false).is_err());
+        assert_eq!(parse("STREAMS a x", false), Err(error(INVALID_ID)));
+    }
+}
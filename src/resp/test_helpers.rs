@@ -0,0 +1,26 @@
+use bytes::Bytes;
+
+use super::{client::Client, datastore::Db, resp_value::RespType, server};
+
+/// The arguments of a command line, split on single spaces.
+pub fn args(line: &str) -> Vec<Bytes> {
+    line.split(' ')
+        .map(|a| Bytes::copy_from_slice(a.as_bytes()))
+        .collect()
+}
+
+/// The replies the server sends `client` for a command line.
+pub fn send(client: &mut Client, db: &mut Db, line: &str) -> Vec<RespType> {
+    let request = args(line)
+        .into_iter()
+        .map(|a| RespType::BulkString(Some(a)))
+        .collect();
+    server::reply(RespType::Array(Some(request)), client, db).unwrap()
+}
+
+/// The reply to a command line that gets exactly one.
+pub fn send_one(client: &mut Client, db: &mut Db, line: &str) -> RespType {
+    let mut replies = send(client, db, line);
+    assert_eq!(replies.len(), 1, "{}: {:?}", line, replies);
+    replies.remove(0)
+}
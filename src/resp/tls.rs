@@ -0,0 +1,320 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        version::{TLS12, TLS13},
+        RootCertStore, ServerConfig, SupportedProtocolVersion,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use super::redisconfig::{self, Config};
+
+lazy_static! {
+    /// Set up from the tls-* directives while `tls-port` is on; replaced
+    /// when CONFIG SET changes any of them, so new certificates are used
+    /// by the connections that come after.
+    static ref ACCEPTOR: RwLock<Option<TlsAcceptor>> = RwLock::new(None);
+}
+
+/// Reads the certificates and settings from the running configuration and
+/// uses them for new TLS connections. Nothing is set up while `tls-port` is
+/// off, and on failure the previous setup stays.
+pub fn configure() -> Result<(), String> {
+    let config = redisconfig::config();
+    let acceptor = match config.get("tls-port") {
+        Some(port) if port != "0" => Some(build_acceptor(&config)?),
+        _ => None,
+    };
+    *ACCEPTOR.write() = acceptor;
+    Ok(())
+}
+
+/// Runs the TLS handshake with a client that connected to the TLS port.
+pub async fn accept(stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+    let acceptor = ACCEPTOR
+        .read()
+        .clone()
+        .ok_or_else(|| io::Error::other("TLS is not configured"))?;
+    acceptor.accept(stream).await
+}
+
+fn build_acceptor(config: &Config) -> Result<TlsAcceptor, String> {
+    let setting = |name: &str| config.get(name).unwrap_or_default().to_string();
+    let cert_file = setting("tls-cert-file");
+    let key_file = setting("tls-key-file");
+    if cert_file.is_empty() || key_file.is_empty() {
+        return Err("tls-cert-file and tls-key-file must be specified".to_string());
+    }
+    if !setting("tls-key-file-pass").is_empty() {
+        return Err("encrypted private keys (tls-key-file-pass) are not supported".to_string());
+    }
+    let certs = read_certs(Path::new(&cert_file))
+        .map_err(|e| format!("Failed to load certificate: {}: {}", cert_file, e))?;
+    let key = read_key(Path::new(&key_file))
+        .map_err(|e| format!("Failed to load private key: {}: {}", key_file, e))?;
+    let versions = protocol_versions(&setting("tls-protocols"))?;
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_protocol_versions(&versions)
+        .map_err(|e| e.to_string())?;
+    let auth_clients = setting("tls-auth-clients");
+    let builder = if auth_clients == "no" {
+        builder.with_no_client_auth()
+    } else {
+        let roots = read_ca_certs(&setting("tls-ca-cert-file"), &setting("tls-ca-cert-dir"))?;
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(ring::default_provider()),
+        );
+        let verifier = if auth_clients == "optional" {
+            verifier.allow_unauthenticated()
+        } else {
+            verifier
+        };
+        builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+    };
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Failed to load private key: {}: {}", key_file, e))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let pem = fs::read(path)?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice()).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::other("no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let pem = fs::read(path)?;
+    rustls_pemfile::private_key(&mut pem.as_slice())?
+        .ok_or_else(|| io::Error::other("no private key found"))
+}
+
+// the CAs client certificates are checked against: the certificates in the
+// file and in the files of the directory, where other files are skipped
+fn read_ca_certs(file: &str, dir: &str) -> Result<RootCertStore, String> {
+    if file.is_empty() && dir.is_empty() {
+        return Err(
+            "Either tls-ca-cert-file or tls-ca-cert-dir must be specified when tls-auth-clients is enabled!"
+                .to_string(),
+        );
+    }
+    let error = |e: io::Error| {
+        format!(
+            "Failed to configure CA certificate(s) file/directory: {}",
+            e
+        )
+    };
+    let mut certs = vec![];
+    if !file.is_empty() {
+        certs.extend(read_certs(Path::new(file)).map_err(error)?);
+    }
+    if !dir.is_empty() {
+        for entry in fs::read_dir(dir).map_err(error)? {
+            let path = entry.map_err(error)?.path();
+            if path.is_file() {
+                certs.extend(read_certs(&path).unwrap_or_default());
+            }
+        }
+    }
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(cert).map_err(|e| error(io::Error::other(e)))?;
+    }
+    if roots.is_empty() {
+        return Err(error(io::Error::other("no certificates found")));
+    }
+    Ok(roots)
+}
+
+/// Reads `tls-protocols`, e.g. "TLSv1.2 TLSv1.3"; empty means both.
+fn protocol_versions(value: &str) -> Result<Vec<&'static SupportedProtocolVersion>, String> {
+    if value.trim().is_empty() {
+        return Ok(vec![&TLS12, &TLS13]);
+    }
+    value
+        .split_whitespace()
+        .map(|protocol| match protocol.to_lowercase().as_str() {
+            "tlsv1.2" => Ok(&TLS12),
+            "tlsv1.3" => Ok(&TLS13),
+            "tlsv1" | "tlsv1.1" => Err(format!("{} is not supported by this server", protocol)),
+            _ => Err(
+                "Invalid tls-protocols specified. Use a combination of 'TLSv1.2' and 'TLSv1.3'."
+                    .to_string(),
+            ),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{datastore::Db, server};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig},
+        TlsConnector,
+    };
+
+    struct Pki {
+        dir: std::path::PathBuf,
+        ca: CertifiedKey,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Pki {
+            let dir =
+                std::env::temp_dir().join(format!("tls-test-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let key_pair = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key_pair).unwrap();
+            fs::write(dir.join("ca.crt"), cert.pem()).unwrap();
+            Pki {
+                dir,
+                ca: CertifiedKey { cert, key_pair },
+            }
+        }
+
+        // writes a certificate signed by the CA as <name>.crt and <name>.key
+        fn issue(&self, name: &str) -> CertifiedKey {
+            let key_pair = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+            let cert = params
+                .signed_by(&key_pair, &self.ca.cert, &self.ca.key_pair)
+                .unwrap();
+            fs::write(self.dir.join(format!("{}.crt", name)), cert.pem()).unwrap();
+            fs::write(
+                self.dir.join(format!("{}.key", name)),
+                key_pair.serialize_pem(),
+            )
+            .unwrap();
+            CertifiedKey { cert, key_pair }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.join(name).display().to_string()
+        }
+
+        fn config(&self, auth_clients: &str) -> Config {
+            let text = format!(
+                "tls-port 6380\ntls-cert-file {}\ntls-key-file {}\ntls-ca-cert-file {}\ntls-auth-clients {}\n",
+                self.path("server.crt"),
+                self.path("server.key"),
+                self.path("ca.crt"),
+                auth_clients
+            );
+            Config::parse(&text, "test.conf").unwrap()
+        }
+
+        fn connector(&self, client: Option<&CertifiedKey>) -> TlsConnector {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.ca.cert.der().clone()).unwrap();
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+            let config = match client {
+                Some(client) => builder
+                    .with_client_auth_cert(
+                        vec![client.cert.der().clone()],
+                        PrivateKeyDer::try_from(client.key_pair.serialize_der()).unwrap(),
+                    )
+                    .unwrap(),
+                None => builder.with_no_client_auth(),
+            };
+            TlsConnector::from(Arc::new(config))
+        }
+    }
+
+    // serves one client over TLS and returns the reply to PING, if any
+    async fn ping(acceptor: TlsAcceptor, connector: TlsConnector) -> Option<Vec<u8>> {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = tcp.accept().await.unwrap();
+            if let Ok(stream) = acceptor.accept(stream).await {
+                server::process(stream, Db::new(1)).await;
+            }
+        });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, stream).await.ok()?;
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.ok()?;
+        let mut reply = vec![0; 64];
+        let n = stream.read(&mut reply).await.ok()?;
+        reply.truncate(n);
+        Some(reply)
+    }
+
+    #[tokio::test]
+    async fn test_tls_client_auth() {
+        let pki = Pki::new("auth");
+        pki.issue("server");
+        let client = pki.issue("client");
+
+        let acceptor = build_acceptor(&pki.config("yes")).unwrap();
+        let reply = ping(acceptor.clone(), pki.connector(Some(&client))).await;
+        assert_eq!(reply.as_deref(), Some(&b"+PONG\r\n"[..]));
+        // without a client certificate the handshake, or the first read
+        // under TLS 1.3, fails
+        let reply = ping(acceptor, pki.connector(None)).await;
+        assert!(reply.is_none_or(|r| r.is_empty()));
+
+        let acceptor = build_acceptor(&pki.config("optional")).unwrap();
+        let reply = ping(acceptor, pki.connector(None)).await;
+        assert_eq!(reply.as_deref(), Some(&b"+PONG\r\n"[..]));
+        fs::remove_dir_all(&pki.dir).unwrap();
+    }
+
+    #[test]
+    fn test_tls_config_errors() {
+        let pki = Pki::new("errors");
+        pki.issue("server");
+        let mut config = pki.config("yes");
+        config.set("tls-ca-cert-file", &["".to_string()]).unwrap();
+        assert!(build_acceptor(&config)
+            .err()
+            .unwrap()
+            .starts_with("Either tls-ca-cert-file or tls-ca-cert-dir"));
+        // the directory also holds the keys, which are skipped
+        config
+            .set("tls-ca-cert-dir", &[pki.dir.display().to_string()])
+            .unwrap();
+        assert!(build_acceptor(&config).is_ok());
+        fs::remove_file(pki.dir.join("server.key")).unwrap();
+        assert!(build_acceptor(&config)
+            .err()
+            .unwrap()
+            .starts_with("Failed to load private key"));
+        pki.issue("server");
+        config
+            .set("tls-protocols", &["TLSv1.3".to_string()])
+            .unwrap();
+        assert!(build_acceptor(&config).is_ok());
+        config
+            .set("tls-protocols", &["TLSv1.1".to_string()])
+            .unwrap();
+        assert_eq!(
+            build_acceptor(&config).err(),
+            Some("TLSv1.1 is not supported by this server".to_string())
+        );
+        fs::remove_dir_all(&pki.dir).unwrap();
+    }
+}
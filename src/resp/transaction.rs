@@ -0,0 +1,260 @@
+use bytes::Bytes;
+
+use super::{
+    blocking, client::Client, commands, datastore::Db, pubsub, redisconfig, resp_value::RespType,
+};
+
+/// The commands a client queued after MULTI.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<Vec<Bytes>>,
+    /// set when a command could not be queued; EXEC then refuses to run
+    pub aborted: bool,
+}
+
+fn ok() -> RespType {
+    RespType::SimpleString("OK".to_string())
+}
+
+fn error(msg: &str) -> RespType {
+    RespType::Error(msg.to_string())
+}
+
+/// Answers MULTI, EXEC, DISCARD, WATCH and UNWATCH, after the client has
+/// checked them like any other command. Returns None for other commands.
+pub fn transaction_command(client: &mut Client, args: &[Bytes], db: &mut Db) -> Option<RespType> {
+    let name = redisconfig::resolve_command_name(&String::from_utf8_lossy(&args[0]))?;
+    let reply = match name.as_str() {
+        "multi" if client.transaction.is_some() => error("ERR MULTI calls can not be nested"),
+        "multi" => {
+            client.transaction = Some(Transaction::default());
+            ok()
+        }
+        "exec" => exec(client, db),
+        "discard" => match client.transaction.take() {
+            Some(_) => {
+                client.watched.clear();
+                ok()
+            }
+            None => error("ERR DISCARD without MULTI"),
+        },
+        "watch" => {
+            for key in &args[1..] {
+                let watched = db.watch(&String::from_utf8_lossy(key));
+                client.watched.push(watched);
+            }
+            ok()
+        }
+        "unwatch" => {
+            client.watched.clear();
+            ok()
+        }
+        _ => return None,
+    };
+    Some(reply)
+}
+
+// Runs the queued commands with the exec lock held exclusively, so no other
+// client's command lands in between. The watched keys are checked under the
+// same lock: if any of them changed since WATCH, nothing runs and the reply
+// is a nil array.
+fn exec(client: &mut Client, db: &mut Db) -> RespType {
+    let Some(transaction) = client.transaction.take() else {
+        return error("ERR EXEC without MULTI");
+    };
+    let watched = std::mem::take(&mut client.watched);
+    if transaction.aborted {
+        return error("EXECABORT Transaction discarded because of previous errors.");
+    }
+    let exec_lock = db.exec_lock();
+    let _exclusive = exec_lock.write();
+    if watched.iter().any(|key| db.is_touched(key)) {
+        return RespType::Array(None);
+    }
+    let replies = transaction
+        .commands
+        .into_iter()
+        .map(|args| {
+            client
+                .connection_command(&args)
+                .or_else(|| client.client_command(&args, db))
+                .or_else(|| transaction_command(client, &args, db))
+                // subscribing can't be queued, so there is one reply
+                .or_else(|| pubsub::pubsub_command(client, &args, db)?.pop())
+                // and blocking commands don't block in a transaction
+                .or_else(|| blocking::blocking_command(client, &args, db, false)?.pop())
+                .unwrap_or_else(|| {
+                    commands::execute_exclusive(args, db)
+                        .unwrap_or_else(|e| RespType::Error(format!("ERR {}", e)))
+                })
+        })
+        .collect();
+    // clients blocked on keys the transaction pushed to
+    db.blocking().serve(db);
+    RespType::Array(Some(replies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resp::{resp_value::bulk, test_helpers::send_one};
+
+    #[test]
+    fn test_multi_exec() {
+        let mut db = Db::new(4);
+        let mut client = Client::new();
+        assert_eq!(send_one(&mut client, &mut db, "MULTI"), ok());
+        assert_eq!(
+            send_one(&mut client, &mut db, "MULTI"),
+            error("ERR MULTI calls can not be nested")
+        );
+        let queued = RespType::SimpleString("QUEUED".to_string());
+        assert_eq!(send_one(&mut client, &mut db, "SET k v"), queued);
+        assert_eq!(send_one(&mut client, &mut db, "GET k"), queued);
+        assert_eq!(send_one(&mut client, &mut db, "HGET k f"), queued);
+        assert_eq!(db.key_type("k"), None);
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(Some(vec![
+                ok(),
+                bulk("v"),
+                RespType::Error(
+                    "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+                ),
+            ]))
+        );
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            error("ERR EXEC without MULTI")
+        );
+
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "DEL k");
+        assert_eq!(send_one(&mut client, &mut db, "DISCARD"), ok());
+        assert_eq!(db.key_type("k"), Some("string"));
+        assert_eq!(
+            send_one(&mut client, &mut db, "DISCARD"),
+            error("ERR DISCARD without MULTI")
+        );
+    }
+
+    #[test]
+    fn test_queueing_errors_abort() {
+        let mut db = Db::new(4);
+        let mut client = Client::new();
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "SET k v");
+        assert_eq!(
+            send_one(&mut client, &mut db, "GET"),
+            error("ERR wrong number of arguments for 'get' command")
+        );
+        assert_eq!(
+            send_one(&mut client, &mut db, "NOSUCHCOMMAND x"),
+            error("ERR Unknown command: NOSUCHCOMMAND x")
+        );
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            error("EXECABORT Transaction discarded because of previous errors.")
+        );
+        assert_eq!(db.key_type("k"), None);
+
+        send_one(&mut client, &mut db, "MULTI");
+        assert_eq!(
+            send_one(&mut client, &mut db, "WATCH k"),
+            error("ERR Command not allowed inside a transaction")
+        );
+        assert_eq!(
+            send_one(&mut client, &mut db, "MIGRATE 127.0.0.1 6379 k 0 1000"),
+            error("ERR Command not allowed inside a transaction")
+        );
+        assert!(matches!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Error(e) if e.starts_with("EXECABORT")
+        ));
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut db = Db::new(4);
+        let mut client = Client::new();
+        let mut other = Client::new();
+        send_one(&mut client, &mut db, "SET stock 5");
+
+        // a change by another client fails the transaction
+        send_one(&mut client, &mut db, "WATCH stock");
+        send_one(&mut other, &mut db, "SET stock 4");
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "SET stock 3");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(None)
+        );
+        assert_eq!(db.get("stock").unwrap(), Bytes::from("4"));
+
+        // EXEC forgot the watched keys, so this one runs
+        send_one(&mut other, &mut db, "SET stock 4");
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "SET stock 3");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(Some(vec![ok()]))
+        );
+
+        // a write counts even when it stores the same value again
+        send_one(&mut client, &mut db, "WATCH stock");
+        send_one(&mut other, &mut db, "SET stock 3");
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "SET stock 2");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(None)
+        );
+
+        // writes that are refused or find nothing to change, and the
+        // client's own commands inside the transaction, don't count
+        send_one(&mut client, &mut db, "HSET h f v");
+        send_one(&mut client, &mut db, "WATCH stock h other");
+        assert!(matches!(
+            send_one(&mut other, &mut db, "HSET stock f v"),
+            RespType::Error(e) if e.starts_with("WRONGTYPE")
+        ));
+        send_one(&mut other, &mut db, "HDEL h nosuch");
+        send_one(&mut other, &mut db, "DEL other");
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "SET stock 2");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(Some(vec![ok()]))
+        );
+
+        send_one(&mut client, &mut db, "WATCH stock");
+        send_one(&mut client, &mut db, "UNWATCH");
+        send_one(&mut other, &mut db, "SET stock 1");
+        send_one(&mut client, &mut db, "MULTI");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(Some(vec![]))
+        );
+        assert_eq!(db.watched_keys(), 0);
+    }
+
+    #[test]
+    fn test_watched_key_expires() {
+        let mut db = Db::new(4);
+        let mut client = Client::new();
+        send_one(&mut client, &mut db, "SET k v PX 20");
+        send_one(&mut client, &mut db, "WATCH k");
+        std::thread::sleep(std::time::Duration::from_millis(40));
+        send_one(&mut client, &mut db, "MULTI");
+        send_one(&mut client, &mut db, "GET k");
+        assert_eq!(
+            send_one(&mut client, &mut db, "EXEC"),
+            RespType::Array(None)
+        );
+
+        // dropping the client, as when it disconnects, stops the watch
+        send_one(&mut client, &mut db, "WATCH k");
+        drop(client);
+        assert_eq!(db.watched_keys(), 0);
+    }
+}
@@ -0,0 +1,555 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use super::errors::UserInputError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    Cosine,
+    L2,
+    Ip,
+}
+
+impl DistanceMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DistanceMetric::Cosine => "COSINE",
+            DistanceMetric::L2 => "L2",
+            DistanceMetric::Ip => "IP",
+        }
+    }
+
+    /// Smaller is closer for every metric, matching the scores redis reports:
+    /// squared euclidean distance for L2, `1 - a.b` for IP and `1 - cos` for COSINE.
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceMetric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum(),
+            DistanceMetric::Ip => 1.0 - dot(a, b),
+            DistanceMetric::Cosine => {
+                let norm = (dot(a, a) * dot(b, b)).sqrt();
+                if norm == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot(a, b) / norm
+                }
+            }
+        }
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VectorType {
+    Float32,
+    Float64,
+}
+
+impl VectorType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VectorType::Float32 => "FLOAT32",
+            VectorType::Float64 => "FLOAT64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Flat,
+    Hnsw {
+        m: usize,
+        ef_construction: usize,
+        ef_runtime: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorSpec {
+    pub algorithm: Algorithm,
+    pub data_type: VectorType,
+    pub dim: usize,
+    pub metric: DistanceMetric,
+}
+
+fn invalid(msg: &str) -> UserInputError {
+    UserInputError::InvalidInput(msg.to_string())
+}
+
+impl VectorSpec {
+    // VECTOR {FLAT | HNSW} count TYPE t DIM d DISTANCE_METRIC m [M m] [EF_CONSTRUCTION e] ...
+    // `args` starts at the algorithm name; returns the spec and how many args were consumed.
+    pub fn parse(args: &[String]) -> Result<(Self, usize), UserInputError> {
+        let algorithm = args
+            .first()
+            .ok_or(invalid("No algorithm provided for VECTOR field"))?
+            .to_uppercase();
+        let count: usize = args
+            .get(1)
+            .and_then(|n| n.parse().ok())
+            .ok_or(invalid("Expected an attribute count for VECTOR field"))?;
+        if !count.is_multiple_of(2) || args.len() < 2 + count {
+            return Err(invalid("Bad number of VECTOR attributes"));
+        }
+        let (mut data_type, mut dim, mut metric) = (None, None, None);
+        let (mut m, mut ef_construction, mut ef_runtime) = (16, 200, 10);
+        for pair in args[2..2 + count].chunks(2) {
+            let value = &pair[1];
+            let number = || -> Result<usize, UserInputError> {
+                value
+                    .parse()
+                    .map_err(|_| invalid(&format!("Bad value for {}: {}", pair[0], value)))
+            };
+            match pair[0].to_uppercase().as_str() {
+                "TYPE" => {
+                    data_type = Some(match value.to_uppercase().as_str() {
+                        "FLOAT32" => VectorType::Float32,
+                        "FLOAT64" => VectorType::Float64,
+                        other => {
+                            return Err(invalid(&format!("Unsupported vector type: {}", other)))
+                        }
+                    })
+                }
+                "DIM" => dim = Some(number()?),
+                "DISTANCE_METRIC" => {
+                    metric = Some(match value.to_uppercase().as_str() {
+                        "COSINE" => DistanceMetric::Cosine,
+                        "L2" => DistanceMetric::L2,
+                        "IP" => DistanceMetric::Ip,
+                        other => {
+                            return Err(invalid(&format!("Unknown distance metric: {}", other)))
+                        }
+                    })
+                }
+                "M" => m = number()?,
+                "EF_CONSTRUCTION" => ef_construction = number()?,
+                "EF_RUNTIME" => ef_runtime = number()?,
+                "INITIAL_CAP" | "BLOCK_SIZE" | "EPSILON" => {}
+                other => return Err(invalid(&format!("Unknown VECTOR attribute: {}", other))),
+            }
+        }
+        let algorithm = match algorithm.as_str() {
+            "FLAT" => Algorithm::Flat,
+            "HNSW" => Algorithm::Hnsw {
+                m: m.max(2),
+                ef_construction: ef_construction.max(1),
+                ef_runtime: ef_runtime.max(1),
+            },
+            other => return Err(invalid(&format!("Unknown vector algorithm: {}", other))),
+        };
+        let spec = Self {
+            algorithm,
+            data_type: data_type.ok_or(invalid("Missing TYPE for VECTOR field"))?,
+            dim: dim
+                .filter(|d| *d > 0)
+                .ok_or(invalid("Missing DIM for VECTOR field"))?,
+            metric: metric.ok_or(invalid("Missing DISTANCE_METRIC for VECTOR field"))?,
+        };
+        Ok((spec, 2 + count))
+    }
+
+    /// Decodes a little-endian blob as stored by clients, e.g. numpy's `tobytes()`.
+    pub fn decode(&self, blob: &[u8]) -> Option<Vec<f32>> {
+        let width = match self.data_type {
+            VectorType::Float32 => 4,
+            VectorType::Float64 => 8,
+        };
+        if blob.len() != self.dim * width {
+            return None;
+        }
+        let vector = blob
+            .chunks_exact(width)
+            .map(|c| match self.data_type {
+                VectorType::Float32 => f32::from_le_bytes(c.try_into().unwrap()),
+                VectorType::Float64 => f64::from_le_bytes(c.try_into().unwrap()) as f32,
+            })
+            .collect::<Vec<_>>();
+        vector.iter().all(|x| x.is_finite()).then_some(vector)
+    }
+}
+
+/// Distance paired with a node id, ordered by distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate(f32, usize);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    key: String,
+    vector: Vec<f32>,
+    // neighbours per layer, from 0 up to the node's level
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Hierarchical navigable small world graph. Removed vectors are tombstoned
+/// and the graph is rebuilt once tombstones outnumber live nodes.
+#[derive(Debug)]
+struct Hnsw {
+    m: usize,
+    ef_construction: usize,
+    metric: DistanceMetric,
+    nodes: Vec<Node>,
+    entry: Option<usize>,
+    live: usize,
+    rng: u64,
+}
+
+impl Hnsw {
+    fn new(m: usize, ef_construction: usize, metric: DistanceMetric) -> Self {
+        Self {
+            m,
+            ef_construction,
+            metric,
+            nodes: vec![],
+            entry: None,
+            live: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.m * 2
+        } else {
+            self.m
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift is plenty for picking levels and keeps builds reproducible
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.m as f64).ln();
+        (-(1.0 - uniform).ln() * ml).floor() as usize
+    }
+
+    fn dist(&self, query: &[f32], id: usize) -> f32 {
+        self.metric.distance(query, &self.nodes[id].vector)
+    }
+
+    /// Best-first search of one layer, returning up to `ef` closest nodes sorted by distance.
+    fn search_layer(&self, query: &[f32], entry: usize, ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited = HashSet::from([entry]);
+        let first = Candidate(self.dist(query, entry), entry);
+        let mut to_visit = BinaryHeap::from([Reverse(first)]);
+        let mut best = BinaryHeap::from([first]);
+        while let Some(Reverse(current)) = to_visit.pop() {
+            if best.len() >= ef && current.0 > best.peek().unwrap().0 {
+                break;
+            }
+            for &next in self.nodes[current.1].links[layer].iter() {
+                if !visited.insert(next) {
+                    continue;
+                }
+                let candidate = Candidate(self.dist(query, next), next);
+                if best.len() < ef || candidate.0 < best.peek().unwrap().0 {
+                    to_visit.push(Reverse(candidate));
+                    best.push(candidate);
+                    if best.len() > ef {
+                        best.pop();
+                    }
+                }
+            }
+        }
+        best.into_sorted_vec()
+    }
+
+    fn insert(&mut self, key: String, vector: Vec<f32>) {
+        let level = self.random_level();
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            key,
+            vector,
+            links: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.live += 1;
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(id);
+            return;
+        };
+        let top = self.nodes[entry].links.len() - 1;
+        let query = self.nodes[id].vector.clone();
+        for layer in (level + 1..=top).rev() {
+            entry = self.search_layer(&query, entry, 1, layer)[0].1;
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, entry, self.ef_construction, layer);
+            let neighbours: Vec<usize> = found.iter().take(self.m).map(|c| c.1).collect();
+            for &n in neighbours.iter() {
+                self.nodes[n].links[layer].push(id);
+                if self.nodes[n].links[layer].len() > self.max_links(layer) {
+                    self.prune(n, layer);
+                }
+            }
+            self.nodes[id].links[layer] = neighbours;
+            entry = found[0].1;
+        }
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    // keep only the closest links of an overfull node
+    fn prune(&mut self, id: usize, layer: usize) {
+        let vector = &self.nodes[id].vector;
+        let mut links: Vec<Candidate> = self.nodes[id].links[layer]
+            .iter()
+            .map(|&n| Candidate(self.metric.distance(vector, &self.nodes[n].vector), n))
+            .collect();
+        links.sort();
+        links.truncate(self.max_links(layer));
+        self.nodes[id].links[layer] = links.into_iter().map(|c| c.1).collect();
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.nodes[id].deleted = true;
+        self.live -= 1;
+        if self.nodes.len() > 2 * self.live.max(16) {
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.entry = None;
+        self.live = 0;
+        for node in nodes.into_iter().filter(|n| !n.deleted) {
+            self.insert(node.key, node.vector);
+        }
+    }
+
+    fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        let Some(mut entry) = self.entry else {
+            return vec![];
+        };
+        let top = self.nodes[entry].links.len() - 1;
+        for layer in (1..=top).rev() {
+            entry = self.search_layer(query, entry, 1, layer)[0].1;
+        }
+        self.search_layer(query, entry, ef.max(k), 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.1].deleted && allowed(&self.nodes[c.1].key))
+            .take(k)
+            .map(|c| (self.nodes[c.1].key.clone(), c.0))
+            .collect()
+    }
+}
+
+/// Vectors of one VECTOR field, keyed by the hash they were read from.
+#[derive(Debug)]
+pub struct VectorIndex {
+    spec: VectorSpec,
+    vectors: HashMap<String, usize>,
+    hnsw: Option<Hnsw>,
+    flat: Vec<(String, Vec<f32>)>,
+}
+
+impl VectorIndex {
+    pub fn new(spec: VectorSpec) -> Self {
+        let hnsw = match spec.algorithm {
+            Algorithm::Hnsw {
+                m, ef_construction, ..
+            } => Some(Hnsw::new(m, ef_construction, spec.metric)),
+            Algorithm::Flat => None,
+        };
+        Self {
+            spec,
+            vectors: HashMap::new(),
+            hnsw,
+            flat: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, vector: Vec<f32>) {
+        self.remove(key);
+        match self.hnsw.as_mut() {
+            Some(hnsw) => {
+                self.vectors.insert(key.to_string(), hnsw.nodes.len());
+                hnsw.insert(key.to_string(), vector);
+            }
+            None => {
+                self.vectors.insert(key.to_string(), self.flat.len());
+                self.flat.push((key.to_string(), vector));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let Some(pos) = self.vectors.remove(key) else {
+            return;
+        };
+        match self.hnsw.as_mut() {
+            Some(hnsw) => {
+                let before = hnsw.nodes.len();
+                hnsw.remove(pos);
+                // a rebuild renumbers nodes
+                if hnsw.nodes.len() != before {
+                    self.reindex();
+                }
+            }
+            None => {
+                self.flat.swap_remove(pos);
+                if let Some((moved, _)) = self.flat.get(pos) {
+                    self.vectors.insert(moved.clone(), pos);
+                }
+            }
+        }
+    }
+
+    fn reindex(&mut self) {
+        if let Some(hnsw) = self.hnsw.as_ref() {
+            self.vectors = hnsw
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| !n.deleted)
+                .map(|(i, n)| (n.key.clone(), i))
+                .collect();
+        }
+    }
+
+    pub fn spec(&self) -> &VectorSpec {
+        &self.spec
+    }
+
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+
+    /// k nearest neighbours among the keys accepted by `allowed`, closest first.
+    /// Small candidate sets are scanned directly; otherwise the HNSW graph is
+    /// searched, falling back to a scan if the filter starved the graph search.
+    pub fn knn(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef_runtime: Option<usize>,
+        allowed: Option<&HashSet<&str>>,
+    ) -> Vec<(String, f32)> {
+        let is_allowed = |key: &str| allowed.is_none_or(|a| a.contains(key));
+        if let (Some(hnsw), Algorithm::Hnsw { ef_runtime: ef, .. }) =
+            (self.hnsw.as_ref(), self.spec.algorithm)
+        {
+            let filtered = allowed.map_or(self.len(), |a| a.len());
+            if filtered * 10 >= self.len() {
+                let res = hnsw.search(query, k, ef_runtime.unwrap_or(ef), &is_allowed);
+                if res.len() >= k.min(filtered) {
+                    return res;
+                }
+            }
+        }
+        let mut scored: Vec<(String, f32)> = self
+            .iter()
+            .filter(|(key, _)| is_allowed(key))
+            .map(|(key, v)| (key.to_string(), self.spec.metric.distance(query, v)))
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&str, &Vec<f32>)> + '_> {
+        match self.hnsw.as_ref() {
+            Some(hnsw) => Box::new(
+                hnsw.nodes
+                    .iter()
+                    .filter(|n| !n.deleted)
+                    .map(|n| (n.key.as_str(), &n.vector)),
+            ),
+            None => Box::new(self.flat.iter().map(|(k, v)| (k.as_str(), v))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(algorithm: &str, metric: &str) -> VectorSpec {
+        let args: Vec<String> = format!(
+            "{} 6 TYPE FLOAT32 DIM 2 DISTANCE_METRIC {}",
+            algorithm, metric
+        )
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect();
+        VectorSpec::parse(&args).unwrap().0
+    }
+
+    #[test]
+    fn test_distance_metrics() {
+        let (a, b) = ([1.0, 0.0], [0.0, 2.0]);
+        assert_eq!(DistanceMetric::L2.distance(&a, &b), 5.0);
+        assert_eq!(DistanceMetric::Ip.distance(&a, &b), 1.0);
+        assert_eq!(DistanceMetric::Cosine.distance(&a, &b), 1.0);
+        assert!(DistanceMetric::Cosine.distance(&a, &[3.0, 0.0]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_decode_blob() {
+        let spec = spec("FLAT", "L2");
+        let blob: Vec<u8> = [1.5f32, -2.0]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        assert_eq!(spec.decode(&blob), Some(vec![1.5, -2.0]));
+        assert_eq!(spec.decode(&blob[..4]), None);
+    }
+
+    #[test]
+    fn test_hnsw_matches_flat() {
+        let mut flat = VectorIndex::new(spec("FLAT", "L2"));
+        let mut hnsw = VectorIndex::new(spec("HNSW", "L2"));
+        for i in 0..500 {
+            let v = vec![(i % 25) as f32, (i / 25) as f32];
+            flat.insert(&format!("k{}", i), v.clone());
+            hnsw.insert(&format!("k{}", i), v);
+        }
+        for i in (0..500).step_by(3) {
+            flat.remove(&format!("k{}", i));
+            hnsw.remove(&format!("k{}", i));
+        }
+        assert_eq!(flat.len(), hnsw.len());
+        let query = [7.2, 11.9];
+        let expected = flat.knn(&query, 5, None, None);
+        assert_eq!(hnsw.knn(&query, 5, Some(50), None), expected);
+
+        let allowed: HashSet<&str> = ["k1", "k499"].into_iter().collect();
+        let res = hnsw.knn(&query, 5, None, Some(&allowed));
+        assert_eq!(
+            res.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(),
+            vec!["k1", "k499"]
+        );
+    }
+}